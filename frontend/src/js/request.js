@@ -73,8 +73,8 @@ class Fetch {
      */
     _request(partialUrl, body, query, method, mode, contentType, headers, options = {}) {
         this.before && this.before();	
-        const mergedHeaders = { ...this._getAuthHeader(), ...headers };
-        const promise = request(partialUrl, body, query, method, mode, contentType, mergedHeaders, options);
+        // Authentication uses the session cookie, sent with every same-origin request
+        const promise = request(partialUrl, body, query, method, mode, contentType, headers, options);
         promise
             .then(response => {
                 if (response.status === 401) {
@@ -87,14 +87,7 @@ class Fetch {
         return promise;
     }
 
-    _getAuthHeader() {
-        const auth = sessionStorage.getItem("auth");
-        if (auth) {
-            const { token } = JSON.parse(auth);
-            return { 'Authorization': `Basic ${token}` };
-        }
-        return {};
-    }    /**
+    /**
      * @param {string} partialUrl
      * @param {Record<string, string | number>} query
      * @param {string} contentType
@@ -47,6 +47,7 @@ export async function login(username, password) {
 // 登出
 export async function logout() {
     try {
+        await fetch('/api/logout', { method: 'POST', credentials: 'same-origin' });
         await updateStorageValue('auth', null);
         isAuthenticated.set(false);
         window.location.reload();
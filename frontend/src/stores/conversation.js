@@ -20,31 +20,14 @@ let eventSource = null;
 let reconnectTimeout = null;
 const RECONNECT_DELAY = 5000; // 5 seconds
 
-const getAuthHeader = () => {
-    const auth = sessionStorage.getItem("auth");
-    if (auth) {
-        const { token } = JSON.parse(auth);
-        return { 'Authorization': `Basic ${token}` };
-    }
-    return {};
-};
-
 const connectSSE = () => {
     if (eventSource) {
         eventSource.close();
     }
 
-    const authHeader = getAuthHeader();
-    if (!authHeader.Authorization) {
-        console.error('SSE Error: Authorization token not found.');
-        sseConnected.set(false);
-        return;
-    }
-
+    // The session cookie authenticates the stream
     const eventSourceInitDict = {
-        headers: {
-            ...authHeader
-        },
+        withCredentials: true,
         heartbeatTimeout: 45000 // ms, to prevent "No activity within 45000 milliseconds"
     };
 
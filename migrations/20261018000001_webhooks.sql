@@ -0,0 +1,10 @@
+-- Webhooks managed through the API, alongside the ones in config.toml
+
+CREATE TABLE webhooks (
+    id         INTEGER PRIMARY KEY AUTOINCREMENT,
+    name       TEXT,
+    enabled    BOOLEAN   NOT NULL DEFAULT 1,
+    config     TEXT      NOT NULL,          -- JSON with the same keys as [[settings.webhooks]]
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,35 @@
+-- Keep the timezone of SMS timestamps and when the gateway received each message.
+-- New rows store `timestamp` in UTC with the original offset in `utc_offset` (seconds east of UTC).
+-- Existing rows keep their local wall-clock time and have no offset.
+ALTER TABLE sms ADD COLUMN utc_offset INTEGER;
+ALTER TABLE sms ADD COLUMN received_at TIMESTAMP;
+
+DROP VIEW v_contacts_with_sim;
+
+CREATE VIEW v_contacts_with_sim AS
+SELECT 
+    c.id, 
+    c.name, 
+    s.timestamp, 
+    s.utc_offset,
+    s.message, 
+    s.status, 
+    s.sim_id,
+    sc.alias as sim_alias,
+    COALESCE(
+        sc.alias,
+        sc.phone_number,
+        'SIM-' || SUBSTR(sc.id, -4)
+    ) as sim_display_name,
+    sc.phone_number
+FROM contacts c
+INNER JOIN (
+    SELECT *
+    FROM (
+        SELECT s.*,
+               ROW_NUMBER() OVER (PARTITION BY contact_id ORDER BY timestamp DESC,id DESC) as rn
+        FROM sms s
+    ) sub
+    WHERE rn = 1
+) s ON c.id = s.contact_id
+LEFT JOIN sim_cards sc ON s.sim_id = sc.id;
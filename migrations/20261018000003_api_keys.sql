@@ -0,0 +1,14 @@
+-- API keys sent as bearer tokens. Only a SHA-256 hash of each token is stored.
+CREATE TABLE api_keys (
+    id           INTEGER PRIMARY KEY AUTOINCREMENT,
+    name         TEXT      NOT NULL,
+    prefix       TEXT      NOT NULL,          -- Start of the token, shown to tell keys apart
+    key_hash     TEXT      NOT NULL,          -- Hex SHA-256 of the token
+    scopes       TEXT      NOT NULL,          -- JSON array, e.g. ["sms:read","sms:send"]
+    sim_ids      TEXT,                        -- JSON array of ICCIDs, NULL allows every SIM
+    expires_at   TIMESTAMP,
+    last_used_at TIMESTAMP,
+    created_at   TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE UNIQUE INDEX idx_api_keys_key_hash ON api_keys (key_hash);
@@ -0,0 +1,25 @@
+-- User accounts with roles, per-SIM visibility and login sessions
+CREATE TABLE users (
+    id            INTEGER PRIMARY KEY AUTOINCREMENT,
+    username      TEXT      NOT NULL,
+    password_hash TEXT      NOT NULL,          -- Argon2id PHC string
+    role          TEXT      NOT NULL,          -- admin, operator or read_only
+    sim_ids       TEXT,                        -- JSON array of ICCIDs, NULL allows every SIM
+    disabled      BOOLEAN   NOT NULL DEFAULT 0,
+    created_at    TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at    TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE UNIQUE INDEX idx_users_username ON users (username);
+
+CREATE TABLE sessions (
+    token_hash TEXT      PRIMARY KEY,          -- Hex SHA-256 of the session cookie
+    user_id    INTEGER   NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    expires_at TIMESTAMP NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_sessions_user_id ON sessions (user_id);
+
+-- User who sent an outgoing message, NULL for incoming messages and API keys
+ALTER TABLE sms ADD COLUMN user_id INTEGER;
@@ -0,0 +1,22 @@
+-- Append-only record of administrative and sending actions
+CREATE TABLE audit_log (
+    id           INTEGER PRIMARY KEY AUTOINCREMENT,
+    timestamp    TIMESTAMP NOT NULL,           -- UTC
+    actor        TEXT      NOT NULL,           -- Username, key:<name> or the configured admin
+    user_id      INTEGER,
+    action       TEXT      NOT NULL,           -- e.g. sim.alias.update, sms.send
+    target       TEXT,                         -- e.g. sim:<iccid>, contact:<id>
+    before_value TEXT,                         -- JSON
+    after_value  TEXT,                         -- JSON
+    source_ip    TEXT
+);
+
+CREATE INDEX idx_audit_log_timestamp ON audit_log (timestamp DESC);
+CREATE INDEX idx_audit_log_actor ON audit_log (actor);
+CREATE INDEX idx_audit_log_action ON audit_log (action);
+
+-- Entries can only be removed by retention, never changed
+CREATE TRIGGER audit_log_no_update BEFORE UPDATE ON audit_log
+BEGIN
+    SELECT RAISE(ABORT, 'audit_log is append-only');
+END;
@@ -0,0 +1,38 @@
+-- Full-text index over SMS messages and contact names, behind /api/sms/search.
+-- The trigram tokenizer matches any substring of three or more characters, which also works
+-- for CJK text that has no spaces between words. The rowid is the sms id.
+CREATE VIRTUAL TABLE sms_fts USING fts5(
+    message,
+    contact_name,
+    tokenize = 'trigram'
+);
+
+INSERT INTO sms_fts (rowid, message, contact_name)
+SELECT s.id, s.message, COALESCE(c.name, '')
+FROM sms s
+LEFT JOIN contacts c ON c.id = s.contact_id;
+
+CREATE TRIGGER sms_fts_insert AFTER INSERT ON sms
+BEGIN
+    INSERT INTO sms_fts (rowid, message, contact_name)
+    VALUES (new.id, new.message, COALESCE((SELECT name FROM contacts WHERE id = new.contact_id), ''));
+END;
+
+CREATE TRIGGER sms_fts_delete AFTER DELETE ON sms
+BEGIN
+    DELETE FROM sms_fts WHERE rowid = old.id;
+END;
+
+CREATE TRIGGER sms_fts_update AFTER UPDATE OF message, contact_id ON sms
+BEGIN
+    UPDATE sms_fts
+    SET message = new.message,
+        contact_name = COALESCE((SELECT name FROM contacts WHERE id = new.contact_id), '')
+    WHERE rowid = new.id;
+END;
+
+CREATE TRIGGER sms_fts_contact_rename AFTER UPDATE OF name ON contacts
+BEGIN
+    UPDATE sms_fts SET contact_name = new.name
+    WHERE rowid IN (SELECT id FROM sms WHERE contact_id = new.id);
+END;
@@ -0,0 +1,4 @@
+-- Keyset pagination of /api/sms orders by (timestamp, id). The rowid is part of every index
+-- entry, so this serves listings across all contacts the way idx_sms_contact_timestamp serves
+-- a single conversation.
+CREATE INDEX idx_sms_timestamp ON sms (timestamp);
@@ -0,0 +1,20 @@
+-- Soft-deleted messages. Deleting moves rows out of `sms` into this table, so every query, view and
+-- the search index ignore them; undo moves them back with their original ids. Rows are purged
+-- once the undo window has passed.
+CREATE TABLE deleted_sms (
+    id          INTEGER PRIMARY KEY,          -- The original sms id
+    contact_id  TEXT      NOT NULL,
+    timestamp   TIMESTAMP NOT NULL,
+    utc_offset  INTEGER,
+    received_at TIMESTAMP,
+    message     TEXT      NOT NULL,
+    sim_id      TEXT      NOT NULL,
+    send        BOOLEAN   NOT NULL,
+    status      INTEGER   NOT NULL,
+    user_id     INTEGER,
+    deletion_id TEXT      NOT NULL,           -- Shared by the rows of one delete request
+    deleted_at  TIMESTAMP NOT NULL
+);
+
+CREATE INDEX idx_deleted_sms_deletion_id ON deleted_sms (deletion_id);
+CREATE INDEX idx_deleted_sms_deleted_at ON deleted_sms (deleted_at);
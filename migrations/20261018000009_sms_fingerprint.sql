@@ -0,0 +1,9 @@
+-- Message fingerprints make storing a message idempotent: reading the same stored messages from
+-- the modem again (after a crash, or a refresh that reads every message) and importing the same
+-- backup twice no longer create duplicates. The fingerprint is computed by the gateway, so rows
+-- from before this migration are filled in at startup; existing duplicates keep a NULL fingerprint.
+ALTER TABLE sms ADD COLUMN fingerprint TEXT;
+CREATE UNIQUE INDEX idx_sms_fingerprint ON sms (fingerprint);
+
+-- Soft-deleted rows keep theirs, so a restore brings the message back with it
+ALTER TABLE deleted_sms ADD COLUMN fingerprint TEXT;
@@ -0,0 +1,54 @@
+-- Contacts become address book entries with a display name, notes, a blocked flag, any number of
+-- phone numbers and tags. `name` stays the primary number that new messages are sent to.
+ALTER TABLE contacts ADD COLUMN display_name TEXT;
+ALTER TABLE contacts ADD COLUMN notes TEXT;
+ALTER TABLE contacts ADD COLUMN blocked BOOLEAN NOT NULL DEFAULT 0;
+
+-- Normalised numbers that incoming senders are matched against. Normalising is done by the
+-- gateway, so the numbers of existing contacts are filled in at startup.
+CREATE TABLE contact_numbers (
+    number     TEXT PRIMARY KEY,
+    contact_id TEXT NOT NULL REFERENCES contacts (id) ON DELETE CASCADE,
+    label      TEXT
+);
+
+CREATE INDEX idx_contact_numbers_contact_id ON contact_numbers (contact_id);
+
+CREATE TABLE contact_tags (
+    contact_id TEXT NOT NULL REFERENCES contacts (id) ON DELETE CASCADE,
+    tag        TEXT NOT NULL,
+    PRIMARY KEY (contact_id, tag)
+);
+
+CREATE INDEX idx_contact_tags_tag ON contact_tags (tag);
+
+DROP VIEW v_contacts_with_sim;
+
+CREATE VIEW v_contacts_with_sim AS
+SELECT
+    c.id,
+    c.name,
+    c.display_name,
+    s.timestamp,
+    s.utc_offset,
+    s.message,
+    s.status,
+    s.sim_id,
+    sc.alias as sim_alias,
+    COALESCE(
+        sc.alias,
+        sc.phone_number,
+        'SIM-' || SUBSTR(sc.id, -4)
+    ) as sim_display_name,
+    sc.phone_number
+FROM contacts c
+INNER JOIN (
+    SELECT *
+    FROM (
+        SELECT s.*,
+               ROW_NUMBER() OVER (PARTITION BY contact_id ORDER BY timestamp DESC,id DESC) as rn
+        FROM sms s
+    ) sub
+    WHERE rn = 1
+) s ON c.id = s.contact_id
+LEFT JOIN sim_cards sc ON s.sim_id = sc.id;
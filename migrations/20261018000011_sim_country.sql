@@ -0,0 +1,3 @@
+-- Country of each SIM, used to write national numbers in E.164. Falls back to
+-- `settings.default_country` when unset.
+ALTER TABLE sim_cards ADD COLUMN default_country TEXT;
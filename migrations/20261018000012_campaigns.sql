@@ -0,0 +1,29 @@
+-- Campaigns send one templated message to many recipients through the normal send path, so every
+-- message sent is also an `sms` row.
+CREATE TABLE campaigns (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    name        TEXT      NOT NULL,
+    template    TEXT      NOT NULL,          -- @name@, @number@ and per-recipient variables
+    sim_ids     TEXT      NOT NULL,          -- JSON array of ICCIDs sending in parallel
+    per_minute  INTEGER   NOT NULL,          -- Throttle across all of its SIMs
+    status      TEXT      NOT NULL,          -- running, paused, cancelled or completed
+    last_error  TEXT,                        -- Why the gateway paused it
+    user_id     INTEGER   REFERENCES users (id) ON DELETE SET NULL,
+    created_at  TIMESTAMP NOT NULL,
+    finished_at TIMESTAMP
+);
+
+CREATE TABLE campaign_recipients (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    campaign_id INTEGER   NOT NULL REFERENCES campaigns (id) ON DELETE CASCADE,
+    number      TEXT      NOT NULL,          -- Normalised
+    contact_id  TEXT,                        -- Known contact at creation, otherwise set when sent
+    variables   TEXT      NOT NULL,          -- JSON object of template variables
+    status      TEXT      NOT NULL,          -- pending, sending, sent, failed, skipped or cancelled
+    sim_id      TEXT,
+    sms_id      INTEGER,
+    error       TEXT,
+    updated_at  TIMESTAMP NOT NULL
+);
+
+CREATE INDEX idx_campaign_recipients_campaign_id_status ON campaign_recipients (campaign_id, status);
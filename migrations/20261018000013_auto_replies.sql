@@ -0,0 +1,26 @@
+-- Rules answering incoming messages, managed through the API like `webhooks`
+CREATE TABLE auto_reply_rules (
+    id         INTEGER PRIMARY KEY AUTOINCREMENT,
+    name       TEXT,
+    enabled    BOOLEAN   NOT NULL DEFAULT 1,
+    priority   INTEGER   NOT NULL DEFAULT 0,  -- Lower runs first
+    config     TEXT      NOT NULL,            -- JSON: keywords, filters, actions, cooldown_secs, stop
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- One row per action a rule took, for cooldowns and the hourly cap on automatic messages
+CREATE TABLE auto_reply_events (
+    id         INTEGER PRIMARY KEY AUTOINCREMENT,
+    rule_id    INTEGER   NOT NULL REFERENCES auto_reply_rules (id) ON DELETE CASCADE,
+    contact    TEXT      NOT NULL,            -- Normalised sender of the triggering message
+    sim_id     TEXT      NOT NULL,
+    action     TEXT      NOT NULL,            -- reply, forward, tag or webhook
+    recipient  TEXT,                          -- Number an automatic message was sent to
+    sms_id     INTEGER,
+    error      TEXT,
+    created_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX idx_auto_reply_events_rule_id_contact ON auto_reply_events (rule_id, contact, created_at);
+CREATE INDEX idx_auto_reply_events_recipient ON auto_reply_events (recipient, created_at);
@@ -0,0 +1,9 @@
+-- Numbers that opted out; nothing is sent to them while they are listed
+CREATE TABLE suppressions (
+    number     TEXT      PRIMARY KEY,       -- Normalised
+    source     TEXT      NOT NULL,          -- keyword (replied STOP) or admin
+    sim_id     TEXT,                        -- SIM the opt-out arrived on
+    reason     TEXT,                        -- The opt-out message or the admin's note
+    user_id    INTEGER   REFERENCES users (id) ON DELETE SET NULL,
+    created_at TIMESTAMP NOT NULL
+);
@@ -0,0 +1,36 @@
+-- The search index holds the contact's display name as well as its number, and is updated when
+-- either changes.
+DELETE FROM sms_fts;
+
+INSERT INTO sms_fts (rowid, message, contact_name)
+SELECT s.id, s.message, COALESCE(COALESCE(c.display_name, '') || ' ' || c.name, '')
+FROM sms s
+LEFT JOIN contacts c ON c.id = s.contact_id;
+
+DROP TRIGGER sms_fts_insert;
+DROP TRIGGER sms_fts_update;
+DROP TRIGGER sms_fts_contact_rename;
+
+CREATE TRIGGER sms_fts_insert AFTER INSERT ON sms
+BEGIN
+    INSERT INTO sms_fts (rowid, message, contact_name)
+    VALUES (
+        new.id,
+        new.message,
+        COALESCE((SELECT COALESCE(display_name, '') || ' ' || name FROM contacts WHERE id = new.contact_id), '')
+    );
+END;
+
+CREATE TRIGGER sms_fts_update AFTER UPDATE OF message, contact_id ON sms
+BEGIN
+    UPDATE sms_fts
+    SET message = new.message,
+        contact_name = COALESCE((SELECT COALESCE(display_name, '') || ' ' || name FROM contacts WHERE id = new.contact_id), '')
+    WHERE rowid = new.id;
+END;
+
+CREATE TRIGGER sms_fts_contact_rename AFTER UPDATE OF name, display_name ON contacts
+BEGIN
+    UPDATE sms_fts SET contact_name = COALESCE(new.display_name, '') || ' ' || new.name
+    WHERE rowid IN (SELECT id FROM sms WHERE contact_id = new.id);
+END;
@@ -0,0 +1,5 @@
+-- Fingerprints now hash the sender's number in E.164 rather than as the modem reported it, so the
+-- same message read with and without a country code is stored once. The existing ones are cleared
+-- and computed again at startup, after contact numbers have been normalised.
+UPDATE sms SET fingerprint = NULL;
+UPDATE deleted_sms SET fingerprint = NULL;
@@ -0,0 +1,620 @@
+use std::str::FromStr;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    db::{Contact, ContactDetails, ContactNumber},
+    phone::normalize,
+};
+
+const MAX_ERRORS: usize = 50;
+/// vCard lines are folded after this many bytes
+const VCARD_LINE_BYTES: usize = 75;
+/// TEL types that say nothing about which of the numbers it is
+const GENERIC_TEL_TYPES: &[&str] = &["voice", "pref", "internet", "x-internet"];
+const CSV_HEADER: [&str; 7] = [
+    "id",
+    "display_name",
+    "numbers",
+    "labels",
+    "tags",
+    "notes",
+    "blocked",
+];
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ContactFormat {
+    #[default]
+    Vcard, // vCard 3.0 on export, 3.0 or 4.0 on import
+    Vcard4,
+    Csv,
+}
+
+impl FromStr for ContactFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "vcard" => Ok(ContactFormat::Vcard),
+            "vcard4" => Ok(ContactFormat::Vcard4),
+            "csv" => Ok(ContactFormat::Csv),
+            _ => Err(format!(
+                "Unknown contact format '{}', expected vcard, vcard4 or csv",
+                s
+            )),
+        }
+    }
+}
+
+impl ContactFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ContactFormat::Vcard | ContactFormat::Vcard4 => "text/vcard; charset=utf-8",
+            ContactFormat::Csv => "text/csv; charset=utf-8",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ContactFormat::Vcard | ContactFormat::Vcard4 => "vcf",
+            ContactFormat::Csv => "csv",
+        }
+    }
+}
+
+/// A field the file and an existing contact disagree on. The stored value is kept.
+#[derive(Debug, Serialize)]
+pub struct ContactConflict {
+    pub record: usize,
+    pub contact_id: Option<String>, // None when the numbers belong to several contacts
+    pub field: String,
+    pub existing: String,
+    pub imported: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ContactImportReport {
+    pub created: u64,
+    pub updated: u64, // Existing contacts that gained numbers, tags or empty fields
+    pub unchanged: u64, // Already stored as in the file, apart from conflicts
+    pub invalid: u64,
+    pub conflicts: Vec<ContactConflict>,
+    pub errors: Vec<String>, // The first few invalid records and why
+}
+
+impl ContactImportReport {
+    fn invalid(&mut self, record: usize, error: String) {
+        self.invalid += 1;
+        if self.errors.len() < MAX_ERRORS {
+            self.errors.push(format!("record {}: {}", record, error));
+        }
+    }
+
+    fn conflict(
+        &mut self,
+        record: usize,
+        contact_id: Option<&str>,
+        field: &str,
+        existing: String,
+        imported: String,
+    ) {
+        self.conflicts.push(ContactConflict {
+            record,
+            contact_id: contact_id.map(str::to_string),
+            field: field.to_string(),
+            existing,
+            imported,
+        });
+    }
+}
+
+/// A contact as read from a file, numbers not yet normalised
+#[derive(Debug, Default)]
+struct Card {
+    display_name: Option<String>,
+    numbers: Vec<ContactNumber>,
+    tags: Vec<String>,
+    notes: Option<String>,
+    blocked: Option<bool>, // Only when the file says
+}
+
+fn non_blank(value: &str) -> Option<String> {
+    let value = value.trim();
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+/// Imports contacts, merging each into the existing contact that owns any of its numbers
+pub async fn import(format: ContactFormat, data: &str) -> Result<ContactImportReport> {
+    let data = data.trim_start_matches('\u{feff}');
+    let cards = match format {
+        ContactFormat::Vcard | ContactFormat::Vcard4 => parse_vcards(data),
+        ContactFormat::Csv => parse_csv(data),
+    };
+
+    let mut report = ContactImportReport::default();
+    for (index, card) in cards.into_iter().enumerate() {
+        match card {
+            Ok(card) => import_card(index + 1, card, &mut report).await?,
+            Err(e) => report.invalid(index + 1, e),
+        }
+    }
+    Ok(report)
+}
+
+async fn import_card(record: usize, card: Card, report: &mut ContactImportReport) -> Result<()> {
+    let mut numbers: Vec<ContactNumber> = Vec::new();
+    for number in card.numbers {
+        let normalized = normalize(&number.number);
+        if !normalized.is_empty() && !numbers.iter().any(|n| n.number == normalized) {
+            numbers.push(ContactNumber {
+                number: normalized,
+                label: number.label,
+            });
+        }
+    }
+    if numbers.is_empty() {
+        report.invalid(record, "No phone number".to_string());
+        return Ok(());
+    }
+    let mut tags: Vec<String> = card.tags.iter().filter_map(|tag| non_blank(tag)).collect();
+    tags.sort();
+    tags.dedup();
+
+    let mut owners: Vec<String> = Vec::new();
+    for number in &numbers {
+        if let Some(owner) = Contact::find_by_number(&number.number).await? {
+            if !owners.contains(&owner.id) {
+                owners.push(owner.id);
+            }
+        }
+    }
+
+    let owner = match owners.as_slice() {
+        [] => {
+            let mut details = ContactDetails::default();
+            details.contact.id = Uuid::new_v4().to_string();
+            details.contact.name = numbers[0].number.clone();
+            details.contact.display_name = card.display_name;
+            details.notes = card.notes;
+            details.blocked = card.blocked.unwrap_or_default();
+            details.numbers = numbers;
+            details.tags = tags;
+            details.save().await?;
+            report.created += 1;
+            return Ok(());
+        }
+        [owner] => owner,
+        _ => {
+            let imported = numbers
+                .iter()
+                .map(|n| n.number.as_str())
+                .collect::<Vec<_>>();
+            report.conflict(
+                record,
+                None,
+                "numbers",
+                owners.join(", "),
+                imported.join(", "),
+            );
+            return Ok(());
+        }
+    };
+    let Some(mut details) = Contact::query_details_by_id(owner).await? else {
+        report.invalid(
+            record,
+            format!("Contact {} was deleted during the import", owner),
+        );
+        return Ok(());
+    };
+
+    let mut changed = false;
+    for number in numbers {
+        match details
+            .numbers
+            .iter_mut()
+            .find(|n| n.number == number.number)
+        {
+            Some(stored) if stored.label.is_none() && number.label.is_some() => {
+                stored.label = number.label;
+                changed = true;
+            }
+            Some(_) => {}
+            None => {
+                details.numbers.push(number);
+                changed = true;
+            }
+        }
+    }
+    for tag in tags {
+        if !details.tags.contains(&tag) {
+            details.tags.push(tag);
+            changed = true;
+        }
+    }
+    details.tags.sort();
+
+    let fields = [
+        (
+            "display_name",
+            &mut details.contact.display_name,
+            card.display_name,
+        ),
+        ("notes", &mut details.notes, card.notes),
+    ];
+    for (field, stored, imported) in fields {
+        match (stored.as_ref(), imported) {
+            (_, None) => {}
+            (None, imported) => {
+                *stored = imported;
+                changed = true;
+            }
+            (Some(existing), Some(imported)) if *existing != imported => {
+                report.conflict(record, Some(owner), field, existing.clone(), imported);
+            }
+            _ => {}
+        }
+    }
+    if let Some(blocked) = card.blocked.filter(|blocked| *blocked != details.blocked) {
+        report.conflict(
+            record,
+            Some(owner),
+            "blocked",
+            details.blocked.to_string(),
+            blocked.to_string(),
+        );
+    }
+
+    if changed {
+        details.save().await?;
+        report.updated += 1;
+    } else {
+        report.unchanged += 1;
+    }
+    Ok(())
+}
+
+/// Contacts as a file in `format`
+pub fn write(format: ContactFormat, contacts: &[ContactDetails]) -> Result<String> {
+    match format {
+        ContactFormat::Vcard => Ok(contacts.iter().map(|c| write_vcard(c, false)).collect()),
+        ContactFormat::Vcard4 => Ok(contacts.iter().map(|c| write_vcard(c, true)).collect()),
+        ContactFormat::Csv => write_csv(contacts),
+    }
+}
+
+fn write_csv(contacts: &[ContactDetails]) -> Result<String> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(CSV_HEADER)?;
+    for details in contacts {
+        let numbers: Vec<&str> = details.numbers.iter().map(|n| n.number.as_str()).collect();
+        let labels: Vec<&str> = details
+            .numbers
+            .iter()
+            .map(|n| n.label.as_deref().unwrap_or_default())
+            .collect();
+        writer.write_record([
+            details.contact.id.as_str(),
+            details.contact.display_name.as_deref().unwrap_or_default(),
+            &numbers.join(";"),
+            &labels.join(";"),
+            &details.tags.join(";"),
+            details.notes.as_deref().unwrap_or_default(),
+            if details.blocked { "true" } else { "false" },
+        ])?;
+    }
+    Ok(String::from_utf8(writer.into_inner()?)?)
+}
+
+/// Rows with the columns of the CSV export. Common alternative headers are understood, other
+/// columns are ignored.
+fn parse_csv(data: &str) -> Vec<Result<Card, String>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(data.as_bytes());
+    let headers: Vec<String> = match reader.headers() {
+        Ok(headers) => headers.iter().map(|h| h.trim().to_lowercase()).collect(),
+        Err(e) => return vec![Err(e.to_string())],
+    };
+    let column = |names: &[&str]| headers.iter().position(|h| names.contains(&h.as_str()));
+    let display_name = column(&["display_name", "name", "full_name", "full name"]);
+    let numbers = column(&[
+        "numbers",
+        "number",
+        "phone",
+        "phones",
+        "phone_number",
+        "tel",
+    ]);
+    let labels = column(&["labels", "label"]);
+    let tags = column(&["tags", "tag", "categories", "groups"]);
+    let notes = column(&["notes", "note"]);
+    let blocked = column(&["blocked"]);
+
+    reader
+        .records()
+        .map(|row| {
+            let row = row.map_err(|e| e.to_string())?;
+            let field = |index: Option<usize>| index.and_then(|i| row.get(i)).unwrap_or_default();
+            let labels: Vec<&str> = field(labels).split(';').collect();
+            let blocked = match field(blocked).trim().to_lowercase().as_str() {
+                "" => None,
+                "true" | "1" | "yes" => Some(true),
+                "false" | "0" | "no" => Some(false),
+                other => return Err(format!("Invalid blocked value '{}'", other)),
+            };
+            Ok(Card {
+                display_name: non_blank(field(display_name)),
+                numbers: field(numbers)
+                    .split(';')
+                    .enumerate()
+                    .filter_map(|(i, number)| {
+                        Some(ContactNumber {
+                            number: non_blank(number)?,
+                            label: labels.get(i).and_then(|label| non_blank(label)),
+                        })
+                    })
+                    .collect(),
+                tags: field(tags).split(';').map(str::to_string).collect(),
+                notes: non_blank(field(notes)),
+                blocked,
+            })
+        })
+        .collect()
+}
+
+fn write_vcard(details: &ContactDetails, version4: bool) -> String {
+    let mut lines = vec![
+        "BEGIN:VCARD".to_string(),
+        format!("VERSION:{}", if version4 { "4.0" } else { "3.0" }),
+        format!("UID:{}", escape(&details.contact.id)),
+    ];
+    let name = details
+        .contact
+        .display_name
+        .as_deref()
+        .unwrap_or(&details.contact.name);
+    lines.push(format!("FN:{}", escape(name)));
+    if !version4 {
+        // Required by 3.0; the display name is not split into its parts
+        lines.push(format!("N:;{};;;", escape(name)));
+    }
+    for (index, number) in details.numbers.iter().enumerate() {
+        let mut params = String::new();
+        let mut types: Vec<String> = number
+            .label
+            .iter()
+            .map(|label| param_value(label))
+            .collect();
+        if index == 0 {
+            if version4 {
+                params.push_str(";PREF=1");
+            } else {
+                types.push("pref".to_string());
+            }
+        }
+        if !types.is_empty() {
+            params.push_str(&format!(";TYPE={}", types.join(",")));
+        }
+        let is_phone = number
+            .number
+            .trim_start_matches('+')
+            .chars()
+            .all(|c| c.is_ascii_digit());
+        let value = match (version4, is_phone) {
+            (true, true) => format!(";VALUE=uri{}:tel:{}", params, number.number),
+            (true, false) => format!(";VALUE=text{}:{}", params, escape(&number.number)),
+            (false, _) => format!("{}:{}", params, escape(&number.number)),
+        };
+        lines.push(format!("TEL{}", value));
+    }
+    if !details.tags.is_empty() {
+        let tags: Vec<String> = details.tags.iter().map(|tag| escape(tag)).collect();
+        lines.push(format!("CATEGORIES:{}", tags.join(",")));
+    }
+    if let Some(notes) = &details.notes {
+        lines.push(format!("NOTE:{}", escape(notes)));
+    }
+    if details.blocked {
+        lines.push("X-BLOCKED:TRUE".to_string());
+    }
+    lines.push("END:VCARD".to_string());
+
+    lines.iter().map(|line| fold(line)).collect()
+}
+
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ',' => escaped.push_str("\\,"),
+            ';' => escaped.push_str("\\;"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// A parameter value, quoted unless it is a plain token
+fn param_value(value: &str) -> String {
+    if value.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        value.to_string()
+    } else {
+        format!("\"{}\"", value.replace('"', ""))
+    }
+}
+
+/// The line with CRLF, split into continuation lines where it is too long
+fn fold(line: &str) -> String {
+    let mut folded = String::with_capacity(line.len() + 4);
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > VCARD_LINE_BYTES {
+            folded.push_str("\r\n ");
+            width = 1;
+        }
+        folded.push(c);
+        width += c.len_utf8();
+    }
+    folded.push_str("\r\n");
+    folded
+}
+
+/// vCard 3.0 and 4.0 cards. Properties the gateway has no place for, like photos and
+/// addresses, are skipped.
+fn parse_vcards(data: &str) -> Vec<Result<Card, String>> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in data.split('\n') {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(continuation), Some(last)) => last.push_str(continuation),
+            _ if line.trim().is_empty() => {}
+            _ => lines.push(line.to_string()),
+        }
+    }
+
+    let mut cards = Vec::new();
+    let mut current: Option<(Card, Option<String>)> = None; // With the name from N
+    for line in &lines {
+        let Some((name, params, value)) = split_property(line) else {
+            continue;
+        };
+        match name.as_str() {
+            "BEGIN" if value.eq_ignore_ascii_case("VCARD") => {
+                if current.replace(Default::default()).is_some() {
+                    cards.push(Err("Missing END:VCARD".to_string()));
+                }
+                continue;
+            }
+            "END" if value.eq_ignore_ascii_case("VCARD") => {
+                match current.take() {
+                    Some((mut card, n)) => {
+                        card.display_name = card.display_name.or(n);
+                        cards.push(Ok(card));
+                    }
+                    None => cards.push(Err("END:VCARD without BEGIN:VCARD".to_string())),
+                }
+                continue;
+            }
+            _ => {}
+        }
+        let Some((card, n)) = current.as_mut() else {
+            continue;
+        };
+        match name.as_str() {
+            "FN" => card.display_name = non_blank(&unescape(value)),
+            "N" => {
+                // Family; Given; Additional; Prefixes; Suffixes
+                let parts = split_escaped(value, ';');
+                let order = [3, 1, 2, 0, 4];
+                let parts: Vec<&str> = order
+                    .iter()
+                    .filter_map(|&i| parts.get(i).map(|part| part.trim()))
+                    .filter(|part| !part.is_empty())
+                    .collect();
+                *n = non_blank(&parts.join(" "));
+            }
+            "TEL" => {
+                let value = value.trim();
+                let value = match value.get(..4) {
+                    Some(scheme) if scheme.eq_ignore_ascii_case("tel:") => &value[4..],
+                    _ => value,
+                };
+                // Drops URI parameters like ";ext="
+                let number = unescape(value.split(';').next().unwrap_or_default());
+                let label = params
+                    .iter()
+                    .filter(|(key, _)| key == "TYPE")
+                    .flat_map(|(_, value)| value.split(','))
+                    .map(|value| value.trim().to_lowercase())
+                    .find(|value| {
+                        !value.is_empty() && !GENERIC_TEL_TYPES.contains(&value.as_str())
+                    });
+                if let Some(number) = non_blank(&number) {
+                    card.numbers.push(ContactNumber { number, label });
+                }
+            }
+            "NOTE" => card.notes = non_blank(&unescape(value)),
+            "CATEGORIES" => card.tags.extend(split_escaped(value, ',')),
+            "X-BLOCKED" => card.blocked = Some(value.trim().eq_ignore_ascii_case("TRUE")),
+            _ => {}
+        }
+    }
+    if current.is_some() {
+        cards.push(Err("Missing END:VCARD".to_string()));
+    }
+    cards
+}
+
+type Property<'a> = (String, Vec<(String, String)>, &'a str);
+
+/// The upper-cased name without its group, the parameters and the raw value of a content line
+fn split_property(line: &str) -> Option<Property<'_>> {
+    let mut quoted = false;
+    let colon = line.char_indices().find_map(|(i, c)| {
+        match c {
+            '"' => quoted = !quoted,
+            ':' if !quoted => return Some(i),
+            _ => {}
+        }
+        None
+    })?;
+    let (head, value) = (&line[..colon], &line[colon + 1..]);
+
+    let mut parts = head.split(';');
+    let name = parts.next()?;
+    let name = name
+        .rsplit('.')
+        .next()
+        .unwrap_or(name)
+        .trim()
+        .to_uppercase();
+    let params = parts
+        .map(|param| match param.split_once('=') {
+            Some((key, value)) => (key.trim().to_uppercase(), value.replace('"', "")),
+            // vCard 2.1 style types, like TEL;CELL
+            None => ("TYPE".to_string(), param.to_string()),
+        })
+        .collect();
+    Some((name, params, value))
+}
+
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') | Some('N') => unescaped.push('\n'),
+            Some(next) => unescaped.push(next),
+            None => {}
+        }
+    }
+    unescaped
+}
+
+/// Splits at `separator`s that are not escaped, then unescapes the parts
+fn split_escaped(value: &str, separator: char) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut escaped = false;
+    for c in value.chars() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == separator {
+            parts.push(String::new());
+            continue;
+        }
+        parts.last_mut().unwrap().push(c);
+    }
+    parts.iter().map(|part| unescape(part)).collect()
+}
@@ -0,0 +1,90 @@
+use axum::{
+    extract::Query,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use log::error;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::auth::Principal;
+use crate::db::{AuditEntry, AuditFilter};
+
+#[derive(Deserialize, Debug)]
+pub struct AuditQuery {
+    page: u32,
+    per_page: u32,
+    actor: Option<String>,
+    user_id: Option<i64>,
+    action: Option<String>,
+    target: Option<String>,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+pub struct PaginatedAuditResponse {
+    data: Vec<AuditEntry>,
+    total: i64,
+    page: u32,
+    per_page: u32,
+}
+
+/// Records an action taken by the principal. Failures are logged and never fail the request.
+pub async fn record(
+    principal: &Principal,
+    action: &str,
+    target: Option<String>,
+    before: Option<Value>,
+    after: Option<Value>,
+) {
+    if let Err(e) = AuditEntry::insert(
+        &principal.name,
+        principal.user_id,
+        action,
+        target.as_deref(),
+        before,
+        after,
+        principal.ip.as_deref(),
+    )
+    .await
+    {
+        error!("Failed to record audit entry {}: {}", action, e);
+    }
+}
+
+/// Serializes a value for `before`/`after`, dropping it if serialization fails
+pub fn snapshot<T: Serialize>(value: &T) -> Option<Value> {
+    serde_json::to_value(value).ok()
+}
+
+/// Newest first; `action` matches by prefix, so `sim.` lists every SIM change
+pub async fn list_audit(Query(query): Query<AuditQuery>) -> Response {
+    let filter = AuditFilter {
+        actor: query.actor,
+        user_id: query.user_id,
+        action: query.action,
+        target: query.target,
+        since: query.since,
+        until: query.until,
+    };
+    match AuditEntry::paginate(&filter, query.page, query.per_page).await {
+        Ok((data, total)) => Json(PaginatedAuditResponse {
+            data,
+            total,
+            page: query.page,
+            per_page: query.per_page,
+        })
+        .into_response(),
+        Err(e) => {
+            error!("Failed to query audit log: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({ "error": format!("Database error: {}", e) })),
+            )
+                .into_response()
+        }
+    }
+}
@@ -1,39 +1,348 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
 use axum::{
-    extract::{Request, State},
+    extract::{ConnectInfo, Path, Request, State},
     http::StatusCode,
     middleware::Next,
-    response::Response,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
 };
 use base64::prelude::*;
+use chrono::{Duration, Utc};
+use log::{error, info, warn};
+use rand::{rngs::OsRng, RngCore};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
 
-pub async fn basic_auth(
-    State((username, password)): State<(String, String)>,
-    req: Request,
+use super::guard::{too_many_attempts, LoginGuard};
+use super::tls::ClientCert;
+use crate::db::{ApiKey, Role, Scope, User};
+
+const TOKEN_PREFIX: &str = "sgw_";
+pub const SESSION_COOKIE: &str = "sgw_session";
+pub const SESSION_DAYS: i64 = 7;
+
+/// Who made a request, added to the request extensions by `authenticate`
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Principal {
+    pub name: String,
+    pub user_id: Option<i64>,         // Set for logged-in users
+    pub role: Option<Role>,
+    pub scopes: Option<Vec<Scope>>,   // None grants every scope
+    pub sim_ids: Option<Vec<String>>, // None allows every SIM
+    #[serde(skip)]
+    pub ip: Option<String>, // Client address, recorded in the audit log
+}
+
+impl Principal {
+    fn full_access(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            user_id: None,
+            role: None,
+            scopes: None,
+            sim_ids: None,
+            ip: None,
+        }
+    }
+
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes.as_ref().is_none_or(|scopes| scopes.contains(&scope))
+    }
+
+    pub fn allows_sim(&self, sim_id: &str) -> bool {
+        self.sim_ids
+            .as_ref()
+            .is_none_or(|sims| sims.iter().any(|s| s == sim_id))
+    }
+
+    /// SIMs the principal is restricted to, for filtering queries
+    pub fn sims(&self) -> Option<&[String]> {
+        self.sim_ids.as_deref()
+    }
+
+    /// Checks that a key or user given `scopes` and `sim_ids` gets no access the principal
+    /// lacks itself. A principal restricted to some SIMs cannot grant access to every SIM.
+    pub fn may_grant(&self, scopes: &[Scope], sim_ids: Option<&[String]>) -> Result<(), String> {
+        if let Some(scope) = scopes.iter().find(|scope| !self.has_scope(**scope)) {
+            let scope = serde_json::to_value(scope).unwrap_or_default();
+            return Err(format!("Not allowed to grant {}", scope.as_str().unwrap_or_default()));
+        }
+        if self.sims().is_none() {
+            return Ok(());
+        }
+        let Some(sim_ids) = sim_ids else {
+            return Err("sim_ids is required, access to every SIM cannot be granted".to_string());
+        };
+        match sim_ids.iter().find(|sim_id| !self.allows_sim(sim_id)) {
+            Some(sim_id) => Err(format!("Not allowed to use SIM {}", sim_id)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl From<&ApiKey> for Principal {
+    fn from(key: &ApiKey) -> Self {
+        Self {
+            name: format!("key:{}", key.name),
+            user_id: None,
+            role: None,
+            scopes: Some(key.scopes.0.clone()),
+            sim_ids: key.sim_ids.as_ref().map(|sims| sims.0.clone()),
+            ip: None,
+        }
+    }
+}
+
+impl From<&ClientCert> for Principal {
+    fn from(cert: &ClientCert) -> Self {
+        Self {
+            name: format!("cert:{}", cert.name),
+            user_id: None,
+            role: None,
+            scopes: cert.scopes.clone(),
+            sim_ids: None,
+            ip: None,
+        }
+    }
+}
+
+impl From<&User> for Principal {
+    fn from(user: &User) -> Self {
+        Self {
+            name: user.username.clone(),
+            user_id: Some(user.id),
+            role: Some(user.role),
+            scopes: Some(user.role.scopes()),
+            sim_ids: user.sim_ids.as_ref().map(|sims| sims.0.clone()),
+            ip: None,
+        }
+    }
+}
+
+fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+/// Returns a new random token and the prefix stored to recognise it
+pub fn generate_token() -> (String, String) {
+    let token = format!("{}{}", TOKEN_PREFIX, random_token());
+    let prefix = token[..TOKEN_PREFIX.len() + 8].to_string();
+    (token, prefix)
+}
+
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+pub fn hash_password(password: &str) -> anyhow::Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| anyhow::anyhow!("Failed to hash password: {}", e))
+}
+
+/// Spends the same time as `verify_password` so unknown usernames are not revealed by timing
+pub fn verify_dummy_password(password: &str) {
+    static DUMMY_HASH: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+    let hash = DUMMY_HASH.get_or_init(|| hash_password("dummy password").unwrap_or_default());
+    verify_password(password, hash);
+}
+
+pub fn verify_password(password: &str, password_hash: &str) -> bool {
+    PasswordHash::new(password_hash).is_ok_and(|hash| {
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok()
+    })
+}
+
+/// Starts a session for the user, returning the cookie token
+pub async fn create_session(user: &User) -> anyhow::Result<String> {
+    let token = random_token();
+    user.create_session(&hash_token(&token), Utc::now() + Duration::days(SESSION_DAYS))
+        .await?;
+    Ok(token)
+}
+
+/// Value of the session cookie, if the request has one
+pub fn session_token(headers: &axum::http::HeaderMap) -> Option<&str> {
+    headers
+        .get_all(axum::http::header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|cookies| cookies.split(';'))
+        .find_map(|cookie| cookie.trim().strip_prefix(SESSION_COOKIE)?.strip_prefix('='))
+}
+
+/// Creates an admin from the configured credentials when there are no users yet
+pub async fn seed_admin(credentials: Option<&(String, String)>) -> anyhow::Result<()> {
+    let Some((username, password)) = credentials else {
+        return Ok(());
+    };
+    if User::any_exists().await? {
+        return Ok(());
+    }
+
+    User::insert(username, &hash_password(password)?, Role::Admin, None).await?;
+    info!("Created admin user '{}' from the configured credentials", username);
+    Ok(())
+}
+
+/// Shared by the `authenticate` middleware
+#[derive(Clone)]
+pub struct AuthState {
+    pub credentials: Option<Arc<(String, String)>>,
+    pub guard: Arc<LoginGuard>,
+}
+
+/// Accepts a bearer API key, the configured Basic credentials, a TLS client certificate or
+/// a session cookie. Without configured credentials and users, unauthenticated requests have
+/// full access.
+pub async fn authenticate(
+    State(auth): State<AuthState>,
+    mut req: Request,
     next: Next,
-) -> Result<Response, StatusCode> {
+) -> Result<Response, Response> {
+    let ip = client_ip(&req);
     let auth_header = req
         .headers()
         .get("Authorization")
         .and_then(|header| header.to_str().ok());
 
-    if let Some(auth_str) = auth_header {
-        if let Some(credentials) = auth_str.strip_prefix("Basic ") {
+    let mut principal = match auth_header {
+        Some(auth_str) if auth_str.starts_with("Bearer ") => {
+            if let Some(retry_after) = auth.guard.locked(ip, None) {
+                return Err(blocked(ip, None, retry_after));
+            }
+            let token = auth_str["Bearer ".len()..].trim();
+            let key = ApiKey::find_by_hash(&hash_token(token)).await.map_err(|e| {
+                error!("Failed to look up API key: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            })?;
+            let key = match key {
+                Some(key) if !key.is_expired() => key,
+                _ => {
+                    auth.guard.record_failure(ip, None);
+                    return Err(StatusCode::UNAUTHORIZED.into_response());
+                }
+            };
+            if let Err(e) = ApiKey::touch(key.id).await {
+                error!("Failed to record API key use: {}", e);
+            }
+            Principal::from(&key)
+        }
+        Some(auth_str) if auth_str.starts_with("Basic ") && auth.credentials.is_some() => {
+            let (username, password) = auth.credentials.as_deref().unwrap();
             let decoded = BASE64_STANDARD
-                .decode(credentials)
-                .map_err(|_| StatusCode::BAD_REQUEST)?;
-            let credential_str = String::from_utf8(decoded).map_err(|_| StatusCode::BAD_REQUEST)?;
-
-            let parts: Vec<&str> = credential_str.splitn(2, ':').collect();
-            if parts.len() == 2 {
-                let username_check = parts[0];
-                let password_check = parts[1];
+                .decode(&auth_str["Basic ".len()..])
+                .map_err(|_| StatusCode::BAD_REQUEST.into_response())?;
+            let credential_str = String::from_utf8(decoded)
+                .map_err(|_| StatusCode::BAD_REQUEST.into_response())?;
+            let (username_check, password_check) = credential_str
+                .split_once(':')
+                .ok_or_else(|| StatusCode::UNAUTHORIZED.into_response())?;
 
-                if username.eq(username_check) && password.eq(password_check) {
-                    return Ok(next.run(req).await);
-                }
+            if let Some(retry_after) = auth.guard.locked(ip, Some(username_check)) {
+                return Err(blocked(ip, Some(username_check), retry_after));
             }
+            let matches = username.as_bytes().ct_eq(username_check.as_bytes())
+                & password.as_bytes().ct_eq(password_check.as_bytes());
+            if !bool::from(matches) {
+                auth.guard.record_failure(ip, Some(username_check));
+                return Err(StatusCode::UNAUTHORIZED.into_response());
+            }
+            auth.guard.record_success(ip, Some(username_check));
+            Principal::full_access(username)
         }
+        _ => match (client_cert(&req), session_token(req.headers())) {
+            (Some(cert), _) => Principal::from(cert),
+            (None, Some(token)) => {
+                let user = User::find_by_session(&hash_token(token)).await.map_err(|e| {
+                    error!("Failed to look up session: {}", e);
+                    StatusCode::INTERNAL_SERVER_ERROR.into_response()
+                })?;
+                match user {
+                    Some(user) => Principal::from(&user),
+                    None => return Err(StatusCode::UNAUTHORIZED.into_response()),
+                }
+            }
+            (None, None)
+                if auth.credentials.is_none()
+                    && !users_exist().await.map_err(IntoResponse::into_response)? =>
+            {
+                Principal::full_access("anonymous")
+            }
+            (None, None) => return Err(StatusCode::UNAUTHORIZED.into_response()),
+        },
+    };
+
+    principal.ip = ip.map(|ip| ip.to_string());
+    req.extensions_mut().insert(principal);
+    Ok(next.run(req).await)
+}
+
+/// Logs a request refused because of a lockout
+pub fn blocked(ip: Option<IpAddr>, username: Option<&str>, retry_after: u64) -> Response {
+    warn!(
+        "Refused login from {} as {} while locked out",
+        ip.map_or("unknown address".to_string(), |ip| ip.to_string()),
+        username.unwrap_or("API key")
+    );
+    too_many_attempts(retry_after)
+}
+
+fn client_cert(req: &Request) -> Option<&ClientCert> {
+    req.extensions().get::<Option<ClientCert>>()?.as_ref()
+}
+
+/// Peer address of the connection, when the server was started with connect info
+pub fn client_ip<B>(req: &axum::http::Request<B>) -> Option<IpAddr> {
+    req.extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip())
+}
+
+async fn users_exist() -> Result<bool, StatusCode> {
+    User::any_exists().await.map_err(|e| {
+        error!("Failed to look up users: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+pub async fn require_scope(
+    State(scope): State<Scope>,
+    Extension(principal): Extension<Principal>,
+    req: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    if principal.has_scope(scope) {
+        Ok(next.run(req).await)
+    } else {
+        Err(StatusCode::FORBIDDEN)
     }
+}
 
-    Err(StatusCode::UNAUTHORIZED)
+/// Rejects requests for a `{sim_id}` the principal is not allowed to use
+pub async fn require_sim_access(
+    Path(params): Path<HashMap<String, String>>,
+    Extension(principal): Extension<Principal>,
+    req: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    match params.get("sim_id") {
+        Some(sim_id) if !principal.allows_sim(sim_id) => Err(StatusCode::FORBIDDEN),
+        _ => Ok(next.run(req).await),
+    }
 }
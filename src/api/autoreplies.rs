@@ -0,0 +1,179 @@
+use axum::{
+    extract::Path,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use log::error;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use serde_json::json;
+
+use super::audit::{self, snapshot};
+use super::auth::Principal;
+use super::webhooks::SampleMessage;
+use crate::{autoreply, autoreply::RuleConfig, db::StoredAutoReply};
+
+#[derive(Deserialize, Debug)]
+pub struct AutoReplyPayload {
+    name: Option<String>,
+    #[serde(default = "default_enabled")]
+    enabled: bool,
+    #[serde(default)]
+    priority: i64,
+    config: serde_json::Value, // Keywords, filters, actions, cooldown_secs and stop
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AutoReplyTestRequest {
+    #[serde(default)]
+    message: SampleMessage,
+}
+
+fn bad_request(message: String) -> Response {
+    (StatusCode::BAD_REQUEST, Json(json!({ "error": message }))).into_response()
+}
+
+fn not_found(id: i64) -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({ "error": format!("Auto-reply rule {} not found", id) })),
+    )
+        .into_response()
+}
+
+fn internal_error(e: anyhow::Error) -> Response {
+    error!("{}", e);
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(json!({ "error": format!("Database error: {}", e) })),
+    )
+        .into_response()
+}
+
+fn validate(config: &serde_json::Value) -> Result<RuleConfig, String> {
+    RuleConfig::from_json(config).map_err(|e| format!("Invalid auto-reply rule: {}", e))
+}
+
+pub async fn list_auto_replies() -> Response {
+    match StoredAutoReply::query_all().await {
+        Ok(rules) => (StatusCode::OK, Json(rules)).into_response(),
+        Err(e) => internal_error(e),
+    }
+}
+
+pub async fn get_auto_reply(Path(id): Path<i64>) -> Response {
+    match StoredAutoReply::query_by_id(id).await {
+        Ok(Some(rule)) => (StatusCode::OK, Json(rule)).into_response(),
+        Ok(None) => not_found(id),
+        Err(e) => internal_error(e),
+    }
+}
+
+pub async fn create_auto_reply(
+    Extension(principal): Extension<Principal>,
+    Json(payload): Json<AutoReplyPayload>,
+) -> Response {
+    if let Err(e) = validate(&payload.config) {
+        return bad_request(e);
+    }
+
+    match StoredAutoReply::insert(
+        payload.name.as_deref(),
+        payload.enabled,
+        payload.priority,
+        &payload.config,
+    )
+    .await
+    {
+        Ok(rule) => {
+            let target = Some(rule.id.to_string());
+            audit::record(
+                &principal,
+                "autoreply.create",
+                target,
+                None,
+                snapshot(&rule),
+            )
+            .await;
+            (StatusCode::CREATED, Json(rule)).into_response()
+        }
+        Err(e) => internal_error(e),
+    }
+}
+
+pub async fn update_auto_reply(
+    Path(id): Path<i64>,
+    Extension(principal): Extension<Principal>,
+    Json(payload): Json<AutoReplyPayload>,
+) -> Response {
+    if let Err(e) = validate(&payload.config) {
+        return bad_request(e);
+    }
+    let before = match StoredAutoReply::query_by_id(id).await {
+        Ok(Some(rule)) => rule,
+        Ok(None) => return not_found(id),
+        Err(e) => return internal_error(e),
+    };
+
+    match StoredAutoReply::update(
+        id,
+        payload.name.as_deref(),
+        payload.enabled,
+        payload.priority,
+        &payload.config,
+    )
+    .await
+    {
+        Ok(Some(rule)) => {
+            audit::record(
+                &principal,
+                "autoreply.update",
+                Some(id.to_string()),
+                snapshot(&before),
+                snapshot(&rule),
+            )
+            .await;
+            (StatusCode::OK, Json(rule)).into_response()
+        }
+        Ok(None) => not_found(id),
+        Err(e) => internal_error(e),
+    }
+}
+
+pub async fn delete_auto_reply(
+    Path(id): Path<i64>,
+    Extension(principal): Extension<Principal>,
+) -> Response {
+    let before = match StoredAutoReply::query_by_id(id).await {
+        Ok(Some(rule)) => rule,
+        Ok(None) => return not_found(id),
+        Err(e) => return internal_error(e),
+    };
+    match StoredAutoReply::delete_by_id(id).await {
+        Ok(true) => {
+            audit::record(
+                &principal,
+                "autoreply.delete",
+                Some(id.to_string()),
+                snapshot(&before),
+                None,
+            )
+            .await;
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(false) => not_found(id),
+        Err(e) => internal_error(e),
+    }
+}
+
+/// Shows which enabled rules a sample message would trigger and what they would send
+pub async fn test_auto_replies(Json(request): Json<AutoReplyTestRequest>) -> Response {
+    match autoreply::dry_run(request.message.into()).await {
+        Ok(matches) => (StatusCode::OK, Json(matches)).into_response(),
+        Err(e) => internal_error(e),
+    }
+}
@@ -0,0 +1,477 @@
+use std::collections::BTreeMap;
+
+use axum::{
+    extract::{Path, Query, State},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use log::error;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use super::{audit, auth::Principal};
+use crate::{
+    campaign::{self, DEFAULT_PER_MINUTE, OPTED_OUT},
+    db::{
+        Campaign, CampaignIssue, CampaignProgress, CampaignSimTally, CampaignStatus, Contact,
+        ContactFilter, NewRecipient, RecipientStatus, Suppression,
+    },
+    phone::normalize,
+    ModemManagerRef,
+};
+
+#[derive(Deserialize, Debug)]
+pub struct RecipientPayload {
+    number: String,
+    #[serde(default)]
+    variables: BTreeMap<String, String>,
+}
+
+/// Recipients come from `recipients`, the contacts tagged `tag`, or both
+#[derive(Deserialize, Debug)]
+pub struct CampaignPayload {
+    name: String,
+    template: String,
+    #[serde(default)]
+    recipients: Vec<RecipientPayload>,
+    tag: Option<String>,
+    sim_ids: Option<Vec<String>>, // Every connected SIM the caller may use when omitted
+    per_minute: Option<u32>,
+}
+
+/// A campaign with how far it got
+#[derive(Serialize)]
+pub struct CampaignView {
+    #[serde(flatten)]
+    campaign: Campaign,
+    progress: CampaignProgress,
+}
+
+#[derive(Serialize)]
+pub struct CampaignReport {
+    #[serde(flatten)]
+    campaign: CampaignView,
+    duration_secs: Option<i64>, // Until it finished
+    sims: Vec<CampaignSimTally>,
+    issues: Vec<CampaignIssue>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RecipientQuery {
+    status: Option<RecipientStatus>,
+    #[serde(default = "default_page")]
+    page: u32,
+    #[serde(default = "default_per_page")]
+    per_page: u32,
+}
+
+fn default_page() -> u32 {
+    1
+}
+
+fn default_per_page() -> u32 {
+    100
+}
+
+fn bad_request(message: String) -> Response {
+    (StatusCode::BAD_REQUEST, Json(json!({ "error": message }))).into_response()
+}
+
+fn conflict(message: String) -> Response {
+    (StatusCode::CONFLICT, Json(json!({ "error": message }))).into_response()
+}
+
+fn not_found(id: i64) -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({ "error": format!("Campaign {} not found", id) })),
+    )
+        .into_response()
+}
+
+fn internal_error(e: anyhow::Error) -> Response {
+    error!("{}", e);
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(json!({ "error": format!("Database error: {}", e) })),
+    )
+        .into_response()
+}
+
+/// The campaign, if the principal may use every SIM it sends from
+async fn find(principal: &Principal, id: i64) -> Result<Campaign, Response> {
+    match Campaign::query_by_id(id).await {
+        Ok(Some(campaign)) if campaign.sim_ids.iter().all(|sim| principal.allows_sim(sim)) => {
+            Ok(campaign)
+        }
+        Ok(_) => Err(not_found(id)),
+        Err(e) => Err(internal_error(e)),
+    }
+}
+
+async fn view(campaign: Campaign) -> Result<CampaignView, Response> {
+    match Campaign::progress(campaign.id).await {
+        Ok(progress) => Ok(CampaignView { campaign, progress }),
+        Err(e) => Err(internal_error(e)),
+    }
+}
+
+/// Recipients of the payload, normalised and without repeated numbers. The tag only reaches
+/// contacts the principal can see.
+async fn recipients(
+    payload: &CampaignPayload,
+    principal: &Principal,
+) -> Result<Vec<NewRecipient>, Response> {
+    let mut recipients: Vec<NewRecipient> = Vec::new();
+    for recipient in &payload.recipients {
+        let number = normalize(&recipient.number);
+        if number.is_empty() {
+            return Err(bad_request(
+                "Recipient numbers must not be empty".to_string(),
+            ));
+        }
+        if recipients.iter().any(|r| r.number == number) {
+            continue;
+        }
+        let contact = match Contact::find_by_number(&number).await {
+            Ok(contact) => contact,
+            Err(e) => return Err(internal_error(e)),
+        };
+        recipients.push(NewRecipient {
+            number,
+            contact_id: contact.map(|contact| contact.id),
+            variables: recipient.variables.clone(),
+            skipped: None,
+        });
+    }
+
+    if let Some(tag) = &payload.tag {
+        let filter = ContactFilter {
+            tag: Some(tag.clone()),
+            ..Default::default()
+        };
+        let contacts = match Contact::query_details(&filter, principal.sims()).await {
+            Ok(contacts) => contacts,
+            Err(e) => return Err(internal_error(e)),
+        };
+        for details in contacts {
+            if recipients.iter().any(|r| r.number == details.contact.name) {
+                continue;
+            }
+            recipients.push(NewRecipient {
+                number: details.contact.name,
+                contact_id: Some(details.contact.id),
+                variables: BTreeMap::new(),
+                skipped: details.blocked.then(|| "Contact is blocked".to_string()),
+            });
+        }
+    }
+
+    // Blocked contacts from the list are skipped too, as are numbers that opted out
+    let blocked = match Contact::blocked_numbers().await {
+        Ok(blocked) => blocked,
+        Err(e) => return Err(internal_error(e)),
+    };
+    let suppressed = match Suppression::numbers().await {
+        Ok(suppressed) => suppressed,
+        Err(e) => return Err(internal_error(e)),
+    };
+    for recipient in &mut recipients {
+        if blocked.contains(&recipient.number) {
+            recipient.skipped = Some("Contact is blocked".to_string());
+        } else if suppressed.contains(&recipient.number) {
+            recipient.skipped = Some(OPTED_OUT.to_string());
+        }
+    }
+    Ok(recipients)
+}
+
+/// Every template variable must be known for every recipient
+fn check_variables(template: &str, recipients: &[NewRecipient]) -> Result<(), String> {
+    let segments = campaign::parse_template(template).map_err(|e| format!("Invalid template: {}", e))?;
+    let variables = campaign::required_variables(&segments);
+    for (index, recipient) in recipients.iter().enumerate() {
+        let missing = variables
+            .iter()
+            .find(|variable| !recipient.variables.contains_key(**variable));
+        if let Some(missing) = missing {
+            return Err(format!(
+                "Recipient {} ({}) has no value for @{}@",
+                index + 1,
+                recipient.number,
+                missing
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Creates a campaign and starts sending right away
+pub async fn create_campaign(
+    State(modem_manager): State<ModemManagerRef>,
+    Extension(principal): Extension<Principal>,
+    Json(payload): Json<CampaignPayload>,
+) -> Response {
+    if payload.name.trim().is_empty() || payload.template.trim().is_empty() {
+        return bad_request("A campaign needs a name and a template".to_string());
+    }
+    let per_minute = payload.per_minute.unwrap_or(DEFAULT_PER_MINUTE);
+    if per_minute == 0 {
+        return bad_request("per_minute must be at least 1".to_string());
+    }
+
+    let sim_ids = match &payload.sim_ids {
+        Some(sim_ids) => {
+            if let Some(sim_id) = sim_ids.iter().find(|sim| !principal.allows_sim(sim)) {
+                return (
+                    StatusCode::FORBIDDEN,
+                    format!("Not allowed to use SIM {}", sim_id),
+                )
+                    .into_response();
+            }
+            sim_ids.clone()
+        }
+        None => {
+            let mut sim_ids = modem_manager.get_sim_ids().await;
+            sim_ids.retain(|sim| principal.allows_sim(sim));
+            sim_ids.sort();
+            sim_ids
+        }
+    };
+    if sim_ids.is_empty() {
+        return bad_request("No SIM to send from".to_string());
+    }
+
+    let recipients = match recipients(&payload, &principal).await {
+        Ok(recipients) => recipients,
+        Err(response) => return response,
+    };
+    if recipients.is_empty() {
+        return bad_request("A campaign needs at least one recipient".to_string());
+    }
+    if let Err(message) = check_variables(&payload.template, &recipients) {
+        return bad_request(message);
+    }
+
+    let campaign = Campaign::insert(
+        payload.name.trim(),
+        &payload.template,
+        &sim_ids,
+        per_minute,
+        principal.user_id,
+        &recipients,
+    )
+    .await;
+    let campaign = match campaign {
+        Ok(campaign) => campaign,
+        Err(e) => return internal_error(e),
+    };
+    let after = json!({
+        "name": campaign.name,
+        "sim_ids": campaign.sim_ids,
+        "per_minute": per_minute,
+        "recipients": recipients.len(),
+        "tag": payload.tag,
+    });
+    audit::record(
+        &principal,
+        "campaign.create",
+        Some(campaign.id.to_string()),
+        None,
+        Some(after),
+    )
+    .await;
+
+    let id = campaign.id;
+    let view = view(campaign).await;
+    campaign::start(modem_manager, id).await;
+    match view {
+        Ok(view) => Json(view).into_response(),
+        Err(response) => response,
+    }
+}
+
+pub async fn list_campaigns(Extension(principal): Extension<Principal>) -> Response {
+    let campaigns = match Campaign::query_all().await {
+        Ok(campaigns) => campaigns,
+        Err(e) => return internal_error(e),
+    };
+    let mut views = Vec::new();
+    for campaign in campaigns {
+        if !campaign.sim_ids.iter().all(|sim| principal.allows_sim(sim)) {
+            continue;
+        }
+        match view(campaign).await {
+            Ok(view) => views.push(view),
+            Err(response) => return response,
+        }
+    }
+    Json(views).into_response()
+}
+
+pub async fn get_campaign(
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<i64>,
+) -> Response {
+    let campaign = match find(&principal, id).await {
+        Ok(campaign) => campaign,
+        Err(response) => return response,
+    };
+    match view(campaign).await {
+        Ok(view) => Json(view).into_response(),
+        Err(response) => response,
+    }
+}
+
+pub async fn list_recipients(
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<i64>,
+    Query(query): Query<RecipientQuery>,
+) -> Response {
+    if let Err(response) = find(&principal, id).await {
+        return response;
+    }
+    match Campaign::query_recipients(id, query.status, query.page, query.per_page).await {
+        Ok((recipients, total)) => Json(json!({
+            "page": query.page,
+            "per_page": query.per_page,
+            "total": total,
+            "data": recipients,
+        }))
+        .into_response(),
+        Err(e) => bad_request(e.to_string()),
+    }
+}
+
+/// Totals per SIM and the reasons recipients were not sent to
+pub async fn get_report(
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<i64>,
+) -> Response {
+    let campaign = match find(&principal, id).await {
+        Ok(campaign) => campaign,
+        Err(response) => return response,
+    };
+    let (sims, issues) = match (Campaign::sim_tallies(id).await, Campaign::issues(id).await) {
+        (Ok(sims), Ok(issues)) => (sims, issues),
+        (Err(e), _) | (_, Err(e)) => return internal_error(e),
+    };
+    let duration_secs = campaign
+        .finished_at
+        .map(|finished_at| (finished_at - campaign.created_at).num_seconds());
+    match view(campaign).await {
+        Ok(campaign) => Json(CampaignReport {
+            campaign,
+            duration_secs,
+            sims,
+            issues,
+        })
+        .into_response(),
+        Err(response) => response,
+    }
+}
+
+async fn transition(
+    principal: &Principal,
+    id: i64,
+    from: &[CampaignStatus],
+    status: CampaignStatus,
+    action: &str,
+) -> Result<Campaign, Response> {
+    let campaign = find(principal, id).await?;
+    match Campaign::transition(id, from, status, None).await {
+        Ok(true) => {}
+        Ok(false) => {
+            return Err(conflict(format!(
+                "Campaign {} is {}",
+                id,
+                json!(campaign.status).as_str().unwrap_or_default()
+            )))
+        }
+        Err(e) => return Err(internal_error(e)),
+    }
+    audit::record(
+        principal,
+        action,
+        Some(id.to_string()),
+        Some(json!({ "status": campaign.status })),
+        Some(json!({ "status": status })),
+    )
+    .await;
+    match Campaign::query_by_id(id).await {
+        Ok(Some(campaign)) => Ok(campaign),
+        Ok(None) => Err(not_found(id)),
+        Err(e) => Err(internal_error(e)),
+    }
+}
+
+/// Stops sending after the message in flight
+pub async fn pause_campaign(
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<i64>,
+) -> Response {
+    let paused = transition(
+        &principal,
+        id,
+        &[CampaignStatus::Running],
+        CampaignStatus::Paused,
+        "campaign.pause",
+    )
+    .await;
+    match paused {
+        Ok(campaign) => match view(campaign).await {
+            Ok(view) => Json(view).into_response(),
+            Err(response) => response,
+        },
+        Err(response) => response,
+    }
+}
+
+pub async fn resume_campaign(
+    State(modem_manager): State<ModemManagerRef>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<i64>,
+) -> Response {
+    let resumed = transition(
+        &principal,
+        id,
+        &[CampaignStatus::Paused],
+        CampaignStatus::Running,
+        "campaign.resume",
+    )
+    .await;
+    match resumed {
+        Ok(campaign) => {
+            let view = view(campaign).await;
+            campaign::start(modem_manager, id).await;
+            match view {
+                Ok(view) => Json(view).into_response(),
+                Err(response) => response,
+            }
+        }
+        Err(response) => response,
+    }
+}
+
+/// Stops the campaign for good; recipients not yet sent to are cancelled
+pub async fn cancel_campaign(
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<i64>,
+) -> Response {
+    let cancelled = transition(
+        &principal,
+        id,
+        &[CampaignStatus::Running, CampaignStatus::Paused],
+        CampaignStatus::Cancelled,
+        "campaign.cancel",
+    )
+    .await;
+    match cancelled {
+        Ok(campaign) => match view(campaign).await {
+            Ok(view) => Json(view).into_response(),
+            Err(response) => response,
+        },
+        Err(response) => response,
+    }
+}
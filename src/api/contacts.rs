@@ -0,0 +1,333 @@
+use axum::{
+    body::Bytes,
+    extract::{Path, Query},
+    http::header,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use log::error;
+use reqwest::StatusCode;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use uuid::Uuid;
+
+use super::{audit, auth::Principal};
+use crate::{
+    address_book::{self, ContactFormat},
+    db::{Contact, ContactDetails, ContactFilter, ContactNumber},
+    phone::normalize,
+};
+
+/// Address books with photos run large, though only the text is kept
+pub const MAX_IMPORT_BYTES: usize = 64 * 1024 * 1024;
+
+/// A new contact, or the fields of a contact to change. Numbers and tags replace the current
+/// ones; an empty display name or note clears it.
+#[derive(Deserialize, Debug)]
+pub struct ContactPayload {
+    id: Option<String>, // Only on creation, generated when omitted
+    name: Option<String>, // Primary number, defaults to the first number
+    display_name: Option<String>,
+    notes: Option<String>,
+    blocked: Option<bool>,
+    numbers: Option<Vec<ContactNumber>>,
+    tags: Option<Vec<String>>,
+}
+
+fn non_blank(value: String) -> Option<String> {
+    let value = value.trim();
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+impl ContactPayload {
+    fn apply(self, details: &mut ContactDetails) -> Result<(), String> {
+        if let Some(display_name) = self.display_name {
+            details.contact.display_name = non_blank(display_name);
+        }
+        if let Some(notes) = self.notes {
+            details.notes = non_blank(notes);
+        }
+        if let Some(blocked) = self.blocked {
+            details.blocked = blocked;
+        }
+        if let Some(tags) = self.tags {
+            let mut tags: Vec<String> = tags.into_iter().filter_map(non_blank).collect();
+            tags.sort();
+            tags.dedup();
+            details.tags = tags;
+        }
+        if let Some(numbers) = self.numbers {
+            details.numbers.clear();
+            for number in numbers {
+                let normalized = normalize(&number.number);
+                if normalized.is_empty() {
+                    return Err("numbers must not be empty".to_string());
+                }
+                if !details.numbers.iter().any(|n| n.number == normalized) {
+                    details.numbers.push(ContactNumber {
+                        number: normalized,
+                        label: number.label.and_then(non_blank),
+                    });
+                }
+            }
+        }
+
+        let primary = &mut details.contact.name;
+        match self.name {
+            Some(name) => *primary = normalize(&name),
+            None if !details.numbers.iter().any(|n| &n.number == primary) => {
+                if let Some(first) = details.numbers.first() {
+                    *primary = first.number.clone();
+                }
+            }
+            None => {}
+        }
+        if primary.is_empty() {
+            return Err("a contact needs a name or at least one number".to_string());
+        }
+        if !details.numbers.iter().any(|n| &n.number == primary) {
+            details.numbers.insert(
+                0,
+                ContactNumber {
+                    number: primary.clone(),
+                    label: None,
+                },
+            );
+        }
+        Ok(())
+    }
+}
+
+fn bad_request(message: String) -> Response {
+    (StatusCode::BAD_REQUEST, Json(json!({ "error": message }))).into_response()
+}
+
+fn conflict(message: String) -> Response {
+    (StatusCode::CONFLICT, Json(json!({ "error": message }))).into_response()
+}
+
+fn not_found(id: &str) -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({ "error": format!("Contact {} not found", id) })),
+    )
+        .into_response()
+}
+
+fn internal_error(e: anyhow::Error) -> Response {
+    error!("{}", e);
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(json!({ "error": format!("Database error: {}", e) })),
+    )
+        .into_response()
+}
+
+/// A number belongs to one contact only
+async fn check_numbers(details: &ContactDetails) -> Result<(), Response> {
+    for number in &details.numbers {
+        match Contact::find_by_number(&number.number).await {
+            Ok(Some(owner)) if owner.id != details.contact.id => {
+                return Err(conflict(format!(
+                    "{} already belongs to contact {}",
+                    number.number, owner.id
+                )))
+            }
+            Ok(_) => {}
+            Err(e) => return Err(internal_error(e)),
+        }
+    }
+    Ok(())
+}
+
+/// Validates and stores the contact, then reads it back as stored
+async fn save(details: &ContactDetails) -> Result<ContactDetails, Response> {
+    check_numbers(details).await?;
+    if let Err(e) = details.save().await {
+        return Err(internal_error(e));
+    }
+    match Contact::query_details_by_id(&details.contact.id).await {
+        Ok(Some(saved)) => Ok(saved),
+        Ok(None) => Err(not_found(&details.contact.id)),
+        Err(e) => Err(internal_error(e)),
+    }
+}
+
+/// Contacts are limited to those with messages on the principal's SIMs, as conversations are
+pub async fn list_contacts(
+    Extension(principal): Extension<Principal>,
+    Query(filter): Query<ContactFilter>,
+) -> Response {
+    match Contact::query_details(&filter, principal.sims()).await {
+        Ok(contacts) => Json(contacts).into_response(),
+        Err(e) => internal_error(e),
+    }
+}
+
+/// Checks that the principal may see the contact, which is not found otherwise
+async fn check_visible(principal: &Principal, id: &str) -> Result<(), Response> {
+    match Contact::visible(id, principal.sims()).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(not_found(id)),
+        Err(e) => Err(internal_error(e)),
+    }
+}
+
+pub async fn get_contact(
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<String>,
+) -> Response {
+    if let Err(response) = check_visible(&principal, &id).await {
+        return response;
+    }
+    match Contact::query_details_by_id(&id).await {
+        Ok(Some(contact)) => Json(contact).into_response(),
+        Ok(None) => not_found(&id),
+        Err(e) => internal_error(e),
+    }
+}
+
+pub async fn create_contact(
+    Extension(principal): Extension<Principal>,
+    Json(payload): Json<ContactPayload>,
+) -> Response {
+    let id = payload
+        .id
+        .clone()
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+    match Contact::query_details_by_id(&id).await {
+        Ok(Some(_)) => return conflict(format!("Contact {} already exists", id)),
+        Ok(None) => {}
+        Err(e) => return internal_error(e),
+    }
+
+    let mut details = ContactDetails::default();
+    details.contact.id = id.clone();
+    if let Err(message) = payload.apply(&mut details) {
+        return bad_request(message);
+    }
+    match save(&details).await {
+        Ok(saved) => {
+            audit::record(&principal, "contact.create", Some(id), None, audit::snapshot(&saved))
+                .await;
+            Json(saved).into_response()
+        }
+        Err(response) => response,
+    }
+}
+
+pub async fn update_contact(
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<String>,
+    Json(payload): Json<ContactPayload>,
+) -> Response {
+    if let Err(response) = check_visible(&principal, &id).await {
+        return response;
+    }
+    let mut details = match Contact::query_details_by_id(&id).await {
+        Ok(Some(details)) => details,
+        Ok(None) => return not_found(&id),
+        Err(e) => return internal_error(e),
+    };
+    let before = audit::snapshot(&details);
+    if let Err(message) = payload.apply(&mut details) {
+        return bad_request(message);
+    }
+    match save(&details).await {
+        Ok(saved) => {
+            audit::record(&principal, "contact.update", Some(id), before, audit::snapshot(&saved))
+                .await;
+            Json(saved).into_response()
+        }
+        Err(response) => response,
+    }
+}
+
+/// Every tag in use, with how many contacts carry it
+pub async fn list_tags(Extension(principal): Extension<Principal>) -> Response {
+    match Contact::query_tags(principal.sims()).await {
+        Ok(tags) => Json(tags).into_response(),
+        Err(e) => internal_error(e),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ImportQuery {
+    #[serde(default)]
+    format: ContactFormat,
+}
+
+/// Imports a vCard or CSV file, merging into existing contacts by number
+pub async fn import_contacts(
+    Extension(principal): Extension<Principal>,
+    Query(query): Query<ImportQuery>,
+    body: Bytes,
+) -> Response {
+    let Ok(data) = std::str::from_utf8(&body) else {
+        return bad_request("Contact files must be UTF-8".to_string());
+    };
+    match address_book::import(query.format, data).await {
+        Ok(report) => {
+            let after = json!({
+                "format": query.format,
+                "created": report.created,
+                "updated": report.updated,
+                "unchanged": report.unchanged,
+                "conflicts": report.conflicts.len(),
+                "invalid": report.invalid,
+            });
+            audit::record(&principal, "contact.import", None, None, Some(after)).await;
+            Json(report).into_response()
+        }
+        Err(e) => internal_error(e),
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ExportQuery {
+    #[serde(default)]
+    format: ContactFormat,
+    q: Option<String>,
+    tag: Option<String>,
+    blocked: Option<bool>,
+}
+
+/// Every matching contact as vCard or CSV
+pub async fn export_contacts(
+    Extension(principal): Extension<Principal>,
+    Query(query): Query<ExportQuery>,
+) -> Response {
+    audit::record(&principal, "contact.export", None, None, audit::snapshot(&query)).await;
+
+    let filter = ContactFilter {
+        q: query.q,
+        tag: query.tag,
+        blocked: query.blocked,
+    };
+    let file = match Contact::query_details(&filter, principal.sims()).await {
+        Ok(contacts) => address_book::write(query.format, &contacts),
+        Err(e) => return internal_error(e),
+    };
+    let file = match file {
+        Ok(file) => file,
+        Err(e) => return internal_error(e),
+    };
+
+    let filename = format!(
+        "contacts-{}.{}",
+        Utc::now().format("%Y%m%d-%H%M%S"),
+        query.format.extension()
+    );
+    (
+        [
+            (header::CONTENT_TYPE, query.format.content_type().to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", filename),
+            ),
+        ],
+        file,
+    )
+        .into_response()
+}
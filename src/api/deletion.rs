@@ -0,0 +1,68 @@
+use axum::{
+    extract::Path,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use log::error;
+use reqwest::StatusCode;
+use serde_json::json;
+
+use super::{audit, auth::Principal};
+use crate::db::Sms;
+
+fn not_found(message: &str) -> Response {
+    (StatusCode::NOT_FOUND, Json(json!({ "error": message }))).into_response()
+}
+
+fn internal_error(e: anyhow::Error) -> Response {
+    error!("Failed to delete messages: {:#}", e);
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "error": e.to_string() }))).into_response()
+}
+
+/// Soft-deletes a message; it can be restored until the undo window of the retention job ends
+pub async fn delete_sms(
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<i64>,
+) -> Response {
+    match Sms::soft_delete_by_ids(&[id], principal.sims()).await {
+        Ok((_, 0)) => not_found("Message not found"),
+        Ok((deletion_id, deleted)) => {
+            let after = json!({ "deletion_id": deletion_id });
+            audit::record(&principal, "sms.delete", Some(id.to_string()), None, Some(after)).await;
+            Json(json!({ "deletion_id": deletion_id, "deleted": deleted })).into_response()
+        }
+        Err(e) => internal_error(e),
+    }
+}
+
+/// Soft-deletes every message of a conversation the principal can see
+pub async fn delete_conversation(
+    Extension(principal): Extension<Principal>,
+    Path(contact_id): Path<String>,
+) -> Response {
+    match Sms::soft_delete_by_contact_id(&contact_id, principal.sims()).await {
+        Ok((_, 0)) => not_found("Conversation not found"),
+        Ok((deletion_id, deleted)) => {
+            let after = json!({ "deletion_id": deletion_id, "deleted": deleted });
+            audit::record(&principal, "conversation.delete", Some(contact_id), None, Some(after))
+                .await;
+            Json(json!({ "deletion_id": deletion_id, "deleted": deleted })).into_response()
+        }
+        Err(e) => internal_error(e),
+    }
+}
+
+pub async fn restore_deletion(
+    Extension(principal): Extension<Principal>,
+    Path(deletion_id): Path<String>,
+) -> Response {
+    match Sms::restore(&deletion_id, principal.sims()).await {
+        Ok(0) => not_found("Deletion not found or no longer restorable"),
+        Ok(restored) => {
+            let after = json!({ "restored": restored });
+            audit::record(&principal, "sms.restore", Some(deletion_id), None, Some(after)).await;
+            Json(json!({ "restored": restored })).into_response()
+        }
+        Err(e) => internal_error(e),
+    }
+}
@@ -0,0 +1,78 @@
+use axum::{
+    body::Body,
+    extract::Query,
+    http::header,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use log::error;
+use serde::{Deserialize, Serialize};
+
+use super::{audit, auth::Principal};
+use crate::{
+    db::{SmsFilter, SmsStatus, SortOrder},
+    export::{Export, ExportFormat},
+};
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ExportQuery {
+    #[serde(default)]
+    format: ExportFormat,
+    #[serde(default)]
+    order: SortOrder,
+    sim_id: Option<String>,
+    contact_id: Option<String>,
+    send: Option<bool>,
+    status: Option<SmsStatus>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+}
+
+/// Streams every matching message as CSV, NDJSON or SMS Backup & Restore XML
+pub async fn export_sms(
+    Extension(principal): Extension<Principal>,
+    Query(query): Query<ExportQuery>,
+) -> Response {
+    audit::record(&principal, "sms.export", None, None, audit::snapshot(&query)).await;
+
+    let format = query.format;
+    let export = Export {
+        format,
+        filter: SmsFilter {
+            sim_id: query.sim_id,
+            contact_id: query.contact_id,
+            send: query.send,
+            status: query.status,
+            from: query.from,
+            to: query.to,
+        },
+        order: query.order,
+        sims: principal.sim_ids.clone(),
+    };
+    // An error midway can only cut the download short, the status line is already sent
+    let body = export.stream().map(|chunk| {
+        chunk.map_err(|e| {
+            error!("SMS export failed: {:#}", e);
+            std::io::Error::other(e.to_string())
+        })
+    });
+
+    let filename = format!(
+        "sms-export-{}.{}",
+        Utc::now().format("%Y%m%d-%H%M%S"),
+        format.extension()
+    );
+    (
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", filename),
+            ),
+        ],
+        Body::from_stream(body),
+    )
+        .into_response()
+}
@@ -0,0 +1,162 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use ipnet::IpNet;
+use log::warn;
+use serde_json::json;
+use tokio::time::Instant;
+
+use super::auth::client_ip;
+use crate::config::SecurityConfig;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Subject {
+    Ip(IpAddr),
+    User(String),
+}
+
+struct Failures {
+    count: u32,
+    since: Instant,
+    locked_until: Option<Instant>,
+}
+
+/// Counts failed logins per client IP and per username and locks them out for a while
+pub struct LoginGuard {
+    max_failures: u32,
+    window: Duration,
+    lockout: Duration,
+    failures: Mutex<HashMap<Subject, Failures>>,
+}
+
+fn subjects(ip: Option<IpAddr>, username: Option<&str>) -> impl Iterator<Item = Subject> {
+    ip.map(Subject::Ip)
+        .into_iter()
+        .chain(username.map(|name| Subject::User(name.to_string())))
+}
+
+impl LoginGuard {
+    pub fn new(config: &SecurityConfig) -> Self {
+        Self {
+            max_failures: config.max_failed_logins,
+            window: Duration::from_secs(config.failed_login_window),
+            lockout: Duration::from_secs(config.lockout_seconds),
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Seconds until the lockout ends, if the IP or the username is locked out
+    pub fn locked(&self, ip: Option<IpAddr>, username: Option<&str>) -> Option<u64> {
+        let failures = self.failures.lock().unwrap();
+        let now = Instant::now();
+        subjects(ip, username)
+            .filter_map(|subject| failures.get(&subject)?.locked_until)
+            .filter(|until| *until > now)
+            .max()
+            .map(|until| until.duration_since(now).as_secs().max(1))
+    }
+
+    pub fn record_failure(&self, ip: Option<IpAddr>, username: Option<&str>) {
+        if self.max_failures == 0 {
+            return;
+        }
+        let mut failures = self.failures.lock().unwrap();
+        let now = Instant::now();
+        failures.retain(|_, f| {
+            f.locked_until.is_some_and(|until| until > now) || now - f.since < self.window
+        });
+
+        for subject in subjects(ip, username) {
+            let entry = failures.entry(subject.clone()).or_insert(Failures {
+                count: 0,
+                since: now,
+                locked_until: None,
+            });
+            if entry.locked_until.is_some_and(|until| until <= now) {
+                *entry = Failures {
+                    count: 0,
+                    since: now,
+                    locked_until: None,
+                };
+            }
+            entry.count += 1;
+            if entry.count >= self.max_failures && entry.locked_until.is_none() {
+                entry.locked_until = Some(now + self.lockout);
+                warn!(
+                    "Locked out {:?} for {}s after {} failed logins",
+                    subject,
+                    self.lockout.as_secs(),
+                    entry.count
+                );
+            }
+        }
+    }
+
+    /// Clears the failures of a successful login
+    pub fn record_success(&self, ip: Option<IpAddr>, username: Option<&str>) {
+        let mut failures = self.failures.lock().unwrap();
+        for subject in subjects(ip, username) {
+            failures.remove(&subject);
+        }
+    }
+}
+
+pub fn too_many_attempts(retry_after: u64) -> Response {
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(header::RETRY_AFTER, retry_after.to_string())],
+        Json(json!({ "error": format!("Too many failed logins, try again in {}s", retry_after) })),
+    )
+        .into_response()
+}
+
+/// Client address allow and deny lists; deny wins, an empty allow list allows everyone
+pub struct AccessList {
+    allow: Vec<IpNet>,
+    deny: Vec<IpNet>,
+}
+
+impl AccessList {
+    pub fn new(config: &SecurityConfig) -> Self {
+        Self {
+            allow: config.allow.clone(),
+            deny: config.deny.clone(),
+        }
+    }
+
+    pub fn permits(&self, ip: IpAddr) -> bool {
+        // IPv4 clients on a dual-stack socket show up as ::ffff:a.b.c.d
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+            v4 => v4,
+        };
+        !self.deny.iter().any(|net| net.contains(&ip))
+            && (self.allow.is_empty() || self.allow.iter().any(|net| net.contains(&ip)))
+    }
+}
+
+/// Rejects clients outside the allow list or inside the deny list, for the API and the UI
+pub async fn filter_ip(
+    State(access): State<Arc<AccessList>>,
+    req: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    match client_ip(&req) {
+        Some(ip) if !access.permits(ip) => {
+            warn!("Blocked {} {} from {}", req.method(), req.uri().path(), ip);
+            Err(StatusCode::FORBIDDEN)
+        }
+        _ => Ok(next.run(req).await),
+    }
+}
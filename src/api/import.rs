@@ -0,0 +1,54 @@
+use axum::{
+    body::Bytes,
+    extract::Query,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use log::error;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use serde_json::json;
+
+use super::{audit, auth::Principal};
+use crate::import::{self, ImportFormat};
+
+/// Uploads can hold years of history
+pub const MAX_IMPORT_BYTES: usize = 256 * 1024 * 1024;
+
+#[derive(Deserialize, Debug)]
+pub struct ImportQuery {
+    format: ImportFormat,
+    sim_id: Option<String>, // Required for phone backups, which do not name the SIM
+}
+
+/// Imports the file in the request body and reports what happened to its records
+pub async fn import_sms(
+    Extension(principal): Extension<Principal>,
+    Query(query): Query<ImportQuery>,
+    body: Bytes,
+) -> Response {
+    let report = import::import(
+        query.format,
+        body.as_ref(),
+        query.sim_id.as_deref(),
+        principal.sims(),
+    )
+    .await;
+    match report {
+        Ok(report) => {
+            let after = json!({
+                "format": query.format,
+                "sim_id": query.sim_id,
+                "inserted": report.inserted,
+                "skipped": report.skipped,
+                "invalid": report.invalid,
+            });
+            audit::record(&principal, "sms.import", None, None, Some(after)).await;
+            Json(report).into_response()
+        }
+        Err(e) => {
+            error!("SMS import failed: {:#}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "error": e.to_string() }))).into_response()
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use axum::{
+    extract::Path,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use chrono::{DateTime, Utc};
+use log::error;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use super::audit::{self, snapshot};
+use super::auth::{generate_token, hash_token, Principal};
+use crate::db::{ApiKey, Scope};
+
+#[derive(Deserialize, Debug)]
+pub struct ApiKeyPayload {
+    name: String,
+    scopes: Vec<Scope>,
+    sim_ids: Option<Vec<String>>, // Omit to allow every SIM
+    expires_at: Option<DateTime<Utc>>,
+}
+
+impl ApiKeyPayload {
+    fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("name must not be empty".to_string());
+        }
+        if self.scopes.is_empty() {
+            return Err("scopes must not be empty".to_string());
+        }
+        if self.sim_ids.as_ref().is_some_and(|sims| sims.is_empty()) {
+            return Err("sim_ids must not be empty, omit it to allow every SIM".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Returned once on creation; the token cannot be retrieved later
+#[derive(Serialize)]
+pub struct CreatedApiKey {
+    #[serde(flatten)]
+    key: ApiKey,
+    token: String,
+}
+
+fn bad_request(message: String) -> Response {
+    (StatusCode::BAD_REQUEST, Json(json!({ "error": message }))).into_response()
+}
+
+fn forbidden(message: String) -> Response {
+    (StatusCode::FORBIDDEN, Json(json!({ "error": message }))).into_response()
+}
+
+fn not_found(id: i64) -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({ "error": format!("API key {} not found", id) })),
+    )
+        .into_response()
+}
+
+fn internal_error(e: anyhow::Error) -> Response {
+    error!("{}", e);
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(json!({ "error": format!("Database error: {}", e) })),
+    )
+        .into_response()
+}
+
+pub async fn list_keys() -> Response {
+    match ApiKey::query_all().await {
+        Ok(keys) => (StatusCode::OK, Json(keys)).into_response(),
+        Err(e) => internal_error(e),
+    }
+}
+
+pub async fn get_key(Path(id): Path<i64>) -> Response {
+    match ApiKey::query_by_id(id).await {
+        Ok(Some(key)) => (StatusCode::OK, Json(key)).into_response(),
+        Ok(None) => not_found(id),
+        Err(e) => internal_error(e),
+    }
+}
+
+pub async fn create_key(
+    Extension(principal): Extension<Principal>,
+    Json(payload): Json<ApiKeyPayload>,
+) -> Response {
+    if let Err(e) = payload.validate() {
+        return bad_request(e);
+    }
+    if let Err(e) = principal.may_grant(&payload.scopes, payload.sim_ids.as_deref()) {
+        return forbidden(e);
+    }
+    if payload.expires_at.is_some_and(|expires_at| expires_at <= Utc::now()) {
+        return bad_request("expires_at must be in the future".to_string());
+    }
+
+    let (token, prefix) = generate_token();
+    match ApiKey::insert(
+        payload.name.trim(),
+        &prefix,
+        &hash_token(&token),
+        &payload.scopes,
+        payload.sim_ids.as_deref(),
+        payload.expires_at,
+    )
+    .await
+    {
+        Ok(key) => {
+            audit::record(&principal, "api_key.create", Some(key.id.to_string()), None, snapshot(&key))
+                .await;
+            (StatusCode::CREATED, Json(CreatedApiKey { key, token })).into_response()
+        }
+        Err(e) => internal_error(e),
+    }
+}
+
+/// Changes name, scopes, SIMs and expiry; the token stays the same
+pub async fn update_key(
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<i64>,
+    Json(payload): Json<ApiKeyPayload>,
+) -> Response {
+    if let Err(e) = payload.validate() {
+        return bad_request(e);
+    }
+    if let Err(e) = principal.may_grant(&payload.scopes, payload.sim_ids.as_deref()) {
+        return forbidden(e);
+    }
+    let before = match ApiKey::query_by_id(id).await {
+        Ok(Some(key)) => key,
+        Ok(None) => return not_found(id),
+        Err(e) => return internal_error(e),
+    };
+
+    match ApiKey::update(
+        id,
+        payload.name.trim(),
+        &payload.scopes,
+        payload.sim_ids.as_deref(),
+        payload.expires_at,
+    )
+    .await
+    {
+        Ok(Some(key)) => {
+            audit::record(
+                &principal,
+                "api_key.update",
+                Some(id.to_string()),
+                snapshot(&before),
+                snapshot(&key),
+            )
+            .await;
+            (StatusCode::OK, Json(key)).into_response()
+        }
+        Ok(None) => not_found(id),
+        Err(e) => internal_error(e),
+    }
+}
+
+pub async fn delete_key(Extension(principal): Extension<Principal>, Path(id): Path<i64>) -> Response {
+    let before = match ApiKey::query_by_id(id).await {
+        Ok(Some(key)) => key,
+        Ok(None) => return not_found(id),
+        Err(e) => return internal_error(e),
+    };
+    match ApiKey::delete_by_id(id).await {
+        Ok(true) => {
+            audit::record(&principal, "api_key.delete", Some(id.to_string()), snapshot(&before), None)
+                .await;
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(false) => not_found(id),
+        Err(e) => internal_error(e),
+    }
+}
@@ -1,12 +1,14 @@
-use std::{convert::Infallible, sync::Arc, time::Duration};
+use std::{convert::Infallible, net::SocketAddr, sync::Arc, time::Duration};
 use fancy_regex::Regex;
 
 use axum::{
-    extract::{Path, Query, State},
+    extract::{DefaultBodyLimit, Path, Query, State},
+    middleware,
     response::{sse::Event, IntoResponse, Response, Sse},
     routing::{delete, get, post, put},
-    Json, Router,
+    Extension, Json, Router,
 };
+use chrono::{DateTime, Utc};
 use futures_util::StreamExt;
 use log::debug;
 use log::error;
@@ -17,11 +19,18 @@ use serde_json::json;
 pub use sse_manager::SseManager;
 
 use crate::{
-    db::{Contact, Conversation, Sms, SimCard},
+    db::{Contact, Conversation, Scope, Sms, SmsCursor, SmsFilter, SmsStatus, SimCard, SortOrder},
     modem::{SmsType, SignalQuality, OperatorInfo, ModemInfo as ModemModel},
-    config::SmsStorage,
+    config::{SecurityConfig, Settings, SmsStorage},
+    optout::OptedOut,
+    phone::{default_country, Country},
+    webhook::WebhookManager,
     ModemManagerRef,
 };
+use auth::Principal;
+pub use auth::seed_admin;
+#[cfg(test)]
+pub use tls::{serve_redirect, serve_tls};
 
 fn decode_sms_center(sms_center: &str) -> String {
     // Check if it's UCS2 encoded (contains sequences like 002B, 0030, etc.)
@@ -75,8 +84,22 @@ fn format_memory_status(memory_status: &str) -> String {
     memory_status.to_string()
 }
 
+mod audit;
 mod auth;
+mod autoreplies;
+mod campaigns;
+mod contacts;
+mod deletion;
+mod export;
+mod guard;
+mod import;
+mod keys;
+mod search;
 mod sse_manager;
+mod suppressions;
+mod tls;
+mod users;
+mod webhooks;
 
 use rust_embed::RustEmbed;
 
@@ -86,60 +109,235 @@ struct Asset;
 
 pub async fn run_api(
     modem_manager: ModemManagerRef,
-    server_host: &str,
-    server_port: &u16,
-    username: &str,
-    password: &str,
+    settings: &Settings,
     sse_manager: Arc<SseManager>,
+    webhook_manager: WebhookManager,
 ) -> anyhow::Result<()> {
-    let api = Router::new()
-        .route("/check", get(check))
+    let app = router(
+        modem_manager,
+        settings.credentials(),
+        &settings.security,
+        sse_manager,
+        webhook_manager,
+    );
+    let (server_host, server_port) = (&settings.server_host, settings.server_port);
+
+    let Some(tls) = settings.tls.clone() else {
+        let listener =
+            tokio::net::TcpListener::bind(format!("{}:{}", server_host, server_port)).await?;
+        debug!("listening on {}", listener.local_addr()?);
+        axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await?;
+        return Ok(());
+    };
+
+    if let Some(redirect_port) = tls.redirect_port {
+        let listener =
+            tokio::net::TcpListener::bind(format!("{}:{}", server_host, redirect_port)).await?;
+        debug!("redirecting HTTP on {} to HTTPS", listener.local_addr()?);
+        tokio::spawn(async move {
+            if let Err(e) = tls::serve_redirect(listener, server_port).await {
+                error!("HTTP redirect listener stopped: {}", e);
+            }
+        });
+    }
+
+    let listener = std::net::TcpListener::bind(format!("{}:{}", server_host, server_port))?;
+    debug!("listening on {} (TLS)", listener.local_addr()?);
+    tls::serve_tls(listener, app, tls).await
+}
+
+pub fn router(
+    modem_manager: ModemManagerRef,
+    credentials: Option<(String, String)>,
+    security: &SecurityConfig,
+    sse_manager: Arc<SseManager>,
+    webhook_manager: WebhookManager,
+) -> Router {
+    let auth_state = auth::AuthState {
+        credentials: credentials.map(Arc::new),
+        guard: Arc::new(guard::LoginGuard::new(security)),
+    };
+
+    let sms_read = Router::new()
         .route("/sms", get(get_sms_paginated))
-        .route("/sms", post(send_sms).with_state(modem_manager.clone()))
+        .route("/sms/search", get(search::search_sms))
+        .route("/export", get(export::export_sms))
         .route("/sms/sse", get(sse_events).with_state(sse_manager.clone()))
         // 破坏性改造: 删除所有/api/device路径，改为/api/sims
         .route(
             "/sims/info",
             get(get_all_sim_info).with_state(modem_manager.clone()),
         )
-        .route(
-            "/sims/{sim_id}/refresh",
-            get(refresh_sim_sms).with_state(modem_manager.clone()),
-        )
-        .route("/contacts", get(get_contacts))
-        .route("/contacts", post(create_contact))
-        .route("/contacts/{id}", delete(delete_contact_by_id))
+        .route("/contacts", get(contacts::list_contacts))
+        .route("/contacts/export", get(contacts::export_contacts))
+        .route("/contacts/{id}", get(contacts::get_contact))
+        .route("/contact-tags", get(contacts::list_tags))
+        .route("/campaigns", get(campaigns::list_campaigns))
+        .route("/campaigns/{id}", get(campaigns::get_campaign))
+        .route("/campaigns/{id}/recipients", get(campaigns::list_recipients))
+        .route("/campaigns/{id}/report", get(campaigns::get_report))
         .route("/conversation", get(get_conversation))
         .route("/conversations/{id}/unread", post(get_conversation_unread))
         .route("/sim-cards", get(get_all_sim_cards)) // 保留用于管理
+        .route_layer(middleware::from_fn_with_state(Scope::SmsRead, auth::require_scope));
+
+    let sim_read = Router::new()
+        .route(
+            "/sims/{sim_id}/refresh",
+            get(refresh_sim_sms).with_state((modem_manager.clone(), webhook_manager.clone())),
+        )
         .route(
             "/sims/{sim_id}/info",
             get(get_enhanced_sim_info).with_state(modem_manager.clone()),
         )
-        .route("/sim-cards/{sim_id}/alias", put(update_sim_alias).with_state(modem_manager.clone()))
-        .route("/sim-cards/{sim_id}/phone", put(update_sim_phone).with_state(modem_manager.clone()))
         .route(
             "/sims/{sim_id}/storage",
             get(get_sms_storage_status).with_state(modem_manager.clone()),
         )
+        .route_layer(middleware::from_fn(auth::require_sim_access))
+        .route_layer(middleware::from_fn_with_state(Scope::SmsRead, auth::require_scope));
+
+    let sms_send = Router::new()
+        .route("/sms", post(send_sms).with_state(modem_manager.clone()))
+        .route("/contacts", post(contacts::create_contact))
+        .route(
+            "/contacts/import",
+            post(contacts::import_contacts).layer(DefaultBodyLimit::max(contacts::MAX_IMPORT_BYTES)),
+        )
+        .route("/contacts/{id}", put(contacts::update_contact))
+        .route(
+            "/campaigns",
+            post(campaigns::create_campaign).with_state(modem_manager.clone()),
+        )
+        .route("/campaigns/{id}/pause", post(campaigns::pause_campaign))
+        .route(
+            "/campaigns/{id}/resume",
+            post(campaigns::resume_campaign).with_state(modem_manager.clone()),
+        )
+        .route("/campaigns/{id}/cancel", post(campaigns::cancel_campaign))
+        .route(
+            "/import",
+            post(import::import_sms).layer(DefaultBodyLimit::max(import::MAX_IMPORT_BYTES)),
+        )
+        .route_layer(middleware::from_fn_with_state(Scope::SmsSend, auth::require_scope));
+
+    let sms_delete = Router::new()
+        .route("/sms/{id}", delete(deletion::delete_sms))
+        .route("/conversations/{id}", delete(deletion::delete_conversation))
+        .route("/contacts/{id}", delete(delete_contact_by_id))
+        .route("/deletions/{id}/restore", post(deletion::restore_deletion))
+        .route_layer(middleware::from_fn_with_state(Scope::SmsDelete, auth::require_scope));
+
+    let sim_admin = Router::new()
+        .route("/sim-cards/{sim_id}/alias", put(update_sim_alias).with_state(modem_manager.clone()))
+        .route("/sim-cards/{sim_id}/phone", put(update_sim_phone).with_state(modem_manager.clone()))
+        .route("/sim-cards/{sim_id}/country", put(update_sim_country).with_state(modem_manager.clone()))
         .route(
             "/sims/{sim_id}/storage",
             put(set_sms_storage).with_state(modem_manager.clone()),
         )
-        .layer(axum::middleware::from_fn_with_state(
-            (username.to_string(), password.to_string()),
-            auth::basic_auth,
+        .route_layer(middleware::from_fn(auth::require_sim_access))
+        .route_layer(middleware::from_fn_with_state(Scope::SimsAdmin, auth::require_scope));
+
+    let webhooks_admin = Router::new()
+        .route("/webhooks", get(webhooks::list_webhooks))
+        .route(
+            "/webhooks",
+            post(webhooks::create_webhook).with_state(webhook_manager.clone()),
+        )
+        .route(
+            "/webhooks/status",
+            get(webhooks::get_webhook_status).with_state(webhook_manager.clone()),
+        )
+        .route(
+            "/webhooks/test",
+            post(webhooks::test_webhook).with_state(webhook_manager.clone()),
+        )
+        .route("/webhooks/{id}", get(webhooks::get_webhook))
+        .route(
+            "/webhooks/{id}",
+            put(webhooks::update_webhook).with_state(webhook_manager.clone()),
+        )
+        .route(
+            "/webhooks/{id}",
+            delete(webhooks::delete_webhook).with_state(webhook_manager.clone()),
+        )
+        .route(
+            "/webhooks/{id}/test",
+            post(webhooks::test_stored_webhook).with_state(webhook_manager),
+        )
+        .route(
+            "/auto-replies",
+            get(autoreplies::list_auto_replies).post(autoreplies::create_auto_reply),
+        )
+        .route("/auto-replies/test", post(autoreplies::test_auto_replies))
+        .route(
+            "/auto-replies/{id}",
+            get(autoreplies::get_auto_reply)
+                .put(autoreplies::update_auto_reply)
+                .delete(autoreplies::delete_auto_reply),
+        )
+        .route_layer(middleware::from_fn_with_state(Scope::WebhooksAdmin, auth::require_scope));
+
+    let keys_admin = Router::new()
+        .route("/keys", get(keys::list_keys).post(keys::create_key))
+        .route(
+            "/keys/{id}",
+            get(keys::get_key).put(keys::update_key).delete(keys::delete_key),
+        )
+        .route_layer(middleware::from_fn_with_state(Scope::KeysAdmin, auth::require_scope));
+
+    let users_admin = Router::new()
+        .route("/users", get(users::list_users).post(users::create_user))
+        .route(
+            "/users/{id}",
+            get(users::get_user).put(users::update_user).delete(users::delete_user),
+        )
+        .route_layer(middleware::from_fn_with_state(Scope::UsersAdmin, auth::require_scope));
+
+    let suppressions_admin = Router::new()
+        .route(
+            "/suppressions",
+            get(suppressions::list_suppressions).post(suppressions::add_suppression),
+        )
+        .route("/suppressions/export", get(suppressions::export_suppressions))
+        .route("/suppressions/{number}", delete(suppressions::remove_suppression))
+        .route_layer(middleware::from_fn_with_state(
+            Scope::SuppressionsAdmin,
+            auth::require_scope,
         ));
 
-    let app = Router::new()
-        .nest_service("/api", api)
-        .fallback(static_handler);
+    let audit_read = Router::new()
+        .route("/audit", get(audit::list_audit))
+        .route_layer(middleware::from_fn_with_state(Scope::AuditRead, auth::require_scope));
 
-    let listener =
-        tokio::net::TcpListener::bind(format!("{}:{}", server_host, server_port)).await?;
-    debug!("listening on {}", listener.local_addr()?);
-    axum::serve(listener, app).await?;
-    Ok(())
+    let api = Router::new()
+        .route("/check", get(check))
+        .route("/me", get(users::me))
+        .merge(sms_read)
+        .merge(sim_read)
+        .merge(sms_send)
+        .merge(sms_delete)
+        .merge(sim_admin)
+        .merge(webhooks_admin)
+        .merge(keys_admin)
+        .merge(users_admin)
+        .merge(audit_read)
+        .merge(suppressions_admin)
+        .layer(middleware::from_fn_with_state(
+            auth_state.clone(),
+            auth::authenticate,
+        ))
+        .route("/login", post(users::login).with_state(auth_state))
+        .route("/logout", post(users::logout));
+
+    Router::new()
+        .nest_service("/api", api)
+        .fallback(static_handler)
+        .layer(middleware::from_fn_with_state(
+            Arc::new(guard::AccessList::new(security)),
+            guard::filter_ip,
+        ))
 }
 
 #[derive(Serialize)]
@@ -150,25 +348,77 @@ pub struct PaginatedSmsResponse {
     per_page: u32,
 }
 
+#[derive(Serialize)]
+pub struct CursorSmsResponse {
+    data: Vec<Sms>,
+    next_cursor: Option<String>, // Pass as `cursor` for the next page; null on the last page
+    per_page: u32,
+}
+
+/// With `page` the results are paged by offset and include `total`. Without it they are paged
+/// by `cursor`, which stays consistent while new messages arrive.
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "snake_case")]
 pub struct SmsQuery {
-    page: u32,
+    page: Option<u32>,
     per_page: u32,
+    cursor: Option<String>,
     #[serde(default)]
+    order: SortOrder,
     contact_id: Option<String>,
+    sim_id: Option<String>,
+    send: Option<bool>,
+    status: Option<SmsStatus>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
 }
 
-async fn get_sms_paginated(Query(query): Query<SmsQuery>) -> Response {
-    let result = match &query.contact_id {
-        Some(contact_id) => {
-            Sms::paginate_by_contact_id(contact_id, query.page, query.per_page).await
+async fn get_sms_paginated(
+    Extension(principal): Extension<Principal>,
+    Query(query): Query<SmsQuery>,
+) -> Response {
+    let sims = principal.sims();
+    let filter = SmsFilter {
+        sim_id: query.sim_id,
+        contact_id: query.contact_id,
+        send: query.send,
+        status: query.status,
+        from: query.from,
+        to: query.to,
+    };
+    let cursor = match query.cursor.as_deref().map(SmsCursor::decode).transpose() {
+        Ok(cursor) => cursor,
+        Err(_) => {
+            return (StatusCode::BAD_REQUEST, Json(json!({ "error": "Invalid cursor" })))
+                .into_response()
         }
-        None => Sms::paginate(query.page, query.per_page).await,
     };
 
-    let (sms_list, total) = match result {
-        Ok(res) => res,
+    let response = match query.page {
+        Some(page) => Sms::paginate(&filter, query.order, page, query.per_page, sims)
+            .await
+            .map(|(data, total)| {
+                Json(PaginatedSmsResponse {
+                    data,
+                    total,
+                    page,
+                    per_page: query.per_page,
+                })
+                .into_response()
+            }),
+        None => Sms::paginate_after(&filter, query.order, cursor, query.per_page, sims)
+            .await
+            .map(|(data, next)| {
+                Json(CursorSmsResponse {
+                    data,
+                    next_cursor: next.map(|cursor| cursor.encode()),
+                    per_page: query.per_page,
+                })
+                .into_response()
+            }),
+    };
+    let response = match response {
+        Ok(response) => response,
         Err(e) => {
             error!("{}", e);
             return (
@@ -179,29 +429,54 @@ async fn get_sms_paginated(Query(query): Query<SmsQuery>) -> Response {
         }
     };
 
-    Json(PaginatedSmsResponse {
-        data: sms_list,
-        total,
-        page: query.page,
-        per_page: query.per_page,
-    })
-    .into_response()
+    // Opening a conversation marks it as read
+    let first_page = query.page.map_or(cursor.is_none(), |page| page == 1);
+    if let (Some(contact_id), true) = (&filter.contact_id, first_page) {
+        if let Err(e) = Sms::mark_read_by_contact_id(contact_id, sims).await {
+            error!("Failed to mark conversation {} as read: {}", contact_id, e);
+        }
+    }
+
+    response
 }
 
 async fn send_sms(
     State(modem_manager): State<ModemManagerRef>,
+    Extension(principal): Extension<Principal>,
     Json(mut payload): Json<SmsPayload>,
 ) -> impl IntoResponse {
+    if !principal.allows_sim(&payload.sim_id) {
+        return (StatusCode::FORBIDDEN, format!("Not allowed to use SIM {}", payload.sim_id))
+            .into_response();
+    }
+    debug!("{} is sending SMS via SIM {}", principal.name, payload.sim_id);
+
     if payload.new {
-        payload.contact.find_or_create().await.unwrap();
+        let country = SimCard::country(&payload.sim_id).await.unwrap_or_else(|e| {
+            error!("Failed to look up the country of SIM {}: {}", payload.sim_id, e);
+            default_country()
+        });
+        payload.contact.find_or_create(country).await.unwrap();
     }
 
-    match modem_manager.send_sms(&payload.sim_id, &payload.contact, &payload.message).await {
+    let result = modem_manager
+        .send_sms(&payload.sim_id, &payload.contact, &payload.message, principal.user_id)
+        .await;
+    let after = match &result {
+        Ok((sms_id, _)) => json!({ "sim_id": payload.sim_id, "sms_id": sms_id, "status": "sent" }),
+        Err(e) => json!({ "sim_id": payload.sim_id, "status": "failed", "error": e.to_string() }),
+    };
+    audit::record(&principal, "sms.send", Some(payload.contact.name.clone()), None, Some(after)).await;
+
+    match result {
         Ok((sms_id, contact_id)) => (
             StatusCode::OK,
             Json(json!({ "sms_id": sms_id, "contact_id": contact_id })),
         )
             .into_response(),
+        Err(e) if e.is::<OptedOut>() => {
+            (StatusCode::FORBIDDEN, format!("Send failed: {}", e)).into_response()
+        }
         Err(e) => (
             StatusCode::INTERNAL_SERVER_ERROR,
             format!("Send failed: {}", e),
@@ -210,7 +485,10 @@ async fn send_sms(
     }
 }
 
-async fn get_all_sim_info(State(modem_manager): State<ModemManagerRef>) -> Response {
+async fn get_all_sim_info(
+    State(modem_manager): State<ModemManagerRef>,
+    Extension(principal): Extension<Principal>,
+) -> Response {
     use futures::future::join_all;
     use tokio::time::{timeout, Duration};
 
@@ -224,7 +502,8 @@ async fn get_all_sim_info(State(modem_manager): State<ModemManagerRef>) -> Respo
     let sim_ids = modem_manager.get_sim_ids();
 
     // 并发获取所有调制解调器信息，带超时控制
-    let sim_ids = sim_ids.await;
+    let mut sim_ids = sim_ids.await;
+    sim_ids.retain(|sim_id| principal.allows_sim(sim_id));
     let modem_futures: Vec<_> = sim_ids.iter().map(|sim_id| {
         let sim_id = sim_id.clone();
         let modem_manager = modem_manager.clone();
@@ -311,8 +590,14 @@ async fn get_all_sim_info(State(modem_manager): State<ModemManagerRef>) -> Respo
     (StatusCode::OK, Json(details)).into_response()
 }
 
-async fn refresh_sim_sms(Path(sim_id): Path<String>, State(modem_manager): State<ModemManagerRef>) -> Response {
-    match modem_manager.read_sms_sync_insert(&sim_id, SmsType::RecUnread).await {
+async fn refresh_sim_sms(
+    Path(sim_id): Path<String>,
+    State((modem_manager, webhook_manager)): State<(ModemManagerRef, WebhookManager)>,
+) -> Response {
+    match modem_manager
+        .read_sms_sync_insert(&sim_id, SmsType::RecUnread, Some(&webhook_manager))
+        .await
+    {
         Ok(_) => (StatusCode::OK).into_response(),
         Err(err) => (StatusCode::BAD_GATEWAY, err.to_string()).into_response(),
     }
@@ -322,30 +607,43 @@ async fn check() -> impl IntoResponse {
     StatusCode::NO_CONTENT
 }
 
-async fn get_contacts() -> Json<Vec<Contact>> {
-    let contacts = Contact::query_all().await.unwrap();
-    Json(contacts)
-}
-
-async fn get_conversation() -> Json<Vec<Conversation>> {
-    let conversation = Conversation::query_all().await.unwrap();
+async fn get_conversation(Extension(principal): Extension<Principal>) -> Json<Vec<Conversation>> {
+    let conversation = Conversation::query_all(principal.sims()).await.unwrap();
     Json(conversation)
 }
 
 
-async fn create_contact(Json(payload): Json<Contact>) -> Response {
-    match Contact::insert(&payload).await {
-        Ok(id) => (StatusCode::OK, Json(id)).into_response(),
-        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+/// Soft-deletes the contact's messages on the principal's SIMs. The contact itself is removed
+/// once no messages of it remain on any SIM, including deleted ones that can still be restored.
+async fn delete_contact_by_id(
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<String>,
+) -> Response {
+    let before = match Contact::query_details_by_id(&id).await {
+        Ok(Some(contact)) => contact,
+        Ok(None) => return (StatusCode::NOT_FOUND, "Contact not found").into_response(),
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+    let (deletion_id, deleted) = match Sms::soft_delete_by_contact_id(&id, principal.sims()).await {
+        Ok(result) => result,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+    let contact_deleted = match Contact::delete_without_messages(&id).await {
+        Ok(contact_deleted) => contact_deleted,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+    if deleted == 0 && !contact_deleted {
+        return (StatusCode::NOT_FOUND, "Contact not found").into_response();
     }
-}
 
-async fn delete_contact_by_id(Path(id): Path<String>) -> Response {
-    match Contact::delete_by_id(&id).await {
-        Ok(true) => (StatusCode::OK).into_response(),
-        Ok(false) => (StatusCode::NOT_FOUND, "Contact not found").into_response(),
-        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
-    }
+    let after = json!({
+        "deletion_id": deletion_id,
+        "deleted": deleted,
+        "contact_deleted": contact_deleted,
+    });
+    let before = audit::snapshot(&before);
+    audit::record(&principal, "contact.delete", Some(id), before, Some(after.clone())).await;
+    (StatusCode::OK, Json(after)).into_response()
 }
 
 async fn static_handler(uri: axum::http::Uri) -> impl IntoResponse {
@@ -374,10 +672,12 @@ async fn static_handler(uri: axum::http::Uri) -> impl IntoResponse {
 
 async fn sse_events(
     State(sse_manager): State<Arc<SseManager>>,
+    Extension(principal): Extension<Principal>,
 ) -> Sse<impl futures_util::Stream<Item = Result<Event, Infallible>>> {
     let rx_stream = tokio_stream::wrappers::BroadcastStream::new(sse_manager.subscribe()).map(
-        |msg| match msg {
-            Ok(cnversations) => {
+        move |msg| match msg {
+            Ok(mut cnversations) => {
+                cnversations.retain(|c| principal.allows_sim(&c.sms_preview.sim_id));
                 let timestamp = chrono::Utc::now().timestamp_millis();
                 Ok(Event::default()
                     .id(timestamp.to_string())
@@ -402,8 +702,11 @@ async fn sse_events(
     )
 }
 
-async fn get_conversation_unread(Path(id): Path<String>) -> Response {
-    match Sms::query_unread_by_contact_id(&id).await {
+async fn get_conversation_unread(
+    Path(id): Path<String>,
+    Extension(principal): Extension<Principal>,
+) -> Response {
+    match Sms::query_unread_by_contact_id(&id, principal.sims()).await {
         Ok(messages) => (StatusCode::OK, Json(messages)).into_response(),
         Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
     }
@@ -438,9 +741,12 @@ pub struct ModemInfo {
     pub baud_rate: u32,
 }
 
-async fn get_all_sim_cards() -> Response {
+async fn get_all_sim_cards(Extension(principal): Extension<Principal>) -> Response {
     match SimCard::query_all().await {
-        Ok(sim_cards) => (StatusCode::OK, Json(sim_cards)).into_response(),
+        Ok(mut sim_cards) => {
+            sim_cards.retain(|sim| principal.allows_sim(&sim.id));
+            (StatusCode::OK, Json(sim_cards)).into_response()
+        }
         Err(e) => {
             error!("Failed to get SIM cards: {}", e);
             (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to get SIM cards: {}", e))
@@ -485,16 +791,26 @@ pub struct UpdatePhoneRequest {
     phone_number: Option<String>,
 }
 
+#[derive(serde::Deserialize)]
+pub struct UpdateCountryRequest {
+    country: Option<String>, // ISO 3166 code, null for the default country
+}
+
 async fn update_sim_alias(
     Path(sim_id): Path<String>,
     State(modem_manager): State<ModemManagerRef>,
+    Extension(principal): Extension<Principal>,
     Json(request): Json<UpdateAliasRequest>,
 ) -> Response {
     match SimCard::query_all().await {
         Ok(sim_cards) => {
             if let Some(mut sim_card) = sim_cards.into_iter().find(|s| s.id == sim_id) {
+                let before = json!({ "alias": sim_card.alias });
                 match sim_card.update_alias(request.alias.clone()).await {
                     Ok(_) => {
+                        let after = json!({ "alias": sim_card.alias });
+                        audit::record(&principal, "sim.alias.update", Some(sim_id), Some(before), Some(after))
+                            .await;
                         // Update cache
                         modem_manager.update_sim_cache(sim_card.clone()).await;
                         (StatusCode::OK, Json(sim_card)).into_response()
@@ -518,13 +834,18 @@ async fn update_sim_alias(
 async fn update_sim_phone(
     Path(sim_id): Path<String>,
     State(modem_manager): State<ModemManagerRef>,
+    Extension(principal): Extension<Principal>,
     Json(request): Json<UpdatePhoneRequest>,
 ) -> Response {
     match SimCard::query_all().await {
         Ok(sim_cards) => {
             if let Some(mut sim_card) = sim_cards.into_iter().find(|s| s.id == sim_id) {
+                let before = json!({ "phone_number": sim_card.phone_number });
                 match sim_card.update_phone_number(request.phone_number.clone()).await {
                     Ok(_) => {
+                        let after = json!({ "phone_number": sim_card.phone_number });
+                        audit::record(&principal, "sim.phone.update", Some(sim_id), Some(before), Some(after))
+                            .await;
                         // Update cache
                         modem_manager.update_sim_cache(sim_card.clone()).await;
                         (StatusCode::OK, Json(sim_card)).into_response()
@@ -545,6 +866,48 @@ async fn update_sim_phone(
     }
 }
 
+/// Sets the country of national numbers on the SIM and renormalises existing contacts
+async fn update_sim_country(
+    Path(sim_id): Path<String>,
+    State(modem_manager): State<ModemManagerRef>,
+    Extension(principal): Extension<Principal>,
+    Json(request): Json<UpdateCountryRequest>,
+) -> Response {
+    let country = match request.country.as_deref().map(Country::from_code).transpose() {
+        Ok(country) => country,
+        Err(e) => return (StatusCode::BAD_REQUEST, Json(json!({ "error": e }))).into_response(),
+    };
+    match SimCard::query_all().await {
+        Ok(sim_cards) => {
+            if let Some(mut sim_card) = sim_cards.into_iter().find(|s| s.id == sim_id) {
+                let before = json!({ "default_country": sim_card.default_country });
+                match sim_card.update_country(country).await {
+                    Ok(_) => {
+                        let after = json!({ "default_country": sim_card.default_country });
+                        audit::record(&principal, "sim.country.update", Some(sim_id), Some(before), Some(after))
+                            .await;
+                        modem_manager.update_sim_cache(sim_card.clone()).await;
+                        if let Err(e) = Contact::normalize_numbers().await {
+                            error!("Failed to normalize contact numbers: {}", e);
+                        }
+                        (StatusCode::OK, Json(sim_card)).into_response()
+                    },
+                    Err(e) => (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        format!("Failed to update country: {}", e),
+                    ).into_response(),
+                }
+            } else {
+                (StatusCode::NOT_FOUND, "SIM card not found").into_response()
+            }
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to query SIM cards: {}", e),
+        ).into_response(),
+    }
+}
+
 // DELETE: refresh_alias_mapping_api - 不再需要，因为直接使用SIM ID作为键
 // async fn refresh_alias_mapping_api() {...}
 
@@ -567,10 +930,15 @@ async fn get_sms_storage_status(
 async fn set_sms_storage(
     Path(sim_id): Path<String>,
     State(modem_manager): State<ModemManagerRef>,
+    Extension(principal): Extension<Principal>,
     Json(request): Json<SmsStorageRequest>,
 ) -> Response {
     match modem_manager.set_sms_storage(&sim_id, request.storage).await {
-        Ok(()) => (StatusCode::OK, Json(json!({"message": "SMS storage location updated successfully"}))).into_response(),
+        Ok(()) => {
+            let after = json!({ "storage": request.storage });
+            audit::record(&principal, "sim.storage.update", Some(sim_id), None, Some(after)).await;
+            (StatusCode::OK, Json(json!({"message": "SMS storage location updated successfully"}))).into_response()
+        }
         Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": format!("Failed to set SMS storage: {}", e)}))).into_response(),
     }
 }
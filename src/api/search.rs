@@ -0,0 +1,130 @@
+use axum::{
+    extract::Query,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use chrono::{DateTime, Utc};
+use log::error;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use super::auth::Principal;
+use crate::db::{SearchOrder, Sms, SmsFilter, SmsSearchHit, SmsStatus};
+
+const MIN_TERM_CHARS: usize = 3;
+
+#[derive(Deserialize, Debug)]
+pub struct SearchQuery {
+    q: String,
+    page: u32,
+    per_page: u32,
+    sim_id: Option<String>,
+    contact_id: Option<String>,
+    send: Option<bool>,
+    status: Option<SmsStatus>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    #[serde(default)]
+    order: SearchOrder,
+}
+
+#[derive(Serialize)]
+pub struct SearchResult {
+    #[serde(flatten)]
+    sms: Sms,
+    contact_name: String,
+    snippet: String, // HTML-escaped, matches wrapped in <mark>
+}
+
+#[derive(Serialize)]
+pub struct PaginatedSearchResponse {
+    data: Vec<SearchResult>,
+    total: i64,
+    page: u32,
+    per_page: u32,
+}
+
+fn bad_request(message: String) -> Response {
+    (StatusCode::BAD_REQUEST, Json(json!({ "error": message }))).into_response()
+}
+
+/// Escapes the snippet for HTML and turns the match markers into <mark> tags
+fn highlight(snippet: &str) -> String {
+    let mut html = String::with_capacity(snippet.len() + 16);
+    for c in snippet.chars() {
+        match c {
+            '\u{2}' => html.push_str("<mark>"),
+            '\u{3}' => html.push_str("</mark>"),
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            c => html.push(c),
+        }
+    }
+    html
+}
+
+impl From<SmsSearchHit> for SearchResult {
+    fn from(hit: SmsSearchHit) -> Self {
+        Self {
+            snippet: highlight(&hit.snippet),
+            sms: hit.sms,
+            contact_name: hit.contact_name,
+        }
+    }
+}
+
+/// Every whitespace-separated term of `q` must appear in the message or the contact name
+pub async fn search_sms(
+    Extension(principal): Extension<Principal>,
+    Query(query): Query<SearchQuery>,
+) -> Response {
+    let terms: Vec<&str> = query.q.split_whitespace().collect();
+    if terms.is_empty() {
+        return bad_request("q must not be empty".to_string());
+    }
+    if let Some(term) = terms.iter().find(|t| t.chars().count() < MIN_TERM_CHARS) {
+        return bad_request(format!(
+            "Search terms need at least {} characters: {}",
+            MIN_TERM_CHARS, term
+        ));
+    }
+
+    let filter = SmsFilter {
+        sim_id: query.sim_id,
+        contact_id: query.contact_id,
+        send: query.send,
+        status: query.status,
+        from: query.from,
+        to: query.to,
+    };
+    match Sms::search(
+        &terms,
+        &filter,
+        query.order,
+        query.page,
+        query.per_page,
+        principal.sims(),
+    )
+    .await
+    {
+        Ok((hits, total)) => Json(PaginatedSearchResponse {
+            data: hits.into_iter().map(SearchResult::from).collect(),
+            total,
+            page: query.page,
+            per_page: query.per_page,
+        })
+        .into_response(),
+        Err(e) => {
+            error!("Failed to search SMS: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({ "error": format!("Database error: {}", e) })),
+            )
+                .into_response()
+        }
+    }
+}
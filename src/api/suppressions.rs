@@ -0,0 +1,158 @@
+use axum::{
+    extract::Path,
+    http::header,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use chrono::Utc;
+use log::error;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use serde_json::json;
+
+use super::audit::{self, snapshot};
+use super::auth::Principal;
+use crate::{
+    db::{Suppression, SuppressionSource},
+    phone::{is_phone_number, normalize},
+};
+
+#[derive(Deserialize, Debug)]
+pub struct SuppressionPayload {
+    number: String,
+    reason: Option<String>,
+}
+
+fn internal_error(e: anyhow::Error) -> Response {
+    error!("{}", e);
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(json!({ "error": format!("Database error: {}", e) })),
+    )
+        .into_response()
+}
+
+pub async fn list_suppressions() -> Response {
+    match Suppression::query_all().await {
+        Ok(suppressions) => (StatusCode::OK, Json(suppressions)).into_response(),
+        Err(e) => internal_error(e),
+    }
+}
+
+/// Stops all messages to a number, as if it had replied with an opt-out keyword
+pub async fn add_suppression(
+    Extension(principal): Extension<Principal>,
+    Json(payload): Json<SuppressionPayload>,
+) -> Response {
+    // Sends are checked in E.164, which a national number only matches with the right country
+    let number = normalize(&payload.number);
+    if !is_phone_number(&number) || !number.starts_with('+') {
+        let error = format!("Not an international phone number: {}", payload.number);
+        return (StatusCode::BAD_REQUEST, Json(json!({ "error": error }))).into_response();
+    }
+
+    let suppression = Suppression {
+        number: number.clone(),
+        source: SuppressionSource::Admin,
+        sim_id: None,
+        reason: payload.reason.filter(|reason| !reason.trim().is_empty()),
+        user_id: principal.user_id,
+        created_at: Utc::now(),
+    };
+    match suppression.insert().await {
+        Ok(true) => {
+            let target = Some(number);
+            audit::record(
+                &principal,
+                "suppression.add",
+                target,
+                None,
+                snapshot(&suppression),
+            )
+            .await;
+            (StatusCode::CREATED, Json(suppression)).into_response()
+        }
+        // An existing entry is kept as it was, with its original source
+        Ok(false) => match Suppression::query_by_number(&number).await {
+            Ok(existing) => (StatusCode::OK, Json(existing)).into_response(),
+            Err(e) => internal_error(e),
+        },
+        Err(e) => internal_error(e),
+    }
+}
+
+/// Allows messages to a number again, whether it opted out itself or an admin added it
+pub async fn remove_suppression(
+    Path(number): Path<String>,
+    Extension(principal): Extension<Principal>,
+) -> Response {
+    let number = normalize(&number);
+    let before = match Suppression::query_by_number(&number).await {
+        Ok(Some(suppression)) => suppression,
+        Ok(None) => {
+            let error = format!("{} is not suppressed", number);
+            return (StatusCode::NOT_FOUND, Json(json!({ "error": error }))).into_response();
+        }
+        Err(e) => return internal_error(e),
+    };
+    match Suppression::delete(&number).await {
+        Ok(_) => {
+            audit::record(
+                &principal,
+                "suppression.remove",
+                Some(number),
+                snapshot(&before),
+                None,
+            )
+            .await;
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(e) => internal_error(e),
+    }
+}
+
+/// The list as CSV, for other systems that must honour it
+pub async fn export_suppressions(Extension(principal): Extension<Principal>) -> Response {
+    audit::record(&principal, "suppression.export", None, None, None).await;
+
+    let suppressions = match Suppression::query_all().await {
+        Ok(suppressions) => suppressions,
+        Err(e) => return internal_error(e),
+    };
+    let file = match write_csv(&suppressions) {
+        Ok(file) => file,
+        Err(e) => return internal_error(e),
+    };
+
+    let filename = format!("suppressions-{}.csv", Utc::now().format("%Y%m%d-%H%M%S"));
+    (
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", filename),
+            ),
+        ],
+        file,
+    )
+        .into_response()
+}
+
+fn write_csv(suppressions: &[Suppression]) -> anyhow::Result<String> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(["number", "source", "sim_id", "reason", "created_at"])?;
+    for suppression in suppressions {
+        let source = match suppression.source {
+            SuppressionSource::Keyword => "keyword",
+            SuppressionSource::Admin => "admin",
+        };
+        writer.write_record([
+            suppression.number.as_str(),
+            source,
+            suppression.sim_id.as_deref().unwrap_or_default(),
+            suppression.reason.as_deref().unwrap_or_default(),
+            &suppression.created_at.to_rfc3339(),
+        ])?;
+    }
+    Ok(String::from_utf8(writer.into_inner()?)?)
+}
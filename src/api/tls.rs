@@ -0,0 +1,206 @@
+use std::{
+    fs::File,
+    io::{self, BufReader},
+    net::SocketAddr,
+    path::Path,
+    sync::Arc,
+    time::SystemTime,
+};
+
+use anyhow::{Context, Result};
+use axum::{
+    extract::Request,
+    http::{header, Uri},
+    response::{IntoResponse, Redirect, Response},
+    Extension, Router,
+};
+use axum_server::{
+    accept::Accept,
+    tls_rustls::{RustlsAcceptor, RustlsConfig},
+};
+use futures::future::BoxFuture;
+use log::{error, info};
+use rustls::{server::WebPkiClientVerifier, RootCertStore, ServerConfig};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::server::TlsStream;
+use tower::Layer;
+
+use crate::{
+    config::{ClientAuth, TlsConfig},
+    db::Scope,
+};
+
+/// Verified client certificate of the connection, added to every request on it
+#[derive(Debug, Clone)]
+pub struct ClientCert {
+    pub name: String, // Subject common name, or a fingerprint when there is none
+    pub scopes: Option<Vec<Scope>>,
+}
+
+fn read_certs(path: &Path) -> Result<Vec<rustls::pki_types::CertificateDer<'static>>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<io::Result<Vec<_>>>()
+        .with_context(|| format!("Failed to read certificates from {}", path.display()))?;
+    if certs.is_empty() {
+        anyhow::bail!("No certificates found in {}", path.display());
+    }
+    Ok(certs)
+}
+
+/// Builds the rustls configuration from the certificate, key and client CA files
+pub fn load_server_config(tls: &TlsConfig) -> Result<ServerConfig> {
+    let certs = read_certs(&tls.cert_file)?;
+    let file = File::open(&tls.key_file)
+        .with_context(|| format!("Failed to open {}", tls.key_file.display()))?;
+    let key = rustls_pemfile::private_key(&mut BufReader::new(file))?
+        .with_context(|| format!("No private key found in {}", tls.key_file.display()))?;
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+    let builder = match &tls.client_ca_file {
+        Some(ca_file) => {
+            let mut roots = RootCertStore::empty();
+            for cert in read_certs(ca_file)? {
+                roots.add(cert)?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+            let verifier = match tls.client_auth {
+                ClientAuth::Optional => verifier.allow_unauthenticated(),
+                ClientAuth::Required => verifier,
+            };
+            builder.with_client_cert_verifier(verifier.build()?)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let mut config = builder.with_single_cert(certs, key)?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(config)
+}
+
+fn certificate_name(der: &[u8]) -> String {
+    x509_parser::parse_x509_certificate(der)
+        .ok()
+        .and_then(|(_, cert)| {
+            let cn = cert.subject().iter_common_name().next()?;
+            cn.as_str().ok().map(str::to_string)
+        })
+        .unwrap_or_else(|| hex::encode(&Sha256::digest(der)[..8]))
+}
+
+/// TLS acceptor that exposes the client certificate to handlers as `Option<ClientCert>`
+#[derive(Clone)]
+struct ClientCertAcceptor {
+    inner: RustlsAcceptor,
+    scopes: Option<Vec<Scope>>,
+}
+
+impl<I, S> Accept<I, S> for ClientCertAcceptor
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    S: Send + 'static,
+{
+    type Stream = TlsStream<I>;
+    type Service = axum::middleware::AddExtension<S, Option<ClientCert>>;
+    type Future = BoxFuture<'static, io::Result<(Self::Stream, Self::Service)>>;
+
+    fn accept(&self, stream: I, service: S) -> Self::Future {
+        let acceptor = self.inner.clone();
+        let scopes = self.scopes.clone();
+        Box::pin(async move {
+            let (stream, service) = acceptor.accept(stream, service).await?;
+            let cert = stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|certs| certs.first())
+                .map(|cert| ClientCert {
+                    name: certificate_name(cert),
+                    scopes,
+                });
+            Ok((stream, Extension(cert).layer(service)))
+        })
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+/// Reloads the certificates when any of the files changes; on errors the old ones stay in use
+async fn watch_certificates(config: RustlsConfig, tls: TlsConfig) {
+    let files = || {
+        [Some(&tls.cert_file), Some(&tls.key_file), tls.client_ca_file.as_ref()]
+            .into_iter()
+            .flatten()
+            .map(|path| modified(path))
+            .collect::<Vec<_>>()
+    };
+    let mut last = files();
+    loop {
+        tokio::time::sleep(std::time::Duration::from_secs(tls.reload_interval)).await;
+        let current = files();
+        if current == last {
+            continue;
+        }
+        match load_server_config(&tls) {
+            Ok(server_config) => {
+                config.reload_from_config(Arc::new(server_config));
+                last = current;
+                info!("Reloaded TLS certificate from {}", tls.cert_file.display());
+            }
+            Err(e) => error!("Failed to reload TLS certificate, keeping the current one: {:#}", e),
+        }
+    }
+}
+
+/// Serves the app over HTTPS on an already bound listener
+pub async fn serve_tls(listener: std::net::TcpListener, app: Router, tls: TlsConfig) -> Result<()> {
+    let config = RustlsConfig::from_config(Arc::new(load_server_config(&tls)?));
+    let acceptor = ClientCertAcceptor {
+        inner: RustlsAcceptor::new(config.clone()),
+        scopes: tls.client_scopes.clone(),
+    };
+    tokio::spawn(watch_certificates(config, tls));
+
+    axum_server::from_tcp(listener)
+        .acceptor(acceptor)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .await?;
+    Ok(())
+}
+
+/// Redirects every plain HTTP request to the same path on the HTTPS port
+pub async fn serve_redirect(listener: tokio::net::TcpListener, https_port: u16) -> Result<()> {
+    let app = Router::new().fallback(move |req: Request| async move { redirect(req, https_port) });
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+fn redirect(req: Request, https_port: u16) -> Response {
+    let Some(host) = req
+        .headers()
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .and_then(|host| host.parse::<axum::http::uri::Authority>().ok())
+    else {
+        return (axum::http::StatusCode::BAD_REQUEST, "Missing Host header").into_response();
+    };
+    let authority = match https_port {
+        443 => host.host().to_string(),
+        port => format!("{}:{}", host.host(), port),
+    };
+    let path = req.uri().path_and_query().map_or("/", |p| p.as_str());
+    match Uri::builder()
+        .scheme("https")
+        .authority(authority)
+        .path_and_query(path)
+        .build()
+    {
+        Ok(uri) => Redirect::permanent(&uri.to_string()).into_response(),
+        Err(_) => (axum::http::StatusCode::BAD_REQUEST, "Invalid Host header").into_response(),
+    }
+}
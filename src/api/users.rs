@@ -0,0 +1,292 @@
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::{header, HeaderMap},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use log::error;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use serde_json::json;
+
+use super::audit::{self, snapshot};
+use super::auth::{
+    blocked, create_session, hash_password, hash_token, session_token, verify_dummy_password,
+    verify_password, AuthState, Principal, SESSION_COOKIE, SESSION_DAYS,
+};
+use crate::db::{Role, User};
+
+const MIN_PASSWORD_LENGTH: usize = 8;
+
+#[derive(Deserialize, Debug)]
+pub struct LoginRequest {
+    username: String,
+    password: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct UserPayload {
+    username: String,
+    password: Option<String>, // Required on creation, keeps the current password on update
+    role: Role,
+    sim_ids: Option<Vec<String>>, // Omit to allow every SIM
+    #[serde(default)]
+    disabled: bool,
+}
+
+impl UserPayload {
+    fn validate(&self) -> Result<(), String> {
+        if self.username.trim().is_empty() {
+            return Err("username must not be empty".to_string());
+        }
+        if self
+            .password
+            .as_ref()
+            .is_some_and(|p| p.chars().count() < MIN_PASSWORD_LENGTH)
+        {
+            return Err(format!(
+                "password must be at least {} characters",
+                MIN_PASSWORD_LENGTH
+            ));
+        }
+        if self.sim_ids.as_ref().is_some_and(|sims| sims.is_empty()) {
+            return Err("sim_ids must not be empty, omit it to allow every SIM".to_string());
+        }
+        Ok(())
+    }
+}
+
+fn bad_request(message: String) -> Response {
+    (StatusCode::BAD_REQUEST, Json(json!({ "error": message }))).into_response()
+}
+
+fn forbidden(message: String) -> Response {
+    (StatusCode::FORBIDDEN, Json(json!({ "error": message }))).into_response()
+}
+
+fn not_found(id: i64) -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({ "error": format!("User {} not found", id) })),
+    )
+        .into_response()
+}
+
+fn internal_error(e: anyhow::Error) -> Response {
+    error!("{}", e);
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(json!({ "error": format!("Database error: {}", e) })),
+    )
+        .into_response()
+}
+
+fn session_cookie(token: &str, max_age: i64) -> String {
+    format!(
+        "{}={}; HttpOnly; SameSite=Strict; Path=/; Max-Age={}",
+        SESSION_COOKIE, token, max_age
+    )
+}
+
+/// Starts a session and sets the session cookie
+pub async fn login(
+    State(auth): State<AuthState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Json(request): Json<LoginRequest>,
+) -> Response {
+    let ip = Some(addr.ip());
+    if let Some(retry_after) = auth.guard.locked(ip, Some(&request.username)) {
+        return blocked(ip, Some(&request.username), retry_after);
+    }
+
+    let user = match User::find_by_username(&request.username).await {
+        Ok(user) => user,
+        Err(e) => return internal_error(e),
+    };
+    let verified = match &user {
+        Some(user) => verify_password(&request.password, &user.password_hash),
+        None => {
+            verify_dummy_password(&request.password);
+            false
+        }
+    };
+    let user = match user {
+        Some(user) if verified && !user.disabled => user,
+        _ => {
+            auth.guard.record_failure(ip, Some(&request.username));
+            return (
+                StatusCode::UNAUTHORIZED,
+                Json(json!({ "error": "Invalid username or password" })),
+            )
+                .into_response()
+        }
+    };
+
+    auth.guard.record_success(ip, Some(&request.username));
+    match create_session(&user).await {
+        Ok(token) => {
+            let principal = Principal {
+                ip: Some(addr.ip().to_string()),
+                ..Principal::from(&user)
+            };
+            audit::record(&principal, "user.login", Some(user.username.clone()), None, None).await;
+            (
+            StatusCode::OK,
+            [(header::SET_COOKIE, session_cookie(&token, SESSION_DAYS * 24 * 3600))],
+            Json(principal),
+        )
+            .into_response()
+        }
+        Err(e) => internal_error(e),
+    }
+}
+
+pub async fn logout(headers: HeaderMap) -> Response {
+    if let Some(token) = session_token(&headers) {
+        if let Err(e) = User::delete_session(&hash_token(token)).await {
+            return internal_error(e);
+        }
+    }
+    (
+        StatusCode::NO_CONTENT,
+        [(header::SET_COOKIE, session_cookie("", 0))],
+    )
+        .into_response()
+}
+
+pub async fn me(Extension(principal): Extension<Principal>) -> Response {
+    (StatusCode::OK, Json(principal)).into_response()
+}
+
+pub async fn list_users() -> Response {
+    match User::query_all().await {
+        Ok(users) => (StatusCode::OK, Json(users)).into_response(),
+        Err(e) => internal_error(e),
+    }
+}
+
+pub async fn get_user(Path(id): Path<i64>) -> Response {
+    match User::query_by_id(id).await {
+        Ok(Some(user)) => (StatusCode::OK, Json(user)).into_response(),
+        Ok(None) => not_found(id),
+        Err(e) => internal_error(e),
+    }
+}
+
+pub async fn create_user(
+    Extension(principal): Extension<Principal>,
+    Json(payload): Json<UserPayload>,
+) -> Response {
+    if let Err(e) = payload.validate() {
+        return bad_request(e);
+    }
+    if let Err(e) = principal.may_grant(&payload.role.scopes(), payload.sim_ids.as_deref()) {
+        return forbidden(e);
+    }
+    let Some(password) = &payload.password else {
+        return bad_request("password is required".to_string());
+    };
+    match User::find_by_username(payload.username.trim()).await {
+        Ok(Some(_)) => return bad_request(format!("User {} already exists", payload.username)),
+        Ok(None) => {}
+        Err(e) => return internal_error(e),
+    }
+
+    let password_hash = match hash_password(password) {
+        Ok(hash) => hash,
+        Err(e) => return internal_error(e),
+    };
+    match User::insert(
+        payload.username.trim(),
+        &password_hash,
+        payload.role,
+        payload.sim_ids.as_deref(),
+    )
+    .await
+    {
+        Ok(user) => {
+            audit::record(&principal, "user.create", Some(user.id.to_string()), None, snapshot(&user))
+                .await;
+            (StatusCode::CREATED, Json(user)).into_response()
+        }
+        Err(e) => internal_error(e),
+    }
+}
+
+/// Changing the password or disabling the user ends their sessions
+pub async fn update_user(
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<i64>,
+    Json(payload): Json<UserPayload>,
+) -> Response {
+    if let Err(e) = payload.validate() {
+        return bad_request(e);
+    }
+    if let Err(e) = principal.may_grant(&payload.role.scopes(), payload.sim_ids.as_deref()) {
+        return forbidden(e);
+    }
+    let before = match User::query_by_id(id).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return not_found(id),
+        Err(e) => return internal_error(e),
+    };
+
+    let password_hash = match payload.password.as_deref().map(hash_password).transpose() {
+        Ok(hash) => hash,
+        Err(e) => return internal_error(e),
+    };
+    let user = match User::update(
+        id,
+        payload.username.trim(),
+        password_hash.as_deref(),
+        payload.role,
+        payload.sim_ids.as_deref(),
+        payload.disabled,
+    )
+    .await
+    {
+        Ok(Some(user)) => user,
+        Ok(None) => return not_found(id),
+        Err(e) => return internal_error(e),
+    };
+
+    if password_hash.is_some() || user.disabled {
+        if let Err(e) = User::delete_sessions(id).await {
+            return internal_error(e);
+        }
+    }
+    audit::record(
+        &principal,
+        "user.update",
+        Some(id.to_string()),
+        snapshot(&before),
+        snapshot(&user),
+    )
+    .await;
+    (StatusCode::OK, Json(user)).into_response()
+}
+
+pub async fn delete_user(
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<i64>,
+) -> Response {
+    let before = match User::query_by_id(id).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return not_found(id),
+        Err(e) => return internal_error(e),
+    };
+    if let Err(e) = User::delete_sessions(id).await {
+        return internal_error(e);
+    }
+    match User::delete_by_id(id).await {
+        Ok(true) => {
+            audit::record(&principal, "user.delete", Some(id.to_string()), snapshot(&before), None)
+                .await;
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(false) => not_found(id),
+        Err(e) => internal_error(e),
+    }
+}
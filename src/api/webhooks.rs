@@ -0,0 +1,240 @@
+use axum::{
+    extract::{Path, State},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use chrono::NaiveDateTime;
+use log::error;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use serde_json::json;
+
+use super::audit::{self, snapshot};
+use super::auth::Principal;
+use crate::{config::WebhookConfig, db::ModemSMS, db::StoredWebhook, webhook::WebhookManager};
+
+#[derive(Deserialize, Debug)]
+pub struct WebhookPayload {
+    name: Option<String>,
+    #[serde(default = "default_enabled")]
+    enabled: bool,
+    config: serde_json::Value, // Same keys as [[settings.webhooks]] in config.toml
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+#[derive(Deserialize, Debug)]
+pub struct WebhookTestRequest {
+    config: Option<serde_json::Value>, // Required unless a stored webhook is tested
+    #[serde(default)]
+    message: SampleMessage,
+    #[serde(default)]
+    send: bool,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct SampleMessage {
+    contact: String,
+    message: String,
+    sim_id: String,
+    timestamp: Option<NaiveDateTime>,
+    send: bool,
+}
+
+impl Default for SampleMessage {
+    fn default() -> Self {
+        Self {
+            contact: "+10000000000".to_string(),
+            message: "This is a test message from SMS Gateway".to_string(),
+            sim_id: "test".to_string(),
+            timestamp: None,
+            send: false,
+        }
+    }
+}
+
+impl From<SampleMessage> for ModemSMS {
+    fn from(sample: SampleMessage) -> Self {
+        ModemSMS {
+            contact: sample.contact,
+            message: sample.message,
+            sim_id: sample.sim_id,
+            timestamp: sample
+                .timestamp
+                .unwrap_or_else(|| chrono::Local::now().naive_local()),
+            send: sample.send,
+            segments: 1,
+            ..Default::default()
+        }
+    }
+}
+
+fn bad_request(message: String) -> Response {
+    (StatusCode::BAD_REQUEST, Json(json!({ "error": message }))).into_response()
+}
+
+fn not_found(id: i64) -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({ "error": format!("Webhook {} not found", id) })),
+    )
+        .into_response()
+}
+
+fn internal_error(e: anyhow::Error) -> Response {
+    error!("{}", e);
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(json!({ "error": format!("Database error: {}", e) })),
+    )
+        .into_response()
+}
+
+fn validate(config: &serde_json::Value) -> Result<WebhookConfig, String> {
+    WebhookConfig::from_json(config).map_err(|e| format!("Invalid webhook: {}", e))
+}
+
+pub async fn list_webhooks() -> Response {
+    match StoredWebhook::query_all().await {
+        Ok(webhooks) => (StatusCode::OK, Json(webhooks)).into_response(),
+        Err(e) => internal_error(e),
+    }
+}
+
+pub async fn get_webhook(Path(id): Path<i64>) -> Response {
+    match StoredWebhook::query_by_id(id).await {
+        Ok(Some(webhook)) => (StatusCode::OK, Json(webhook)).into_response(),
+        Ok(None) => not_found(id),
+        Err(e) => internal_error(e),
+    }
+}
+
+pub async fn create_webhook(
+    State(webhook_manager): State<WebhookManager>,
+    Extension(principal): Extension<Principal>,
+    Json(payload): Json<WebhookPayload>,
+) -> Response {
+    let config = match validate(&payload.config) {
+        Ok(config) => config,
+        Err(e) => return bad_request(e),
+    };
+
+    match StoredWebhook::insert(payload.name.as_deref(), payload.enabled, &payload.config).await {
+        Ok(webhook) => {
+            if webhook.enabled {
+                webhook_manager.upsert_stored(webhook.id, config);
+            }
+            let target = Some(webhook.id.to_string());
+            audit::record(&principal, "webhook.create", target, None, snapshot(&webhook)).await;
+            (StatusCode::CREATED, Json(webhook)).into_response()
+        }
+        Err(e) => internal_error(e),
+    }
+}
+
+pub async fn update_webhook(
+    Path(id): Path<i64>,
+    State(webhook_manager): State<WebhookManager>,
+    Extension(principal): Extension<Principal>,
+    Json(payload): Json<WebhookPayload>,
+) -> Response {
+    let config = match validate(&payload.config) {
+        Ok(config) => config,
+        Err(e) => return bad_request(e),
+    };
+    let before = match StoredWebhook::query_by_id(id).await {
+        Ok(Some(webhook)) => webhook,
+        Ok(None) => return not_found(id),
+        Err(e) => return internal_error(e),
+    };
+
+    match StoredWebhook::update(id, payload.name.as_deref(), payload.enabled, &payload.config).await {
+        Ok(Some(webhook)) => {
+            if webhook.enabled {
+                webhook_manager.upsert_stored(id, config);
+            } else {
+                webhook_manager.remove_stored(id);
+            }
+            audit::record(
+                &principal,
+                "webhook.update",
+                Some(id.to_string()),
+                snapshot(&before),
+                snapshot(&webhook),
+            )
+            .await;
+            (StatusCode::OK, Json(webhook)).into_response()
+        }
+        Ok(None) => not_found(id),
+        Err(e) => internal_error(e),
+    }
+}
+
+pub async fn delete_webhook(
+    Path(id): Path<i64>,
+    State(webhook_manager): State<WebhookManager>,
+    Extension(principal): Extension<Principal>,
+) -> Response {
+    let before = match StoredWebhook::query_by_id(id).await {
+        Ok(Some(webhook)) => webhook,
+        Ok(None) => return not_found(id),
+        Err(e) => return internal_error(e),
+    };
+    match StoredWebhook::delete_by_id(id).await {
+        Ok(true) => {
+            webhook_manager.remove_stored(id);
+            audit::record(&principal, "webhook.delete", Some(id.to_string()), snapshot(&before), None)
+                .await;
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(false) => not_found(id),
+        Err(e) => internal_error(e),
+    }
+}
+
+/// Renders a webhook definition for a sample message without saving it
+pub async fn test_webhook(
+    State(webhook_manager): State<WebhookManager>,
+    Json(request): Json<WebhookTestRequest>,
+) -> Response {
+    let Some(config) = request.config else {
+        return bad_request("Missing webhook config".to_string());
+    };
+    let config = match validate(&config) {
+        Ok(config) => config,
+        Err(e) => return bad_request(e),
+    };
+
+    let result = webhook_manager
+        .test_webhook(&config, request.message.into(), request.send)
+        .await;
+    (StatusCode::OK, Json(result)).into_response()
+}
+
+pub async fn test_stored_webhook(
+    Path(id): Path<i64>,
+    State(webhook_manager): State<WebhookManager>,
+    Json(request): Json<WebhookTestRequest>,
+) -> Response {
+    let webhook = match StoredWebhook::query_by_id(id).await {
+        Ok(Some(webhook)) => webhook,
+        Ok(None) => return not_found(id),
+        Err(e) => return internal_error(e),
+    };
+    let config = match validate(&webhook.config) {
+        Ok(config) => config,
+        Err(e) => return bad_request(e),
+    };
+
+    let result = webhook_manager
+        .test_webhook(&config, request.message.into(), request.send)
+        .await;
+    (StatusCode::OK, Json(result)).into_response()
+}
+
+pub async fn get_webhook_status(State(webhook_manager): State<WebhookManager>) -> Response {
+    (StatusCode::OK, Json(webhook_manager.endpoint_statuses())).into_response()
+}
@@ -0,0 +1,480 @@
+use std::{collections::HashSet, future::Future};
+
+use anyhow::{anyhow, Result};
+use chrono::{TimeDelta, Utc};
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::{
+    config::{parse_template_segments, FilterSet, TemplateSegment, WebhookConfig},
+    db::{AutoReplyEvent, Contact, ModemSMS, SimCard, StoredAutoReply, Suppression},
+    modem::core::Modem,
+    optout,
+    phone::{default_country, is_phone_number, normalize, to_e164, Country},
+    webhook::{
+        apply_template_segments, delivery::DeliveryOutcome, filter, TemplateContext, WebhookManager,
+    },
+};
+
+/// Automatic messages one number gets per hour at most, from all rules together
+pub const MAX_MESSAGES_PER_HOUR: i64 = 5;
+const DEFAULT_FORWARD_TEMPLATE: &str = "@contact@: @message@";
+
+/// What a rule matches and does; stored as the rule's `config`
+#[derive(Debug, Clone, Deserialize)]
+pub struct RuleConfig {
+    #[serde(default)]
+    pub keywords: Vec<String>, // Leading words of the message, any case; empty matches any message
+    #[serde(flatten)]
+    pub filters: FilterSet, // The webhook filters: contact_filter, sim_filter, message_filter, ...
+    pub actions: Vec<Action>,
+    #[serde(default = "default_cooldown_secs")]
+    pub cooldown_secs: u64, // Per sender; a rule cooling down still stops later rules
+    #[serde(default = "default_stop")]
+    pub stop: bool, // Skip the rules after this one when it matches
+}
+
+fn default_cooldown_secs() -> u64 {
+    60
+}
+
+fn default_stop() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Action {
+    /// Answers the sender from the SIM the message arrived on
+    Reply {
+        #[serde(deserialize_with = "template")]
+        template: Vec<TemplateSegment>,
+    },
+    /// Sends the message on to another number from the same SIM
+    Forward {
+        to: String,
+        #[serde(default = "default_forward_template", deserialize_with = "template")]
+        template: Vec<TemplateSegment>,
+    },
+    /// Tags the sender's contact
+    Tag { tag: String },
+    /// Delivers the message to a webhook once, with the keys of [[settings.webhooks]]
+    Webhook(Box<WebhookConfig>),
+}
+
+impl Action {
+    fn name(&self) -> &'static str {
+        match self {
+            Action::Reply { .. } => "reply",
+            Action::Forward { .. } => "forward",
+            Action::Tag { .. } => "tag",
+            Action::Webhook(_) => "webhook",
+        }
+    }
+}
+
+fn template<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<TemplateSegment>, D::Error> {
+    let template = String::deserialize(deserializer)?;
+    parse_template_segments(&template).map_err(serde::de::Error::custom)
+}
+
+fn default_forward_template() -> Vec<TemplateSegment> {
+    parse_template_segments(DEFAULT_FORWARD_TEMPLATE).expect("default forward template is valid")
+}
+
+impl RuleConfig {
+    /// Parses and checks a rule stored as JSON
+    pub fn from_json(value: &serde_json::Value) -> Result<Self, String> {
+        let config = RuleConfig::deserialize(value).map_err(|e| e.to_string())?;
+        if config.actions.is_empty() {
+            return Err("A rule needs at least one action".to_string());
+        }
+        if config
+            .keywords
+            .iter()
+            .any(|keyword| words(keyword).is_empty())
+        {
+            return Err("Keywords must contain a word".to_string());
+        }
+        for action in &config.actions {
+            match action {
+                Action::Forward { to, .. } if !is_phone_number(&normalize(to)) => {
+                    return Err(format!("Cannot forward to {}: not a phone number", to));
+                }
+                Action::Tag { tag } if tag.trim().is_empty() => {
+                    return Err("Tags must not be empty".to_string());
+                }
+                _ => {}
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// Sends automatic messages; the modem the triggering messages arrived on
+pub trait AutoSender: Sync {
+    /// Returns the id of the sent message
+    fn send(&self, contact: &Contact, message: &str) -> impl Future<Output = Result<i64>> + Send;
+
+    /// Sends an opt-out or opt-in confirmation, past the suppression list
+    fn confirm(&self, number: &str, message: &str) -> impl Future<Output = Result<()>> + Send;
+}
+
+impl AutoSender for Modem {
+    async fn send(&self, contact: &Contact, message: &str) -> Result<i64> {
+        let (sms_id, _) = self.send_sms_pdu(contact, message, None).await?;
+        Ok(sms_id)
+    }
+
+    async fn confirm(&self, number: &str, message: &str) -> Result<()> {
+        let mut contact = Contact {
+            name: number.to_string(),
+            ..Default::default()
+        };
+        contact.find_or_create(self.country().await).await?;
+        self.send_sms_pdu_unchecked(&contact, message, None).await?;
+        Ok(())
+    }
+}
+
+pub struct Rule {
+    pub id: i64,
+    pub name: Option<String>,
+    pub config: RuleConfig,
+}
+
+impl Rule {
+    async fn matches(&self, ctx: &TemplateContext) -> bool {
+        keyword_matches(&self.config.keywords, &ctx.msg().message)
+            && filter::passes(&self.config.filters, ctx).await
+    }
+
+    async fn cooling_down(&self, contact: &str) -> Result<bool> {
+        let cooldown = TimeDelta::seconds(self.config.cooldown_secs as i64);
+        let last = AutoReplyEvent::last_for(self.id, contact).await?;
+        Ok(last.is_some_and(|last| Utc::now() - last < cooldown))
+    }
+}
+
+/// Enabled rules in the order they are evaluated; invalid ones are logged and left out
+pub async fn load_rules() -> Result<Vec<Rule>> {
+    let mut rules = Vec::new();
+    for stored in StoredAutoReply::query_all().await? {
+        if !stored.enabled {
+            continue;
+        }
+        match RuleConfig::from_json(&stored.config) {
+            Ok(config) => rules.push(Rule {
+                id: stored.id,
+                name: stored.name,
+                config,
+            }),
+            Err(e) => error!(
+                "Auto-reply rule {} is invalid and was skipped: {}",
+                stored.id, e
+            ),
+        }
+    }
+    Ok(rules)
+}
+
+/// True when the message starts with one of `keywords`, ignoring case and punctuation
+pub fn keyword_matches(keywords: &[String], message: &str) -> bool {
+    if keywords.is_empty() {
+        return true;
+    }
+    let message = words(message);
+    keywords.iter().any(|keyword| {
+        let keyword = words(keyword);
+        !keyword.is_empty() && message.starts_with(&keyword)
+    })
+}
+
+/// Lowercase words of `text` without surrounding punctuation
+pub fn words(text: &str) -> Vec<String> {
+    text.split_whitespace()
+        .map(|word| {
+            word.trim_matches(|c: char| !c.is_alphanumeric())
+                .to_lowercase()
+        })
+        .filter(|word| !word.is_empty())
+        .collect()
+}
+
+/// An action a rule takes, or would take, for one message
+#[derive(Debug, Serialize)]
+pub struct PlannedAction {
+    #[serde(rename = "type")]
+    pub action: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub suppressed: Option<String>, // Why the message is not sent
+}
+
+#[derive(Debug, Serialize)]
+pub struct RuleMatch {
+    pub rule_id: i64,
+    pub name: Option<String>,
+    pub cooling_down: bool,
+    pub actions: Vec<PlannedAction>,
+}
+
+/// Normalised numbers of the gateway's own SIMs, which are never sent automatic messages
+async fn own_numbers() -> Result<HashSet<String>> {
+    let sims = SimCard::query_all().await?;
+    Ok(sims
+        .iter()
+        .filter_map(|sim| sim.phone_number.as_deref())
+        .map(normalize)
+        .collect())
+}
+
+async fn sim_country(sim_id: &str) -> Option<&'static Country> {
+    SimCard::country(sim_id).await.unwrap_or_else(|e| {
+        error!("Failed to look up the country of SIM {}: {}", sim_id, e);
+        default_country()
+    })
+}
+
+/// Why no automatic message may go to `number`. The hourly cap keeps two automatic senders from
+/// answering each other forever.
+async fn loop_guard(number: &str, own_numbers: &HashSet<String>) -> Result<Option<String>> {
+    if !is_phone_number(number) {
+        return Ok(Some("Not a phone number".to_string()));
+    }
+    if own_numbers.contains(&normalize(number)) {
+        return Ok(Some("Number of one of the gateway's SIMs".to_string()));
+    }
+    if Suppression::contains(number).await? {
+        return Ok(Some("Number opted out".to_string()));
+    }
+    let sent = AutoReplyEvent::count_sent_to(number, Utc::now() - TimeDelta::hours(1)).await?;
+    if sent >= MAX_MESSAGES_PER_HOUR {
+        return Ok(Some(format!(
+            "{} automatic messages in the last hour",
+            sent
+        )));
+    }
+    Ok(None)
+}
+
+async fn plan<'a>(
+    rule: &'a Rule,
+    ctx: &TemplateContext,
+    own_numbers: &HashSet<String>,
+) -> Result<Vec<(&'a Action, PlannedAction)>> {
+    let msg = ctx.msg();
+    let mut planned = Vec::new();
+    for action in &rule.config.actions {
+        let (to, template) = match action {
+            Action::Reply { template } => (Some(msg.contact.clone()), Some(template)),
+            Action::Forward { to, template } => (
+                Some(to_e164(to, sim_country(&msg.sim_id).await)),
+                Some(template),
+            ),
+            Action::Tag { .. } | Action::Webhook(_) => (None, None),
+        };
+        let text = match template {
+            Some(template) => Some(apply_template_segments(template, ctx).await),
+            None => None,
+        };
+        let suppressed = match &to {
+            Some(to) => loop_guard(to, own_numbers).await?,
+            None => None,
+        };
+        planned.push((
+            action,
+            PlannedAction {
+                action: action.name(),
+                to,
+                text,
+                suppressed,
+            },
+        ));
+    }
+    Ok(planned)
+}
+
+/// What the enabled rules would do with `msg`, without doing it
+pub async fn dry_run(mut msg: ModemSMS) -> Result<Vec<RuleMatch>> {
+    msg.contact = to_e164(&msg.contact, sim_country(&msg.sim_id).await);
+    if optout::keyword(&msg.message).is_some() {
+        return Ok(Vec::new());
+    }
+    let rules = load_rules().await?;
+    let own_numbers = own_numbers().await?;
+    let ctx = TemplateContext::new(msg, None);
+
+    let mut matches = Vec::new();
+    for rule in &rules {
+        if !rule.matches(&ctx).await {
+            continue;
+        }
+        let cooling_down = rule.cooling_down(&ctx.msg().contact).await?;
+        let actions = match cooling_down {
+            true => Vec::new(),
+            false => plan(rule, &ctx, &own_numbers)
+                .await?
+                .into_iter()
+                .map(|(_, planned)| planned)
+                .collect(),
+        };
+        matches.push(RuleMatch {
+            rule_id: rule.id,
+            name: rule.name.clone(),
+            cooling_down,
+            actions,
+        });
+        if rule.config.stop {
+            break;
+        }
+    }
+    Ok(matches)
+}
+
+/// Runs the rules on new incoming messages; automatic messages are sent by `sender`
+pub async fn handle<S: AutoSender>(
+    sender: &S,
+    messages: &[ModemSMS],
+    webhook_manager: Option<&WebhookManager>,
+) {
+    if messages.is_empty() {
+        return;
+    }
+    if let Err(e) = try_handle(sender, messages, webhook_manager).await {
+        error!("Failed to run auto-reply rules: {:#}", e);
+    }
+}
+
+async fn try_handle<S: AutoSender>(
+    sender: &S,
+    messages: &[ModemSMS],
+    webhook_manager: Option<&WebhookManager>,
+) -> Result<()> {
+    let rules = load_rules().await?;
+    if rules.is_empty() {
+        return Ok(());
+    }
+    let own_numbers = own_numbers().await?;
+
+    // Opt-out and opt-in keywords get their confirmation instead
+    let incoming = messages
+        .iter()
+        .filter(|msg| !msg.send && optout::keyword(&msg.message).is_none());
+    for msg in incoming {
+        let ctx = TemplateContext::new(msg.clone(), None);
+        for rule in &rules {
+            if !rule.matches(&ctx).await {
+                continue;
+            }
+            // A failure only skips this rule for this message; the others still run
+            match rule.cooling_down(&msg.contact).await {
+                Ok(true) => debug!(
+                    "Auto-reply rule {} is cooling down for {}",
+                    rule.id, msg.contact
+                ),
+                Ok(false) => {
+                    info!(
+                        "Auto-reply rule {} matched a message from {}",
+                        rule.id, msg.contact
+                    );
+                    // Planned per rule, so the hourly cap counts what earlier rules sent
+                    let planned = match plan(rule, &ctx, &own_numbers).await {
+                        Ok(planned) => planned,
+                        Err(e) => {
+                            error!("Failed to plan auto-reply rule {}: {:#}", rule.id, e);
+                            Vec::new()
+                        }
+                    };
+                    for (action, planned) in planned {
+                        let event = execute(sender, rule, msg, action, planned, webhook_manager).await;
+                        if let Err(e) = event.insert().await {
+                            error!("Failed to record auto-reply rule {}: {:#}", rule.id, e);
+                        }
+                    }
+                }
+                Err(e) => error!(
+                    "Failed to check the cooldown of auto-reply rule {}: {:#}",
+                    rule.id, e
+                ),
+            }
+            if rule.config.stop {
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn execute<S: AutoSender>(
+    sender: &S,
+    rule: &Rule,
+    msg: &ModemSMS,
+    action: &Action,
+    planned: PlannedAction,
+    webhook_manager: Option<&WebhookManager>,
+) -> AutoReplyEvent {
+    let mut event = AutoReplyEvent {
+        rule_id: rule.id,
+        contact: msg.contact.clone(),
+        sim_id: msg.sim_id.clone(),
+        action: planned.action,
+        ..Default::default()
+    };
+    if let Some(reason) = planned.suppressed {
+        warn!(
+            "Auto-reply rule {} did not {} to {:?}: {}",
+            rule.id, planned.action, planned.to, reason
+        );
+        event.error = Some(reason);
+        return event;
+    }
+
+    let country = sim_country(&msg.sim_id).await;
+    let result = match action {
+        Action::Reply { .. } | Action::Forward { .. } => {
+            let mut contact = Contact {
+                name: planned.to.unwrap_or_default(),
+                ..Default::default()
+            };
+            event.recipient = Some(contact.name.clone());
+            match contact.find_or_create(country).await {
+                Ok(()) => sender
+                    .send(&contact, planned.text.as_deref().unwrap_or_default())
+                    .await
+                    .map(|sms_id| event.sms_id = Some(sms_id)),
+                Err(e) => Err(e),
+            }
+        }
+        Action::Tag { tag } => {
+            let mut contact = Contact {
+                name: msg.contact.clone(),
+                ..Default::default()
+            };
+            match contact.find_or_create(country).await {
+                Ok(()) => Contact::add_tag(&contact.id, tag.trim()).await,
+                Err(e) => Err(e),
+            }
+        }
+        Action::Webhook(config) => match webhook_manager {
+            Some(webhook_manager) => {
+                match webhook_manager.deliver_once(config, msg.clone()).await {
+                    DeliveryOutcome::Failed => Err(anyhow!("Webhook delivery failed")),
+                    DeliveryOutcome::Delivered | DeliveryOutcome::Skipped => Ok(()),
+                }
+            }
+            None => Err(anyhow!("Webhooks are not running")),
+        },
+    };
+    if let Err(e) = result {
+        warn!(
+            "Auto-reply rule {} failed to {}: {:#}",
+            rule.id, planned.action, e
+        );
+        event.error = Some(e.to_string());
+    }
+    event
+}
@@ -0,0 +1,266 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    sync::LazyLock,
+    time::Duration,
+};
+
+use anyhow::Result;
+use log::{error, info, warn};
+use tokio::{sync::Mutex, time::Instant};
+
+use crate::{
+    config::{self, SegmentName, TemplateFilter, TemplateSegment},
+    db::{
+        Campaign, CampaignRecipient, CampaignStatus, Contact, ModemSMS, RecipientStatus, SimCard,
+        Suppression,
+    },
+    phone::default_country,
+    webhook::{self, TemplateContext},
+    ModemManagerRef,
+};
+
+/// Variables every recipient has; the recipient's own variables take precedence over `name`
+pub const BUILTIN_VARIABLES: &[&str] = &["name", "number"];
+pub const DEFAULT_PER_MINUTE: u32 = 10;
+pub const OPTED_OUT: &str = "Number opted out";
+
+/// Campaigns with a task sending their messages
+static ACTIVE: LazyLock<Mutex<HashSet<i64>>> = LazyLock::new(Default::default);
+
+/// Parses a campaign body with the webhook template syntax. Names that are not message
+/// fields, such as `@name@` or `@order@`, are the recipient's variables.
+pub fn parse_template(template: &str) -> Result<Vec<TemplateSegment>, String> {
+    config::parse_template_with_variables(template)
+}
+
+/// Variables a recipient must give: those used outside conditionals and without a default
+pub fn required_variables(segments: &[TemplateSegment]) -> Vec<&str> {
+    let mut variables = Vec::new();
+    for segment in segments {
+        let TemplateSegment::Placeholder(placeholder) = segment else {
+            continue;
+        };
+        let SegmentName::Variable(name) = &placeholder.name else {
+            continue;
+        };
+        let has_default = placeholder
+            .filters
+            .iter()
+            .any(|filter| matches!(filter, TemplateFilter::Default(_)));
+        if !has_default && !BUILTIN_VARIABLES.contains(&name.as_str()) && !variables.contains(&name.as_str()) {
+            variables.push(name.as_str());
+        }
+    }
+    variables
+}
+
+/// The message for one recipient; variables it lacks render empty
+pub async fn render(
+    segments: &[TemplateSegment],
+    sim_id: &str,
+    number: &str,
+    variables: BTreeMap<String, String>,
+) -> String {
+    let msg = ModemSMS {
+        contact: number.to_string(),
+        sim_id: sim_id.to_string(),
+        send: true,
+        ..Default::default()
+    };
+    let ctx = TemplateContext::new(msg, None).with_variables(variables);
+    webhook::apply_template_segments(segments, &ctx).await
+}
+
+/// Continues the campaigns that were running when the gateway stopped
+pub async fn resume_running(modem_manager: ModemManagerRef) -> Result<()> {
+    let interrupted = Campaign::fail_interrupted().await?;
+    if interrupted > 0 {
+        warn!(
+            "{} campaign messages were interrupted by a restart and marked failed",
+            interrupted
+        );
+    }
+    for id in Campaign::query_ids_by_status(CampaignStatus::Running).await? {
+        start(modem_manager.clone(), id).await;
+    }
+    Ok(())
+}
+
+/// Sends the campaign's pending messages in the background, unless that is already happening
+pub async fn start(modem_manager: ModemManagerRef, id: i64) {
+    if ACTIVE.lock().await.insert(id) {
+        tokio::spawn(run(modem_manager, id));
+    }
+}
+
+async fn run(modem_manager: ModemManagerRef, id: i64) {
+    loop {
+        if let Err(e) = send_pending(&modem_manager, id).await {
+            error!("Campaign {} stopped: {:#}", id, e);
+        }
+        // A campaign resumed while this task was finishing is picked up again here, as `start`
+        // sees it still active
+        let mut active = ACTIVE.lock().await;
+        let resumed = match Campaign::query_by_id(id).await {
+            Ok(Some(campaign)) if campaign.status == CampaignStatus::Running => {
+                Campaign::progress(id)
+                    .await
+                    .is_ok_and(|progress| progress.pending > 0)
+            }
+            _ => false,
+        };
+        if !resumed {
+            active.remove(&id);
+            return;
+        }
+    }
+}
+
+/// Sends from every connected SIM of the campaign until it is paused, cancelled or done
+async fn send_pending(modem_manager: &ModemManagerRef, id: i64) -> Result<()> {
+    let Some(campaign) = Campaign::query_by_id(id).await? else {
+        return Ok(());
+    };
+    if campaign.status != CampaignStatus::Running {
+        return Ok(());
+    }
+
+    let mut sims = Vec::new();
+    for sim_id in campaign.sim_ids.iter() {
+        if modem_manager.get_modem(sim_id).await.is_some() {
+            sims.push(sim_id.clone());
+        }
+    }
+    if sims.is_empty() {
+        let reason = "None of the campaign's SIMs is connected";
+        warn!("Pausing campaign {}: {}", id, reason);
+        Campaign::transition(
+            id,
+            &[CampaignStatus::Running],
+            CampaignStatus::Paused,
+            Some(reason),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    // Each SIM waits long enough for the campaign as a whole to keep to `per_minute`
+    let interval = Duration::from_secs_f64(60.0 * sims.len() as f64 / campaign.per_minute as f64);
+    let senders = sims
+        .iter()
+        .map(|sim_id| send_from_sim(modem_manager, &campaign, sim_id, interval));
+    for result in futures::future::join_all(senders).await {
+        result?;
+    }
+
+    if Campaign::complete_if_done(id).await? {
+        let progress = Campaign::progress(id).await?;
+        info!(
+            "Campaign {} ({}) completed: {} sent, {} failed, {} skipped",
+            id, campaign.name, progress.sent, progress.failed, progress.skipped
+        );
+    }
+    Ok(())
+}
+
+async fn send_from_sim(
+    modem_manager: &ModemManagerRef,
+    campaign: &Campaign,
+    sim_id: &str,
+    interval: Duration,
+) -> Result<()> {
+    loop {
+        // Checked before every message, so pausing and cancelling take effect within one
+        let status = Campaign::query_by_id(campaign.id).await?.map(|c| c.status);
+        if status != Some(CampaignStatus::Running) {
+            return Ok(());
+        }
+        if modem_manager.get_modem(sim_id).await.is_none() {
+            warn!("Campaign {} lost SIM {}", campaign.id, sim_id);
+            return Ok(());
+        }
+        let Some(recipient) = Campaign::claim_next(campaign.id, sim_id).await? else {
+            return Ok(());
+        };
+
+        let started = Instant::now();
+        match send_to(modem_manager, campaign, sim_id, &recipient).await {
+            Ok(Sent::Message { contact_id, sms_id }) => {
+                recipient
+                    .finish(RecipientStatus::Sent, Some(&contact_id), Some(sms_id), None)
+                    .await?;
+            }
+            Ok(Sent::Skipped { contact_id, reason }) => {
+                recipient
+                    .finish(RecipientStatus::Skipped, Some(&contact_id), None, Some(reason))
+                    .await?;
+                continue;
+            }
+            Err(e) => {
+                warn!(
+                    "Campaign {} failed to send to {}: {:#}",
+                    campaign.id, recipient.number, e
+                );
+                let error = e.to_string();
+                recipient
+                    .finish(RecipientStatus::Failed, None, None, Some(&error))
+                    .await?;
+            }
+        }
+        tokio::time::sleep_until(started + interval).await;
+    }
+}
+
+enum Sent {
+    Message { contact_id: String, sms_id: i64 },
+    Skipped {
+        contact_id: String,
+        reason: &'static str,
+    },
+}
+
+async fn send_to(
+    modem_manager: &ModemManagerRef,
+    campaign: &Campaign,
+    sim_id: &str,
+    recipient: &CampaignRecipient,
+) -> Result<Sent> {
+    let country = SimCard::country(sim_id).await.unwrap_or_else(|e| {
+        error!("Failed to look up the country of SIM {}: {}", sim_id, e);
+        default_country()
+    });
+    let mut contact = Contact {
+        name: recipient.number.clone(),
+        ..Default::default()
+    };
+    contact.find_or_create(country).await?;
+    let details = Contact::query_details_by_id(&contact.id).await?;
+    // Blocked or opted out after the campaign was created
+    if details.as_ref().is_some_and(|details| details.blocked) {
+        return Ok(Sent::Skipped {
+            contact_id: contact.id,
+            reason: "Contact is blocked",
+        });
+    }
+    if Suppression::contains(&contact.name).await? {
+        return Ok(Sent::Skipped {
+            contact_id: contact.id,
+            reason: OPTED_OUT,
+        });
+    }
+
+    let mut variables = recipient.variables.0.clone();
+    variables.insert("number".to_string(), contact.name.clone());
+    variables.entry("name".to_string()).or_insert_with(|| {
+        details
+            .and_then(|details| details.contact.display_name)
+            .unwrap_or_default()
+    });
+    let segments = parse_template(&campaign.template).map_err(anyhow::Error::msg)?;
+    let message = render(&segments, sim_id, &contact.name, variables).await;
+
+    let (sms_id, contact_id) = modem_manager
+        .send_sms(sim_id, &contact, &message, campaign.user_id)
+        .await?;
+    Ok(Sent::Message { contact_id, sms_id })
+}
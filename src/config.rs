@@ -1,9 +1,24 @@
 use anyhow::{Context, Result};
-use chrono::{NaiveTime, Weekday};
+use chrono::{Datelike, NaiveDate, NaiveTime, Weekday};
+use chrono_tz::Tz;
 use config::{Config, File};
 use fancy_regex::Regex;
+use ipnet::IpNet;
 use serde::Deserialize;
-use std::{collections::HashMap, fmt, path::Path, str::FromStr};
+use std::{
+    collections::HashMap,
+    fmt,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
+
+use crate::{
+    db::Scope,
+    phone::{to_e164, Country},
+    webhook::{channels::Channel, script::WebhookScript},
+};
 
 #[derive(Debug, Deserialize)]
 pub struct AppConfig {
@@ -19,18 +34,230 @@ pub struct Settings {
     pub password: Option<String>,
     pub read_sms_frequency: u64,
     pub webhooks_max_concurrent: Option<usize>,
+    pub audit_retention_days: Option<u32>, // Keep audit entries forever when unset
+    pub tls: Option<TlsConfig>,            // Serve HTTPS instead of HTTP when set
+    #[serde(default)]
+    pub security: SecurityConfig,
+    #[serde(default)]
+    pub retention: RetentionConfig,
+    #[serde(default)]
+    pub opt_out: OptOutConfig,
     pub webhooks: Option<Vec<WebhookConfig>>,
+    pub channels: Option<Vec<ChannelConfig>>,
     pub sms_storage: Option<SmsStorage>,
+    #[serde(default, deserialize_with = "country")]
+    pub default_country: Option<&'static Country>, // For national numbers on SIMs without a country
+}
+
+impl Settings {
+    /// Basic auth username and password, if both are set
+    pub fn credentials(&self) -> Option<(String, String)> {
+        self.username.clone().zip(self.password.clone())
+    }
+
+    /// Webhooks plus the built-in channels, all delivered by the webhook worker
+    pub fn webhook_targets(&self) -> Result<Vec<WebhookConfig>> {
+        let mut targets = self.webhooks.clone().unwrap_or_default();
+        for (index, channel) in self.channels.iter().flatten().enumerate() {
+            let target = WebhookConfig::try_from(channel)
+                .map_err(|e| anyhow::anyhow!("Fatal: Channel {} is invalid: {}", index, e))?;
+            targets.push(target);
+        }
+        Ok(targets)
+    }
+}
+
+/// HTTPS for the built-in web server; certificate files are reloaded when they change
+#[derive(Debug, Deserialize, Clone)]
+pub struct TlsConfig {
+    pub cert_file: PathBuf, // PEM certificate chain
+    pub key_file: PathBuf,  // PEM private key
+    pub client_ca_file: Option<PathBuf>, // Accept client certificates signed by these CAs
+    #[serde(default)]
+    pub client_auth: ClientAuth,
+    pub client_scopes: Option<Vec<Scope>>, // Scopes for certificate clients, all when unset
+    pub redirect_port: Option<u16>,        // Plain HTTP port that redirects to HTTPS
+    #[serde(default = "default_reload_interval")]
+    pub reload_interval: u64, // Seconds between checks for changed certificate files
+}
+
+fn default_reload_interval() -> u64 {
+    60
+}
+
+/// Login lockouts and the client address allow/deny lists
+#[derive(Debug, Deserialize, Clone)]
+pub struct SecurityConfig {
+    #[serde(default = "default_max_failed_logins")]
+    pub max_failed_logins: u32, // Failures before a client IP or username is locked out, 0 disables
+    #[serde(default = "default_failed_login_window")]
+    pub failed_login_window: u64, // Seconds in which the failures are counted
+    #[serde(default = "default_lockout_seconds")]
+    pub lockout_seconds: u64, // How long the lockout lasts
+    #[serde(default, deserialize_with = "ip_networks")]
+    pub allow: Vec<IpNet>, // When set, only these networks may connect
+    #[serde(default, deserialize_with = "ip_networks")]
+    pub deny: Vec<IpNet>, // Checked before `allow`
+}
+
+impl Default for SecurityConfig {
+    fn default() -> Self {
+        Self {
+            max_failed_logins: default_max_failed_logins(),
+            failed_login_window: default_failed_login_window(),
+            lockout_seconds: default_lockout_seconds(),
+            allow: Vec::new(),
+            deny: Vec::new(),
+        }
+    }
+}
+
+fn default_max_failed_logins() -> u32 {
+    5
+}
+
+fn default_failed_login_window() -> u64 {
+    900
+}
+
+fn default_lockout_seconds() -> u64 {
+    900
+}
+
+/// Accepts CIDR networks as well as single addresses
+fn ip_networks<'de, D>(deserializer: D) -> Result<Vec<IpNet>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|s| {
+            s.parse::<IpNet>()
+                .or_else(|_| s.parse::<IpAddr>().map(IpNet::from))
+                .map_err(|_| serde::de::Error::custom(format!("Invalid IP network: {}", s)))
+        })
+        .collect()
+}
+
+/// ISO 3166 country code, e.g. "CN"
+fn country<'de, D>(deserializer: D) -> Result<Option<&'static Country>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|code| Country::from_code(&code).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+/// Background cleanup of deleted messages and of messages matched by retention rules
+#[derive(Debug, Deserialize, Clone)]
+pub struct RetentionConfig {
+    #[serde(default = "default_undo_hours")]
+    pub undo_hours: u64, // How long deleted messages can be restored
+    #[serde(default = "default_retention_interval")]
+    pub interval: u64, // Seconds between runs
+    pub archive_dir: Option<PathBuf>, // Messages removed by rules are saved here as .ndjson.gz first
+    #[serde(default)]
+    pub rules: Vec<RetentionRule>,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            undo_hours: default_undo_hours(),
+            interval: default_retention_interval(),
+            archive_dir: None,
+            rules: Vec::new(),
+        }
+    }
+}
+
+impl RetentionConfig {
+    /// Writes the rules' contacts in E.164, as contact names are stored, national numbers in
+    /// `country`
+    pub fn normalize_contacts(&mut self, country: Option<&Country>) {
+        for contact in self.rules.iter_mut().filter_map(|rule| rule.contact.as_mut()) {
+            *contact = to_e164(contact, country);
+        }
+    }
+}
+
+fn default_undo_hours() -> u64 {
+    24
+}
+
+fn default_retention_interval() -> u64 {
+    3600
+}
+
+/// Keywords that put the sender on the suppression list or take them off it again
+#[derive(Debug, Deserialize, Clone)]
+pub struct OptOutConfig {
+    #[serde(default = "default_opt_out_keywords")]
+    pub opt_out_keywords: Vec<String>, // Whole message, any case; empty disables opting out by SMS
+    #[serde(default = "default_opt_in_keywords")]
+    pub opt_in_keywords: Vec<String>,
+    #[serde(default = "default_opt_out_reply")]
+    pub opt_out_reply: String, // Confirmation, not sent when empty
+    #[serde(default = "default_opt_in_reply")]
+    pub opt_in_reply: String,
+}
+
+impl Default for OptOutConfig {
+    fn default() -> Self {
+        Self {
+            opt_out_keywords: default_opt_out_keywords(),
+            opt_in_keywords: default_opt_in_keywords(),
+            opt_out_reply: default_opt_out_reply(),
+            opt_in_reply: default_opt_in_reply(),
+        }
+    }
+}
+
+fn default_opt_out_keywords() -> Vec<String> {
+    ["STOP", "STOPALL", "UNSUBSCRIBE", "CANCEL", "END", "QUIT"]
+        .map(String::from)
+        .to_vec()
+}
+
+fn default_opt_in_keywords() -> Vec<String> {
+    ["START", "UNSTOP", "SUBSCRIBE"].map(String::from).to_vec()
+}
+
+fn default_opt_out_reply() -> String {
+    "You have been unsubscribed and will receive no further messages. Reply START to resubscribe."
+        .to_string()
+}
+
+fn default_opt_in_reply() -> String {
+    "You have been resubscribed. Reply STOP to unsubscribe.".to_string()
+}
+
+/// Removes messages older than `max_age_days`, optionally only for one SIM or contact
+#[derive(Debug, Deserialize, Clone)]
+pub struct RetentionRule {
+    pub max_age_days: u32,
+    pub sim_id: Option<String>,
+    pub contact: Option<String>, // Phone number, in E.164 once the config is loaded
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientAuth {
+    #[default]
+    Optional, // Clients without a certificate fall back to the other login methods
+    Required, // Connections without a valid client certificate are refused
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Device {
+    pub name: Option<String>,
     pub com_port: String,
     pub baud_rate: u32,
     pub sms_storage: Option<SmsStorage>,
 }
 
-#[derive(Debug, Deserialize, Clone, Copy)]
+#[derive(Debug, Deserialize, serde::Serialize, Clone, Copy)]
 #[serde(rename_all = "UPPERCASE")]
 pub enum SmsStorage {
     SIM,  // Store on SIM card
@@ -102,20 +329,187 @@ pub struct WebhookConfig {
     pub body: Option<Vec<TemplateSegment>>,
     pub url_params: Option<HashMap<String, Vec<TemplateSegment>>>,
     pub timeout: Option<u64>,
+    pub script: Option<Arc<WebhookScript>>, // Optional Rhai transformer, runs after templating
+    pub channel: Option<Arc<Channel>>,      // Set for built-in notification channels
+    pub filters: FilterSet,
+    pub delivery: DeliveryOptions,
+}
+
+impl WebhookConfig {
+    /// Parses a webhook stored as JSON, using the same keys and validation as config.toml
+    pub fn from_json(value: &serde_json::Value) -> Result<Self, String> {
+        WebhookConfig::deserialize(value).map_err(|e| e.to_string())
+    }
+}
+
+/// Per-endpoint delivery controls shared by webhooks and notification channels
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct DeliveryOptions {
+    pub max_concurrent: Option<usize>, // Parallel requests to this endpoint, defaults to webhooks_max_concurrent
+    pub ordered: Option<bool>,         // Deliver one at a time in the order messages arrived
+    pub rate_limit: Option<u32>,       // Maximum requests per minute
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+}
 
-    // Filters
+impl DeliveryOptions {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_concurrent == Some(0) {
+            return Err("max_concurrent cannot be zero".to_string());
+        }
+        if self.ordered.unwrap_or(false) && self.max_concurrent.is_some_and(|n| n > 1) {
+            return Err("ordered delivery cannot be combined with max_concurrent > 1".to_string());
+        }
+        if self.rate_limit == Some(0) {
+            return Err("rate_limit cannot be zero".to_string());
+        }
+        if let Some(breaker) = &self.circuit_breaker {
+            if breaker.failure_threshold == 0 {
+                return Err("circuit_breaker.failure_threshold cannot be zero".to_string());
+            }
+            if breaker.open_seconds == 0 {
+                return Err("circuit_breaker.open_seconds cannot be zero".to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Pauses an endpoint after consecutive failures; deliveries queue up until it recovers
+#[derive(Debug, Clone, Deserialize)]
+pub struct CircuitBreakerConfig {
+    #[serde(default = "default_failure_threshold")]
+    pub failure_threshold: u32, // Consecutive failures before the endpoint is paused
+    #[serde(default = "default_open_seconds")]
+    pub open_seconds: u64, // How long to pause before sending a trial request
+}
+
+fn default_failure_threshold() -> u32 {
+    5
+}
+
+fn default_open_seconds() -> u64 {
+    60
+}
+
+/// Filters shared by webhooks and notification channels
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct FilterSet {
     pub contact_filter: Option<Vec<String>>, // List of contacts to include
     pub sim_filter: Option<Vec<String>>,     // List of SIM cards to include
     pub time_filter: Option<TimeFilter>,     // Time-based filtering
     pub message_filter: Option<MessageFilter>, // Content-based filtering
     pub include_self_sent: Option<bool>, // If true, include messages sent by the user in webhook
+    pub filter: Option<FilterExpr>,      // Boolean expression, checked after the filters above
+}
+
+/// A built-in notification channel, delivered through the webhook worker
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChannelConfig {
+    #[serde(flatten)]
+    pub kind: ChannelKind,
+    pub template: Option<String>, // Message text, defaults to sender, SIM, time and content
+    pub title: Option<String>,    // Email subject / push title, defaults to "SMS from @contact@"
+    pub timeout: Option<u64>,
+    #[serde(flatten)]
+    pub filters: FilterSet,
+    #[serde(flatten)]
+    pub delivery: DeliveryOptions,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ChannelKind {
+    Telegram {
+        bot_token: String,
+        #[serde(deserialize_with = "string_or_number")]
+        chat_id: String,
+        api_url: Option<String>, // Defaults to https://api.telegram.org
+    },
+    Slack {
+        webhook_url: String,
+    },
+    Discord {
+        webhook_url: String,
+        username: Option<String>,
+    },
+    Matrix {
+        homeserver: String,
+        access_token: String,
+        room_id: String,
+    },
+    Ntfy {
+        server: Option<String>, // Defaults to https://ntfy.sh
+        topic: String,
+        token: Option<String>,
+        priority: Option<u8>,
+    },
+    Gotify {
+        server: String,
+        token: String,
+        priority: Option<u8>,
+    },
+    Email(EmailChannelConfig),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct EmailChannelConfig {
+    pub smtp_host: String,
+    pub smtp_port: Option<u16>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub tls: Option<SmtpTls>, // Defaults to starttls
+    pub from: String,
+    pub to: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    None,
+    Starttls,
+    Tls,
+}
+
+fn string_or_number<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::String(s) => Ok(s),
+        serde_json::Value::Number(n) => Ok(n.to_string()),
+        other => Err(serde::de::Error::custom(format!("Expected string or number, got {}", other))),
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct TimeFilter {
-    pub start_time: Option<NaiveTime>,      // Format: HH:MM
-    pub end_time: Option<NaiveTime>,        // Format: HH:MM
-    pub days_of_week: Option<Vec<Weekday>>, // 0-6, where 0 is Sunday
+    pub windows: Vec<TimeWindow>,          // Matches when any window matches; empty means any time
+    pub timezone: Option<Tz>,              // Defaults to the gateway's local time
+    pub exclude_dates: Vec<DateExclusion>, // Holidays, never matched
+    pub basis: TimeBasis,
+}
+
+/// A daily window; when `start` is after `end` it wraps past midnight and
+/// `days` refers to the day the window opens
+#[derive(Debug, Clone)]
+pub struct TimeWindow {
+    pub range: Option<(NaiveTime, NaiveTime)>, // Format: HH:MM, inclusive
+    pub days: Option<Vec<Weekday>>,            // 0-6, where 0 is Sunday
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DateExclusion {
+    Date(NaiveDate),   // "2025-12-24"
+    Annual(u32, u32),  // "12-25", every year
+}
+
+/// Which time a filter looks at
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeBasis {
+    #[default]
+    Timestamp, // The SMSC timestamp from the PDU
+    Received,  // When the gateway read the message
 }
 
 #[derive(Debug, Clone)]
@@ -125,10 +519,68 @@ pub struct MessageFilter {
     pub regex: Option<Regex>,          // Regular expression pattern to match
 }
 
+/// Composable filter, e.g. `filter = { all = [{ direction = "incoming" }, { not = { contact = { prefix = ["+1800"] } } }] }`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterExpr {
+    All(#[serde(deserialize_with = "non_empty_exprs")] Vec<FilterExpr>),
+    Any(#[serde(deserialize_with = "non_empty_exprs")] Vec<FilterExpr>),
+    Not(Box<FilterExpr>),
+    Contact(ContactMatch),
+    Sim(SimMatch),
+    Message(MessageFilter),
+    Length(LengthRange),
+    Direction(Direction),
+    Time(TimeFilter),
+}
+
+#[derive(Debug, Clone)]
+pub struct ContactMatch {
+    pub equals: Vec<String>,  // Exact numbers
+    pub prefix: Vec<String>,  // Number prefixes, e.g. "+44"
+    pub regex: Option<Regex>, // Pattern matched against the number
+    pub exclude: Vec<String>, // Exact numbers that never match
+}
+
+/// Matches when any listed identifier matches; `exclude` accepts ICCIDs, IMSIs and aliases
+#[derive(Debug, Clone)]
+pub struct SimMatch {
+    pub iccid: Vec<String>,
+    pub imsi: Vec<String>,
+    pub alias: Vec<String>, // Effective alias, as in @sim@
+    pub exclude: Vec<String>,
+}
+
+/// Message length in characters
+#[derive(Debug, Clone)]
+pub struct LengthRange {
+    pub min: Option<usize>,
+    pub max: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Incoming,
+    Outgoing,
+}
+
+fn non_empty_exprs<'de, D>(deserializer: D) -> Result<Vec<FilterExpr>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let exprs = Vec::<FilterExpr>::deserialize(deserializer)?;
+    if exprs.is_empty() {
+        return Err(serde::de::Error::custom("all/any filter groups cannot be empty"));
+    }
+    Ok(exprs)
+}
+
 #[derive(Debug, Clone)]
 pub enum TemplateSegment {
     Fixed(String),
     Placeholder(Placeholder),
+    Conditional(Conditional),
 }
 
 #[derive(Debug, Clone)]
@@ -137,15 +589,296 @@ pub struct Placeholder {
     pub regex: Option<Regex>,
     pub regex_name: Option<String>,
     pub regex_index: Option<usize>,
+    pub filters: Vec<TemplateFilter>,
 }
 
+/// `@if:name@ ... @else@ ... @endif@` block
 #[derive(Debug, Clone)]
+pub struct Conditional {
+    pub name: SegmentName,
+    pub condition: Condition,
+    pub then: Vec<TemplateSegment>,
+    pub otherwise: Vec<TemplateSegment>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    Present,            // @if:name@
+    Absent,             // @if:!name@
+    Equals(String),     // @if:name==value@
+    NotEquals(String),  // @if:name!=value@
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TemplateFilter {
+    Json,
+    Url,
+    Xml,
+    Base64,
+    Upper,
+    Lower,
+    Truncate(usize),
+    Format(String), // strftime format, timestamp placeholders only
+    Default(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum SegmentName {
     Contact,
+    ContactName,
     Timestamp,
     Message,
+    MessageId,
+    Segments,
     Sim,
+    SimId,
+    SimAlias,
+    PhoneNumber,
+    Operator,
+    Modem,
     Send,
+    Variable(String), // Supplied by the caller, as campaigns do for their recipients
+}
+
+impl SegmentName {
+    pub fn is_timestamp(&self) -> bool {
+        matches!(self, SegmentName::Timestamp)
+    }
+}
+
+impl FromStr for SegmentName {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "contact" => Ok(SegmentName::Contact),
+            "contact_name" => Ok(SegmentName::ContactName),
+            "timestamp" => Ok(SegmentName::Timestamp),
+            "message" => Ok(SegmentName::Message),
+            "message_id" => Ok(SegmentName::MessageId),
+            "segments" => Ok(SegmentName::Segments),
+            "sim" => Ok(SegmentName::Sim),
+            "sim_id" => Ok(SegmentName::SimId),
+            "sim_alias" => Ok(SegmentName::SimAlias),
+            "phone_number" => Ok(SegmentName::PhoneNumber),
+            "operator" => Ok(SegmentName::Operator),
+            "modem" => Ok(SegmentName::Modem),
+            "send" => Ok(SegmentName::Send),
+            _ => Err(format!(
+                "Unknown segment name: '{}'. Valid names are: contact, contact_name, timestamp, message, message_id, segments, sim, sim_id, sim_alias, phone_number, operator, modem, send",
+                s
+            )),
+        }
+    }
+}
+
+impl TemplateFilter {
+    fn parse(s: &str, name: &SegmentName) -> Result<Self, String> {
+        let (filter, arg) = match s.split_once(':') {
+            Some((f, a)) => (f.trim(), Some(a)),
+            None => (s.trim(), None),
+        };
+
+        let filter = match (filter.to_lowercase().as_str(), arg) {
+            ("json", None) => TemplateFilter::Json,
+            ("url", None) => TemplateFilter::Url,
+            ("xml", None) => TemplateFilter::Xml,
+            ("base64", None) => TemplateFilter::Base64,
+            ("upper", None) => TemplateFilter::Upper,
+            ("lower", None) => TemplateFilter::Lower,
+            ("truncate", Some(n)) => TemplateFilter::Truncate(
+                n.trim()
+                    .parse()
+                    .map_err(|_| format!("Invalid truncate length: '{}'", n))?,
+            ),
+            ("format", Some(fmt)) => {
+                if !name.is_timestamp() {
+                    return Err(format!("Filter 'format' can only be applied to timestamps, not {:?}", name));
+                }
+                if chrono::format::StrftimeItems::new(fmt).any(|i| matches!(i, chrono::format::Item::Error)) {
+                    return Err(format!("Invalid timestamp format: '{}'", fmt));
+                }
+                TemplateFilter::Format(fmt.to_string())
+            }
+            ("default", Some(value)) => TemplateFilter::Default(value.to_string()),
+            _ => {
+                return Err(format!(
+                    "Unknown filter: '{}'. Valid filters are: json, url, xml, base64, upper, lower, truncate:N, format:FMT, default:VALUE",
+                    s
+                ))
+            }
+        };
+
+        Ok(filter)
+    }
+}
+
+/// Parse a webhook template such as `{"text":"@message|json@"}` into segments.
+///
+/// Placeholder syntax: `@name[|filter...][::regex[::group]]@`, filters are applied
+/// after regex extraction. Conditionals: `@if:name@`, `@if:!name@`,
+/// `@if:name==value@`, `@if:name!=value@`, closed by `@endif@` with optional `@else@`.
+pub fn parse_template_segments(s: &str) -> Result<Vec<TemplateSegment>, String> {
+    parse_segments(s, false)
+}
+
+/// Like [`parse_template_segments`], but names that are not message fields become
+/// [`SegmentName::Variable`]s, whose values are given when rendering
+pub fn parse_template_with_variables(s: &str) -> Result<Vec<TemplateSegment>, String> {
+    parse_segments(s, true)
+}
+
+fn parse_segments(s: &str, variables: bool) -> Result<Vec<TemplateSegment>, String> {
+    enum Token<'a> {
+        Fixed(&'a str),
+        Tag(&'a str),
+    }
+
+    let re = match Regex::new(r"\@(.*?)\@") {
+        Ok(re) => re,
+        Err(e) => return Err(format!("Invalid template syntax: {}", e)),
+    };
+
+    let mut tokens = Vec::new();
+    let mut last = 0;
+    for caps in re.captures_iter(s).flatten() {
+        let m = caps.get(0).unwrap();
+        if m.start() > last {
+            tokens.push(Token::Fixed(&s[last..m.start()]));
+        }
+        tokens.push(Token::Tag(caps.get(1).unwrap().as_str()));
+        last = m.end();
+    }
+    if last < s.len() {
+        tokens.push(Token::Fixed(&s[last..]));
+    }
+
+    // Returns the parsed segments and the closing tag (`else`/`endif`) that stopped it
+    fn parse_block<'a>(
+        tokens: &mut std::vec::IntoIter<Token<'a>>,
+        variables: bool,
+    ) -> Result<(Vec<TemplateSegment>, Option<&'a str>), String> {
+        let mut segments = Vec::new();
+
+        while let Some(token) = tokens.next() {
+            let inner = match token {
+                Token::Fixed(fixed) => {
+                    if !fixed.is_empty() {
+                        segments.push(TemplateSegment::Fixed(fixed.to_string()));
+                    }
+                    continue;
+                }
+                Token::Tag(inner) => inner,
+            };
+
+            match inner.trim() {
+                "else" | "endif" => return Ok((segments, Some(inner.trim()))),
+                _ => {}
+            }
+
+            if let Some(expr) = inner.trim().strip_prefix("if:") {
+                let (name, condition) = parse_condition(expr, variables)?;
+                let (then, closing) = parse_block(tokens, variables)?;
+                let otherwise = match closing {
+                    Some("else") => match parse_block(tokens, variables)? {
+                        (otherwise, Some("endif")) => otherwise,
+                        _ => return Err(format!("Missing @endif@ for @if:{}@", expr)),
+                    },
+                    Some("endif") => Vec::new(),
+                    _ => return Err(format!("Missing @endif@ for @if:{}@", expr)),
+                };
+                segments.push(TemplateSegment::Conditional(Conditional {
+                    name,
+                    condition,
+                    then,
+                    otherwise,
+                }));
+                continue;
+            }
+
+            segments.push(TemplateSegment::Placeholder(parse_placeholder(inner, variables)?));
+        }
+
+        Ok((segments, None))
+    }
+
+    fn segment_name(name: &str, variables: bool) -> Result<SegmentName, String> {
+        match SegmentName::from_str(name) {
+            Err(_) if variables => {
+                let name = name.trim();
+                if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                    return Err(format!("Invalid variable name: '{}'", name));
+                }
+                Ok(SegmentName::Variable(name.to_string()))
+            }
+            result => result,
+        }
+    }
+
+    fn parse_condition(expr: &str, variables: bool) -> Result<(SegmentName, Condition), String> {
+        let expr = expr.trim();
+        if let Some((name, value)) = expr.split_once("!=") {
+            Ok((segment_name(name, variables)?, Condition::NotEquals(value.trim().to_string())))
+        } else if let Some((name, value)) = expr.split_once("==") {
+            Ok((segment_name(name, variables)?, Condition::Equals(value.trim().to_string())))
+        } else if let Some(name) = expr.strip_prefix('!') {
+            Ok((segment_name(name, variables)?, Condition::Absent))
+        } else {
+            Ok((segment_name(expr, variables)?, Condition::Present))
+        }
+    }
+
+    fn parse_placeholder(inner: &str, variables: bool) -> Result<Placeholder, String> {
+        let parts: Vec<&str> = inner.split("::").collect();
+        let (name, regex, regex_name, regex_index) = match parts.len() {
+            1 => (parts[0], None, None, None),
+            2 => (parts[0], Some(parts[1].to_string()), None, None),
+            n if n >= 3 => {
+                let name = parts[0];
+                let regex = if n > 2 {
+                    Some(parts[1..n - 1].join("::"))
+                } else {
+                    None
+                };
+                let last = parts[n - 1];
+                if let Ok(idx) = last.parse::<usize>() {
+                    (name, regex, None, Some(idx))
+                } else {
+                    (name, regex, Some(last.to_string()), None)
+                }
+            }
+            _ => return Err(format!("Invalid template format in placeholder: {}", inner)),
+        };
+
+        let regex_obj = if let Some(r) = regex.as_ref() {
+            match Regex::new(r) {
+                Ok(re) => Some(re),
+                Err(e) => return Err(format!("Invalid regex in template '{}': {}", r, e)),
+            }
+        } else {
+            None
+        };
+
+        let mut name_parts = name.split('|');
+        let segment_name = segment_name(name_parts.next().unwrap_or_default(), variables)?;
+        let filters = name_parts
+            .map(|f| TemplateFilter::parse(f, &segment_name))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Placeholder {
+            name: segment_name,
+            regex: regex_obj,
+            regex_name,
+            regex_index,
+            filters,
+        })
+    }
+
+    let mut tokens = tokens.into_iter();
+    match parse_block(&mut tokens, variables)? {
+        (segments, None) => Ok(segments),
+        (_, Some(tag)) => Err(format!("Unexpected @{}@ without matching @if:...@", tag)),
+    }
 }
 
 impl AppConfig {
@@ -158,7 +891,9 @@ impl AppConfig {
             .context("Failed to load config file")?;
 
         // Deserialize the config file into the `AppConfig` struct
-        let app_config: AppConfig = config.try_deserialize()?;
+        let mut app_config: AppConfig = config.try_deserialize()?;
+        let default_country = app_config.settings.default_country;
+        app_config.settings.retention.normalize_contacts(default_country);
 
         // Validate the configuration
         test_config(&app_config)?;
@@ -177,6 +912,32 @@ fn test_config(app_config: &AppConfig) -> Result<()> {
         anyhow::bail!("Fatal: server_port is not set");
     }
 
+    if app_config.settings.username.is_some() != app_config.settings.password.is_some() {
+        anyhow::bail!("Fatal: username and password must be set together");
+    }
+
+    if let Some(tls) = &app_config.settings.tls {
+        if tls.client_auth == ClientAuth::Required && tls.client_ca_file.is_none() {
+            anyhow::bail!("Fatal: tls.client_auth = \"required\" needs tls.client_ca_file");
+        }
+        if tls.redirect_port == Some(app_config.settings.server_port) {
+            anyhow::bail!("Fatal: tls.redirect_port must differ from server_port");
+        }
+        if tls.reload_interval == 0 {
+            anyhow::bail!("Fatal: tls.reload_interval cannot be zero");
+        }
+    }
+
+    let retention = &app_config.settings.retention;
+    if retention.interval == 0 {
+        anyhow::bail!("Fatal: retention.interval cannot be zero");
+    }
+    if let Some(index) = retention.rules.iter().position(|rule| rule.max_age_days == 0) {
+        anyhow::bail!("Fatal: Retention rule {} max_age_days cannot be zero", index);
+    }
+
+    app_config.settings.webhook_targets()?;
+
     // Validate DEVICES section
     if app_config.devices.is_empty() {
         anyhow::bail!("Fatal: No devices configured");
@@ -194,12 +955,26 @@ fn test_config(app_config: &AppConfig) -> Result<()> {
     Ok(())
 }
 
+pub fn validate_url(url_str: &str) -> Result<(), String> {
+    if !(url_str.starts_with("http://") || url_str.starts_with("https://")) {
+        return Err(format!(
+            "URL must start with http:// or https:// (got: {})",
+            url_str
+        ));
+    }
+
+    if url_str.trim().is_empty() {
+        return Err("URL cannot be empty".to_string());
+    }
+
+    Ok(())
+}
+
 impl<'de> Deserialize<'de> for WebhookConfig {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: serde::Deserializer<'de>,
     {
-        use fancy_regex::Regex;
         use serde::{de::Error, Deserialize};
 
         #[derive(Debug, Clone, Deserialize)]
@@ -210,126 +985,12 @@ impl<'de> Deserialize<'de> for WebhookConfig {
             pub body: Option<String>,
             pub url_params: Option<HashMap<String, String>>,
             pub timeout: Option<u64>,
-            pub contact_filter: Option<Vec<String>>,
-            pub sim_filter: Option<Vec<String>>,
-            pub time_filter: Option<TimeFilter>,
-            pub message_filter: Option<MessageFilterDeserializer>,
-            pub include_self_sent: Option<bool>,
-        }
-
-        #[derive(Debug, Clone, Deserialize)]
-        struct MessageFilterDeserializer {
-            pub contains: Option<Vec<String>>,
-            pub not_contains: Option<Vec<String>>,
-            pub regex: Option<String>,
-        }
-
-        fn parse_template_segments(s: &str) -> Result<Vec<TemplateSegment>, String> {
-            let mut segments = Vec::new();
-            let re = match Regex::new(r"\@(.*?)\@") {
-                Ok(re) => re,
-                Err(e) => return Err(format!("Invalid template syntax: {}", e)),
-            };
-
-            let mut last = 0;
-            for caps in re.captures_iter(s).flatten() {
-                let m = caps.get(0).unwrap();
-                if m.start() > last {
-                    let fixed = &s[last..m.start()];
-                    if !fixed.is_empty() {
-                        segments.push(TemplateSegment::Fixed(fixed.to_string()));
-                    }
-                }
-                let inner = &caps[1];
-                let parts: Vec<&str> = inner.split("::").collect();
-                let (name, regex, regex_name, regex_index) = match parts.len() {
-                    1 => (parts[0], None, None, None),
-                    2 => (parts[0], Some(parts[1].to_string()), None, None),
-                    n if n >= 3 => {
-                        let name = parts[0];
-                        let regex = if n > 2 {
-                            Some(parts[1..n - 1].join("::"))
-                        } else {
-                            None
-                        };
-                        let last = parts[n - 1];
-                        if let Ok(idx) = last.parse::<usize>() {
-                            (name, regex, None, Some(idx))
-                        } else {
-                            (name, regex, Some(last.to_string()), None)
-                        }
-                    }
-                    _ => return Err(format!("Invalid template format in placeholder: {}", inner)),
-                };
-
-                let regex_obj = if let Some(r) = regex.as_ref() {
-                    match Regex::new(r) {
-                        Ok(re) => Some(re),
-                        Err(e) => return Err(format!("Invalid regex in template '{}': {}", r, e)),
-                    }
-                } else {
-                    None
-                };
-
-                let segment_name = parse_segment_name(name)?;
-
-                segments.push(TemplateSegment::Placeholder(Placeholder {
-                    name: segment_name,
-                    regex: regex_obj,
-                    regex_name,
-                    regex_index,
-                }));
-                last = m.end();
-            }
-
-            if last < s.len() {
-                let fixed = &s[last..];
-                if !fixed.is_empty() {
-                    segments.push(TemplateSegment::Fixed(fixed.to_string()));
-                }
-            }
-
-            Ok(segments)
-        }
-
-        fn parse_segment_name(s: &str) -> Result<SegmentName, String> {
-            match s.trim().to_lowercase().as_str() {
-                "contact" => Ok(SegmentName::Contact),
-                "timestamp" => Ok(SegmentName::Timestamp),
-                "message" => Ok(SegmentName::Message),
-                "sim" => Ok(SegmentName::Sim),
-                "send" => Ok(SegmentName::Send),
-                _ => Err(format!("Unknown segment name: '{}'. Valid names are: contact, timestamp, message, sim, send", s)),
-            }
-        }
-
-        fn validate_url(url_str: &str) -> Result<(), String> {
-            if !(url_str.starts_with("http://") || url_str.starts_with("https://")) {
-                return Err(format!(
-                    "URL must start with http:// or https:// (got: {})",
-                    url_str
-                ));
-            }
-
-            if url_str.trim().is_empty() {
-                return Err("URL cannot be empty".to_string());
-            }
-
-            Ok(())
-        }
-
-        fn validate_time_filter(filter: &Option<TimeFilter>) -> Result<(), String> {
-            if let Some(tf) = filter {
-                if let (Some(start), Some(end)) = (tf.start_time, tf.end_time) {
-                    if start > end {
-                        return Err(format!(
-                            "Invalid time range: start_time {} is after end_time {}",
-                            start, end
-                        ));
-                    }
-                }
-            }
-            Ok(())
+            pub script: Option<String>,
+            pub script_file: Option<String>,
+            #[serde(flatten)]
+            pub filters: FilterSet,
+            #[serde(flatten)]
+            pub delivery: DeliveryOptions,
         }
 
         let raw = WebhookConfigDeserializer::deserialize(deserializer)
@@ -385,38 +1046,31 @@ impl<'de> Deserialize<'de> for WebhookConfig {
             None => None,
         };
 
-        let message_filter = match raw.message_filter {
-            Some(mf) => {
-                let regex = match mf.regex {
-                    Some(r) => match Regex::new(&r) {
-                        Ok(re) => Some(re),
-                        Err(e) => {
-                            return Err(D::Error::custom(format!(
-                                "Invalid regex in message filter: {}",
-                                e
-                            )))
-                        }
-                    },
-                    None => None,
-                };
-
-                Some(MessageFilter {
-                    contains: mf.contains,
-                    not_contains: mf.not_contains,
-                    regex,
-                })
-            }
-            None => None,
-        };
-
-        validate_time_filter(&raw.time_filter).map_err(D::Error::custom)?;
-
         if let Some(timeout) = raw.timeout {
             if timeout == 0 {
                 return Err(D::Error::custom("Webhook timeout cannot be zero"));
             }
         }
 
+        let script_source = match (raw.script, raw.script_file) {
+            (Some(_), Some(_)) => {
+                return Err(D::Error::custom("Only one of script and script_file can be set"))
+            }
+            (Some(source), None) => Some(source),
+            (None, Some(path)) => Some(std::fs::read_to_string(&path).map_err(|e| {
+                D::Error::custom(format!("Failed to read webhook script '{}': {}", path, e))
+            })?),
+            (None, None) => None,
+        };
+        let script = match script_source {
+            Some(source) => Some(Arc::new(
+                WebhookScript::compile(&source).map_err(D::Error::custom)?,
+            )),
+            None => None,
+        };
+
+        raw.delivery.validate().map_err(D::Error::custom)?;
+
         Ok(WebhookConfig {
             url,
             method,
@@ -424,11 +1078,165 @@ impl<'de> Deserialize<'de> for WebhookConfig {
             body,
             url_params,
             timeout: raw.timeout,
-            contact_filter: raw.contact_filter,
-            sim_filter: raw.sim_filter,
-            time_filter: raw.time_filter,
-            message_filter,
-            include_self_sent: raw.include_self_sent,
+            script,
+            channel: None,
+            filters: raw.filters,
+            delivery: raw.delivery,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for MessageFilter {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        use serde::{de::Error, Deserialize};
+
+        #[derive(Deserialize)]
+        struct MessageFilterHelper {
+            pub contains: Option<Vec<String>>,
+            pub not_contains: Option<Vec<String>>,
+            pub regex: Option<String>,
+        }
+
+        let helper = MessageFilterHelper::deserialize(deserializer)?;
+
+        let regex = match helper.regex {
+            Some(r) => Some(
+                Regex::new(&r)
+                    .map_err(|e| D::Error::custom(format!("Invalid regex in message filter: {}", e)))?,
+            ),
+            None => None,
+        };
+
+        Ok(MessageFilter {
+            contains: helper.contains,
+            not_contains: helper.not_contains,
+            regex,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for ContactMatch {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        use serde::{de::Error, Deserialize};
+
+        #[derive(Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct ContactMatchHelper {
+            #[serde(default)]
+            pub equals: Vec<String>,
+            #[serde(default)]
+            pub prefix: Vec<String>,
+            pub regex: Option<String>,
+            #[serde(default)]
+            pub exclude: Vec<String>,
+        }
+
+        let helper = ContactMatchHelper::deserialize(deserializer)?;
+
+        if helper.equals.is_empty()
+            && helper.prefix.is_empty()
+            && helper.regex.is_none()
+            && helper.exclude.is_empty()
+        {
+            return Err(D::Error::custom(
+                "Contact filter needs at least one of equals, prefix, regex or exclude",
+            ));
+        }
+
+        let regex = match helper.regex {
+            Some(r) => Some(
+                Regex::new(&r)
+                    .map_err(|e| D::Error::custom(format!("Invalid regex in contact filter: {}", e)))?,
+            ),
+            None => None,
+        };
+
+        Ok(ContactMatch {
+            equals: helper.equals,
+            prefix: helper.prefix,
+            regex,
+            exclude: helper.exclude,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for SimMatch {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        use serde::{de::Error, Deserialize};
+
+        #[derive(Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct SimMatchHelper {
+            #[serde(default)]
+            pub iccid: Vec<String>,
+            #[serde(default)]
+            pub imsi: Vec<String>,
+            #[serde(default)]
+            pub alias: Vec<String>,
+            #[serde(default)]
+            pub exclude: Vec<String>,
+        }
+
+        let helper = SimMatchHelper::deserialize(deserializer)?;
+
+        if helper.iccid.is_empty()
+            && helper.imsi.is_empty()
+            && helper.alias.is_empty()
+            && helper.exclude.is_empty()
+        {
+            return Err(D::Error::custom(
+                "SIM filter needs at least one of iccid, imsi, alias or exclude",
+            ));
+        }
+
+        Ok(SimMatch {
+            iccid: helper.iccid,
+            imsi: helper.imsi,
+            alias: helper.alias,
+            exclude: helper.exclude,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for LengthRange {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        use serde::{de::Error, Deserialize};
+
+        #[derive(Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct LengthRangeHelper {
+            pub min: Option<usize>,
+            pub max: Option<usize>,
+        }
+
+        let helper = LengthRangeHelper::deserialize(deserializer)?;
+
+        match (helper.min, helper.max) {
+            (None, None) => return Err(D::Error::custom("Length filter needs min or max")),
+            (Some(min), Some(max)) if min > max => {
+                return Err(D::Error::custom(format!(
+                    "Invalid length range: min {} is greater than max {}",
+                    min, max
+                )))
+            }
+            _ => {}
+        }
+
+        Ok(LengthRange {
+            min: helper.min,
+            max: helper.max,
         })
     }
 }
@@ -440,88 +1248,144 @@ impl<'de> Deserialize<'de> for TimeFilter {
     {
         use serde::{de::Error, Deserialize};
 
+        #[derive(Deserialize)]
+        struct TimeWindowHelper {
+            pub start: Option<String>,
+            pub end: Option<String>,
+            pub days: Option<Vec<serde_json::Value>>,
+        }
+
         #[derive(Deserialize)]
         struct TimeFilterHelper {
             pub start_time: Option<String>,
             pub end_time: Option<String>,
             pub days_of_week: Option<Vec<serde_json::Value>>,
+            pub windows: Option<Vec<TimeWindowHelper>>,
+            pub timezone: Option<String>,
+            pub exclude_dates: Option<Vec<String>>,
+            pub basis: Option<TimeBasis>,
         }
 
         let helper = TimeFilterHelper::deserialize(deserializer)?;
 
-        let start_time = match helper.start_time {
-            Some(s) => Some(
-                NaiveTime::parse_from_str(&s, "%H:%M")
-                    .map_err(|e| D::Error::custom(format!("Invalid start_time format: {}", e)))?,
-            ),
-            None => None,
+        let legacy = helper.start_time.is_some()
+            || helper.end_time.is_some()
+            || helper.days_of_week.is_some();
+
+        let windows = match helper.windows {
+            Some(_) if legacy => {
+                return Err(D::Error::custom(
+                    "Use either windows or start_time/end_time/days_of_week in a time filter",
+                ))
+            }
+            Some(windows) => windows
+                .into_iter()
+                .map(|w| match (&w.start, &w.end) {
+                    (Some(_), None) | (None, Some(_)) => {
+                        Err("A time window needs both start and end".to_string())
+                    }
+                    _ => parse_time_window(w.start, w.end, w.days),
+                })
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(D::Error::custom)?,
+            None if legacy => {
+                // A lone start_time or end_time has always been ignored
+                let (start, end) = match (helper.start_time, helper.end_time) {
+                    (Some(start), Some(end)) => (Some(start), Some(end)),
+                    _ => (None, None),
+                };
+                vec![parse_time_window(start, end, helper.days_of_week).map_err(D::Error::custom)?]
+            }
+            None => Vec::new(),
         };
 
-        let end_time = match helper.end_time {
-            Some(s) => Some(
-                NaiveTime::parse_from_str(&s, "%H:%M")
-                    .map_err(|e| D::Error::custom(format!("Invalid end_time format: {}", e)))?,
+        let timezone = match helper.timezone {
+            Some(tz) => Some(
+                tz.parse::<Tz>()
+                    .map_err(|_| D::Error::custom(format!("Unknown timezone: {}", tz)))?,
             ),
             None => None,
         };
 
-        let days_of_week = match helper.days_of_week {
-            Some(days) => {
-                let mut weekdays = Vec::new();
-                for day in days {
-                    let weekday = match day {
-                        serde_json::Value::Number(n) => {
-                            let num = n
-                                .as_u64()
-                                .ok_or_else(|| D::Error::custom("Invalid weekday number"))?;
-                            match num {
-                                0 => Weekday::Sun,
-                                1 => Weekday::Mon,
-                                2 => Weekday::Tue,
-                                3 => Weekday::Wed,
-                                4 => Weekday::Thu,
-                                5 => Weekday::Fri,
-                                6 => Weekday::Sat,
-                                _ => {
-                                    return Err(D::Error::custom(format!(
-                                        "Invalid weekday number: {}, must be 0-6",
-                                        num
-                                    )))
-                                }
-                            }
-                        }
-                        serde_json::Value::String(s) => match s.to_lowercase().as_str() {
-                            "sunday" | "sun" => Weekday::Sun,
-                            "monday" | "mon" => Weekday::Mon,
-                            "tuesday" | "tue" => Weekday::Tue,
-                            "wednesday" | "wed" => Weekday::Wed,
-                            "thursday" | "thu" => Weekday::Thu,
-                            "friday" | "fri" => Weekday::Fri,
-                            "saturday" | "sat" => Weekday::Sat,
-                            _ => {
-                                return Err(D::Error::custom(format!(
-                                    "Invalid weekday string: {}",
-                                    s
-                                )))
-                            }
-                        },
-                        _ => {
-                            return Err(D::Error::custom(
-                                "Weekday must be a number (0-6) or string",
-                            ))
-                        }
-                    };
-                    weekdays.push(weekday);
-                }
-                Some(weekdays)
-            }
-            None => None,
-        };
+        let exclude_dates = helper
+            .exclude_dates
+            .unwrap_or_default()
+            .iter()
+            .map(|d| parse_date_exclusion(d))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(D::Error::custom)?;
 
         Ok(TimeFilter {
-            start_time,
-            end_time,
-            days_of_week,
+            windows,
+            timezone,
+            exclude_dates,
+            basis: helper.basis.unwrap_or_default(),
         })
     }
 }
+
+fn parse_time_window(
+    start: Option<String>,
+    end: Option<String>,
+    days: Option<Vec<serde_json::Value>>,
+) -> Result<TimeWindow, String> {
+    let parse_time = |s: &str, field: &str| {
+        NaiveTime::parse_from_str(s, "%H:%M")
+            .map_err(|e| format!("Invalid {} format: {}", field, e))
+    };
+
+    let range = match (start, end) {
+        (Some(start), Some(end)) => Some((parse_time(&start, "start_time")?, parse_time(&end, "end_time")?)),
+        _ => None,
+    };
+
+    let days = match days {
+        Some(days) => Some(days.iter().map(parse_weekday).collect::<Result<Vec<_>, _>>()?),
+        None => None,
+    };
+
+    Ok(TimeWindow { range, days })
+}
+
+fn parse_weekday(day: &serde_json::Value) -> Result<Weekday, String> {
+    match day {
+        serde_json::Value::Number(n) => {
+            let num = n.as_u64().ok_or_else(|| "Invalid weekday number".to_string())?;
+            match num {
+                0 => Ok(Weekday::Sun),
+                1 => Ok(Weekday::Mon),
+                2 => Ok(Weekday::Tue),
+                3 => Ok(Weekday::Wed),
+                4 => Ok(Weekday::Thu),
+                5 => Ok(Weekday::Fri),
+                6 => Ok(Weekday::Sat),
+                _ => Err(format!("Invalid weekday number: {}, must be 0-6", num)),
+            }
+        }
+        serde_json::Value::String(s) => match s.to_lowercase().as_str() {
+            "sunday" | "sun" => Ok(Weekday::Sun),
+            "monday" | "mon" => Ok(Weekday::Mon),
+            "tuesday" | "tue" => Ok(Weekday::Tue),
+            "wednesday" | "wed" => Ok(Weekday::Wed),
+            "thursday" | "thu" => Ok(Weekday::Thu),
+            "friday" | "fri" => Ok(Weekday::Fri),
+            "saturday" | "sat" => Ok(Weekday::Sat),
+            _ => Err(format!("Invalid weekday string: {}", s)),
+        },
+        _ => Err("Weekday must be a number (0-6) or string".to_string()),
+    }
+}
+
+fn parse_date_exclusion(s: &str) -> Result<DateExclusion, String> {
+    if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        return Ok(DateExclusion::Date(date));
+    }
+    // Validate against a leap year so "02-29" is accepted
+    match NaiveDate::parse_from_str(&format!("2000-{}", s), "%Y-%m-%d") {
+        Ok(date) => Ok(DateExclusion::Annual(date.month(), date.day())),
+        Err(_) => Err(format!(
+            "Invalid excluded date '{}', expected YYYY-MM-DD or MM-DD",
+            s
+        )),
+    }
+}
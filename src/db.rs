@@ -1,15 +1,19 @@
 use anyhow::Result;
-use chrono::NaiveDateTime;
-use serde::{Deserialize, Serialize};
+use base64::prelude::*;
+use chrono::{DateTime, FixedOffset, Local, NaiveDateTime, TimeDelta, TimeZone, Timelike, Utc};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
 use sqlx::migrate::MigrateDatabase;
 use sqlx::sqlite::{SqlitePool, SqlitePoolOptions};
 use sqlx::Row;
 use sqlx::{migrate, Sqlite, Transaction};
 use sqlx::{FromRow, QueryBuilder};
-use std::collections::{HashMap, HashSet};
+use std::collections::{hash_map::Entry, BTreeMap, HashMap, HashSet};
 use std::sync::OnceLock;
 use uuid::Uuid;
 
+use crate::phone::{default_country, normalize, to_e164, Country};
+
 const MAX_BATCH_SIZE: usize = 500;
 
 static POOL: OnceLock<SqlitePool> = OnceLock::new();
@@ -19,11 +23,77 @@ static POOL: OnceLock<SqlitePool> = OnceLock::new();
 pub struct Sms {
     pub id: i64,
     pub contact_id: String,
-    pub timestamp: NaiveDateTime,
+    #[sqlx(flatten)]
+    pub timestamp: SmsTimestamp,
+    pub received_at: Option<DateTime<Utc>>, // When the gateway read an incoming message
     pub message: String,
     pub sim_id: String,
     pub send: bool,
     pub status: SmsStatus,
+    pub user_id: Option<i64>, // User who sent an outgoing message
+}
+
+/// Message time as stored: UTC plus the original offset
+#[derive(Debug, FromRow, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SmsTimestamp {
+    pub timestamp: NaiveDateTime,
+    pub utc_offset: Option<i32>, // Seconds east of UTC
+}
+
+impl SmsTimestamp {
+    /// Current time with the gateway's local offset
+    pub fn now() -> Self {
+        let now = Local::now().with_nanosecond(0).unwrap();
+        Self {
+            timestamp: now.naive_utc(),
+            utc_offset: Some(now.offset().local_minus_utc()),
+        }
+    }
+
+    /// A wall-clock time without an offset, taken as the gateway's local time
+    pub fn from_local(timestamp: NaiveDateTime) -> Self {
+        let offset = match Local.from_local_datetime(&timestamp).earliest() {
+            Some(dt) => dt.offset().local_minus_utc(),
+            // Skipped by a DST change; use the offset in effect just after it
+            None => Local.from_utc_datetime(&timestamp).offset().local_minus_utc(),
+        };
+        Self {
+            timestamp: timestamp - TimeDelta::seconds(offset.into()),
+            utc_offset: Some(offset),
+        }
+    }
+
+    /// The time in its original offset, if it is known
+    pub fn with_offset(&self) -> Option<DateTime<FixedOffset>> {
+        let offset = FixedOffset::east_opt(self.utc_offset?)?;
+        Some(offset.from_utc_datetime(&self.timestamp))
+    }
+}
+
+// RFC 3339 with the original offset
+impl Serialize for SmsTimestamp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.with_offset() {
+            Some(dt) => serializer.serialize_str(&dt.to_rfc3339()),
+            None => serializer.serialize_str(&self.timestamp.and_utc().to_rfc3339()),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for SmsTimestamp {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        if let Ok(dt) = DateTime::parse_from_rfc3339(&value) {
+            return Ok(Self {
+                timestamp: dt.naive_utc(),
+                utc_offset: Some(dt.offset().local_minus_utc()),
+            });
+        }
+        let timestamp = value
+            .parse::<NaiveDateTime>()
+            .map_err(|e| serde::de::Error::custom(format!("Invalid timestamp '{}': {}", value, e)))?;
+        Ok(Self::from_local(timestamp))
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, sqlx::Type, Default)]
@@ -54,25 +124,83 @@ impl From<SmsStatus> for i32 {
     }
 }
 
-#[derive(Debug,Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct ModemSMS {
+    pub id: Option<i64>, // Row id, set once the message has been stored
     pub contact: String,
-    pub timestamp: NaiveDateTime,
+    pub timestamp: NaiveDateTime,            // SMSC local time from the PDU
+    pub utc_offset: Option<FixedOffset>,     // SMSC offset from the PDU, if it was valid
+    pub received_at: Option<DateTime<Utc>>, // When the gateway read the message
     pub message: String,
     pub send: bool,
     pub sim_id: String,
+    pub segments: u8, // Number of PDU parts the message arrived in
+    pub concat_ref: Option<u8>, // Reference shared by the parts of a multipart message
 }
 
 #[derive(Debug, FromRow, Deserialize, Serialize, Default, Clone)]
 pub struct Contact {
     pub id: String,
-    pub name: String,
+    pub name: String, // Primary number
+    #[sqlx(default)]
+    #[serde(default)]
+    pub display_name: Option<String>,
+}
+
+#[derive(Debug, FromRow, Deserialize, Serialize, Clone, PartialEq)]
+pub struct ContactNumber {
+    pub number: String,
+    pub label: Option<String>, // e.g. "mobile", "work"
+}
+
+/// A contact with everything the address book keeps about it
+#[derive(Debug, FromRow, Deserialize, Serialize, Default, Clone)]
+pub struct ContactDetails {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub contact: Contact,
+    pub notes: Option<String>,
+    pub blocked: bool, // Messages are stored as read and trigger no webhooks
+    #[sqlx(json)]
+    pub numbers: Vec<ContactNumber>, // Primary number first
+    #[sqlx(json)]
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, FromRow, Serialize)]
+pub struct TagCount {
+    pub tag: String,
+    pub contacts: i64,
+}
+
+/// Narrows the contact list; unset fields match everything
+#[derive(Debug, Default, Deserialize)]
+pub struct ContactFilter {
+    pub q: Option<String>, // Part of the display name or of a number
+    pub tag: Option<String>,
+    pub blocked: Option<bool>,
+}
+
+/// A contact as `Contact::normalize_numbers` will store it
+struct NormalizedContact {
+    id: String,
+    old_name: String,
+    name: String,
+    numbers: Vec<ContactNumber>,
 }
 
+const CONTACT_DETAILS_COLUMNS: &str = "id, name, display_name, notes, blocked, \
+    (SELECT json_group_array(json_object('number', n.number, 'label', n.label) \
+        ORDER BY n.number = contacts.name DESC, n.number) \
+     FROM contact_numbers n WHERE n.contact_id = contacts.id) AS numbers, \
+    (SELECT json_group_array(t.tag ORDER BY t.tag) \
+     FROM contact_tags t WHERE t.contact_id = contacts.id) AS tags";
+
 #[derive(Debug, FromRow, Deserialize, Serialize, Default, Clone)]
 pub struct SMSPreview {
     pub message: String,
-    pub timestamp: NaiveDateTime,
+    #[sqlx(flatten)]
+    pub timestamp: SmsTimestamp,
     pub status: SmsStatus,
     pub sim_id: String,
 }
@@ -86,6 +214,8 @@ pub struct SimCard {
     // Note: port_path removed - SIM to port mapping is runtime only
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
+    #[sqlx(default)]
+    pub default_country: Option<String>, // ISO 3166 code for national numbers
 }
 
 #[derive(Debug, FromRow, Deserialize, Serialize, Default, Clone)]
@@ -96,61 +226,443 @@ pub struct Conversation {
     pub sms_preview: SMSPreview,
 }
 
-impl Sms {
-    pub async fn count() -> Result<i64> {
-        let pool = get_pool()?;
-        let count = sqlx::query_scalar(
-            r#"
-            SELECT COUNT(*) FROM sms
-            "#,
-        )
-        .fetch_one(pool)
-        .await?;
-        Ok(count)
+/// A webhook managed through the API; `config` uses the same keys as config.toml
+#[derive(Debug, FromRow, Serialize, Clone)]
+pub struct StoredWebhook {
+    pub id: i64,
+    pub name: Option<String>,
+    pub enabled: bool,
+    pub config: sqlx::types::Json<serde_json::Value>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+/// An auto-reply rule; `config` holds its keywords, filters and actions
+#[derive(Debug, FromRow, Serialize, Clone)]
+pub struct StoredAutoReply {
+    pub id: i64,
+    pub name: Option<String>,
+    pub enabled: bool,
+    pub priority: i64,
+    pub config: sqlx::types::Json<serde_json::Value>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+/// An action taken by an auto-reply rule
+#[derive(Debug, Default)]
+pub struct AutoReplyEvent {
+    pub rule_id: i64,
+    pub contact: String,
+    pub sim_id: String,
+    pub action: &'static str,
+    pub recipient: Option<String>,
+    pub sms_id: Option<i64>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum SuppressionSource {
+    Keyword, // The number replied with an opt-out keyword
+    Admin,
+}
+
+/// A number that opted out of messages
+#[derive(Debug, FromRow, Serialize, Clone)]
+pub struct Suppression {
+    pub number: String,
+    pub source: SuppressionSource,
+    pub sim_id: Option<String>,
+    pub reason: Option<String>,
+    pub user_id: Option<i64>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Permission granted to an API key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Scope {
+    #[serde(rename = "sms:read")]
+    SmsRead,
+    #[serde(rename = "sms:send")]
+    SmsSend,
+    #[serde(rename = "sms:delete")]
+    SmsDelete,
+    #[serde(rename = "sims:admin")]
+    SimsAdmin,
+    #[serde(rename = "webhooks:admin")]
+    WebhooksAdmin,
+    #[serde(rename = "keys:admin")]
+    KeysAdmin,
+    #[serde(rename = "users:admin")]
+    UsersAdmin,
+    #[serde(rename = "audit:read")]
+    AuditRead,
+    #[serde(rename = "suppressions:admin")]
+    SuppressionsAdmin,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum Role {
+    Admin,    // Everything, including users, keys and webhooks
+    Operator, // Read and send messages
+    ReadOnly, // Read messages
+}
+
+impl Role {
+    pub fn scopes(&self) -> Vec<Scope> {
+        match self {
+            Role::Admin => vec![
+                Scope::SmsRead,
+                Scope::SmsSend,
+                Scope::SmsDelete,
+                Scope::SimsAdmin,
+                Scope::WebhooksAdmin,
+                Scope::KeysAdmin,
+                Scope::UsersAdmin,
+                Scope::AuditRead,
+                Scope::SuppressionsAdmin,
+            ],
+            Role::Operator => vec![Scope::SmsRead, Scope::SmsSend],
+            Role::ReadOnly => vec![Scope::SmsRead],
+        }
     }
+}
 
-    pub async fn count_by_contact_id(contact_id: &str) -> Result<i64> {
-        let pool = get_pool()?;
-        let count = sqlx::query_scalar(
-            r#"
-            SELECT COUNT(*) FROM sms WHERE contact_id = ?
-            "#,
-        )
-        .bind(contact_id)
-        .fetch_one(pool)
-        .await?;
-        Ok(count)
+#[derive(Debug, FromRow, Serialize, Clone)]
+pub struct User {
+    pub id: i64,
+    pub username: String,
+    #[serde(skip)]
+    pub password_hash: String,
+    pub role: Role,
+    pub sim_ids: Option<sqlx::types::Json<Vec<String>>>, // None allows every SIM
+    pub disabled: bool,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+/// A named bearer token; the token itself is only known when the key is created
+#[derive(Debug, FromRow, Serialize, Clone)]
+pub struct ApiKey {
+    pub id: i64,
+    pub name: String,
+    pub prefix: String,
+    pub scopes: sqlx::types::Json<Vec<Scope>>,
+    pub sim_ids: Option<sqlx::types::Json<Vec<String>>>, // None allows every SIM
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub created_at: NaiveDateTime,
+}
+
+/// An SMS with its contact name and SIM alias, as written to exports and archives
+#[derive(Debug, FromRow, Serialize)]
+pub struct SmsExport {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub sms: Sms,
+    pub contact_name: Option<String>,
+    pub sim_alias: Option<String>,
+}
+
+const SMS_EXPORT_COLUMNS: &str = "id, contact_id, timestamp, utc_offset, received_at, message, \
+    sim_id, send, status, user_id, \
+    (SELECT name FROM contacts WHERE id = sms.contact_id) AS contact_name, \
+    (SELECT alias FROM sim_cards WHERE id = sms.sim_id) AS sim_alias";
+
+const SMS_COLUMNS: &str =
+    "id, contact_id, timestamp, utc_offset, received_at, message, sim_id, send, status, user_id";
+
+/// Narrows SMS listings and searches; unset fields match everything
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct SmsFilter {
+    pub sim_id: Option<String>,
+    pub contact_id: Option<String>,
+    pub send: Option<bool>, // true for outgoing, false for incoming
+    pub status: Option<SmsStatus>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>, // Exclusive
+}
+
+impl SmsFilter {
+    fn push(&self, query: &mut QueryBuilder<'_, Sqlite>) {
+        if let Some(sim_id) = &self.sim_id {
+            query.push(" AND sim_id = ").push_bind(sim_id.clone());
+        }
+        if let Some(contact_id) = &self.contact_id {
+            query.push(" AND contact_id = ").push_bind(contact_id.clone());
+        }
+        if let Some(send) = self.send {
+            query.push(" AND send = ").push_bind(send);
+        }
+        if let Some(status) = self.status {
+            query.push(" AND status = ").push_bind(status as i32);
+        }
+        // Timestamps are stored in UTC
+        if let Some(from) = self.from {
+            query.push(" AND timestamp >= ").push_bind(from.naive_utc());
+        }
+        if let Some(to) = self.to {
+            query.push(" AND timestamp < ").push_bind(to.naive_utc());
+        }
     }
+}
 
-    /// Retrieves paginated SMS records
-    pub async fn paginate(page: u32, per_page: u32) -> Result<(Vec<Self>, i64)> {
-        if page == 0 {
-            return Err(anyhow::anyhow!("Page number must be greater than 0"));
+/// A search result; the matched text in `snippet` is between \x02 and \x03
+#[derive(Debug, FromRow)]
+pub struct SmsSearchHit {
+    #[sqlx(flatten)]
+    pub sms: Sms,
+    pub contact_name: String,
+    pub snippet: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchOrder {
+    #[default]
+    Relevance,
+    Newest,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    #[default]
+    Desc, // Newest first
+    Asc,
+}
+
+impl SortOrder {
+    fn sql(self) -> &'static str {
+        match self {
+            SortOrder::Desc => " ORDER BY timestamp DESC, id DESC",
+            SortOrder::Asc => " ORDER BY timestamp ASC, id ASC",
         }
-        let offset = (page - 1) * per_page;
-        let pool = get_pool()?;
+    }
+}
 
-        let sms_list = sqlx::query_as(
-            r#"
-            SELECT id, contact_id, timestamp, message, sim_id, send, status
-            FROM sms 
-            ORDER BY timestamp DESC
-            LIMIT ? OFFSET ?
-            "#,
-        )
-        .bind(per_page as i32)
-        .bind(offset as i32)
-        .fetch_all(pool)
-        .await?;
+/// Position after the last row of a page, for keyset pagination over `(timestamp, id)`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SmsCursor {
+    pub timestamp: NaiveDateTime,
+    pub id: i64,
+}
 
-        let total = Sms::count().await?;
+impl SmsCursor {
+    /// Opaque URL-safe form handed to clients
+    pub fn encode(&self) -> String {
+        let timestamp = self.timestamp.format("%Y-%m-%dT%H:%M:%S%.f");
+        BASE64_URL_SAFE_NO_PAD.encode(format!("{}|{}", timestamp, self.id))
+    }
 
-        Ok((sms_list, total))
+    pub fn decode(cursor: &str) -> Result<Self> {
+        let decoded = String::from_utf8(BASE64_URL_SAFE_NO_PAD.decode(cursor)?)?;
+        let (timestamp, id) = decoded
+            .split_once('|')
+            .ok_or_else(|| anyhow::anyhow!("Malformed cursor"))?;
+        Ok(Self {
+            timestamp: NaiveDateTime::parse_from_str(timestamp, "%Y-%m-%dT%H:%M:%S%.f")?,
+            id: id.parse()?,
+        })
     }
-    pub async fn paginate_by_contact_id(
-        contact_id: &str,
+
+    /// Cursor after the last row of a full page; a short page is the last one
+    fn after(last: Option<&Sms>, len: usize, limit: u32) -> Option<Self> {
+        last.filter(|_| len == limit as usize).map(|sms| Self {
+            timestamp: sms.timestamp.timestamp,
+            id: sms.id,
+        })
+    }
+}
+
+/// Columns copied between `sms` and `deleted_sms`
+const STORED_SMS_COLUMNS: &str = "id, contact_id, timestamp, utc_offset, received_at, message, \
+    sim_id, send, status, user_id, fingerprint";
+
+/// Identifies a message independently of when and how often it was read: SIM, sender,
+/// direction, SMSC timestamp (as stored, in UTC), text and the multipart reference
+fn fingerprint(
+    sim_id: &str,
+    contact: &str,
+    send: bool,
+    timestamp: NaiveDateTime,
+    concat_ref: Option<u8>,
+    message: &str,
+) -> String {
+    let content = hex::encode(Sha256::digest(message.as_bytes()));
+    let key = format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        sim_id,
+        contact,
+        if send { "out" } else { "in" },
+        timestamp.format("%Y-%m-%dT%H:%M:%S"),
+        concat_ref.map_or("-".to_string(), |reference| reference.to_string()),
+        content
+    );
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+/// Restricts a query that already has a WHERE clause to the given SIMs; None means every SIM
+/// Limits contacts (`column` holding their id) to those with messages on `sims`
+fn push_contact_sim_filter(query: &mut QueryBuilder<'_, Sqlite>, column: &str, sims: Option<&[String]>) {
+    if sims.is_some() {
+        query.push(format!(" AND {} IN (SELECT contact_id FROM sms WHERE 1 = 1", column));
+        push_sim_filter(query, sims);
+        query.push(")");
+    }
+}
+
+fn push_sim_filter(query: &mut QueryBuilder<'_, Sqlite>, sims: Option<&[String]>) {
+    if let Some(sims) = sims {
+        query.push(" AND sim_id IN (");
+        if sims.is_empty() {
+            query.push("NULL");
+        }
+        let mut separated = query.separated(", ");
+        for sim in sims {
+            separated.push_bind(sim.clone());
+        }
+        query.push(")");
+    }
+}
+
+/// One recorded action; `before` and `after` hold the changed values
+#[derive(Debug, FromRow, Serialize, Clone)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub timestamp: DateTime<Utc>,
+    pub actor: String,
+    pub user_id: Option<i64>,
+    pub action: String,
+    pub target: Option<String>,
+    #[sqlx(rename = "before_value")]
+    pub before: Option<sqlx::types::Json<serde_json::Value>>,
+    #[sqlx(rename = "after_value")]
+    pub after: Option<sqlx::types::Json<serde_json::Value>>,
+    pub source_ip: Option<String>,
+}
+
+/// Filters for listing audit entries; unset fields match everything
+#[derive(Debug, Default)]
+pub struct AuditFilter {
+    pub actor: Option<String>,
+    pub user_id: Option<i64>,
+    pub action: Option<String>, // Prefix, e.g. "sim." for every SIM change
+    pub target: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum CampaignStatus {
+    Running,
+    Paused,
+    Cancelled,
+    Completed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum RecipientStatus {
+    Pending,
+    Sending,
+    Sent,
+    Failed,
+    Skipped,   // Blocked contacts
+    Cancelled, // Still pending when the campaign was cancelled
+}
+
+#[derive(Debug, FromRow, Serialize, Clone)]
+pub struct Campaign {
+    pub id: i64,
+    pub name: String,
+    pub template: String,
+    pub sim_ids: sqlx::types::Json<Vec<String>>,
+    pub per_minute: u32,
+    pub status: CampaignStatus,
+    pub last_error: Option<String>, // Why the gateway paused it
+    pub user_id: Option<i64>,
+    pub created_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, FromRow, Serialize, Clone)]
+pub struct CampaignRecipient {
+    pub id: i64,
+    pub number: String,
+    pub contact_id: Option<String>,
+    pub variables: sqlx::types::Json<BTreeMap<String, String>>,
+    pub status: RecipientStatus,
+    pub sim_id: Option<String>,
+    pub sms_id: Option<i64>,
+    pub error: Option<String>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A recipient to add to a new campaign
+#[derive(Debug)]
+pub struct NewRecipient {
+    pub number: String,
+    pub contact_id: Option<String>,
+    pub variables: BTreeMap<String, String>,
+    pub skipped: Option<String>, // Why it is not sent to
+}
+
+/// Recipients of a campaign by status
+#[derive(Debug, Default, FromRow, Serialize, Clone, Copy)]
+pub struct CampaignProgress {
+    pub pending: i64,
+    pub sending: i64,
+    pub sent: i64,
+    pub failed: i64,
+    pub skipped: i64,
+    pub cancelled: i64,
+}
+
+#[derive(Debug, FromRow, Serialize)]
+pub struct CampaignSimTally {
+    pub sim_id: String,
+    pub sent: i64,
+    pub failed: i64,
+}
+
+/// Recipients that were not sent to, grouped by why
+#[derive(Debug, FromRow, Serialize)]
+pub struct CampaignIssue {
+    pub status: RecipientStatus,
+    pub error: Option<String>,
+    pub recipients: i64,
+}
+
+const CAMPAIGN_COLUMNS: &str = "id, name, template, sim_ids, per_minute, status, last_error, \
+    user_id, created_at, finished_at";
+const CAMPAIGN_RECIPIENT_COLUMNS: &str =
+    "id, number, contact_id, variables, status, sim_id, sms_id, error, updated_at";
+
+impl Sms {
+    pub async fn count(filter: &SmsFilter, sims: Option<&[String]>) -> Result<i64> {
+        let pool = get_pool()?;
+        let mut query = QueryBuilder::new("SELECT COUNT(*) FROM sms WHERE 1 = 1");
+        filter.push(&mut query);
+        push_sim_filter(&mut query, sims);
+        let count = query.build_query_scalar().fetch_one(pool).await?;
+        Ok(count)
+    }
+
+    /// Retrieves a page of SMS records by offset, limited to `sims` when given
+    pub async fn paginate(
+        filter: &SmsFilter,
+        order: SortOrder,
         page: u32,
         per_page: u32,
+        sims: Option<&[String]>,
     ) -> Result<(Vec<Self>, i64)> {
         if page == 0 {
             return Err(anyhow::anyhow!("Page number must be greater than 0"));
@@ -158,92 +670,221 @@ impl Sms {
         let offset = (page - 1) * per_page;
         let pool = get_pool()?;
 
-        let mut tx = pool.begin().await?;
+        let mut query = QueryBuilder::new(format!("SELECT {} FROM sms WHERE 1 = 1", SMS_COLUMNS));
+        filter.push(&mut query);
+        push_sim_filter(&mut query, sims);
+        query
+            .push(order.sql())
+            .push(" LIMIT ")
+            .push_bind(per_page as i32)
+            .push(" OFFSET ")
+            .push_bind(offset as i32);
+        let sms_list = query.build_query_as().fetch_all(pool).await?;
 
-        let sms_list = sqlx::query_as(
-            r#"
-            SELECT id, contact_id, timestamp, message, sim_id, send, status
-            FROM sms 
-            WHERE contact_id = ?
-            ORDER BY timestamp DESC, id DESC
-            LIMIT ? OFFSET ?
-            "#,
-        )
-        .bind(contact_id)
-        .bind(per_page as i32)
-        .bind(offset as i32)
-        .fetch_all(&mut *tx)
-        .await?;
+        let total = Sms::count(filter, sims).await?;
 
-        if page == 1 {
-            sqlx::query(
-                r#"
-                UPDATE sms
-                SET status = ?
-                WHERE contact_id = ? AND status = ?
-                "#,
-            )
-            .bind(SmsStatus::Read as i32)
-            .bind(contact_id)
-            .bind(SmsStatus::Unread as i32)
-            .execute(&mut *tx)
-            .await?;
+        Ok((sms_list, total))
+    }
+
+    /// Retrieves the SMS records after `cursor` in the given order, and the cursor for the next
+    /// page when there may be more. Unlike offsets, rows arriving meanwhile do not shift pages.
+    pub async fn paginate_after(
+        filter: &SmsFilter,
+        order: SortOrder,
+        cursor: Option<SmsCursor>,
+        limit: u32,
+        sims: Option<&[String]>,
+    ) -> Result<(Vec<Self>, Option<SmsCursor>)> {
+        let pool = get_pool()?;
+        let mut query = Self::query_after(SMS_COLUMNS, filter, order, cursor, limit, sims);
+        let sms_list: Vec<Self> = query.build_query_as().fetch_all(pool).await?;
+        let next = SmsCursor::after(sms_list.last(), sms_list.len(), limit);
+        Ok((sms_list, next))
+    }
+
+    /// Like `paginate_after`, with the contact name and SIM alias of every message
+    pub async fn export_after(
+        filter: &SmsFilter,
+        order: SortOrder,
+        cursor: Option<SmsCursor>,
+        limit: u32,
+        sims: Option<&[String]>,
+    ) -> Result<(Vec<SmsExport>, Option<SmsCursor>)> {
+        let pool = get_pool()?;
+        let mut query = Self::query_after(SMS_EXPORT_COLUMNS, filter, order, cursor, limit, sims);
+        let rows: Vec<SmsExport> = query.build_query_as().fetch_all(pool).await?;
+        let next = SmsCursor::after(rows.last().map(|row| &row.sms), rows.len(), limit);
+        Ok((rows, next))
+    }
+
+    fn query_after(
+        columns: &str,
+        filter: &SmsFilter,
+        order: SortOrder,
+        cursor: Option<SmsCursor>,
+        limit: u32,
+        sims: Option<&[String]>,
+    ) -> QueryBuilder<'static, Sqlite> {
+        let mut query = QueryBuilder::new(format!("SELECT {} FROM sms WHERE 1 = 1", columns));
+        filter.push(&mut query);
+        push_sim_filter(&mut query, sims);
+        if let Some(cursor) = cursor {
+            query
+                .push(match order {
+                    SortOrder::Desc => " AND (timestamp, id) < (",
+                    SortOrder::Asc => " AND (timestamp, id) > (",
+                })
+                .push_bind(cursor.timestamp)
+                .push(", ")
+                .push_bind(cursor.id)
+                .push(")");
         }
+        query.push(order.sql()).push(" LIMIT ").push_bind(limit as i32);
+        query
+    }
 
-        tx.commit().await?;
+    /// Marks the unread messages of a conversation as read
+    pub async fn mark_read_by_contact_id(contact_id: &str, sims: Option<&[String]>) -> Result<()> {
+        let pool = get_pool()?;
+        let mut update = QueryBuilder::new("UPDATE sms SET status = ");
+        update
+            .push_bind(SmsStatus::Read as i32)
+            .push(" WHERE contact_id = ")
+            .push_bind(contact_id)
+            .push(" AND status = ")
+            .push_bind(SmsStatus::Unread as i32);
+        push_sim_filter(&mut update, sims);
+        update.build().execute(pool).await?;
+        Ok(())
+    }
 
-        let total = Sms::count_by_contact_id(contact_id).await?;
+    /// Full-text search over messages and contact names. `terms` must all match, each as a
+    /// substring of at least three characters.
+    pub async fn search(
+        terms: &[&str],
+        filter: &SmsFilter,
+        order: SearchOrder,
+        page: u32,
+        per_page: u32,
+        sims: Option<&[String]>,
+    ) -> Result<(Vec<SmsSearchHit>, i64)> {
+        if page == 0 {
+            return Err(anyhow::anyhow!("Page number must be greater than 0"));
+        }
+        let offset = (page - 1) * per_page;
+        let pool = get_pool()?;
+        // Quoted as phrases so user input cannot use FTS5 query syntax
+        let fts_query = terms
+            .iter()
+            .map(|term| format!("\"{}\"", term.replace('"', "\"\"")))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        // With the trigram tokenizer the snippet length of 64 tokens is about 64 characters
+        let build = |select: &str| {
+            let mut query = QueryBuilder::new(format!(
+                "SELECT {} FROM sms JOIN ( \
+                     SELECT rowid AS fts_id, rank, \
+                            snippet(sms_fts, 0, char(2), char(3), '…', 64) AS snippet \
+                     FROM sms_fts WHERE sms_fts MATCH ",
+                select
+            ));
+            query.push_bind(fts_query.clone());
+            query.push(") ON fts_id = sms.id WHERE 1 = 1");
+            filter.push(&mut query);
+            push_sim_filter(&mut query, sims);
+            query
+        };
 
-        Ok((sms_list, total))
+        // The indexed contact_name also holds the display name, so the number comes from contacts
+        let mut query = build(&format!(
+            "{}, COALESCE((SELECT name FROM contacts WHERE id = sms.contact_id), '') AS contact_name, snippet",
+            SMS_COLUMNS
+        ));
+        query.push(match order {
+            SearchOrder::Relevance => " ORDER BY rank, timestamp DESC, id DESC",
+            SearchOrder::Newest => " ORDER BY timestamp DESC, id DESC",
+        });
+        query
+            .push(" LIMIT ")
+            .push_bind(per_page as i32)
+            .push(" OFFSET ")
+            .push_bind(offset as i32);
+        let hits = query.build_query_as().fetch_all(pool).await?;
+
+        let total = build("COUNT(*)").build_query_scalar().fetch_one(pool).await?;
+
+        Ok((hits, total))
     }
 
     pub async fn insert(&self) -> Result<i64> {
         let pool = get_pool()?;
+        let contact: Option<String> = sqlx::query_scalar("SELECT name FROM contacts WHERE id = ?")
+            .bind(&self.contact_id)
+            .fetch_optional(pool)
+            .await?;
+        let fingerprint = fingerprint(
+            &self.sim_id,
+            contact.as_deref().unwrap_or_default(),
+            self.send,
+            self.timestamp.timestamp,
+            None,
+            &self.message,
+        );
+
+        // Sending the same text twice within a second is legitimate; the second copy goes
+        // without a fingerprint
         let sms_id = sqlx::query_scalar::<_, i64>(
             r#"
-            INSERT INTO sms (contact_id, timestamp, message, sim_id, send, status)
-            VALUES (?, ?, ?, ?, ?, ?) RETURNING id
+            INSERT INTO sms (contact_id, timestamp, utc_offset, received_at, message, sim_id, send, status, user_id, fingerprint)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?,
+                    CASE WHEN EXISTS (SELECT 1 FROM sms WHERE fingerprint = ?10) THEN NULL ELSE ?10 END)
+            RETURNING id
             "#,
         )
         .bind(&self.contact_id)
-        .bind(self.timestamp)
+        .bind(self.timestamp.timestamp)
+        .bind(self.timestamp.utc_offset)
+        .bind(self.received_at)
         .bind(&self.message)
         .bind(&self.sim_id)
         .bind(self.send)
         .bind(self.status as i32)
+        .bind(self.user_id)
+        .bind(fingerprint)
         .fetch_one(pool)
         .await?;
 
         Ok(sms_id)
     }
-    pub async fn query_unread_by_contact_id(contact_id: &str) -> Result<Vec<Self>> {
+
+    pub async fn query_unread_by_contact_id(
+        contact_id: &str,
+        sims: Option<&[String]>,
+    ) -> Result<Vec<Self>> {
         let pool = get_pool()?;
         let mut tx = pool.begin().await?;
-        let sms_list = sqlx::query_as(
-            r#"
-            SELECT id, contact_id, timestamp, message, sim_id, send, status
-            FROM sms 
-            WHERE contact_id = ? AND status = ?
-            ORDER BY timestamp DESC
-            "#,
-        )
-        .bind(contact_id)
-        .bind(SmsStatus::Unread as i32)
-        .fetch_all(&mut *tx)
-        .await?;
-
-        sqlx::query(
-            r#"
-                UPDATE sms
-                SET status = ?
-                WHERE contact_id = ? AND status = ?
-                "#,
-        )
-        .bind(SmsStatus::Read as i32)
-        .bind(contact_id)
-        .bind(SmsStatus::Unread as i32)
-        .execute(&mut *tx)
-        .await?;
+        let mut query = QueryBuilder::new(format!(
+            "SELECT {} FROM sms WHERE contact_id = ",
+            SMS_COLUMNS
+        ));
+        query
+            .push_bind(contact_id)
+            .push(" AND status = ")
+            .push_bind(SmsStatus::Unread as i32);
+        push_sim_filter(&mut query, sims);
+        query.push(" ORDER BY timestamp DESC");
+        let sms_list = query.build_query_as().fetch_all(&mut *tx).await?;
+
+        let mut update = QueryBuilder::new("UPDATE sms SET status = ");
+        update
+            .push_bind(SmsStatus::Read as i32)
+            .push(" WHERE contact_id = ")
+            .push_bind(contact_id)
+            .push(" AND status = ")
+            .push_bind(SmsStatus::Unread as i32);
+        push_sim_filter(&mut update, sims);
+        update.build().execute(&mut *tx).await?;
 
         Ok(sms_list)
     }
@@ -265,93 +906,483 @@ impl Sms {
         Ok(())
     }
 
-    pub async fn update_status_by_id(id: i64, status: SmsStatus) -> Result<()> {
-        let pool = get_pool()?;
-        sqlx::query(
-            r#"
-            UPDATE sms
-            SET status = ?
-            WHERE id = ?
-            "#,
-        )
-        .bind(status as i32)
-        .bind(id)
-        .execute(pool)
-        .await?;
+    /// Moves the given messages to `deleted_sms`, returning the deletion id and how many moved
+    pub async fn soft_delete_by_ids(ids: &[i64], sims: Option<&[String]>) -> Result<(String, u64)> {
+        Self::soft_delete(|query| {
+            query.push(" AND id IN (");
+            let mut separated = query.separated(", ");
+            for id in ids {
+                separated.push_bind(*id);
+            }
+            query.push(")");
+            push_sim_filter(query, sims);
+        })
+        .await
+    }
 
-        Ok(())
+    /// Moves every message of a conversation to `deleted_sms`
+    pub async fn soft_delete_by_contact_id(
+        contact_id: &str,
+        sims: Option<&[String]>,
+    ) -> Result<(String, u64)> {
+        let contact_id = contact_id.to_string();
+        Self::soft_delete(|query| {
+            query.push(" AND contact_id = ").push_bind(contact_id);
+            push_sim_filter(query, sims);
+        })
+        .await
     }
-}
 
-impl Contact {
-    pub async fn query_all() -> Result<Vec<Self>> {
+    async fn soft_delete(
+        filter: impl FnOnce(&mut QueryBuilder<'_, Sqlite>),
+    ) -> Result<(String, u64)> {
         let pool = get_pool()?;
-        let contacts = sqlx::query_as("SELECT id, name FROM contacts")
-            .fetch_all(pool)
+        let deletion_id = Uuid::new_v4().to_string();
+        let mut tx = pool.begin().await?;
+
+        let mut query = QueryBuilder::new(format!(
+            "INSERT INTO deleted_sms ({}, deletion_id, deleted_at) SELECT {}, ",
+            STORED_SMS_COLUMNS, STORED_SMS_COLUMNS
+        ));
+        query
+            .push_bind(deletion_id.clone())
+            .push(", ")
+            .push_bind(Utc::now())
+            .push(" FROM sms WHERE 1 = 1");
+        filter(&mut query);
+        let moved = query.build().execute(&mut *tx).await?.rows_affected();
+
+        sqlx::query("DELETE FROM sms WHERE id IN (SELECT id FROM deleted_sms WHERE deletion_id = ?)")
+            .bind(&deletion_id)
+            .execute(&mut *tx)
             .await?;
+        tx.commit().await?;
 
-        Ok(contacts)
+        Ok((deletion_id, moved))
     }
-    pub async fn query_by_id(id: &str) -> Result<Self> {
+
+    /// Moves the messages of a deletion back, returning how many were restored. Copies that
+    /// were stored again in the meantime are dropped.
+    pub async fn restore(deletion_id: &str, sims: Option<&[String]>) -> Result<u64> {
         let pool = get_pool()?;
-        let contact = sqlx::query_as("SELECT id, name FROM contacts WHERE id = ?")
-            .bind(id)
-            .fetch_one(pool)
+        let mut tx = pool.begin().await?;
+
+        let mut query = QueryBuilder::new(format!(
+            "INSERT OR IGNORE INTO sms ({}) SELECT {} FROM deleted_sms WHERE deletion_id = ",
+            STORED_SMS_COLUMNS, STORED_SMS_COLUMNS
+        ));
+        query.push_bind(deletion_id);
+        push_sim_filter(&mut query, sims);
+        let restored = query.build().execute(&mut *tx).await?.rows_affected();
+
+        let mut delete = QueryBuilder::new("DELETE FROM deleted_sms WHERE deletion_id = ");
+        delete.push_bind(deletion_id);
+        push_sim_filter(&mut delete, sims);
+        delete.build().execute(&mut *tx).await?;
+        tx.commit().await?;
+
+        Ok(restored)
+    }
+
+    /// Permanently removes deleted messages older than `cutoff`
+    pub async fn purge_deleted_before(cutoff: DateTime<Utc>) -> Result<u64> {
+        let pool = get_pool()?;
+        let result = sqlx::query("DELETE FROM deleted_sms WHERE deleted_at < ?")
+            .bind(cutoff)
+            .execute(pool)
             .await?;
+        Ok(result.rows_affected())
+    }
 
-        Ok(contact)
+    /// Oldest messages sent before `cutoff`, optionally only for one SIM or contact name
+    pub async fn query_expired(
+        cutoff: DateTime<Utc>,
+        sim_id: Option<&str>,
+        contact: Option<&str>,
+        limit: u32,
+    ) -> Result<Vec<SmsExport>> {
+        let pool = get_pool()?;
+        let mut query = QueryBuilder::new(format!(
+            "SELECT {} FROM sms WHERE timestamp < ",
+            SMS_EXPORT_COLUMNS
+        ));
+        query.push_bind(cutoff.naive_utc());
+        if let Some(sim_id) = sim_id {
+            query.push(" AND sim_id = ").push_bind(sim_id);
+        }
+        if let Some(contact) = contact {
+            query
+                .push(" AND contact_id IN (SELECT id FROM contacts WHERE name = ")
+                .push_bind(contact)
+                .push(")");
+        }
+        query.push(" ORDER BY id LIMIT ").push_bind(limit as i32);
+        Ok(query.build_query_as().fetch_all(pool).await?)
     }
-    pub async fn insert(&self) -> Result<()> {
+
+    /// Permanently removes messages, bypassing `deleted_sms`
+    pub async fn delete_by_ids(ids: &[i64]) -> Result<u64> {
+        if ids.is_empty() {
+            return Ok(0);
+        }
+        let pool = get_pool()?;
+        let mut query = QueryBuilder::new("DELETE FROM sms WHERE id IN (");
+        let mut separated = query.separated(", ");
+        for id in ids {
+            separated.push_bind(*id);
+        }
+        query.push(")");
+        Ok(query.build().execute(pool).await?.rows_affected())
+    }
+
+    pub async fn update_status_by_ids(ids: &[i64], status: SmsStatus) -> Result<()> {
+        if ids.is_empty() {
+            return Ok(());
+        }
         let pool = get_pool()?;
+        let mut query = QueryBuilder::new("UPDATE sms SET status = ");
+        query.push_bind(status as i32).push(" WHERE id IN (");
+        let mut separated = query.separated(", ");
+        for id in ids {
+            separated.push_bind(*id);
+        }
+        query.push(")");
+        query.build().execute(pool).await?;
+        Ok(())
+    }
 
+    pub async fn update_status_by_id(id: i64, status: SmsStatus) -> Result<()> {
+        let pool = get_pool()?;
         sqlx::query(
             r#"
-            INSERT INTO contacts (id, name) VALUES (?, ?)
+            UPDATE sms
+            SET status = ?
+            WHERE id = ?
             "#,
         )
-        .bind(&self.id)
-        .bind(&self.name)
+        .bind(status as i32)
+        .bind(id)
         .execute(pool)
         .await?;
 
         Ok(())
-    }    pub async fn find_or_create(&mut self) -> Result<()> {
+    }
+}
+
+impl Contact {
+    pub async fn query_by_id(id: &str) -> Result<Self> {
         let pool = get_pool()?;
+        let contact = sqlx::query_as("SELECT id, name, display_name FROM contacts WHERE id = ?")
+            .bind(id)
+            .fetch_one(pool)
+            .await?;
 
-        let existing_id = sqlx::query_scalar::<_, Option<String>>(
+        Ok(contact)
+    }
+
+    /// The contact owning `number`, in any notation
+    pub async fn find_by_number(number: &str) -> Result<Option<Self>> {
+        let pool = get_pool()?;
+        let contact = sqlx::query_as(
             r#"
-            SELECT id FROM contacts WHERE name = ?
+            SELECT c.id, c.name, c.display_name
+            FROM contact_numbers n JOIN contacts c ON c.id = n.contact_id
+            WHERE n.number = ?
             "#,
         )
-        .bind(&self.name)
-        .fetch_one(pool)
-        .await;
+        .bind(normalize(number))
+        .fetch_optional(pool)
+        .await?;
 
-        if let Ok(Some(id)) = existing_id {
-            self.id = id;
-            return Ok(());
+        Ok(contact)
+    }
+
+    /// Resolves `name` to the contact owning that number and creates one if nobody does. `name`
+    /// is normalised for `country` and stays the number to send to, which need not be the
+    /// primary one.
+    pub async fn find_or_create(&mut self, country: Option<&Country>) -> Result<()> {
+        let pool = get_pool()?;
+        let mut transaction = pool.begin().await?;
+
+        self.name = to_e164(&self.name, country);
+        match contact_id_by_number(&mut transaction, &self.name).await? {
+            Some(id) => self.id = id,
+            None => self.id = create_contact_for_number(&mut transaction, &self.name).await?,
         }
 
-        sqlx::query(
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    /// Contacts matching `filter`; with `sims` only those with messages on them
+    pub async fn query_details(
+        filter: &ContactFilter,
+        sims: Option<&[String]>,
+    ) -> Result<Vec<ContactDetails>> {
+        let pool = get_pool()?;
+        let mut query = QueryBuilder::new(format!(
+            "SELECT {} FROM contacts WHERE 1 = 1",
+            CONTACT_DETAILS_COLUMNS
+        ));
+        if let Some(q) = filter.q.as_deref().map(str::trim).filter(|q| !q.is_empty()) {
+            query
+                .push(" AND (display_name LIKE ")
+                .push_bind(format!("%{}%", q))
+                .push(" OR id IN (SELECT contact_id FROM contact_numbers WHERE number LIKE ")
+                .push_bind(format!("%{}%", normalize(q)))
+                .push("))");
+        }
+        if let Some(tag) = &filter.tag {
+            query
+                .push(" AND id IN (SELECT contact_id FROM contact_tags WHERE tag = ")
+                .push_bind(tag.clone())
+                .push(")");
+        }
+        if let Some(blocked) = filter.blocked {
+            query.push(" AND blocked = ").push_bind(blocked);
+        }
+        push_contact_sim_filter(&mut query, "id", sims);
+        query.push(" ORDER BY COALESCE(display_name, name)");
+
+        Ok(query.build_query_as().fetch_all(pool).await?)
+    }
+
+    pub async fn query_details_by_id(id: &str) -> Result<Option<ContactDetails>> {
+        let pool = get_pool()?;
+        let contact = sqlx::query_as(&format!(
+            "SELECT {} FROM contacts WHERE id = ?",
+            CONTACT_DETAILS_COLUMNS
+        ))
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(contact)
+    }
+
+    /// Whether the contact has messages on `sims`; every contact does without a restriction
+    pub async fn visible(id: &str, sims: Option<&[String]>) -> Result<bool> {
+        if sims.is_none() {
+            return Ok(true);
+        }
+        let pool = get_pool()?;
+        let mut query = QueryBuilder::new("SELECT COUNT(*) FROM contacts WHERE id = ");
+        query.push_bind(id.to_string());
+        push_contact_sim_filter(&mut query, "id", sims);
+        let count: i64 = query.build_query_scalar().fetch_one(pool).await?;
+
+        Ok(count > 0)
+    }
+
+    /// Every tag with the number of contacts carrying it, counting only contacts on `sims`
+    pub async fn query_tags(sims: Option<&[String]>) -> Result<Vec<TagCount>> {
+        let pool = get_pool()?;
+        let mut query =
+            QueryBuilder::new("SELECT tag, COUNT(*) AS contacts FROM contact_tags WHERE 1 = 1");
+        push_contact_sim_filter(&mut query, "contact_id", sims);
+        query.push(" GROUP BY tag ORDER BY tag");
+        let tags = query.build_query_as().fetch_all(pool).await?;
+
+        Ok(tags)
+    }
+
+    pub async fn add_tag(id: &str, tag: &str) -> Result<()> {
+        let pool = get_pool()?;
+        sqlx::query("INSERT OR IGNORE INTO contact_tags (contact_id, tag) VALUES (?, ?)")
+            .bind(id)
+            .bind(tag)
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Numbers of blocked contacts, normalised
+    pub async fn blocked_numbers() -> Result<HashSet<String>> {
+        let pool = get_pool()?;
+        let numbers = sqlx::query_scalar(
             r#"
-            INSERT INTO contacts (id, name) VALUES (?, ?)
+            SELECT n.number FROM contact_numbers n JOIN contacts c ON c.id = n.contact_id
+            WHERE c.blocked
             "#,
         )
-        .bind(&self.id)
-        .bind(&self.name)
-        .execute(pool)
+        .fetch_all(pool)
         .await?;
 
-        Ok(())
+        Ok(numbers.into_iter().collect())
+    }
+
+    /// Rewrites contact numbers in E.164, using the country of the SIM a contact has most of its
+    /// messages on, and merges contacts that turn out to share a number into the oldest of them.
+    /// Returns how many contacts were merged away.
+    pub async fn normalize_numbers() -> Result<u64> {
+        let pool = get_pool()?;
+        let mut transaction = pool.begin().await?;
+
+        let contacts: Vec<(String, String, Option<String>)> = sqlx::query_as(
+            r#"
+            SELECT c.id, c.name,
+                   (SELECT sim_id FROM sms WHERE contact_id = c.id
+                    GROUP BY sim_id ORDER BY COUNT(*) DESC LIMIT 1)
+            FROM contacts c ORDER BY c.rowid
+            "#,
+        )
+        .fetch_all(&mut *transaction)
+        .await?;
+        let mut current: HashMap<String, Vec<ContactNumber>> = HashMap::new();
+        let rows: Vec<(String, String, Option<String>)> =
+            sqlx::query_as("SELECT contact_id, number, label FROM contact_numbers ORDER BY rowid")
+                .fetch_all(&mut *transaction)
+                .await?;
+        for (contact_id, number, label) in rows {
+            current
+                .entry(contact_id)
+                .or_default()
+                .push(ContactNumber { number, label });
+        }
+
+        let mut countries = HashMap::new();
+        let mut owners: HashMap<String, usize> = HashMap::new(); // Number -> index in survivors
+        let mut survivors: Vec<NormalizedContact> = Vec::new();
+        let mut merges = Vec::new();
+        for (id, name, sim_id) in contacts {
+            let sim_id = sim_id.unwrap_or_default();
+            if let Entry::Vacant(entry) = countries.entry(sim_id.clone()) {
+                entry.insert(sim_country(&mut *transaction, &sim_id).await?);
+            }
+            let country = countries[&sim_id];
+
+            let primary = to_e164(&name, country);
+            let existing = current.get(&id).map(Vec::as_slice).unwrap_or_default();
+            let numbers: Vec<ContactNumber> = std::iter::once(ContactNumber {
+                number: primary.clone(),
+                label: None,
+            })
+            .chain(existing.iter().map(|n| ContactNumber {
+                number: to_e164(&n.number, country),
+                label: n.label.clone(),
+            }))
+            .collect();
+
+            // A contact sharing a number with an earlier one is merged into it
+            let index = match numbers.iter().find_map(|n| owners.get(&n.number)) {
+                Some(&index) => {
+                    merges.push((id, survivors[index].id.clone()));
+                    index
+                }
+                None => {
+                    survivors.push(NormalizedContact {
+                        id,
+                        old_name: name,
+                        name: primary,
+                        numbers: Vec::new(),
+                    });
+                    survivors.len() - 1
+                }
+            };
+            for number in numbers {
+                match owners.entry(number.number.clone()) {
+                    Entry::Vacant(entry) => {
+                        entry.insert(index);
+                        survivors[index].numbers.push(number);
+                    }
+                    // The primary number comes first without a label; keep the label it has
+                    Entry::Occupied(_) if number.label.is_some() => {
+                        if let Some(known) = survivors[index]
+                            .numbers
+                            .iter_mut()
+                            .find(|n| n.number == number.number && n.label.is_none())
+                        {
+                            known.label = number.label;
+                        }
+                    }
+                    Entry::Occupied(_) => {}
+                }
+            }
+        }
+
+        for (loser, survivor) in &merges {
+            for statement in [
+                "UPDATE sms SET contact_id = ?2 WHERE contact_id = ?1",
+                "UPDATE deleted_sms SET contact_id = ?2 WHERE contact_id = ?1",
+                "INSERT OR IGNORE INTO contact_tags (contact_id, tag) \
+                 SELECT ?2, tag FROM contact_tags WHERE contact_id = ?1",
+                "UPDATE contacts SET \
+                     display_name = COALESCE(display_name, (SELECT display_name FROM contacts WHERE id = ?1)), \
+                     notes = COALESCE(notes, (SELECT notes FROM contacts WHERE id = ?1)), \
+                     blocked = blocked OR (SELECT blocked FROM contacts WHERE id = ?1) \
+                 WHERE id = ?2",
+                "DELETE FROM contacts WHERE id = ?1",
+            ] {
+                sqlx::query(statement)
+                    .bind(loser)
+                    .bind(survivor)
+                    .execute(&mut *transaction)
+                    .await?;
+            }
+        }
+
+        // Names and numbers are unique, so changed contacts give theirs up before any is
+        // written back
+        let sorted = |numbers: &[ContactNumber]| {
+            let mut numbers = numbers.to_vec();
+            numbers.sort_by(|a, b| a.number.cmp(&b.number));
+            numbers
+        };
+        let changed: Vec<_> = survivors
+            .into_iter()
+            .filter(|contact| {
+                let existing = current.get(&contact.id).map(Vec::as_slice).unwrap_or_default();
+                contact.name != contact.old_name || sorted(existing) != sorted(&contact.numbers)
+            })
+            .collect();
+        for contact in &changed {
+            sqlx::query("DELETE FROM contact_numbers WHERE contact_id = ?")
+                .bind(&contact.id)
+                .execute(&mut *transaction)
+                .await?;
+            if contact.name != contact.old_name {
+                sqlx::query("UPDATE contacts SET name = '~' || id WHERE id = ?")
+                    .bind(&contact.id)
+                    .execute(&mut *transaction)
+                    .await?;
+            }
+        }
+        for contact in &changed {
+            if contact.name != contact.old_name {
+                sqlx::query("UPDATE contacts SET name = ? WHERE id = ?")
+                    .bind(&contact.name)
+                    .bind(&contact.id)
+                    .execute(&mut *transaction)
+                    .await?;
+            }
+            for number in &contact.numbers {
+                sqlx::query(
+                    "INSERT INTO contact_numbers (number, contact_id, label) VALUES (?, ?, ?)",
+                )
+                .bind(&number.number)
+                .bind(&contact.id)
+                .bind(&number.label)
+                .execute(&mut *transaction)
+                .await?;
+            }
+        }
+
+        transaction.commit().await?;
+        Ok(merges.len() as u64)
     }
 
+    /// Removes contacts nobody wrote to and nobody edited
     pub async fn delete_contacts_without_messages() -> Result<u64> {
         let pool = get_pool()?;
 
         let affected_rows = sqlx::query(
             r#"
             DELETE FROM contacts 
-            WHERE id NOT IN (SELECT DISTINCT contact_id FROM sms)
+            WHERE id NOT IN (SELECT contact_id FROM sms UNION SELECT contact_id FROM deleted_sms)
+              AND display_name IS NULL AND notes IS NULL AND NOT blocked
+              AND id NOT IN (SELECT contact_id FROM contact_tags)
+              AND id NOT IN (SELECT contact_id FROM contact_numbers GROUP BY contact_id HAVING COUNT(*) > 1)
             "#,
         )
         .execute(pool)
@@ -359,30 +1390,132 @@ impl Contact {
 
         Ok(affected_rows.rows_affected())
     }
-    pub async fn delete_by_id(id: &str) -> Result<bool> {
+    /// Removes the contact once none of its messages remain, deleted ones awaiting undo included
+    pub async fn delete_without_messages(id: &str) -> Result<bool> {
         let pool = get_pool()?;
 
-        sqlx::query(
+        let result = sqlx::query(
             r#"
-            DELETE FROM sms WHERE contact_id = ?
+            DELETE FROM contacts
+            WHERE id = ?
+              AND id NOT IN (SELECT contact_id FROM sms UNION SELECT contact_id FROM deleted_sms)
             "#,
         )
         .bind(id)
         .execute(pool)
         .await?;
 
-        let result = sqlx::query(
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+impl ContactDetails {
+    /// Creates or updates the contact; its numbers and tags are replaced
+    pub async fn save(&self) -> Result<()> {
+        let pool = get_pool()?;
+        let mut transaction = pool.begin().await?;
+
+        sqlx::query(
             r#"
-            DELETE FROM contacts WHERE id = ?
+            INSERT INTO contacts (id, name, display_name, notes, blocked) VALUES (?, ?, ?, ?, ?)
+            ON CONFLICT (id) DO UPDATE SET
+                name = excluded.name,
+                display_name = excluded.display_name,
+                notes = excluded.notes,
+                blocked = excluded.blocked
             "#,
         )
-        .bind(id)
-        .execute(pool)
+        .bind(&self.contact.id)
+        .bind(&self.contact.name)
+        .bind(&self.contact.display_name)
+        .bind(&self.notes)
+        .bind(self.blocked)
+        .execute(&mut *transaction)
         .await?;
 
-        Ok(result.rows_affected() > 0)
+        sqlx::query("DELETE FROM contact_numbers WHERE contact_id = ?")
+            .bind(&self.contact.id)
+            .execute(&mut *transaction)
+            .await?;
+        for number in &self.numbers {
+            sqlx::query("INSERT INTO contact_numbers (number, contact_id, label) VALUES (?, ?, ?)")
+                .bind(&number.number)
+                .bind(&self.contact.id)
+                .bind(&number.label)
+                .execute(&mut *transaction)
+                .await?;
+        }
+
+        sqlx::query("DELETE FROM contact_tags WHERE contact_id = ?")
+            .bind(&self.contact.id)
+            .execute(&mut *transaction)
+            .await?;
+        for tag in &self.tags {
+            sqlx::query("INSERT OR IGNORE INTO contact_tags (contact_id, tag) VALUES (?, ?)")
+                .bind(&self.contact.id)
+                .bind(tag)
+                .execute(&mut *transaction)
+                .await?;
+        }
+
+        transaction.commit().await?;
+        Ok(())
+    }
+}
+
+/// The country of a SIM, or the default country when it has none
+async fn sim_country<'c, E>(executor: E, sim_id: &str) -> Result<Option<&'static Country>>
+where
+    E: sqlx::SqliteExecutor<'c>,
+{
+    let code: Option<Option<String>> =
+        sqlx::query_scalar("SELECT default_country FROM sim_cards WHERE id = ?")
+            .bind(sim_id)
+            .fetch_optional(executor)
+            .await?;
+    match code.flatten() {
+        Some(code) => Ok(Some(Country::from_code(&code).map_err(anyhow::Error::msg)?)),
+        None => Ok(default_country()),
     }
+}
+
+/// The contact owning `number`, which must be normalised
+async fn contact_id_by_number(
+    transaction: &mut Transaction<'_, Sqlite>,
+    number: &str,
+) -> Result<Option<String>> {
+    let contact_id = sqlx::query_scalar("SELECT contact_id FROM contact_numbers WHERE number = ?")
+        .bind(number)
+        .fetch_optional(&mut **transaction)
+        .await?;
+
+    Ok(contact_id)
+}
+
+/// Creates a contact for a number nobody owns yet; the number becomes its primary one
+async fn create_contact_for_number(
+    transaction: &mut Transaction<'_, Sqlite>,
+    number: &str,
+) -> Result<String> {
+    let uuid = Uuid::new_v4().to_string();
+
+    sqlx::query(
+        r#"
+        INSERT INTO contacts (id, name) VALUES (?, ?)
+        "#,
+    )
+    .bind(&uuid)
+    .bind(number)
+    .execute(&mut **transaction)
+    .await?;
+
+    sqlx::query("INSERT INTO contact_numbers (number, contact_id) VALUES (?, ?)")
+        .bind(number)
+        .bind(&uuid)
+        .execute(&mut **transaction)
+        .await?;
 
+    Ok(uuid)
 }
 
 impl SimCard {
@@ -394,7 +1527,7 @@ impl SimCard {
         phone_number: Option<&str>
     ) -> Result<Vec<Self>> {
         let pool = get_pool()?;
-        let mut query = String::from("SELECT id, imsi, phone_number, alias, created_at, updated_at FROM sim_cards WHERE 1=1");
+        let mut query = String::from("SELECT id, imsi, phone_number, alias, created_at, updated_at, default_country FROM sim_cards WHERE 1=1");
         let mut binds = Vec::new();
 
         if let Some(id) = id {
@@ -446,6 +1579,23 @@ impl SimCard {
         Ok(())
     }
 
+    /// Sets the country national numbers on this SIM belong to; None uses the default country
+    pub async fn update_country(&mut self, country: Option<&Country>) -> Result<()> {
+        let pool = get_pool()?;
+        self.default_country = country.map(|c| c.code.to_string());
+        sqlx::query("UPDATE sim_cards SET default_country = ?, updated_at = CURRENT_TIMESTAMP WHERE id = ?")
+            .bind(&self.default_country)
+            .bind(&self.id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    /// The country of the SIM, or the default country
+    pub async fn country(sim_id: &str) -> Result<Option<&'static Country>> {
+        sim_country(get_pool()?, sim_id).await
+    }
+
     /// 4. 删除
     pub async fn delete(&self) -> Result<bool> {
         let pool = get_pool()?;
@@ -467,7 +1617,7 @@ impl SimCard {
 
         for chunk in sim_cards.chunks(MAX_BATCH_SIZE) {
             let mut query_builder = QueryBuilder::new(
-                "INSERT INTO sim_cards (id, imsi, phone_number, alias, created_at, updated_at) "
+                "INSERT INTO sim_cards (id, imsi, phone_number, alias, created_at, updated_at, default_country) "
             );
             query_builder.push_values(chunk, |mut b, sim_card| {
                 b.push_bind(&sim_card.id)
@@ -475,7 +1625,8 @@ impl SimCard {
                     .push_bind(&sim_card.phone_number)
                     .push_bind(&sim_card.alias)
                     .push_bind(sim_card.created_at)
-                    .push_bind(sim_card.updated_at);
+                    .push_bind(sim_card.updated_at)
+                    .push_bind(&sim_card.default_country);
             });
             query_builder.build().execute(&mut *transaction).await?;
         }
@@ -493,7 +1644,7 @@ impl SimCard {
     pub async fn query_all() -> Result<Vec<Self>> {
         let pool = get_pool()?;
         let sim_cards = sqlx::query_as::<_, SimCard>(
-            "SELECT id, imsi, phone_number, alias, created_at, updated_at FROM sim_cards ORDER BY created_at"
+            "SELECT id, imsi, phone_number, alias, created_at, updated_at, default_country FROM sim_cards ORDER BY created_at"
         )
         .fetch_all(pool)
         .await?;
@@ -517,7 +1668,7 @@ impl SimCard {
 
         let pool = get_pool()?;
         let mut query_builder = QueryBuilder::new(
-            "SELECT id, imsi, phone_number, alias, created_at, updated_at FROM sim_cards WHERE id IN ("
+            "SELECT id, imsi, phone_number, alias, created_at, updated_at, default_country FROM sim_cards WHERE id IN ("
         );
         let mut separated = query_builder.separated(", ");
         for id in ids {
@@ -557,6 +1708,7 @@ impl SimCard {
             alias: None,
             created_at: now,
             updated_at: now,
+            default_country: None,
         };
         sim_card.insert().await?;
         Ok(sim_card)
@@ -564,14 +1716,16 @@ impl SimCard {
 }
 
 impl Conversation {
-    pub async fn query_all() -> Result<Vec<Self>> {
+    /// Latest message per contact, limited to conversations whose latest message is on `sims`
+    pub async fn query_all(sims: Option<&[String]>) -> Result<Vec<Self>> {
         let pool = get_pool()?;
 
-        let conversations = sqlx::query_as(
-              "SELECT id, name, timestamp, message, status, sim_id FROM v_contacts_with_sim ORDER BY timestamp DESC"
-        )
-        .fetch_all(pool)
-        .await?;
+        let mut query = QueryBuilder::new(
+            "SELECT id, name, display_name, timestamp, utc_offset, message, status, sim_id FROM v_contacts_with_sim WHERE 1 = 1",
+        );
+        push_sim_filter(&mut query, sims);
+        query.push(" ORDER BY timestamp DESC");
+        let conversations = query.build_query_as().fetch_all(pool).await?;
 
         Ok(conversations)
     }
@@ -580,7 +1734,7 @@ impl Conversation {
         let pool = get_pool()?;
 
         let conversations = sqlx::query_as(
-              "SELECT id, name, timestamp, message, status, sim_id FROM v_contacts_with_sim where status = ? ORDER BY timestamp DESC"
+              "SELECT id, name, display_name, timestamp, utc_offset, message, status, sim_id FROM v_contacts_with_sim where status = ? ORDER BY timestamp DESC"
         )
         .bind(SmsStatus::Unread as i32)
         .fetch_all(pool)
@@ -596,7 +1750,7 @@ impl Conversation {
         }
 
         let mut query_builder = QueryBuilder::new(
-            "SELECT id, name, timestamp, message, status, sim_id FROM v_contacts_with_sim WHERE id IN (",
+            "SELECT id, name, display_name, timestamp, utc_offset, message, status, sim_id FROM v_contacts_with_sim WHERE id IN (",
         );
 
         let mut separated = query_builder.separated(", ");
@@ -637,38 +1791,40 @@ impl Conversation {
 }
 
 impl ModemSMS {
-    pub async fn get_contact_id<'a>(
-        &self,
-        transaction: &'a mut Transaction<'_, Sqlite>,
-    ) -> Result<String> {
-        let contact_id = sqlx::query_scalar::<_, String>(
-            r#"
-            SELECT id FROM contacts WHERE name = ?
-            "#,
-        )
-        .bind(&self.contact)
-        .fetch_optional(&mut **transaction)
-        .await?;
-
-        if let Some(contact_id) = contact_id {
-            Ok(contact_id)
-        } else {
-            let uuid = Uuid::new_v4().to_string();
+    /// The PDU time normalised to UTC, with the gateway's offset when the SMSC sent no valid one
+    pub fn stored_timestamp(&self) -> SmsTimestamp {
+        match self
+            .utc_offset
+            .and_then(|offset| offset.from_local_datetime(&self.timestamp).single())
+        {
+            Some(dt) => SmsTimestamp {
+                timestamp: dt.naive_utc(),
+                utc_offset: Some(dt.offset().local_minus_utc()),
+            },
+            None => SmsTimestamp::from_local(self.timestamp),
+        }
+    }
 
-            sqlx::query(
-                r#"
-                INSERT INTO contacts (id, name) VALUES (?, ?)
-                "#,
-            )
-            .bind(&uuid)
-            .bind(&self.contact)
-            .execute(&mut **transaction)
-            .await?;
+    /// `number` is the contact's number in E.164, so a sender read once with and once without
+    /// its country code gives the same fingerprint
+    pub fn fingerprint(&self, number: &str) -> String {
+        fingerprint(
+            &self.sim_id,
+            number,
+            self.send,
+            self.stored_timestamp().timestamp,
+            self.concat_ref,
+            &self.message,
+        )
+    }
 
-            Ok(uuid)
-        }
+    /// The contact's number in E.164, national numbers completed with the SIM's country
+    async fn number(&self, transaction: &mut Transaction<'_, Sqlite>) -> Result<String> {
+        let country = sim_country(&mut **transaction, &self.sim_id).await?;
+        Ok(to_e164(&self.contact, country))
     }
 
+    /// Stores the message, or returns the id of the identical message already stored
     pub async fn insert(&self) -> Result<i64> {
         let pool = get_pool()?;
 
@@ -682,50 +1838,77 @@ impl ModemSMS {
         &self,
         transaction: &mut Transaction<'_, Sqlite>,
     ) -> Result<i64> {
-        let contact_id = self.get_contact_id(transaction).await?;
+        let number = self.number(transaction).await?;
+        let contact_id = match contact_id_by_number(transaction, &number).await? {
+            Some(contact_id) => contact_id,
+            None => create_contact_for_number(transaction, &number).await?,
+        };
+        if let Some(sms_id) = self.insert_if_new(&contact_id, &number, transaction).await? {
+            return Ok(sms_id);
+        }
+        let sms_id = sqlx::query_scalar("SELECT id FROM sms WHERE fingerprint = ?")
+            .bind(self.fingerprint(&number))
+            .fetch_one(&mut **transaction)
+            .await?;
+        Ok(sms_id)
+    }
+
+    /// Inserts the message unless one with the same fingerprint exists; returns the new id
+    async fn insert_if_new(
+        &self,
+        contact_id: &str,
+        number: &str,
+        transaction: &mut Transaction<'_, Sqlite>,
+    ) -> Result<Option<i64>> {
+        let stored = self.stored_timestamp();
 
-        //When send is true, status defaults to Read
+        // Outgoing messages and messages from blocked contacts are stored as read
         let sms_id = sqlx::query_scalar::<_, i64>(
             r#"
-            INSERT INTO sms (contact_id, timestamp, message, sim_id, send, status)
-            VALUES (?, ?, ?, ?, ?, ?) RETURNING id
+            INSERT INTO sms (contact_id, timestamp, utc_offset, received_at, message, sim_id, send, status, fingerprint)
+            SELECT id, ?, ?, ?, ?, ?, ?, CASE WHEN ? OR blocked THEN ? ELSE ? END, ?
+            FROM contacts WHERE id = ?
+            ON CONFLICT (fingerprint) DO NOTHING
+            RETURNING id
             "#,
         )
-        .bind(contact_id)
-        .bind(self.timestamp)
+        .bind(stored.timestamp)
+        .bind(stored.utc_offset)
+        .bind(self.received_at)
         .bind(&self.message)
         .bind(&self.sim_id)
         .bind(self.send)
-        .bind(if self.send {
-            SmsStatus::Read as i32
-        } else {
-            SmsStatus::Unread as i32
-        })
-        .fetch_one(&mut **transaction)
+        .bind(self.send)
+        .bind(SmsStatus::Read as i32)
+        .bind(SmsStatus::Unread as i32)
+        .bind(self.fingerprint(number))
+        .bind(contact_id)
+        .fetch_optional(&mut **transaction)
         .await?;
 
         Ok(sms_id)
     }
-    pub async fn bulk_insert(records: &[Self]) -> Result<Vec<String>> {
+    pub async fn bulk_insert(records: &mut [Self]) -> Result<Vec<String>> {
         let pool = get_pool()?;
 
         let mut transaction = pool.begin().await?;
 
-        let mut contact_names = HashSet::new();
-        for record in records {
-            contact_names.insert(record.contact.clone());
+        let mut countries = HashMap::new();
+        for record in records.iter() {
+            if let Entry::Vacant(entry) = countries.entry(record.sim_id.clone()) {
+                entry.insert(sim_country(&mut *transaction, &record.sim_id).await?);
+            }
         }
-
-        let contact_names = contact_names
-            .iter().cloned()
-            .collect::<Vec<String>>();
+        let number = |record: &Self| to_e164(&record.contact, countries[&record.sim_id]);
+        let numbers = records.iter().map(number).collect::<HashSet<String>>();
 
         // 查询已存在的联系人
-        let mut query_builder = QueryBuilder::new("SELECT id, name FROM contacts WHERE name IN (");
+        let mut query_builder =
+            QueryBuilder::new("SELECT number, contact_id FROM contact_numbers WHERE number IN (");
 
         let mut separated = query_builder.separated(", ");
-        for contact in contact_names.iter() {
-            separated.push_bind(contact);
+        for number in numbers.iter() {
+            separated.push_bind(number);
         }
         separated.push_unseparated(") ");
 
@@ -734,63 +1917,996 @@ impl ModemSMS {
         let mut contact_map: HashMap<String, String> = rows
             .into_iter()
             .map(|row| {
-                let id: String = row.try_get(0).unwrap();
-                let name: String = row.try_get(1).unwrap();
+                let number: String = row.try_get(0).unwrap();
+                let id: String = row.try_get(1).unwrap();
 
-                (name, id)
+                (number, id)
             })
             .collect();
 
         // 插入新联系人
-        for contact_name in contact_names.iter() {
-            if !contact_map.contains_key(contact_name) {
-                let uuid = Uuid::new_v4().to_string();
-
-                sqlx::query(
-                    r#"
-                    INSERT INTO contacts (id, name) VALUES (?, ?)
-                    "#,
-                )
-                .bind(&uuid)
-                .bind(contact_name)
-                .execute(&mut *transaction)
-                .await?;
-
-                contact_map.insert(contact_name.clone(), uuid);
+        for number in numbers {
+            if let Entry::Vacant(entry) = contact_map.entry(number) {
+                let uuid = create_contact_for_number(&mut transaction, entry.key()).await?;
+                entry.insert(uuid);
             }
         }
 
-        for chunk in records.chunks(MAX_BATCH_SIZE) {
-            let mut query_builder = QueryBuilder::new(
-                "INSERT INTO sms (contact_id, timestamp, message, sim_id, send, status) ",
-            );
-
-            query_builder.push_values(chunk, |mut b, sms| {
-                b.push_bind(contact_map.get(&sms.contact))
-                    .push_bind(sms.timestamp)
-                    .push_bind(&sms.message)
-                    .push_bind(&sms.sim_id)
-                    .push_bind(sms.send)
-                    .push_bind(if sms.send {
-                        SmsStatus::Read as i32
-                    } else {
-                        SmsStatus::Unread as i32
-                    });
-            });
-
-            query_builder.build().execute(&mut *transaction).await?;
+        // Row by row so every new record learns its id; messages already stored keep None
+        let mut changed_contacts = HashSet::new();
+        for sms in records.iter_mut() {
+            let number = number(sms);
+            let contact_id = &contact_map[&number];
+            sms.id = sms.insert_if_new(contact_id, &number, &mut transaction).await?;
+            if sms.id.is_some() {
+                changed_contacts.insert(contact_id.clone());
+            }
         }
 
         transaction.commit().await?;
 
-        Ok(contact_map.into_values().collect())
+        Ok(changed_contacts.into_iter().collect())
     }
 }
 
-/// Initializes SQLite database
-pub async fn db_init() -> Result<()> {
-    #[cfg(debug_assertions)]
-    let db_path = "sqlite://./data/data.db";
+impl StoredWebhook {
+    pub async fn query_all() -> Result<Vec<Self>> {
+        let pool = get_pool()?;
+        let webhooks = sqlx::query_as(
+            "SELECT id, name, enabled, config, created_at, updated_at FROM webhooks ORDER BY id",
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(webhooks)
+    }
+
+    pub async fn query_by_id(id: i64) -> Result<Option<Self>> {
+        let pool = get_pool()?;
+        let webhook = sqlx::query_as(
+            "SELECT id, name, enabled, config, created_at, updated_at FROM webhooks WHERE id = ?",
+        )
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+        Ok(webhook)
+    }
+
+    pub async fn insert(
+        name: Option<&str>,
+        enabled: bool,
+        config: &serde_json::Value,
+    ) -> Result<Self> {
+        let pool = get_pool()?;
+        let webhook = sqlx::query_as(
+            r#"
+            INSERT INTO webhooks (name, enabled, config) VALUES (?, ?, ?)
+            RETURNING id, name, enabled, config, created_at, updated_at
+            "#,
+        )
+        .bind(name)
+        .bind(enabled)
+        .bind(sqlx::types::Json(config))
+        .fetch_one(pool)
+        .await?;
+        Ok(webhook)
+    }
+
+    pub async fn update(
+        id: i64,
+        name: Option<&str>,
+        enabled: bool,
+        config: &serde_json::Value,
+    ) -> Result<Option<Self>> {
+        let pool = get_pool()?;
+        let webhook = sqlx::query_as(
+            r#"
+            UPDATE webhooks SET name = ?, enabled = ?, config = ?, updated_at = CURRENT_TIMESTAMP
+            WHERE id = ?
+            RETURNING id, name, enabled, config, created_at, updated_at
+            "#,
+        )
+        .bind(name)
+        .bind(enabled)
+        .bind(sqlx::types::Json(config))
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+        Ok(webhook)
+    }
+
+    pub async fn delete_by_id(id: i64) -> Result<bool> {
+        let pool = get_pool()?;
+        let result = sqlx::query("DELETE FROM webhooks WHERE id = ?")
+            .bind(id)
+            .execute(pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+const AUTO_REPLY_COLUMNS: &str = "id, name, enabled, priority, config, created_at, updated_at";
+
+impl StoredAutoReply {
+    /// Rules in the order they are evaluated
+    pub async fn query_all() -> Result<Vec<Self>> {
+        let pool = get_pool()?;
+        let rules = sqlx::query_as(&format!(
+            "SELECT {} FROM auto_reply_rules ORDER BY priority, id",
+            AUTO_REPLY_COLUMNS
+        ))
+        .fetch_all(pool)
+        .await?;
+        Ok(rules)
+    }
+
+    pub async fn query_by_id(id: i64) -> Result<Option<Self>> {
+        let pool = get_pool()?;
+        let rule = sqlx::query_as(&format!(
+            "SELECT {} FROM auto_reply_rules WHERE id = ?",
+            AUTO_REPLY_COLUMNS
+        ))
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+        Ok(rule)
+    }
+
+    pub async fn insert(
+        name: Option<&str>,
+        enabled: bool,
+        priority: i64,
+        config: &serde_json::Value,
+    ) -> Result<Self> {
+        let pool = get_pool()?;
+        let rule = sqlx::query_as(&format!(
+            "INSERT INTO auto_reply_rules (name, enabled, priority, config) VALUES (?, ?, ?, ?) \
+             RETURNING {}",
+            AUTO_REPLY_COLUMNS
+        ))
+        .bind(name)
+        .bind(enabled)
+        .bind(priority)
+        .bind(sqlx::types::Json(config))
+        .fetch_one(pool)
+        .await?;
+        Ok(rule)
+    }
+
+    pub async fn update(
+        id: i64,
+        name: Option<&str>,
+        enabled: bool,
+        priority: i64,
+        config: &serde_json::Value,
+    ) -> Result<Option<Self>> {
+        let pool = get_pool()?;
+        let rule = sqlx::query_as(&format!(
+            r#"
+            UPDATE auto_reply_rules
+            SET name = ?, enabled = ?, priority = ?, config = ?, updated_at = CURRENT_TIMESTAMP
+            WHERE id = ?
+            RETURNING {}
+            "#,
+            AUTO_REPLY_COLUMNS
+        ))
+        .bind(name)
+        .bind(enabled)
+        .bind(priority)
+        .bind(sqlx::types::Json(config))
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+        Ok(rule)
+    }
+
+    pub async fn delete_by_id(id: i64) -> Result<bool> {
+        let pool = get_pool()?;
+        let result = sqlx::query("DELETE FROM auto_reply_rules WHERE id = ?")
+            .bind(id)
+            .execute(pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+impl AutoReplyEvent {
+    pub async fn insert(&self) -> Result<()> {
+        let pool = get_pool()?;
+        sqlx::query(
+            r#"
+            INSERT INTO auto_reply_events
+                (rule_id, contact, sim_id, action, recipient, sms_id, error, created_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(self.rule_id)
+        .bind(&self.contact)
+        .bind(&self.sim_id)
+        .bind(self.action)
+        .bind(&self.recipient)
+        .bind(self.sms_id)
+        .bind(&self.error)
+        .bind(Utc::now())
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// When `rule_id` last acted on a message from `contact`
+    pub async fn last_for(rule_id: i64, contact: &str) -> Result<Option<DateTime<Utc>>> {
+        let pool = get_pool()?;
+        let last = sqlx::query_scalar(
+            "SELECT MAX(created_at) FROM auto_reply_events WHERE rule_id = ? AND contact = ?",
+        )
+        .bind(rule_id)
+        .bind(contact)
+        .fetch_one(pool)
+        .await?;
+        Ok(last)
+    }
+
+    /// Automatic messages sent to `recipient` since `since`, by any rule
+    pub async fn count_sent_to(recipient: &str, since: DateTime<Utc>) -> Result<i64> {
+        let pool = get_pool()?;
+        let count = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM auto_reply_events WHERE recipient = ? AND created_at >= ?",
+        )
+        .bind(recipient)
+        .bind(since)
+        .fetch_one(pool)
+        .await?;
+        Ok(count)
+    }
+}
+
+impl Suppression {
+    pub async fn query_all() -> Result<Vec<Self>> {
+        let pool = get_pool()?;
+        let suppressions = sqlx::query_as(
+            "SELECT number, source, sim_id, reason, user_id, created_at FROM suppressions \
+             ORDER BY created_at DESC",
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(suppressions)
+    }
+
+    pub async fn query_by_number(number: &str) -> Result<Option<Self>> {
+        let pool = get_pool()?;
+        let suppression = sqlx::query_as(
+            "SELECT number, source, sim_id, reason, user_id, created_at FROM suppressions \
+             WHERE number = ?",
+        )
+        .bind(number)
+        .fetch_optional(pool)
+        .await?;
+        Ok(suppression)
+    }
+
+    pub async fn contains(number: &str) -> Result<bool> {
+        Ok(Self::query_by_number(number).await?.is_some())
+    }
+
+    /// Every suppressed number
+    pub async fn numbers() -> Result<HashSet<String>> {
+        let pool = get_pool()?;
+        let numbers = sqlx::query_scalar("SELECT number FROM suppressions")
+            .fetch_all(pool)
+            .await?;
+        Ok(numbers.into_iter().collect())
+    }
+
+    /// False when the number was already suppressed, which keeps the original entry
+    pub async fn insert(&self) -> Result<bool> {
+        let pool = get_pool()?;
+        let result = sqlx::query(
+            r#"
+            INSERT OR IGNORE INTO suppressions (number, source, sim_id, reason, user_id, created_at)
+            VALUES (?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&self.number)
+        .bind(self.source)
+        .bind(&self.sim_id)
+        .bind(&self.reason)
+        .bind(self.user_id)
+        .bind(self.created_at)
+        .execute(pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn delete(number: &str) -> Result<bool> {
+        let pool = get_pool()?;
+        let result = sqlx::query("DELETE FROM suppressions WHERE number = ?")
+            .bind(number)
+            .execute(pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+const API_KEY_COLUMNS: &str =
+    "id, name, prefix, scopes, sim_ids, expires_at, last_used_at, created_at";
+
+impl ApiKey {
+    pub async fn query_all() -> Result<Vec<Self>> {
+        let pool = get_pool()?;
+        let keys = sqlx::query_as(&format!("SELECT {} FROM api_keys ORDER BY id", API_KEY_COLUMNS))
+            .fetch_all(pool)
+            .await?;
+        Ok(keys)
+    }
+
+    pub async fn query_by_id(id: i64) -> Result<Option<Self>> {
+        let pool = get_pool()?;
+        let key = sqlx::query_as(&format!("SELECT {} FROM api_keys WHERE id = ?", API_KEY_COLUMNS))
+            .bind(id)
+            .fetch_optional(pool)
+            .await?;
+        Ok(key)
+    }
+
+    pub async fn find_by_hash(key_hash: &str) -> Result<Option<Self>> {
+        let pool = get_pool()?;
+        let key = sqlx::query_as(&format!(
+            "SELECT {} FROM api_keys WHERE key_hash = ?",
+            API_KEY_COLUMNS
+        ))
+        .bind(key_hash)
+        .fetch_optional(pool)
+        .await?;
+        Ok(key)
+    }
+
+    pub async fn insert(
+        name: &str,
+        prefix: &str,
+        key_hash: &str,
+        scopes: &[Scope],
+        sim_ids: Option<&[String]>,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<Self> {
+        let pool = get_pool()?;
+        let key = sqlx::query_as(&format!(
+            r#"
+            INSERT INTO api_keys (name, prefix, key_hash, scopes, sim_ids, expires_at)
+            VALUES (?, ?, ?, ?, ?, ?)
+            RETURNING {}
+            "#,
+            API_KEY_COLUMNS
+        ))
+        .bind(name)
+        .bind(prefix)
+        .bind(key_hash)
+        .bind(sqlx::types::Json(scopes))
+        .bind(sim_ids.map(sqlx::types::Json))
+        .bind(expires_at)
+        .fetch_one(pool)
+        .await?;
+        Ok(key)
+    }
+
+    pub async fn update(
+        id: i64,
+        name: &str,
+        scopes: &[Scope],
+        sim_ids: Option<&[String]>,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<Option<Self>> {
+        let pool = get_pool()?;
+        let key = sqlx::query_as(&format!(
+            r#"
+            UPDATE api_keys SET name = ?, scopes = ?, sim_ids = ?, expires_at = ?
+            WHERE id = ?
+            RETURNING {}
+            "#,
+            API_KEY_COLUMNS
+        ))
+        .bind(name)
+        .bind(sqlx::types::Json(scopes))
+        .bind(sim_ids.map(sqlx::types::Json))
+        .bind(expires_at)
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+        Ok(key)
+    }
+
+    pub async fn touch(id: i64) -> Result<()> {
+        let pool = get_pool()?;
+        sqlx::query("UPDATE api_keys SET last_used_at = ? WHERE id = ?")
+            .bind(Utc::now())
+            .bind(id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn delete_by_id(id: i64) -> Result<bool> {
+        let pool = get_pool()?;
+        let result = sqlx::query("DELETE FROM api_keys WHERE id = ?")
+            .bind(id)
+            .execute(pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= Utc::now())
+    }
+}
+
+const USER_COLUMNS: &str =
+    "id, username, password_hash, role, sim_ids, disabled, created_at, updated_at";
+
+impl User {
+    pub async fn query_all() -> Result<Vec<Self>> {
+        let pool = get_pool()?;
+        let users = sqlx::query_as(&format!("SELECT {} FROM users ORDER BY id", USER_COLUMNS))
+            .fetch_all(pool)
+            .await?;
+        Ok(users)
+    }
+
+    pub async fn query_by_id(id: i64) -> Result<Option<Self>> {
+        let pool = get_pool()?;
+        let user = sqlx::query_as(&format!("SELECT {} FROM users WHERE id = ?", USER_COLUMNS))
+            .bind(id)
+            .fetch_optional(pool)
+            .await?;
+        Ok(user)
+    }
+
+    pub async fn find_by_username(username: &str) -> Result<Option<Self>> {
+        let pool = get_pool()?;
+        let user = sqlx::query_as(&format!("SELECT {} FROM users WHERE username = ?", USER_COLUMNS))
+            .bind(username)
+            .fetch_optional(pool)
+            .await?;
+        Ok(user)
+    }
+
+    pub async fn any_exists() -> Result<bool> {
+        let pool = get_pool()?;
+        let exists = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM users)")
+            .fetch_one(pool)
+            .await?;
+        Ok(exists)
+    }
+
+    pub async fn insert(
+        username: &str,
+        password_hash: &str,
+        role: Role,
+        sim_ids: Option<&[String]>,
+    ) -> Result<Self> {
+        let pool = get_pool()?;
+        let user = sqlx::query_as(&format!(
+            r#"
+            INSERT INTO users (username, password_hash, role, sim_ids) VALUES (?, ?, ?, ?)
+            RETURNING {}
+            "#,
+            USER_COLUMNS
+        ))
+        .bind(username)
+        .bind(password_hash)
+        .bind(role)
+        .bind(sim_ids.map(sqlx::types::Json))
+        .fetch_one(pool)
+        .await?;
+        Ok(user)
+    }
+
+    /// Updates the account; the password is kept when `password_hash` is None
+    pub async fn update(
+        id: i64,
+        username: &str,
+        password_hash: Option<&str>,
+        role: Role,
+        sim_ids: Option<&[String]>,
+        disabled: bool,
+    ) -> Result<Option<Self>> {
+        let pool = get_pool()?;
+        let user = sqlx::query_as(&format!(
+            r#"
+            UPDATE users
+            SET username = ?, password_hash = COALESCE(?, password_hash), role = ?, sim_ids = ?,
+                disabled = ?, updated_at = CURRENT_TIMESTAMP
+            WHERE id = ?
+            RETURNING {}
+            "#,
+            USER_COLUMNS
+        ))
+        .bind(username)
+        .bind(password_hash)
+        .bind(role)
+        .bind(sim_ids.map(sqlx::types::Json))
+        .bind(disabled)
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+        Ok(user)
+    }
+
+    pub async fn delete_by_id(id: i64) -> Result<bool> {
+        let pool = get_pool()?;
+        let result = sqlx::query("DELETE FROM users WHERE id = ?")
+            .bind(id)
+            .execute(pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// The enabled user owning an unexpired session
+    pub async fn find_by_session(token_hash: &str) -> Result<Option<Self>> {
+        let pool = get_pool()?;
+        let user = sqlx::query_as(
+            r#"
+            SELECT u.id, u.username, u.password_hash, u.role, u.sim_ids, u.disabled,
+                   u.created_at, u.updated_at
+            FROM sessions s
+            INNER JOIN users u ON u.id = s.user_id
+            WHERE s.token_hash = ? AND s.expires_at > ? AND u.disabled = 0
+            "#,
+        )
+        .bind(token_hash)
+        .bind(Utc::now())
+        .fetch_optional(pool)
+        .await?;
+        Ok(user)
+    }
+
+    pub async fn create_session(&self, token_hash: &str, expires_at: DateTime<Utc>) -> Result<()> {
+        let pool = get_pool()?;
+        sqlx::query("INSERT INTO sessions (token_hash, user_id, expires_at) VALUES (?, ?, ?)")
+            .bind(token_hash)
+            .bind(self.id)
+            .bind(expires_at)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn delete_session(token_hash: &str) -> Result<()> {
+        let pool = get_pool()?;
+        sqlx::query("DELETE FROM sessions WHERE token_hash = ? OR expires_at <= ?")
+            .bind(token_hash)
+            .bind(Utc::now())
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Logs the user out everywhere
+    pub async fn delete_sessions(id: i64) -> Result<()> {
+        let pool = get_pool()?;
+        sqlx::query("DELETE FROM sessions WHERE user_id = ?")
+            .bind(id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+}
+
+impl AuditEntry {
+    #[allow(clippy::too_many_arguments)]
+    pub async fn insert(
+        actor: &str,
+        user_id: Option<i64>,
+        action: &str,
+        target: Option<&str>,
+        before: Option<serde_json::Value>,
+        after: Option<serde_json::Value>,
+        source_ip: Option<&str>,
+    ) -> Result<()> {
+        let pool = get_pool()?;
+        sqlx::query(
+            r#"
+            INSERT INTO audit_log
+                (timestamp, actor, user_id, action, target, before_value, after_value, source_ip)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(Utc::now())
+        .bind(actor)
+        .bind(user_id)
+        .bind(action)
+        .bind(target)
+        .bind(before.map(sqlx::types::Json))
+        .bind(after.map(sqlx::types::Json))
+        .bind(source_ip)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    fn push_filter(query: &mut QueryBuilder<'_, Sqlite>, filter: &AuditFilter) {
+        if let Some(actor) = &filter.actor {
+            query.push(" AND actor = ").push_bind(actor.clone());
+        }
+        if let Some(user_id) = filter.user_id {
+            query.push(" AND user_id = ").push_bind(user_id);
+        }
+        if let Some(action) = &filter.action {
+            query
+                .push(" AND substr(action, 1, length(")
+                .push_bind(action.clone())
+                .push(")) = ")
+                .push_bind(action.clone());
+        }
+        if let Some(target) = &filter.target {
+            query.push(" AND target = ").push_bind(target.clone());
+        }
+        if let Some(since) = filter.since {
+            query.push(" AND timestamp >= ").push_bind(since);
+        }
+        if let Some(until) = filter.until {
+            query.push(" AND timestamp < ").push_bind(until);
+        }
+    }
+
+    /// Newest first
+    pub async fn paginate(
+        filter: &AuditFilter,
+        page: u32,
+        per_page: u32,
+    ) -> Result<(Vec<Self>, i64)> {
+        if page == 0 {
+            return Err(anyhow::anyhow!("Page number must be greater than 0"));
+        }
+        let offset = (page - 1) * per_page;
+        let pool = get_pool()?;
+
+        let mut query = QueryBuilder::new(
+            "SELECT id, timestamp, actor, user_id, action, target, before_value, after_value, source_ip \
+             FROM audit_log WHERE 1 = 1",
+        );
+        Self::push_filter(&mut query, filter);
+        query
+            .push(" ORDER BY id DESC LIMIT ")
+            .push_bind(per_page as i32)
+            .push(" OFFSET ")
+            .push_bind(offset as i32);
+        let entries = query.build_query_as().fetch_all(pool).await?;
+
+        let mut count = QueryBuilder::new("SELECT COUNT(*) FROM audit_log WHERE 1 = 1");
+        Self::push_filter(&mut count, filter);
+        let total = count.build_query_scalar().fetch_one(pool).await?;
+
+        Ok((entries, total))
+    }
+
+    /// Deletes entries older than `cutoff`, returning how many were removed
+    pub async fn purge_before(cutoff: DateTime<Utc>) -> Result<u64> {
+        let pool = get_pool()?;
+        let result = sqlx::query("DELETE FROM audit_log WHERE timestamp < ?")
+            .bind(cutoff)
+            .execute(pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+}
+
+impl Campaign {
+    /// Stores a running campaign with its recipients
+    pub async fn insert(
+        name: &str,
+        template: &str,
+        sim_ids: &[String],
+        per_minute: u32,
+        user_id: Option<i64>,
+        recipients: &[NewRecipient],
+    ) -> Result<Self> {
+        let pool = get_pool()?;
+        let mut transaction = pool.begin().await?;
+        let now = Utc::now();
+
+        let campaign: Self = sqlx::query_as(&format!(
+            r#"
+            INSERT INTO campaigns (name, template, sim_ids, per_minute, status, user_id, created_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+            RETURNING {}
+            "#,
+            CAMPAIGN_COLUMNS
+        ))
+        .bind(name)
+        .bind(template)
+        .bind(sqlx::types::Json(sim_ids))
+        .bind(per_minute)
+        .bind(CampaignStatus::Running)
+        .bind(user_id)
+        .bind(now)
+        .fetch_one(&mut *transaction)
+        .await?;
+
+        for chunk in recipients.chunks(MAX_BATCH_SIZE) {
+            let mut query = QueryBuilder::new(
+                "INSERT INTO campaign_recipients \
+                 (campaign_id, number, contact_id, variables, status, error, updated_at) ",
+            );
+            query.push_values(chunk, |mut row, recipient| {
+                let status = match recipient.skipped {
+                    Some(_) => RecipientStatus::Skipped,
+                    None => RecipientStatus::Pending,
+                };
+                row.push_bind(campaign.id)
+                    .push_bind(&recipient.number)
+                    .push_bind(&recipient.contact_id)
+                    .push_bind(sqlx::types::Json(&recipient.variables))
+                    .push_bind(status)
+                    .push_bind(&recipient.skipped)
+                    .push_bind(now);
+            });
+            query.build().execute(&mut *transaction).await?;
+        }
+
+        transaction.commit().await?;
+        Ok(campaign)
+    }
+
+    /// Newest first
+    pub async fn query_all() -> Result<Vec<Self>> {
+        let pool = get_pool()?;
+        let campaigns = sqlx::query_as(&format!(
+            "SELECT {} FROM campaigns ORDER BY id DESC",
+            CAMPAIGN_COLUMNS
+        ))
+        .fetch_all(pool)
+        .await?;
+        Ok(campaigns)
+    }
+
+    pub async fn query_by_id(id: i64) -> Result<Option<Self>> {
+        let pool = get_pool()?;
+        let campaign = sqlx::query_as(&format!("SELECT {} FROM campaigns WHERE id = ?", CAMPAIGN_COLUMNS))
+            .bind(id)
+            .fetch_optional(pool)
+            .await?;
+        Ok(campaign)
+    }
+
+    pub async fn query_ids_by_status(status: CampaignStatus) -> Result<Vec<i64>> {
+        let pool = get_pool()?;
+        let ids = sqlx::query_scalar("SELECT id FROM campaigns WHERE status = ? ORDER BY id")
+            .bind(status)
+            .fetch_all(pool)
+            .await?;
+        Ok(ids)
+    }
+
+    /// Moves the campaign to `status` if it is in one of `from`. Cancelling also cancels the
+    /// recipients still pending. Returns whether the campaign changed.
+    pub async fn transition(
+        id: i64,
+        from: &[CampaignStatus],
+        status: CampaignStatus,
+        last_error: Option<&str>,
+    ) -> Result<bool> {
+        let pool = get_pool()?;
+        let mut transaction = pool.begin().await?;
+        let finished = matches!(status, CampaignStatus::Cancelled | CampaignStatus::Completed);
+
+        let mut query = QueryBuilder::new("UPDATE campaigns SET status = ");
+        query
+            .push_bind(status)
+            .push(", last_error = ")
+            .push_bind(last_error)
+            .push(", finished_at = ")
+            .push_bind(finished.then(Utc::now))
+            .push(" WHERE id = ")
+            .push_bind(id)
+            .push(" AND status IN (");
+        let mut separated = query.separated(", ");
+        for from in from {
+            separated.push_bind(*from);
+        }
+        query.push(")");
+        let changed = query.build().execute(&mut *transaction).await?.rows_affected() > 0;
+
+        if changed && status == CampaignStatus::Cancelled {
+            sqlx::query(
+                "UPDATE campaign_recipients SET status = ?, updated_at = ? \
+                 WHERE campaign_id = ? AND status = ?",
+            )
+            .bind(RecipientStatus::Cancelled)
+            .bind(Utc::now())
+            .bind(id)
+            .bind(RecipientStatus::Pending)
+            .execute(&mut *transaction)
+            .await?;
+        }
+
+        transaction.commit().await?;
+        Ok(changed)
+    }
+
+    /// Completes a running campaign that has nobody left to send to
+    pub async fn complete_if_done(id: i64) -> Result<bool> {
+        let pool = get_pool()?;
+        let result = sqlx::query(
+            r#"
+            UPDATE campaigns SET status = ?, last_error = NULL, finished_at = ?
+            WHERE id = ? AND status = ? AND NOT EXISTS (
+                SELECT 1 FROM campaign_recipients WHERE campaign_id = ? AND status IN (?, ?)
+            )
+            "#,
+        )
+        .bind(CampaignStatus::Completed)
+        .bind(Utc::now())
+        .bind(id)
+        .bind(CampaignStatus::Running)
+        .bind(id)
+        .bind(RecipientStatus::Pending)
+        .bind(RecipientStatus::Sending)
+        .execute(pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn progress(id: i64) -> Result<CampaignProgress> {
+        let pool = get_pool()?;
+        let progress = sqlx::query_as(
+            r#"
+            SELECT
+                COALESCE(SUM(status = 'pending'), 0) AS pending,
+                COALESCE(SUM(status = 'sending'), 0) AS sending,
+                COALESCE(SUM(status = 'sent'), 0) AS sent,
+                COALESCE(SUM(status = 'failed'), 0) AS failed,
+                COALESCE(SUM(status = 'skipped'), 0) AS skipped,
+                COALESCE(SUM(status = 'cancelled'), 0) AS cancelled
+            FROM campaign_recipients WHERE campaign_id = ?
+            "#,
+        )
+        .bind(id)
+        .fetch_one(pool)
+        .await?;
+        Ok(progress)
+    }
+
+    /// Sent and failed messages of each SIM
+    pub async fn sim_tallies(id: i64) -> Result<Vec<CampaignSimTally>> {
+        let pool = get_pool()?;
+        let tallies = sqlx::query_as(
+            r#"
+            SELECT sim_id, SUM(status = 'sent') AS sent, SUM(status = 'failed') AS failed
+            FROM campaign_recipients WHERE campaign_id = ? AND sim_id IS NOT NULL
+            GROUP BY sim_id ORDER BY sim_id
+            "#,
+        )
+        .bind(id)
+        .fetch_all(pool)
+        .await?;
+        Ok(tallies)
+    }
+
+    pub async fn issues(id: i64) -> Result<Vec<CampaignIssue>> {
+        let pool = get_pool()?;
+        let issues = sqlx::query_as(
+            r#"
+            SELECT status, error, COUNT(*) AS recipients
+            FROM campaign_recipients WHERE campaign_id = ? AND status IN (?, ?, ?)
+            GROUP BY status, error ORDER BY recipients DESC
+            "#,
+        )
+        .bind(id)
+        .bind(RecipientStatus::Failed)
+        .bind(RecipientStatus::Skipped)
+        .bind(RecipientStatus::Cancelled)
+        .fetch_all(pool)
+        .await?;
+        Ok(issues)
+    }
+
+    /// Recipients in the order they are sent to, optionally only those in `status`
+    pub async fn query_recipients(
+        id: i64,
+        status: Option<RecipientStatus>,
+        page: u32,
+        per_page: u32,
+    ) -> Result<(Vec<CampaignRecipient>, i64)> {
+        if page == 0 {
+            return Err(anyhow::anyhow!("Page number must be greater than 0"));
+        }
+        let pool = get_pool()?;
+
+        let mut query = QueryBuilder::new(format!(
+            "SELECT {} FROM campaign_recipients WHERE campaign_id = ",
+            CAMPAIGN_RECIPIENT_COLUMNS
+        ));
+        query.push_bind(id);
+        let mut count = QueryBuilder::new("SELECT COUNT(*) FROM campaign_recipients WHERE campaign_id = ");
+        count.push_bind(id);
+        if let Some(status) = status {
+            query.push(" AND status = ").push_bind(status);
+            count.push(" AND status = ").push_bind(status);
+        }
+        query
+            .push(" ORDER BY id LIMIT ")
+            .push_bind(per_page as i32)
+            .push(" OFFSET ")
+            .push_bind(((page - 1) * per_page) as i32);
+
+        let recipients = query.build_query_as().fetch_all(pool).await?;
+        let total = count.build_query_scalar().fetch_one(pool).await?;
+        Ok((recipients, total))
+    }
+
+    /// Takes the next pending recipient for `sim_id` to send to
+    pub async fn claim_next(id: i64, sim_id: &str) -> Result<Option<CampaignRecipient>> {
+        let pool = get_pool()?;
+        let recipient = sqlx::query_as(&format!(
+            r#"
+            UPDATE campaign_recipients SET status = ?, sim_id = ?, updated_at = ?
+            WHERE id = (
+                SELECT id FROM campaign_recipients WHERE campaign_id = ? AND status = ?
+                ORDER BY id LIMIT 1
+            ) AND status = ?
+            RETURNING {}
+            "#,
+            CAMPAIGN_RECIPIENT_COLUMNS
+        ))
+        .bind(RecipientStatus::Sending)
+        .bind(sim_id)
+        .bind(Utc::now())
+        .bind(id)
+        .bind(RecipientStatus::Pending)
+        .bind(RecipientStatus::Pending)
+        .fetch_optional(pool)
+        .await?;
+        Ok(recipient)
+    }
+
+    /// Recipients left in `sending` by a restart may or may not have been sent; they are failed
+    /// rather than sent twice
+    pub async fn fail_interrupted() -> Result<u64> {
+        let pool = get_pool()?;
+        let result = sqlx::query(
+            "UPDATE campaign_recipients SET status = ?, error = ?, updated_at = ? WHERE status = ?",
+        )
+        .bind(RecipientStatus::Failed)
+        .bind("Interrupted by a restart")
+        .bind(Utc::now())
+        .bind(RecipientStatus::Sending)
+        .execute(pool)
+        .await?;
+        Ok(result.rows_affected())
+    }
+}
+
+impl CampaignRecipient {
+    /// Records the outcome of sending to the recipient
+    pub async fn finish(
+        &self,
+        status: RecipientStatus,
+        contact_id: Option<&str>,
+        sms_id: Option<i64>,
+        error: Option<&str>,
+    ) -> Result<()> {
+        let pool = get_pool()?;
+        sqlx::query(
+            r#"
+            UPDATE campaign_recipients
+            SET status = ?, contact_id = COALESCE(?, contact_id), sms_id = ?, error = ?, updated_at = ?
+            WHERE id = ?
+            "#,
+        )
+        .bind(status)
+        .bind(contact_id)
+        .bind(sms_id)
+        .bind(error)
+        .bind(Utc::now())
+        .bind(self.id)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+}
+
+/// Initializes SQLite database
+pub async fn db_init() -> Result<()> {
+    #[cfg(debug_assertions)]
+    let db_path = "sqlite://./data/data.db";
     #[cfg(not(debug_assertions))]
     let db_path = "sqlite:///var/lib/sms-gateway/data.db";
 
@@ -804,18 +2920,7 @@ pub async fn db_init() -> Result<()> {
         std::fs::create_dir_all("/var/lib/sms-gateway")?;
     }
 
-    if !sqlx::Sqlite::database_exists(db_path).await? {
-        sqlx::Sqlite::create_database(db_path).await?;
-    };
-
-    let pool = SqlitePoolOptions::new()
-        .max_connections(5)
-        .connect(db_path)
-        .await?;
-
-    migrate!("./migrations").run(&pool).await?;
-
-    POOL.set(pool)
+    POOL.set(connect(db_path).await?)
         .map_err(|_| anyhow::anyhow!("Failed to initialize database connection pool"))?;
 
     tokio::spawn(async {
@@ -832,8 +2937,153 @@ pub async fn db_init() -> Result<()> {
     Ok(())
 }
 
+/// Converts rows stored before offsets were kept, which hold local wall-clock time, to UTC with
+/// the gateway's offset. Their fingerprints are cleared so they are computed again from UTC.
+async fn backfill_utc_timestamps(pool: &SqlitePool) -> Result<()> {
+    for table in ["sms", "deleted_sms"] {
+        let clear_fingerprint = if table == "sms" { ", fingerprint = NULL" } else { "" };
+        let update = format!(
+            "UPDATE {} SET timestamp = ?, utc_offset = ?{} WHERE id = ?",
+            table, clear_fingerprint
+        );
+        loop {
+            let rows: Vec<(i64, NaiveDateTime)> = sqlx::query_as(&format!(
+                "SELECT id, timestamp FROM {} WHERE utc_offset IS NULL ORDER BY id LIMIT 1000",
+                table
+            ))
+            .fetch_all(pool)
+            .await?;
+            if rows.is_empty() {
+                break;
+            }
+
+            let mut transaction = pool.begin().await?;
+            for (id, timestamp) in &rows {
+                let stored = SmsTimestamp::from_local(*timestamp);
+                sqlx::query(&update)
+                    .bind(stored.timestamp)
+                    .bind(stored.utc_offset)
+                    .bind(id)
+                    .execute(&mut *transaction)
+                    .await?;
+            }
+            transaction.commit().await?;
+        }
+    }
+    Ok(())
+}
+
+/// Fingerprints stored and deleted messages that have none, from the number of their contact.
+/// Runs after `Contact::normalize_numbers`, so the numbers are in E.164 as for new messages. Later
+/// copies of a message that is already stored stay without one, as the unique index allows only one.
+pub async fn backfill_fingerprints() -> Result<()> {
+    let pool = get_pool()?;
+    let mut duplicates = 0;
+    for table in ["sms", "deleted_sms"] {
+        let select = format!(
+            r#"
+            SELECT s.id, s.sim_id, COALESCE(c.name, ''), s.send, s.timestamp, s.message
+            FROM {} s LEFT JOIN contacts c ON c.id = s.contact_id
+            WHERE s.fingerprint IS NULL AND s.id > ?
+            ORDER BY s.id LIMIT 1000
+            "#,
+            table
+        );
+        let update = format!("UPDATE OR IGNORE {} SET fingerprint = ? WHERE id = ?", table);
+        let mut last_id = 0;
+        loop {
+            let rows = sqlx::query(&select).bind(last_id).fetch_all(pool).await?;
+            let Some(last) = rows.last() else {
+                break;
+            };
+            last_id = last.try_get(0)?;
+
+            let mut transaction = pool.begin().await?;
+            for row in &rows {
+                let id: i64 = row.try_get(0)?;
+                let fingerprint = fingerprint(
+                    row.try_get(1)?,
+                    row.try_get(2)?,
+                    row.try_get(3)?,
+                    row.try_get(4)?,
+                    None,
+                    row.try_get(5)?,
+                );
+                let result = sqlx::query(&update)
+                    .bind(fingerprint)
+                    .bind(id)
+                    .execute(&mut *transaction)
+                    .await?;
+                if result.rows_affected() == 0 {
+                    duplicates += 1;
+                }
+            }
+            transaction.commit().await?;
+        }
+    }
+    if duplicates > 0 {
+        log::warn!("{} stored messages are duplicates of earlier ones", duplicates);
+    }
+    Ok(())
+}
+
+/// Gives contacts from before contact numbers existed their name as number. When two names
+/// normalise to the same number, only the first contact gets it.
+async fn backfill_contact_numbers(pool: &SqlitePool) -> Result<()> {
+    let contacts: Vec<(String, String)> = sqlx::query_as(
+        "SELECT id, name FROM contacts WHERE id NOT IN (SELECT contact_id FROM contact_numbers)",
+    )
+    .fetch_all(pool)
+    .await?;
+    if contacts.is_empty() {
+        return Ok(());
+    }
+
+    let mut transaction = pool.begin().await?;
+    for (id, name) in &contacts {
+        sqlx::query("INSERT OR IGNORE INTO contact_numbers (number, contact_id) VALUES (?, ?)")
+            .bind(normalize(name))
+            .bind(id)
+            .execute(&mut *transaction)
+            .await?;
+    }
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Creates the database if needed and applies migrations
+async fn connect(db_path: &str) -> Result<SqlitePool> {
+    if !sqlx::Sqlite::database_exists(db_path).await? {
+        sqlx::Sqlite::create_database(db_path).await?;
+    };
+
+    let pool = SqlitePoolOptions::new()
+        .max_connections(5)
+        .connect(db_path)
+        .await?;
+
+    migrate!("./migrations").run(&pool).await?;
+    backfill_utc_timestamps(&pool).await?;
+    backfill_contact_numbers(&pool).await?;
+
+    Ok(pool)
+}
+
+/// Initializes the pool with a database at `db_path`, once per test run
+#[cfg(test)]
+pub async fn db_init_at(db_path: &str) -> Result<()> {
+    static INIT: tokio::sync::OnceCell<()> = tokio::sync::OnceCell::const_new();
+    INIT.get_or_try_init(|| async {
+        let pool = connect(db_path).await?;
+        POOL.set(pool)
+            .map_err(|_| anyhow::anyhow!("Database already initialized"))
+    })
+    .await?;
+    Ok(())
+}
+
 /// Retrieves the database connection pool
-fn get_pool() -> Result<&'static SqlitePool> {
+pub(crate) fn get_pool() -> Result<&'static SqlitePool> {
     POOL.get()
         .ok_or(anyhow::anyhow!("Database not initialized"))
 }
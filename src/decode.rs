@@ -1,13 +1,16 @@
-use chrono::NaiveDateTime;
+use chrono::{FixedOffset, NaiveDateTime, Utc};
 use fancy_regex::Regex;
 use std::collections::HashMap;
 
 use crate::db::ModemSMS;
+use crate::phone::{to_e164, Country};
 
 // --------- Multipart SMS Handler ----------
+type PartTimestamp = (NaiveDateTime, Option<FixedOffset>);
+
 struct MultipartHandler {
     // (reference number, total parts) -> (timestamp, sender, message parts, original indices)
-    pending_parts: HashMap<(u8, u8), (NaiveDateTime, String, Vec<Option<String>>, Vec<u32>)>,
+    pending_parts: HashMap<(u8, u8), (PartTimestamp, String, Vec<Option<String>>, Vec<u32>)>,
 }
 
 impl MultipartHandler {
@@ -24,7 +27,7 @@ impl MultipartHandler {
         total: u8,
         current: u8,
         message: String,
-        timestamp: NaiveDateTime,
+        timestamp: PartTimestamp,
         sender: String,
         index: u32,
         sim_id: String,
@@ -54,17 +57,22 @@ impl MultipartHandler {
                 .2
                 .iter()
                 .filter_map(|x| x.as_ref())
-                .fold(String::new(), |acc, s| acc + s);
+                .fold(String::new(), |acc, s| acc + s.as_str());
 
             log::info!("多段短信组合完成: 引用{}, 总{}段, 最终消息长度: {}", reference, total, combined.len());
             
             // Create the result before removing from pending
             let result = Some(ModemSMS {
+                id: None,
                 contact: entry.1.clone(),
-                timestamp: entry.0,
+                timestamp: entry.0 .0,
+                utc_offset: entry.0 .1,
+                received_at: Some(Utc::now()),
                 message: combined,
                 send: false,
                 sim_id,
+                segments: total,
+                concat_ref: Some(reference),
             });
             
             // Remove the completed multipart message from pending
@@ -80,7 +88,9 @@ impl MultipartHandler {
 }
 
 // ---------- Main Parser Function ----------
-pub fn parse_pdu_sms(cmgl_entries: &str, sim_id: &str) -> Vec<ModemSMS> {
+/// Decodes a +CMGL listing. Senders are written in E.164, national numbers in the numbering plan
+/// of `country`.
+pub fn parse_pdu_sms(cmgl_entries: &str, sim_id: &str, country: Option<&Country>) -> Vec<ModemSMS> {
     let mut handler = MultipartHandler::new();
     let mut messages = Vec::new();
     let entry_re = Regex::new(r#"\+(CMGL): (\d+).*?\n([0-9A-F]+)"#).unwrap();
@@ -99,11 +109,11 @@ pub fn parse_pdu_sms(cmgl_entries: &str, sim_id: &str) -> Vec<ModemSMS> {
         // Parse basic headers
         let pdu_type = pdu[pos];
         pos += 1; // Skip PDU type
-        let sender = parse_sender(&pdu, &mut pos);
+        let sender = parse_sender(&pdu, &mut pos, country);
         pos += 1; // Skip protocol identifier
         let dcs = pdu[pos];
         pos += 1;
-        let timestamp = parse_timestamp(&pdu[pos..pos + 7]);
+        let (timestamp, utc_offset) = parse_timestamp(&pdu[pos..pos + 7]);
         pos += 7;
 
         // Parse message content
@@ -130,7 +140,7 @@ pub fn parse_pdu_sms(cmgl_entries: &str, sim_id: &str) -> Vec<ModemSMS> {
                     total,
                     current,
                     content,
-                    timestamp,
+                    (timestamp, utc_offset),
                     sender.clone(),
                     index,
                     String::from(sim_id),
@@ -142,11 +152,16 @@ pub fn parse_pdu_sms(cmgl_entries: &str, sim_id: &str) -> Vec<ModemSMS> {
             MessageContent::Single(content) => {
                 log::debug!("解析到单条短信: 索引{}, 内容长度: {}", index, content.len());
                 messages.push(ModemSMS {
+                    id: None,
                     contact: sender,
                     timestamp,
+                    utc_offset,
+                    received_at: Some(Utc::now()),
                     message: content,
                     send: false,
                     sim_id: sim_id.to_string(),
+                    segments: 1,
+                    concat_ref: None,
                 });
             }
         }
@@ -204,7 +219,7 @@ fn parse_message_content(bytes: &[u8], dcs: u8, _udl: usize, has_udhi: bool) ->
 }
 
 // ---------- Decoding Utilities ----------
-fn parse_sender(pdu: &[u8], pos: &mut usize) -> String {
+fn parse_sender(pdu: &[u8], pos: &mut usize, country: Option<&Country>) -> String {
     let sender_len = pdu[*pos] as usize;
     *pos += 1;
     let sender_type = pdu[*pos];
@@ -222,12 +237,14 @@ fn parse_sender(pdu: &[u8], pos: &mut usize) -> String {
             decode_alphanumeric_sender(sender_bytes, sender_len)
         }
         _ => {
-            // International or unknown type - use BCD decoding
-            let mut number = decode_bcd(sender_bytes, sender_len);
+            // International, national or unknown type - use BCD decoding
+            let number = decode_bcd(sender_bytes, sender_len);
+            let number = number.trim_end_matches(['F', 'f']);
             if (sender_type & 0x70) == 0x10 {
-                number.insert(0, '+');
+                format!("+{}", number)
+            } else {
+                to_e164(number, country)
             }
-            number.trim_end_matches(['F', 'f']).to_string()
         }
     }
 }
@@ -441,8 +458,14 @@ fn decode_bcd(bytes: &[u8], len: usize) -> String {
         .collect()
 }
 
-fn parse_timestamp(bytes: &[u8]) -> NaiveDateTime {
+/// SCTS as the SMSC's local time plus its UTC offset, when the offset is valid
+fn parse_timestamp(bytes: &[u8]) -> (NaiveDateTime, Option<FixedOffset>) {
     let decode = |b| ((b & 0x0F) * 10) + (b >> 4);
+
+    // Offset in quarter hours; bit 3 of the first semi-octet is the sign
+    let quarters = decode(bytes[6] & 0xF7) as i32;
+    let sign = if bytes[6] & 0x08 != 0 { -1 } else { 1 };
+    let offset = FixedOffset::east_opt(sign * quarters * 15 * 60);
     
     let year = 2000 + decode(bytes[0]) as i32;
     let month = decode(bytes[1]) as u32;
@@ -451,7 +474,9 @@ fn parse_timestamp(bytes: &[u8]) -> NaiveDateTime {
     let minute = decode(bytes[4]) as u32;
     let second = decode(bytes[5]) as u32;
     
-    chrono::NaiveDate::from_ymd_opt(year, month, day)
+    let timestamp = chrono::NaiveDate::from_ymd_opt(year, month, day)
         .and_then(|date| date.and_hms_opt(hour, minute, second))
-        .unwrap_or_else(|| chrono::NaiveDate::from_ymd_opt(2025, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap())
+        .unwrap_or_else(|| chrono::NaiveDate::from_ymd_opt(2025, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap());
+
+    (timestamp, offset)
 }
@@ -0,0 +1,232 @@
+use std::str::FromStr;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use futures::{stream, Stream};
+use serde::{Deserialize, Serialize};
+
+use crate::db::{Sms, SmsCursor, SmsExport, SmsFilter, SmsStatus, SmsTimestamp, SortOrder};
+
+const BATCH_SIZE: u32 = 500;
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Ndjson,
+    Xml, // Android "SMS Backup & Restore"
+}
+
+impl ExportFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Ndjson => "application/x-ndjson",
+            ExportFormat::Xml => "application/xml",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Ndjson => "ndjson",
+            ExportFormat::Xml => "xml",
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(ExportFormat::Csv),
+            "ndjson" => Ok(ExportFormat::Ndjson),
+            "xml" => Ok(ExportFormat::Xml),
+            _ => Err(format!("Unknown export format '{}', expected csv, ndjson or xml", s)),
+        }
+    }
+}
+
+enum Step {
+    Header,
+    Rows(Option<SmsCursor>),
+    Footer,
+    Done,
+}
+
+/// Messages matching a filter, read from the database in batches while they are written out
+pub struct Export {
+    pub format: ExportFormat,
+    pub filter: SmsFilter,
+    pub order: SortOrder,
+    pub sims: Option<Vec<String>>, // None exports every SIM
+}
+
+impl Export {
+    /// The export as text chunks: the header, one chunk per batch of messages and the footer
+    pub fn stream(self) -> impl Stream<Item = Result<String>> {
+        stream::unfold(Some((self, Step::Header)), |state| async move {
+            let (export, step) = state?;
+            match export.chunk(step).await {
+                Ok(Some((chunk, next))) => Some((Ok(chunk), Some((export, next)))),
+                Ok(None) => None,
+                Err(e) => Some((Err(e), None)),
+            }
+        })
+    }
+
+    async fn chunk(&self, step: Step) -> Result<Option<(String, Step)>> {
+        match step {
+            Step::Header => Ok(Some((self.header().await?, Step::Rows(None)))),
+            Step::Rows(cursor) => {
+                let sims = self.sims.as_deref();
+                let (rows, next) =
+                    Sms::export_after(&self.filter, self.order, cursor, BATCH_SIZE, sims).await?;
+                let chunk = match self.format {
+                    ExportFormat::Csv => csv_rows(&rows)?,
+                    ExportFormat::Ndjson => ndjson_rows(&rows)?,
+                    ExportFormat::Xml => rows.iter().map(xml_row).collect(),
+                };
+                let next = match next {
+                    Some(cursor) => Step::Rows(Some(cursor)),
+                    None => Step::Footer,
+                };
+                Ok(Some((chunk, next)))
+            }
+            Step::Footer => match self.format {
+                ExportFormat::Xml => Ok(Some(("</smses>\n".to_string(), Step::Done))),
+                _ => Ok(None),
+            },
+            Step::Done => Ok(None),
+        }
+    }
+
+    async fn header(&self) -> Result<String> {
+        Ok(match self.format {
+            ExportFormat::Csv => CSV_HEADER.to_string() + "\n",
+            ExportFormat::Ndjson => String::new(),
+            ExportFormat::Xml => {
+                let count = Sms::count(&self.filter, self.sims.as_deref()).await?;
+                format!(
+                    "<?xml version='1.0' encoding='UTF-8' standalone='yes' ?>\n<smses count=\"{}\">\n",
+                    count
+                )
+            }
+        })
+    }
+}
+
+const CSV_HEADER: &str =
+    "id,timestamp,received_at,direction,contact,sim_id,sim_alias,status,message";
+
+fn direction(sms: &Sms) -> &'static str {
+    if sms.send {
+        "outgoing"
+    } else {
+        "incoming"
+    }
+}
+
+fn status_name(status: SmsStatus) -> &'static str {
+    match status {
+        SmsStatus::Unread => "unread",
+        SmsStatus::Read => "read",
+        SmsStatus::Loading => "sending",
+        SmsStatus::Failed => "failed",
+    }
+}
+
+/// RFC 3339 in the original offset
+fn timestamp_string(timestamp: &SmsTimestamp) -> String {
+    match timestamp.with_offset() {
+        Some(dt) => dt.to_rfc3339(),
+        None => timestamp.timestamp.and_utc().to_rfc3339(),
+    }
+}
+
+fn instant(timestamp: &SmsTimestamp) -> DateTime<Utc> {
+    timestamp.timestamp.and_utc()
+}
+
+fn csv_rows(rows: &[SmsExport]) -> Result<String> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for row in rows {
+        let sms = &row.sms;
+        writer.write_record([
+            sms.id.to_string().as_str(),
+            &timestamp_string(&sms.timestamp),
+            &sms.received_at.map(|at| at.to_rfc3339()).unwrap_or_default(),
+            direction(sms),
+            row.contact_name.as_deref().unwrap_or_default(),
+            &sms.sim_id,
+            row.sim_alias.as_deref().unwrap_or_default(),
+            status_name(sms.status),
+            &sms.message,
+        ])?;
+    }
+    Ok(String::from_utf8(writer.into_inner()?)?)
+}
+
+fn ndjson_rows(rows: &[SmsExport]) -> Result<String> {
+    let mut out = String::new();
+    for row in rows {
+        out.push_str(&serde_json::to_string(row)?);
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+fn xml_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\n' => escaped.push_str("&#10;"),
+            '\r' => escaped.push_str("&#13;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// One <sms> element as written by SMS Backup & Restore; sim_id and sim_alias are extra
+fn xml_row(row: &SmsExport) -> String {
+    let sms = &row.sms;
+    let date = instant(&sms.timestamp);
+    let readable = match sms.timestamp.with_offset() {
+        Some(dt) => dt.format("%b %-d, %Y %H:%M:%S").to_string(),
+        None => date.format("%b %-d, %Y %H:%M:%S").to_string(),
+    };
+    // 1 = inbox, 2 = sent, 5 = failed
+    let kind = match (sms.send, sms.status) {
+        (false, _) => 1,
+        (true, SmsStatus::Failed) => 5,
+        (true, _) => 2,
+    };
+    let status = match sms.status {
+        SmsStatus::Loading => 32,
+        SmsStatus::Failed => 64,
+        _ if sms.send => 0,
+        _ => -1,
+    };
+    format!(
+        "  <sms protocol=\"0\" address=\"{}\" date=\"{}\" type=\"{}\" subject=\"null\" body=\"{}\" \
+         toa=\"null\" sc_toa=\"null\" service_center=\"null\" read=\"{}\" status=\"{}\" locked=\"0\" \
+         date_sent=\"0\" readable_date=\"{}\" contact_name=\"(Unknown)\" sim_id=\"{}\" sim_alias=\"{}\" />\n",
+        xml_escape(row.contact_name.as_deref().unwrap_or_default()),
+        date.timestamp_millis(),
+        kind,
+        xml_escape(&sms.message),
+        u8::from(sms.status != SmsStatus::Unread),
+        status,
+        readable,
+        xml_escape(&sms.sim_id),
+        xml_escape(row.sim_alias.as_deref().unwrap_or_default()),
+    )
+}
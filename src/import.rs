@@ -0,0 +1,418 @@
+use std::{
+    collections::HashMap,
+    io::BufRead,
+    str::FromStr,
+};
+
+use anyhow::Result;
+use chrono::{DateTime, FixedOffset, Local, NaiveDateTime, Offset, TimeZone, Utc};
+use quick_xml::events::{BytesStart, Event};
+use serde::{Deserialize, Serialize};
+
+use crate::db::{ModemSMS, SimCard, Sms, SmsStatus, SmsTimestamp};
+
+const BATCH_SIZE: usize = 500;
+const MAX_ERRORS: usize = 50;
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportFormat {
+    Csv,    // As written by the CSV export
+    Ndjson, // As written by the NDJSON export
+    Xml,    // Android "SMS Backup & Restore"
+}
+
+impl FromStr for ImportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(ImportFormat::Csv),
+            "ndjson" => Ok(ImportFormat::Ndjson),
+            "xml" => Ok(ImportFormat::Xml),
+            _ => Err(format!("Unknown import format '{}', expected csv, ndjson or xml", s)),
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ImportReport {
+    pub inserted: u64,
+    pub skipped: u64, // Already stored, repeated in the file, drafts and MMS
+    pub invalid: u64,
+    pub errors: Vec<String>, // The first few invalid records and why
+}
+
+impl ImportReport {
+    fn invalid(&mut self, record: usize, error: String) {
+        self.invalid += 1;
+        if self.errors.len() < MAX_ERRORS {
+            self.errors.push(format!("record {}: {}", record, error));
+        }
+    }
+}
+
+/// A parsed message with what `ModemSMS` does not carry
+struct ImportRecord {
+    sms: ModemSMS,
+    status: SmsStatus,
+    sim_alias: Option<String>,
+}
+
+/// Ok(None) is a record that is deliberately not imported
+type Parsed = Result<Option<ImportRecord>, String>;
+
+/// Imports messages, creating contacts and unknown SIMs. `sim_id` overrides the SIM of every
+/// record; records on SIMs outside `sims` are invalid.
+pub async fn import<R: BufRead + Send>(
+    format: ImportFormat,
+    reader: R,
+    sim_id: Option<&str>,
+    sims: Option<&[String]>,
+) -> Result<ImportReport> {
+    let records: Box<dyn Iterator<Item = Parsed> + Send> = match format {
+        ImportFormat::Csv => Box::new(
+            csv::Reader::from_reader(reader)
+                .into_deserialize::<CsvRecord>()
+                .map(|record| record.map_err(|e| e.to_string())?.into_record()),
+        ),
+        ImportFormat::Ndjson => Box::new(
+            reader
+                .lines()
+                .filter(|line| !line.as_ref().is_ok_and(|line| line.trim().is_empty()))
+                .map(|line| {
+                    let line = line.map_err(|e| e.to_string())?;
+                    serde_json::from_str::<JsonRecord>(&line)
+                        .map_err(|e| e.to_string())?
+                        .into_record()
+                }),
+        ),
+        ImportFormat::Xml => Box::new(XmlRecords::new(reader)),
+    };
+
+    let mut report = ImportReport::default();
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    for (index, record) in records.enumerate() {
+        let record = match record.and_then(|record| assign_sim(record, sim_id, sims)) {
+            Ok(Some(record)) => record,
+            Ok(None) => {
+                report.skipped += 1;
+                continue;
+            }
+            Err(e) => {
+                report.invalid(index + 1, e);
+                continue;
+            }
+        };
+        batch.push(record);
+        if batch.len() == BATCH_SIZE {
+            insert_batch(std::mem::take(&mut batch), &mut report).await?;
+        }
+    }
+    insert_batch(std::mem::take(&mut batch), &mut report).await?;
+    Ok(report)
+}
+
+fn assign_sim(record: Option<ImportRecord>, sim_id: Option<&str>, sims: Option<&[String]>) -> Parsed {
+    let Some(mut record) = record else {
+        return Ok(None);
+    };
+    if let Some(sim_id) = sim_id {
+        record.sms.sim_id = sim_id.to_string();
+    }
+    if record.sms.sim_id.is_empty() {
+        return Err("No SIM, pass sim_id for files without one".to_string());
+    }
+    if sims.is_some_and(|sims| !sims.contains(&record.sms.sim_id)) {
+        return Err(format!("No access to SIM {}", record.sms.sim_id));
+    }
+    if record.sms.contact.trim().is_empty() {
+        return Err("Missing contact".to_string());
+    }
+    Ok(Some(record))
+}
+
+async fn insert_batch(batch: Vec<ImportRecord>, report: &mut ImportReport) -> Result<()> {
+    if batch.is_empty() {
+        return Ok(());
+    }
+
+    let mut aliases: HashMap<&str, Option<&String>> = HashMap::new();
+    for record in &batch {
+        let alias = aliases.entry(record.sms.sim_id.as_str()).or_default();
+        *alias = alias.or(record.sim_alias.as_ref());
+    }
+    let known = SimCard::get_by_ids(&aliases.keys().copied().collect::<Vec<_>>()).await?;
+    let now = Utc::now().naive_utc();
+    let new_sims: Vec<SimCard> = aliases
+        .into_iter()
+        .filter(|(id, _)| !known.contains_key(*id))
+        .map(|(id, alias)| SimCard {
+            id: id.to_string(),
+            alias: alias.cloned(),
+            created_at: now,
+            updated_at: now,
+            ..Default::default()
+        })
+        .collect();
+    SimCard::bulk_insert(&new_sims).await?;
+
+    let (mut messages, statuses): (Vec<ModemSMS>, Vec<SmsStatus>) =
+        batch.into_iter().map(|record| (record.sms, record.status)).unzip();
+    // Messages already stored, or repeated in the file, match a fingerprint and are left out
+    ModemSMS::bulk_insert(&mut messages).await?;
+
+    // bulk_insert stores incoming messages as unread and outgoing ones as read
+    let mut changed: HashMap<i32, Vec<i64>> = HashMap::new();
+    for (sms, status) in messages.iter().zip(statuses) {
+        let Some(id) = sms.id else {
+            report.skipped += 1;
+            continue;
+        };
+        report.inserted += 1;
+        let default = if sms.send { SmsStatus::Read } else { SmsStatus::Unread };
+        if status != default {
+            changed.entry(status as i32).or_default().push(id);
+        }
+    }
+    for (status, ids) in changed {
+        Sms::update_status_by_ids(&ids, SmsStatus::from(status)).await?;
+    }
+
+    Ok(())
+}
+
+/// The stored form of a timestamp back in the local time and offset `ModemSMS` expects
+fn modem_time(timestamp: SmsTimestamp) -> (NaiveDateTime, Option<FixedOffset>) {
+    match timestamp.utc_offset.and_then(FixedOffset::east_opt) {
+        Some(offset) => (offset.from_utc_datetime(&timestamp.timestamp).naive_local(), Some(offset)),
+        None => (timestamp.timestamp, None),
+    }
+}
+
+fn status_from_name(name: &str) -> Result<SmsStatus, String> {
+    match name {
+        "unread" => Ok(SmsStatus::Unread),
+        "read" => Ok(SmsStatus::Read),
+        "sending" => Ok(SmsStatus::Loading),
+        "failed" => Ok(SmsStatus::Failed),
+        _ => Err(format!("Unknown status '{}'", name)),
+    }
+}
+
+/// A row of the CSV export; other columns are ignored
+#[derive(Deserialize)]
+struct CsvRecord {
+    timestamp: SmsTimestamp,
+    direction: Option<String>, // "incoming" (default) or "outgoing"
+    contact: String,
+    sim_id: Option<String>,
+    sim_alias: Option<String>,
+    status: Option<String>,
+    message: String,
+}
+
+impl CsvRecord {
+    fn into_record(self) -> Parsed {
+        let send = match self.direction.as_deref() {
+            None | Some("incoming") => false,
+            Some("outgoing") => true,
+            Some(other) => return Err(format!("Unknown direction '{}'", other)),
+        };
+        let status = match self.status.as_deref() {
+            Some(name) => status_from_name(name)?,
+            None if send => SmsStatus::Read,
+            None => SmsStatus::Unread,
+        };
+        let (timestamp, utc_offset) = modem_time(self.timestamp);
+        Ok(Some(ImportRecord {
+            sms: ModemSMS {
+                contact: self.contact,
+                timestamp,
+                utc_offset,
+                message: self.message,
+                send,
+                sim_id: self.sim_id.unwrap_or_default(),
+                ..Default::default()
+            },
+            status,
+            sim_alias: self.sim_alias,
+        }))
+    }
+}
+
+/// A line of the NDJSON export
+#[derive(Deserialize)]
+struct JsonRecord {
+    timestamp: SmsTimestamp,
+    received_at: Option<DateTime<Utc>>,
+    #[serde(alias = "contact_name")]
+    contact: String,
+    message: String,
+    #[serde(default)]
+    sim_id: String,
+    sim_alias: Option<String>,
+    #[serde(default)]
+    send: bool,
+    status: Option<SmsStatus>,
+}
+
+impl JsonRecord {
+    fn into_record(self) -> Parsed {
+        let (timestamp, utc_offset) = modem_time(self.timestamp);
+        let status = self.status.unwrap_or(if self.send { SmsStatus::Read } else { SmsStatus::Unread });
+        Ok(Some(ImportRecord {
+            sms: ModemSMS {
+                contact: self.contact,
+                timestamp,
+                utc_offset,
+                received_at: self.received_at,
+                message: self.message,
+                send: self.send,
+                sim_id: self.sim_id,
+                ..Default::default()
+            },
+            status,
+            sim_alias: self.sim_alias,
+        }))
+    }
+}
+
+/// The <sms> and <mms> elements of an SMS Backup & Restore file, one record each
+struct XmlRecords<R> {
+    reader: quick_xml::Reader<R>,
+    buf: Vec<u8>,
+    done: bool,
+}
+
+impl<R: BufRead> XmlRecords<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            reader: quick_xml::Reader::from_reader(reader),
+            buf: Vec::new(),
+            done: false,
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for XmlRecords<R> {
+    type Item = Parsed;
+
+    fn next(&mut self) -> Option<Parsed> {
+        while !self.done {
+            self.buf.clear();
+            match self.reader.read_event_into(&mut self.buf) {
+                Ok(Event::Empty(e) | Event::Start(e)) => match e.name().as_ref() {
+                    b"sms" => return Some(xml_record(&e)),
+                    b"mms" => return Some(Ok(None)),
+                    _ => {}
+                },
+                Ok(Event::Eof) => self.done = true,
+                Ok(_) => {}
+                Err(e) => {
+                    // The rest of the file cannot be trusted after a syntax error
+                    self.done = true;
+                    let position = self.reader.error_position();
+                    return Some(Err(format!("Invalid XML at byte {}: {}", position, e)));
+                }
+            }
+        }
+        None
+    }
+}
+
+fn xml_record(element: &BytesStart) -> Parsed {
+    let mut attributes = HashMap::new();
+    for attribute in element.attributes() {
+        let attribute = attribute.map_err(|e| e.to_string())?;
+        let value = std::str::from_utf8(&attribute.value).map_err(|e| e.to_string())?;
+        attributes.insert(attribute.key.as_ref().to_vec(), unescape(value)?);
+    }
+    let get = |name: &str| attributes.get(name.as_bytes()).map(String::as_str);
+
+    let date: i64 = get("date")
+        .ok_or("Missing date")?
+        .parse()
+        .map_err(|_| "Invalid date".to_string())?;
+    let date = Utc
+        .timestamp_millis_opt(date)
+        .single()
+        .ok_or("Invalid date")?
+        .with_timezone(&Local);
+
+    // 1 inbox, 2 sent, 3 draft, 4 outbox, 5 failed, 6 queued
+    let (send, status) = match get("type") {
+        Some("1") if get("read") == Some("1") => (false, SmsStatus::Read),
+        Some("1") => (false, SmsStatus::Unread),
+        Some("2") | Some("4") | Some("6") => (true, SmsStatus::Read),
+        Some("5") => (true, SmsStatus::Failed),
+        Some("3") => return Ok(None),
+        other => return Err(format!("Unknown message type {:?}", other)),
+    };
+
+    Ok(Some(ImportRecord {
+        sms: ModemSMS {
+            contact: get("address").ok_or("Missing address")?.to_string(),
+            timestamp: date.naive_local(),
+            utc_offset: Some(date.offset().fix()),
+            message: get("body").unwrap_or_default().to_string(),
+            send,
+            sim_id: get("sim_id").unwrap_or_default().to_string(),
+            ..Default::default()
+        },
+        status,
+        sim_alias: get("sim_alias").filter(|alias| !alias.is_empty()).map(str::to_string),
+    }))
+}
+
+/// Resolves XML entities. Backup apps write emoji as two UTF-16 surrogate references
+/// (`&#55357;&#56832;`), which are combined here instead of being rejected.
+fn unescape(raw: &str) -> Result<String, String> {
+    let mut out = String::with_capacity(raw.len());
+    let mut high_surrogate: Option<u32> = None;
+    let mut rest = raw;
+    while let Some(start) = rest.find('&') {
+        if high_surrogate.is_some() && start > 0 {
+            return Err(format!("Unpaired surrogate in '{}'", raw));
+        }
+        out.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find(';')
+            .ok_or_else(|| format!("Unterminated entity in '{}'", raw))?
+            + start;
+        let entity = &rest[start + 1..end];
+        rest = &rest[end + 1..];
+
+        let code = match entity {
+            "amp" => '&' as u32,
+            "lt" => '<' as u32,
+            "gt" => '>' as u32,
+            "quot" => '"' as u32,
+            "apos" => '\'' as u32,
+            _ => match entity.strip_prefix("#x").or_else(|| entity.strip_prefix("#X")) {
+                Some(hex) => u32::from_str_radix(hex, 16),
+                None => entity
+                    .strip_prefix('#')
+                    .ok_or_else(|| format!("Unknown entity '&{};'", entity))?
+                    .parse(),
+            }
+            .map_err(|_| format!("Invalid character reference '&{};'", entity))?,
+        };
+
+        let code = match (high_surrogate.take(), code) {
+            (None, 0xD800..=0xDBFF) => {
+                high_surrogate = Some(code);
+                continue;
+            }
+            (Some(high), 0xDC00..=0xDFFF) => 0x10000 + ((high - 0xD800) << 10) + (code - 0xDC00),
+            (None, code) => code,
+            (Some(_), _) => return Err(format!("Unpaired surrogate in '{}'", raw)),
+        };
+        out.push(char::from_u32(code).ok_or_else(|| format!("Invalid character in '{}'", raw))?);
+    }
+    if high_surrogate.is_some() {
+        return Err(format!("Unpaired surrogate in '{}'", raw));
+    }
+    out.push_str(rest);
+    Ok(out)
+}
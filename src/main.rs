@@ -9,11 +9,19 @@ use log::LevelFilter;
 use modem::{SmsType, ModemManager};
 use structopt::StructOpt;
 
+mod address_book;
 mod api;
+mod autoreply;
+mod campaign;
 mod config;
 mod db;
 mod decode;
+mod export;
+mod import;
 mod modem;
+mod optout;
+mod phone;
+mod retention;
 mod webhook;
 #[cfg(test)]
 mod tests;
@@ -48,6 +56,34 @@ async fn main() {
         }
     };
 
+    // Before the modems are read or messages imported, so new numbers are stored in E.164
+    phone::set_default_country(config.settings.default_country);
+    optout::configure(config.settings.opt_out.clone());
+
+    // Before commands too, so imports match stored messages by their normalised numbers
+    match db::Contact::normalize_numbers().await {
+        Ok(0) => {}
+        Ok(merged) => log::info!("Merged {} contacts that share a number", merged),
+        Err(err) => log::error!("Failed to normalize contact numbers: {}", err),
+    }
+    if let Err(err) = db::backfill_fingerprints().await {
+        eprintln!("Error: {}", err);
+        std::process::exit(1);
+    }
+
+    if let Some(command) = param.command {
+        if let Err(err) = run_command(command).await {
+            eprintln!("Error: {:#}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Err(err) = api::seed_admin(config.settings.credentials().as_ref()).await {
+        eprintln!("Error: {}", err);
+        std::process::exit(1);
+    }
+
     let modem_manager = match ModemManager::initialize(&config).await {
         Ok(manager) => Arc::new(manager),
         Err(err) => {
@@ -58,26 +94,41 @@ async fn main() {
     
     let sse_manager = Arc::new(api::SseManager::new());
 
-    let webhook_manager = match config.settings.webhooks.clone() { Some(cfgs) => {
-        Some(webhook::start_webhook_worker_with_concurrency(cfgs, config.settings.webhooks_max_concurrent.unwrap_or(1)))
-    } _ => {
-        None
-    }};
+    let webhook_targets = match config.settings.webhook_targets() {
+        Ok(targets) => targets,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    // Always running so webhooks added through the API take effect without a restart
+    let webhook_manager = webhook::start_webhook_worker_with_concurrency(webhook_targets, config.settings.webhooks_max_concurrent.unwrap_or(1));
+    webhook_manager.attach_modem_manager(modem_manager.clone());
+    if let Err(err) = webhook_manager.load_stored().await {
+        log::error!("Failed to load stored webhooks: {}", err);
+    }
 
     tokio::spawn(read_sms_worker(
         modem_manager.clone(),
         config.settings.read_sms_frequency,
         sse_manager.clone(),
-        webhook_manager,
+        Some(webhook_manager.clone()),
     ));
 
+    if let Some(days) = config.settings.audit_retention_days {
+        tokio::spawn(audit_retention_worker(days));
+    }
+    tokio::spawn(retention::retention_worker(config.settings.retention.clone()));
+    if let Err(err) = campaign::resume_running(modem_manager.clone()).await {
+        log::error!("Failed to resume campaigns: {}", err);
+    }
+
     if let Ok(_) = api::run_api(
         modem_manager.clone(),
-        &config.settings.server_host,
-        &config.settings.server_port,
-        &config.settings.username.unwrap(),
-        &config.settings.password.unwrap(),
+        &config.settings,
         sse_manager.clone(),
+        webhook_manager,
     )
     .await {};
 }
@@ -99,6 +150,114 @@ async fn read_sms_worker(
     }
 }
 
+/// Deletes audit entries older than `days`, once at startup and then daily
+async fn audit_retention_worker(days: u32) {
+    loop {
+        let cutoff = chrono::Utc::now() - chrono::Duration::days(days as i64);
+        match db::AuditEntry::purge_before(cutoff).await {
+            Ok(0) => {}
+            Ok(count) => log::info!("Purged {} audit entries older than {} days", count, days),
+            Err(err) => log::error!("Failed to purge audit log: {}", err),
+        }
+
+        tokio::time::sleep(tokio::time::Duration::from_secs(24 * 3600)).await;
+    }
+}
+
+/// Runs a one-off command instead of the gateway
+async fn run_command(command: Command) -> anyhow::Result<()> {
+    use futures::StreamExt;
+    use std::io::Write;
+
+    match command {
+        Command::Export {
+            format,
+            output,
+            order,
+            sim_id,
+            contact_id,
+            direction,
+            status,
+            from,
+            to,
+        } => {
+            let mut out: Box<dyn Write> = match &output {
+                Some(path) => Box::new(std::fs::File::create(path)?),
+                None => Box::new(std::io::stdout().lock()),
+            };
+            let export = export::Export {
+                format,
+                filter: db::SmsFilter {
+                    sim_id,
+                    contact_id,
+                    send: direction.map(|direction| direction == "outgoing"),
+                    status: status.map(db::SmsStatus::from),
+                    from,
+                    to,
+                },
+                order: if order == "asc" { db::SortOrder::Asc } else { db::SortOrder::Desc },
+                sims: None,
+            };
+            let mut chunks = std::pin::pin!(export.stream());
+            while let Some(chunk) = chunks.next().await {
+                out.write_all(chunk?.as_bytes())?;
+            }
+            out.flush()?;
+        }
+        Command::Import { format, sim_id, file } => {
+            let reader = std::io::BufReader::new(std::fs::File::open(&file)?);
+            let report = import::import(format, reader, sim_id.as_deref(), None).await?;
+            for error in &report.errors {
+                eprintln!("{}", error);
+            }
+            println!(
+                "Inserted {}, skipped {}, invalid {}",
+                report.inserted, report.skipped, report.invalid
+            );
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, StructOpt)]
+pub enum Command {
+    /// Export messages as CSV, NDJSON or SMS Backup & Restore XML
+    Export {
+        #[structopt(short = "f", long = "format", default_value = "csv", possible_values = &["csv", "ndjson", "xml"])]
+        format: export::ExportFormat,
+        /// File to write, stdout when omitted
+        #[structopt(short = "o", long = "output", parse(from_os_str))]
+        output: Option<PathBuf>,
+        #[structopt(long = "order", default_value = "asc", possible_values = &["asc", "desc"])]
+        order: String,
+        #[structopt(long = "sim-id")]
+        sim_id: Option<String>,
+        #[structopt(long = "contact-id")]
+        contact_id: Option<String>,
+        #[structopt(long = "direction", possible_values = &["incoming", "outgoing"])]
+        direction: Option<String>,
+        /// 0 unread, 1 read, 2 sending, 3 failed
+        #[structopt(long = "status")]
+        status: Option<i32>,
+        /// RFC 3339, inclusive
+        #[structopt(long = "from")]
+        from: Option<chrono::DateTime<chrono::Utc>>,
+        /// RFC 3339, exclusive
+        #[structopt(long = "to")]
+        to: Option<chrono::DateTime<chrono::Utc>>,
+    },
+    /// Import messages from a CSV or NDJSON export or an SMS Backup & Restore XML file
+    Import {
+        #[structopt(short = "f", long = "format", possible_values = &["csv", "ndjson", "xml"])]
+        format: import::ImportFormat,
+        /// SIM for every message; required for phone backups
+        #[structopt(long = "sim-id")]
+        sim_id: Option<String>,
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+    },
+}
+
 #[derive(Debug, StructOpt)]
 pub struct Param {
 #[cfg(debug_assertions)]
@@ -146,6 +305,9 @@ pub struct Param {
         default_value = "/etc/sms-gateway/config.toml"
     )]
     pub config_file: PathBuf,
+
+    #[structopt(subcommand)]
+    pub command: Option<Command>,
 }
 
 fn log_init(log_path: &PathBuf, log_level: &LevelFilter) -> anyhow::Result<()> {
@@ -1,4 +1,3 @@
-use chrono::{Local, Timelike};
 use futures::stream::{FuturesUnordered, StreamExt};
 use log::{debug, error, info};
 use std::io;
@@ -9,9 +8,12 @@ use tokio::sync::{mpsc, Mutex, RwLock, Semaphore};
 use tokio_serial::{SerialPortBuilderExt, SerialStream};
 
 use crate::api::SseManager;
+use crate::autoreply;
+use crate::optout;
 use crate::config::SmsStorage;
-use crate::db::{Contact, ModemSMS, SimCard, Sms};
+use crate::db::{Contact, ModemSMS, SimCard, Sms, SmsTimestamp};
 use crate::decode::parse_pdu_sms;
+use crate::phone::{default_country, normalize, to_e164, Country};
 use crate::webhook;
 
 use super::pdu::{build_pdu, string_to_ucs2_pub};
@@ -458,10 +460,23 @@ impl Modem {
         }
     }
 
+    /// Fails with `optout::OptedOut`, before anything is stored, when the recipient opted out
     pub async fn send_sms_pdu(
         &self,
         contact: &Contact,
         message: &str,
+        user_id: Option<i64>,
+    ) -> anyhow::Result<(i64, String)> {
+        optout::ensure_allowed(&contact.name, self.country().await).await?;
+        self.send_sms_pdu_unchecked(contact, message, user_id).await
+    }
+
+    /// Sends past the suppression list, which only opt-out confirmations may do
+    pub(crate) async fn send_sms_pdu_unchecked(
+        &self,
+        contact: &Contact,
+        message: &str,
+        user_id: Option<i64>,
     ) -> anyhow::Result<(i64, String)> {
         info!("Sending SMS via PDU to {}: {}", contact.name, message);
 
@@ -470,11 +485,13 @@ impl Modem {
         let sms = Sms {
             id: 0,
             contact_id: contact.id.clone(),
-            timestamp: Local::now().naive_local().with_nanosecond(0).unwrap(),
+            timestamp: SmsTimestamp::now(),
+            received_at: None,
             message: message.to_string(),
             sim_id,
             send: true,
             status: crate::db::SmsStatus::Loading,
+            user_id,
         };
 
         let sms_id = sms.insert().await?;
@@ -492,7 +509,7 @@ impl Modem {
     }
 
     async fn send_pdu_message(&self, phone: &str, message: &str) -> anyhow::Result<()> {
-        let (pdu_data, tpdu_length) = build_pdu(phone, message)?;
+        let (pdu_data, tpdu_length) = build_pdu(phone, message, self.country().await)?;
 
         self.send_sms_content(&format!("AT+CMGS={}\r", tpdu_length), &pdu_data, |pdu| {
             Ok(pdu.to_string())
@@ -508,36 +525,30 @@ impl Modem {
         sse_manager: Arc<SseManager>,
         webhook_manager: Option<webhook::WebhookManager>,
     ) -> anyhow::Result<()> {
-        let sms_list = self.read_sms(sms_type).await?;
+        let mut sms_list = self.read_sms(sms_type).await?;
 
         if sms_list.is_empty() {
             return Ok(());
         }
 
-        let webhook_future = async {
-            if let Some(webhook_mgr) = webhook_manager {
-                for sms in &sms_list {
-                    if let Err(e) = webhook_mgr.send(sms.clone()) {
-                        log::error!("Failed to send webhook: {}", e);
-                    }
+        // Store first so webhooks can reference the message id
+        let stored = match ModemSMS::bulk_insert(&mut sms_list).await {
+            Ok(contact_ids) => {
+                if let Ok(conversations) =
+                    crate::db::Conversation::query_by_contact_ids(&contact_ids).await
+                {
+                    sse_manager.send(conversations);
                 }
+                true
             }
-        };
-
-        let db_future = async {
-            match ModemSMS::bulk_insert(&sms_list).await {
-                Ok(contact_ids) => {
-                    if let Ok(conversations) =
-                        crate::db::Conversation::query_by_contact_ids(&contact_ids).await
-                    {
-                        sse_manager.send(conversations);
-                    }
-                }
-                Err(e) => log::error!("Insert SMS error: {}", e),
+            Err(e) => {
+                log::error!("Insert SMS error: {}", e);
+                false
             }
         };
 
-        tokio::join!(webhook_future, db_future);
+        handle_new_messages(self, sms_list, stored, webhook_manager.as_ref()).await;
+
         Ok(())
     }
 
@@ -546,7 +557,17 @@ impl Modem {
         let response = self.send_command_with_ok(&command).await?;
 
         let sim_id = self.sim_id.read().await.clone().unwrap_or_default();
-        Ok(parse_pdu_sms(&response, &sim_id))
+        let country = self.country().await;
+        Ok(parse_pdu_sms(&response, &sim_id, country))
+    }
+
+    /// Country of the SIM, for numbers written without a country code
+    pub(crate) async fn country(&self) -> Option<&'static Country> {
+        let sim_id = self.sim_id.read().await.clone().unwrap_or_default();
+        SimCard::country(&sim_id).await.unwrap_or_else(|e| {
+            error!("Failed to look up the country of SIM {}: {}", sim_id, e);
+            default_country()
+        })
     }
 
     async fn get_modem_info<T>(
@@ -647,7 +668,9 @@ impl Modem {
         &self,
         contact: &Contact,
         message: &str,
+        user_id: Option<i64>,
     ) -> anyhow::Result<(i64, String)> {
+        optout::ensure_allowed(&contact.name, self.country().await).await?;
         info!("Sending SMS text to {}: {}", contact.name, message);
 
         let sim_id = self.sim_id.read().await.clone().unwrap_or_default();
@@ -655,11 +678,13 @@ impl Modem {
         let sms = Sms {
             id: 0,
             contact_id: contact.id.clone(),
-            timestamp: Local::now().naive_local().with_nanosecond(0).unwrap(),
+            timestamp: SmsTimestamp::now(),
+            received_at: None,
             message: message.to_string(),
             sim_id,
             send: true,
             status: crate::db::SmsStatus::Loading,
+            user_id,
         };
 
         let sms_id = sms.insert().await?;
@@ -677,6 +702,7 @@ impl Modem {
     }
 
     async fn send_text_message(&self, phone: &str, message: &str) -> anyhow::Result<()> {
+        let phone = to_e164(phone, self.country().await);
         self.send_sms_content(&format!("AT+CMGS=\"{}\"\r", phone), message, |msg| {
             string_to_ucs2_pub(msg)
         })
@@ -684,10 +710,24 @@ impl Modem {
         Ok(())
     }
 
-    pub async fn read_sms_sync_insert(&self, sms_type: SmsType) -> anyhow::Result<()> {
-        let sms_list = self.read_sms(sms_type).await?;
+    /// Stores the messages on the SIM without treating them as new arrivals
+    pub async fn import_sms(&self, sms_type: SmsType) -> anyhow::Result<()> {
+        let mut sms_list = self.read_sms(sms_type).await?;
         if !sms_list.is_empty() {
-            ModemSMS::bulk_insert(&sms_list).await?;
+            ModemSMS::bulk_insert(&mut sms_list).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn read_sms_sync_insert(
+        &self,
+        sms_type: SmsType,
+        webhook_manager: Option<&webhook::WebhookManager>,
+    ) -> anyhow::Result<()> {
+        let mut sms_list = self.read_sms(sms_type).await?;
+        if !sms_list.is_empty() {
+            ModemSMS::bulk_insert(&mut sms_list).await?;
+            handle_new_messages(self, sms_list, true, webhook_manager).await;
         }
         Ok(())
     }
@@ -783,4 +823,51 @@ impl Modem {
         })
         .await
     }
-}
\ No newline at end of file
+}
+
+/// Runs everything that follows storing freshly read messages. Opt-out keywords count for every
+/// new incoming message; blocked contacts trigger no webhooks and no auto-replies. When storing
+/// failed, `stored` is false and every message is taken as new.
+pub async fn handle_new_messages<S: autoreply::AutoSender>(
+    sender: &S,
+    sms_list: Vec<ModemSMS>,
+    stored: bool,
+    webhook_manager: Option<&webhook::WebhookManager>,
+) {
+    // Messages stored by an earlier read get no id and were already handled
+    let new_messages: Vec<ModemSMS> = sms_list
+        .into_iter()
+        .filter(|sms| !stored || sms.id.is_some())
+        .collect();
+    if new_messages.is_empty() {
+        return;
+    }
+
+    for confirmation in optout::handle(&new_messages).await {
+        if let Err(e) = sender.confirm(&confirmation.number, &confirmation.text).await {
+            log::error!("Failed to confirm opt-out change to {}: {}", confirmation.number, e);
+        }
+    }
+
+    let blocked = Contact::blocked_numbers().await.unwrap_or_else(|e| {
+        log::error!("Failed to load blocked contacts: {}", e);
+        Default::default()
+    });
+    let new_messages: Vec<ModemSMS> = new_messages
+        .into_iter()
+        .filter(|sms| !blocked.contains(&normalize(&sms.contact)))
+        .collect();
+
+    if let Some(webhook_mgr) = webhook_manager {
+        for sms in &new_messages {
+            if let Err(e) = webhook_mgr.send(sms.clone()) {
+                log::error!("Failed to send webhook: {}", e);
+            }
+        }
+    }
+
+    // Without storage there is no telling which messages were answered before
+    if stored {
+        autoreply::handle(sender, &new_messages, webhook_manager).await;
+    }
+}
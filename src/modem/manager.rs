@@ -27,7 +27,10 @@ impl ModemManager {
             let port = device.com_port.clone();
             let baud_rate = device.baud_rate;
             let sms_storage = device.sms_storage.or(config.settings.sms_storage);
-            let temp_device_id = format!("device_{}", index);
+            let temp_device_id = device
+                .name
+                .clone()
+                .unwrap_or_else(|| format!("device_{}", index));
             let semaphore = initialization_semaphore.clone();
 
             initialization_futures.push(async move {
@@ -81,6 +84,16 @@ impl ModemManager {
         Ok(manager)
     }
 
+    /// A manager without modems, for API tests
+    #[cfg(test)]
+    pub fn empty() -> Self {
+        Self {
+            modems: Arc::new(RwLock::new(HashMap::new())),
+            sim_cards_cache: Arc::new(RwLock::new(HashMap::new())),
+            _initialization_semaphore: Arc::new(Semaphore::new(3)),
+        }
+    }
+
     async fn initialize_single_modem(
         port: String,
         baud_rate: u32,
@@ -137,6 +150,8 @@ impl ModemManager {
         Ok(())
     }
 
+    /// Stores the messages already on new SIMs. They are history, not new arrivals, so they
+    /// trigger no webhooks, auto-replies or opt-out changes.
     async fn init_new_sim_sms_data(&self, new_sim_ids: Vec<String>) {
         let mut futures = FuturesUnordered::new();
 
@@ -145,7 +160,7 @@ impl ModemManager {
             futures.push(async move {
                 let modems = modems.read().await;
                 if let Some(modem) = modems.get(&sim_id) {
-                    match modem.read_sms_sync_insert(SmsType::All).await {
+                    match modem.import_sms(SmsType::All).await {
                         Ok(()) => info!("Initialized SMS data for new SIM: {}", sim_id),
                         Err(e) => error!("Failed to initialize SMS data for {}: {}", sim_id, e),
                     }
@@ -169,13 +184,14 @@ impl ModemManager {
         sim_id: &str,
         contact: &Contact,
         message: &str,
+        user_id: Option<i64>, // Recorded as the sender
     ) -> anyhow::Result<(i64, String)> {
         let modem = self
             .get_modem(sim_id)
             .await
             .ok_or_else(|| anyhow::anyhow!("Modem not found for SIM ID: {}", sim_id))?;
 
-        modem.send_sms_pdu(contact, message).await
+        modem.send_sms_pdu(contact, message, user_id).await
     }
 
     pub async fn read_sms(&self, sim_id: &str, sms_type: SmsType) -> anyhow::Result<Vec<ModemSMS>> {
@@ -208,13 +224,14 @@ impl ModemManager {
         &self,
         sim_id: &str,
         sms_type: SmsType,
+        webhook_manager: Option<&webhook::WebhookManager>,
     ) -> anyhow::Result<()> {
         let modem = self
             .get_modem(sim_id)
             .await
             .ok_or_else(|| anyhow::anyhow!("Modem not found for SIM ID: {}", sim_id))?;
 
-        modem.read_sms_sync_insert(sms_type).await
+        modem.read_sms_sync_insert(sms_type, webhook_manager).await
     }
 
     pub async fn read_all_sms_async(
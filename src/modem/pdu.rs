@@ -1,3 +1,5 @@
+use crate::phone::{to_e164, Country};
+
 fn string_to_ucs2(message: &str) -> anyhow::Result<String> {
     let encoded: Vec<u16> = message.encode_utf16().collect();
 
@@ -16,12 +18,20 @@ fn string_to_ucs2(message: &str) -> anyhow::Result<String> {
     Ok(hex::encode_upper(bytes))
 }
 
-fn parse_number(number: &str) -> anyhow::Result<(u8, String)> {
-    let cleaned_number = number.trim_start_matches('+').replace(' ', "");
-    let addr_type = if number.starts_with('+') { 0x91 } else { 0x81 };
+/// Destination address: the number of digits, the type of address and the digits as swapped
+/// semi-octets. International numbers are sent as such (0x91), anything else as unknown (0x81)
+/// and left to the network.
+fn parse_number(number: &str) -> anyhow::Result<(usize, u8, String)> {
+    let (addr_type, digits) = match number.strip_prefix('+') {
+        Some(digits) => (0x91, digits),
+        None => (0x81, number),
+    };
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return Err(anyhow::anyhow!("'{}' is not a phone number", number));
+    }
 
-    let mut chars: Vec<char> = cleaned_number.chars().collect();
-    if chars.len() % 2 != 0 {
+    let mut chars: Vec<char> = digits.chars().collect();
+    if !chars.len().is_multiple_of(2) {
         chars.push('F');
     }
 
@@ -33,20 +43,22 @@ fn parse_number(number: &str) -> anyhow::Result<(u8, String)> {
         }
     }
 
-    Ok((addr_type, swapped))
+    Ok((digits.len(), addr_type, swapped))
 }
 
-fn encode_tpdu(mobile: &str, message: &str) -> anyhow::Result<(String, usize)> {
+fn encode_tpdu(
+    mobile: &str,
+    message: &str,
+    country: Option<&Country>,
+) -> anyhow::Result<(String, usize)> {
     const FIRST_OCTET: &str = "11";
     const MESSAGE_REF: &str = "00";
     const PID: &str = "00";
     const DCS: &str = "08";
     const VP: &str = "00";
 
-    let destination_phone = mobile.trim_start_matches('+').replace(' ', "");
-    let phone_len = format!("{:02X}", destination_phone.len());
-    let (addr_type, swapped_number) = parse_number(mobile)?;
-    let destination = format!("{}{:02X}{}", phone_len, addr_type, swapped_number);
+    let (phone_len, addr_type, swapped_number) = parse_number(&to_e164(mobile, country))?;
+    let destination = format!("{:02X}{:02X}{}", phone_len, addr_type, swapped_number);
 
     let encoded_text = string_to_ucs2(message)?;
     let udl = format!("{:02X}", message.chars().count() * 2);
@@ -60,9 +72,14 @@ fn encode_tpdu(mobile: &str, message: &str) -> anyhow::Result<(String, usize)> {
     Ok((tpdu, tpdu_length))
 }
 
-pub fn build_pdu(mobile: &str, message: &str) -> anyhow::Result<(String, usize)> {
+/// PDU for sending `message` to `mobile`; national numbers are written in E.164 with `country`
+pub fn build_pdu(
+    mobile: &str,
+    message: &str,
+    country: Option<&Country>,
+) -> anyhow::Result<(String, usize)> {
     const SMSC_INFO: &str = "00";
-    let (tpdu, tpdu_length) = encode_tpdu(mobile, message)?;
+    let (tpdu, tpdu_length) = encode_tpdu(mobile, message, country)?;
     let full_pdu = format!("{}{}", SMSC_INFO, tpdu);
     Ok((full_pdu, tpdu_length))
 }
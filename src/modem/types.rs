@@ -103,6 +103,10 @@ pub struct OperatorInfo {
 }
 
 impl OperatorInfo {
+    pub fn operator_name(&self) -> &str {
+        &self.operator_name
+    }
+
     pub fn from_response(response: &str) -> Option<Self> {
         response
             .lines()
@@ -0,0 +1,128 @@
+use std::{fmt, sync::OnceLock};
+
+use anyhow::Result;
+use chrono::Utc;
+use log::{error, info, warn};
+
+use crate::{
+    autoreply::words,
+    config::OptOutConfig,
+    db::{ModemSMS, Suppression, SuppressionSource},
+    phone::{is_phone_number, to_e164, Country},
+};
+
+static CONFIG: OnceLock<OptOutConfig> = OnceLock::new();
+
+/// Sets the keywords and confirmations, once at startup
+pub fn configure(config: OptOutConfig) {
+    if CONFIG.set(config).is_err() {
+        warn!("Opt-out handling is already configured");
+    }
+}
+
+fn config() -> &'static OptOutConfig {
+    CONFIG.get_or_init(OptOutConfig::default)
+}
+
+/// Why a send was refused; sending functions return it inside their `anyhow::Error`
+#[derive(Debug)]
+pub struct OptedOut(pub String);
+
+impl fmt::Display for OptedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} has opted out of messages", self.0)
+    }
+}
+
+impl std::error::Error for OptedOut {}
+
+/// Fails with [`OptedOut`] when `number`, completed with the sending SIM's `country`, is on the
+/// suppression list
+pub async fn ensure_allowed(number: &str, country: Option<&Country>) -> Result<()> {
+    let number = to_e164(number, country);
+    if Suppression::contains(&number).await? {
+        return Err(OptedOut(number).into());
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Keyword {
+    OptOut,
+    OptIn,
+}
+
+/// The keyword `message` consists of, ignoring case and punctuation. Keywords inside a longer
+/// message don't count, so "don't stop" opts nobody out.
+pub fn keyword(message: &str) -> Option<Keyword> {
+    let message = words(message);
+    if message.is_empty() {
+        return None;
+    }
+    let matches = |keywords: &[String]| keywords.iter().any(|keyword| words(keyword) == message);
+    let config = config();
+    if matches(&config.opt_out_keywords) {
+        Some(Keyword::OptOut)
+    } else if matches(&config.opt_in_keywords) {
+        Some(Keyword::OptIn)
+    } else {
+        None
+    }
+}
+
+/// A confirmation for a number that opted out or back in. It is sent past the suppression list,
+/// from the SIM the keyword arrived on.
+#[derive(Debug, PartialEq)]
+pub struct Confirmation {
+    pub number: String,
+    pub text: String,
+}
+
+/// Updates the suppression list from the keywords among new incoming `messages` and returns
+/// the confirmations to send. Repeating a keyword changes nothing and is not confirmed again.
+pub async fn handle(messages: &[ModemSMS]) -> Vec<Confirmation> {
+    let mut confirmations = Vec::new();
+    for msg in messages.iter().filter(|msg| !msg.send) {
+        let Some(keyword) = keyword(&msg.message) else {
+            continue;
+        };
+        if !is_phone_number(&msg.contact) {
+            continue;
+        }
+        let (changed, text, change) = match keyword {
+            Keyword::OptOut => {
+                let suppression = Suppression {
+                    number: msg.contact.clone(),
+                    source: SuppressionSource::Keyword,
+                    sim_id: Some(msg.sim_id.clone()),
+                    reason: Some(msg.message.clone()),
+                    user_id: None,
+                    created_at: Utc::now(),
+                };
+                (suppression.insert().await, &config().opt_out_reply, "out")
+            }
+            Keyword::OptIn => (
+                Suppression::delete(&msg.contact).await,
+                &config().opt_in_reply,
+                "back in",
+            ),
+        };
+        match changed {
+            Ok(true) => {
+                info!("{} opted {}", msg.contact, change);
+                if !text.is_empty() {
+                    confirmations.push(Confirmation {
+                        number: msg.contact.clone(),
+                        text: text.clone(),
+                    });
+                }
+            }
+            Ok(false) => {}
+            Err(e) => error!(
+                "Failed to update the suppression list for {}: {}",
+                msg.contact, e
+            ),
+        }
+    }
+    confirmations
+}
@@ -0,0 +1,167 @@
+use std::sync::OnceLock;
+
+/// Separators people write phone numbers with
+const SEPARATORS: &[char] = &[' ', '-', '.', '/', '(', ')'];
+
+/// Numbering plan of a country, enough to write its national numbers in E.164
+#[derive(Debug, PartialEq, Eq)]
+pub struct Country {
+    pub code: &'static str, // ISO 3166-1 alpha-2
+    pub calling_code: &'static str,
+    trunk_prefix: Option<&'static str>, // Dialled before national numbers, usually "0"
+    national_digits: (usize, usize),    // Length of national numbers without the trunk prefix
+}
+
+const fn country(
+    code: &'static str,
+    calling_code: &'static str,
+    trunk_prefix: Option<&'static str>,
+    min: usize,
+    max: usize,
+) -> Country {
+    Country {
+        code,
+        calling_code,
+        trunk_prefix,
+        national_digits: (min, max),
+    }
+}
+
+static COUNTRIES: &[Country] = &[
+    country("AE", "971", Some("0"), 8, 9),
+    country("AR", "54", Some("0"), 10, 10),
+    country("AT", "43", Some("0"), 7, 12),
+    country("AU", "61", Some("0"), 9, 9),
+    country("BD", "880", Some("0"), 10, 10),
+    country("BE", "32", Some("0"), 8, 9),
+    country("BR", "55", Some("0"), 10, 11),
+    country("CA", "1", Some("1"), 10, 10),
+    country("CH", "41", Some("0"), 9, 9),
+    country("CN", "86", Some("0"), 9, 11),
+    country("DE", "49", Some("0"), 7, 11),
+    country("DK", "45", None, 8, 8),
+    country("EG", "20", Some("0"), 9, 10),
+    country("ES", "34", None, 9, 9),
+    country("FI", "358", Some("0"), 7, 10),
+    country("FR", "33", Some("0"), 9, 9),
+    country("GB", "44", Some("0"), 9, 10),
+    country("HK", "852", None, 8, 8),
+    country("ID", "62", Some("0"), 9, 12),
+    country("IE", "353", Some("0"), 7, 9),
+    country("IL", "972", Some("0"), 8, 9),
+    country("IN", "91", Some("0"), 10, 10),
+    country("IT", "39", None, 7, 11),
+    country("JP", "81", Some("0"), 9, 10),
+    country("KE", "254", Some("0"), 9, 9),
+    country("KR", "82", Some("0"), 8, 10),
+    country("MO", "853", None, 8, 8),
+    country("MX", "52", None, 10, 10),
+    country("MY", "60", Some("0"), 9, 10),
+    country("NG", "234", Some("0"), 8, 10),
+    country("NL", "31", Some("0"), 9, 9),
+    country("NO", "47", None, 8, 8),
+    country("NZ", "64", Some("0"), 8, 10),
+    country("PH", "63", Some("0"), 10, 10),
+    country("PK", "92", Some("0"), 9, 10),
+    country("PL", "48", None, 9, 9),
+    country("PT", "351", None, 9, 9),
+    country("RU", "7", Some("8"), 10, 10),
+    country("SA", "966", Some("0"), 9, 9),
+    country("SE", "46", Some("0"), 7, 9),
+    country("SG", "65", None, 8, 8),
+    country("TH", "66", Some("0"), 8, 9),
+    country("TR", "90", Some("0"), 10, 10),
+    country("TW", "886", Some("0"), 8, 9),
+    country("UA", "380", Some("0"), 9, 9),
+    country("US", "1", Some("1"), 10, 10),
+    country("VN", "84", Some("0"), 9, 10),
+    country("ZA", "27", Some("0"), 9, 9),
+];
+
+static DEFAULT_COUNTRY: OnceLock<Option<&'static Country>> = OnceLock::new();
+
+impl Country {
+    pub fn from_code(code: &str) -> Result<&'static Country, String> {
+        COUNTRIES
+            .iter()
+            .find(|country| country.code.eq_ignore_ascii_case(code.trim()))
+            .ok_or_else(|| {
+                format!(
+                    "Unsupported country '{}', expected an ISO 3166 code like \"CN\" or \"GB\"",
+                    code
+                )
+            })
+    }
+
+    fn is_national(&self, digits: &str) -> bool {
+        (self.national_digits.0..=self.national_digits.1).contains(&digits.len())
+    }
+
+    /// `digits` in E.164 when they look like a number of this country. Shorter numbers, like
+    /// service short codes, are left alone.
+    fn international(&self, digits: &str) -> Option<String> {
+        let trunk = self.trunk_prefix.unwrap_or_default();
+        if let Some(national) = digits.strip_prefix(trunk).filter(|_| !trunk.is_empty()) {
+            if self.is_national(national) {
+                return Some(format!("+{}{}", self.calling_code, national));
+            }
+        }
+        // The country code without the leading +
+        if let Some(national) = digits.strip_prefix(self.calling_code) {
+            if self.is_national(national) {
+                return Some(format!("+{}", digits));
+            }
+        }
+        if self.is_national(digits) && (trunk.is_empty() || !digits.starts_with(trunk)) {
+            return Some(format!("+{}{}", self.calling_code, digits));
+        }
+        None
+    }
+}
+
+/// Sets the country of SIMs that have none, once at startup
+pub fn set_default_country(country: Option<&'static Country>) {
+    if DEFAULT_COUNTRY.set(country).is_err() {
+        log::warn!("The default country is already set");
+    }
+}
+
+pub fn default_country() -> Option<&'static Country> {
+    DEFAULT_COUNTRY.get().copied().flatten()
+}
+
+/// The number in E.164 (`+` and digits) when it is international or a national number of
+/// `country`, otherwise only its digits. Anything that is not a phone number, like an
+/// alphanumeric sender id, is only trimmed.
+pub fn to_e164(number: &str, country: Option<&Country>) -> String {
+    let trimmed = number.trim();
+    let digits: String = trimmed.chars().filter(|c| !SEPARATORS.contains(c)).collect();
+    let (plus, digits) = match digits.strip_prefix('+') {
+        Some(rest) => (true, rest),
+        None => (false, digits.as_str()),
+    };
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return trimmed.to_string();
+    }
+    if plus {
+        return format!("+{}", digits);
+    }
+    if let Some(international) = digits.strip_prefix("00").filter(|rest| !rest.is_empty()) {
+        return format!("+{}", international);
+    }
+    country
+        .and_then(|country| country.international(digits))
+        .unwrap_or_else(|| digits.to_string())
+}
+
+/// True for numbers in the form `to_e164` returns, as opposed to alphanumeric sender ids, which
+/// cannot be sent to
+pub fn is_phone_number(number: &str) -> bool {
+    let digits = number.strip_prefix('+').unwrap_or(number);
+    !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit())
+}
+
+/// The form phone numbers are stored and matched in, for numbers not tied to a SIM
+pub fn normalize(number: &str) -> String {
+    to_e164(number, default_country())
+}
@@ -0,0 +1,107 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use chrono::{Duration, Utc};
+use flate2::{write::GzEncoder, Compression};
+use log::{error, info};
+
+use crate::{
+    config::{RetentionConfig, RetentionRule},
+    db::Sms,
+};
+
+const BATCH_SIZE: u32 = 500;
+
+/// Daily archive file; every run appends a new gzip member, which gzip readers concatenate
+struct Archive {
+    path: PathBuf,
+    encoder: GzEncoder<File>,
+}
+
+impl Archive {
+    fn open(dir: &Path) -> Result<Self> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+        let path = dir.join(format!("sms-archive-{}.ndjson.gz", Utc::now().format("%Y-%m-%d")));
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        Ok(Self {
+            path,
+            encoder: GzEncoder::new(file, Compression::default()),
+        })
+    }
+}
+
+/// Runs the retention job every `interval` seconds
+pub async fn retention_worker(config: RetentionConfig) {
+    loop {
+        if let Err(err) = apply(&config).await {
+            error!("Retention run failed: {:#}", err);
+        }
+        tokio::time::sleep(tokio::time::Duration::from_secs(config.interval)).await;
+    }
+}
+
+/// Purges deleted messages past the undo window, then archives and removes messages matched
+/// by the rules. Returns how many messages the rules removed.
+pub async fn apply(config: &RetentionConfig) -> Result<u64> {
+    let cutoff = Utc::now() - Duration::hours(config.undo_hours as i64);
+    let purged = Sms::purge_deleted_before(cutoff).await?;
+    if purged > 0 {
+        info!("Purged {} deleted messages older than {}h", purged, config.undo_hours);
+    }
+
+    let mut archive = None;
+    let mut removed = 0;
+    for rule in &config.rules {
+        removed += apply_rule(rule, config.archive_dir.as_deref(), &mut archive).await?;
+    }
+    if let Some(archive) = archive {
+        archive.encoder.finish()?.sync_all()?;
+        info!("Archived {} messages to {}", removed, archive.path.display());
+    }
+    if removed > 0 {
+        info!("Retention rules removed {} messages", removed);
+    }
+    Ok(removed)
+}
+
+async fn apply_rule(
+    rule: &RetentionRule,
+    archive_dir: Option<&Path>,
+    archive: &mut Option<Archive>,
+) -> Result<u64> {
+    let cutoff = Utc::now() - Duration::days(rule.max_age_days as i64);
+    let mut removed = 0;
+    loop {
+        let batch =
+            Sms::query_expired(cutoff, rule.sim_id.as_deref(), rule.contact.as_deref(), BATCH_SIZE)
+                .await?;
+        if batch.is_empty() {
+            return Ok(removed);
+        }
+
+        // Messages are only removed once they are written to the archive
+        if let Some(dir) = archive_dir {
+            if archive.is_none() {
+                *archive = Some(Archive::open(dir)?);
+            }
+            let encoder = &mut archive.as_mut().unwrap().encoder;
+            for row in &batch {
+                serde_json::to_writer(&mut *encoder, row)?;
+                encoder.write_all(b"\n")?;
+            }
+            encoder.flush()?;
+        }
+
+        let ids: Vec<i64> = batch.iter().map(|row| row.sms.id).collect();
+        removed += Sms::delete_by_ids(&ids).await?;
+    }
+}
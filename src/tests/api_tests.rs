@@ -0,0 +1,1841 @@
+use crate::{api, config::SecurityConfig, db, modem::ModemManager, webhook::start_webhook_worker_with_concurrency};
+
+use serde_json::{json, Value};
+use std::{net::SocketAddr, sync::Arc};
+
+/// Serves the API on a random port with Basic credentials admin:secret
+async fn start_api() -> String {
+    start_api_with(SecurityConfig::default()).await
+}
+
+async fn start_api_with(security: SecurityConfig) -> String {
+    let db_path = std::env::temp_dir().join(format!("sms-gateway-test-{}.db", std::process::id()));
+    db::db_init_at(&format!("sqlite://{}", db_path.display()))
+        .await
+        .expect("Failed to initialize test database");
+
+    let app = api::router(
+        Arc::new(ModemManager::empty()),
+        Some(("admin".to_string(), "secret".to_string())),
+        &security,
+        Arc::new(api::SseManager::new()),
+        start_webhook_worker_with_concurrency(vec![], 1),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await
+    });
+
+    format!("http://{}/api", addr)
+}
+
+#[tokio::test]
+async fn test_scoped_api_keys() {
+    let base = start_api().await;
+    let client = reqwest::Client::new();
+
+    let status = client.get(format!("{}/sms?page=1&per_page=10", base)).send().await.unwrap().status();
+    assert_eq!(status, 401);
+
+    let created: Value = client
+        .post(format!("{}/keys", base))
+        .basic_auth("admin", Some("secret"))
+        .json(&json!({ "name": "reader", "scopes": ["sms:read"], "sim_ids": ["sim-a"] }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let token = created["token"].as_str().expect("token is returned on creation");
+    let id = created["id"].as_i64().unwrap();
+    assert!(token.starts_with(created["prefix"].as_str().unwrap()));
+
+    let get = |path: &str| client.get(format!("{}{}", base, path)).bearer_auth(token).send();
+    assert_eq!(get("/sms?page=1&per_page=10").await.unwrap().status(), 200);
+    assert_eq!(get("/webhooks").await.unwrap().status(), 403);
+    assert_eq!(get("/keys").await.unwrap().status(), 403);
+    assert_eq!(get("/sims/sim-b/storage").await.unwrap().status(), 403);
+
+    let status = client
+        .get(format!("{}/sms?page=1&per_page=10", base))
+        .bearer_auth("sgw_not-a-key")
+        .send()
+        .await
+        .unwrap()
+        .status();
+    assert_eq!(status, 401);
+
+    let keys: Value = client
+        .get(format!("{}/keys/{}", base, id))
+        .basic_auth("admin", Some("secret"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert!(keys["last_used_at"].is_string());
+    assert!(keys.get("token").is_none());
+
+    // Expired keys are rejected
+    let status = client
+        .put(format!("{}/keys/{}", base, id))
+        .basic_auth("admin", Some("secret"))
+        .json(&json!({ "name": "reader", "scopes": ["sms:read"], "expires_at": "2020-01-01T00:00:00Z" }))
+        .send()
+        .await
+        .unwrap()
+        .status();
+    assert_eq!(status, 200);
+    assert_eq!(get("/sms?page=1&per_page=10").await.unwrap().status(), 401);
+
+    let status = client
+        .post(format!("{}/keys", base))
+        .basic_auth("admin", Some("secret"))
+        .json(&json!({ "name": "nothing", "scopes": [] }))
+        .send()
+        .await
+        .unwrap()
+        .status();
+    assert_eq!(status, 400);
+
+    // Keys cannot hand out more than they hold themselves
+    let created: Value = client
+        .post(format!("{}/keys", base))
+        .basic_auth("admin", Some("secret"))
+        .json(&json!({ "name": "key-admin", "scopes": ["keys:admin", "sms:read"], "sim_ids": ["sim-a"] }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let admin_token = created["token"].as_str().unwrap().to_string();
+    let create = |body: Value| {
+        client.post(format!("{}/keys", base)).bearer_auth(&admin_token).json(&body).send()
+    };
+    let status = create(json!({ "name": "wider", "scopes": ["sms:read"] })).await.unwrap().status();
+    assert_eq!(status, 403);
+    let status = create(json!({ "name": "other", "scopes": ["sms:read"], "sim_ids": ["sim-b"] }))
+        .await
+        .unwrap()
+        .status();
+    assert_eq!(status, 403);
+    let status = create(json!({ "name": "sender", "scopes": ["sms:send"], "sim_ids": ["sim-a"] }))
+        .await
+        .unwrap()
+        .status();
+    assert_eq!(status, 403);
+    let status = create(json!({ "name": "narrow", "scopes": ["sms:read"], "sim_ids": ["sim-a"] }))
+        .await
+        .unwrap()
+        .status();
+    assert_eq!(status, 201);
+    let status = client
+        .put(format!("{}/keys/{}", base, created["id"]))
+        .bearer_auth(&admin_token)
+        .json(&json!({ "name": "key-admin", "scopes": ["keys:admin", "sms:read"] }))
+        .send()
+        .await
+        .unwrap()
+        .status();
+    assert_eq!(status, 403);
+}
+
+#[tokio::test]
+async fn test_user_sessions_and_roles() {
+    let base = start_api().await;
+    let client = reqwest::Client::new();
+
+    let status = client
+        .post(format!("{}/users", base))
+        .basic_auth("admin", Some("secret"))
+        .json(&json!({ "username": "intern", "password": "correct horse", "role": "read_only", "sim_ids": ["sim-a"] }))
+        .send()
+        .await
+        .unwrap()
+        .status();
+    assert_eq!(status, 201);
+
+    let login = |password: &'static str| {
+        client
+            .post(format!("{}/login", base))
+            .json(&json!({ "username": "intern", "password": password }))
+            .send()
+    };
+    assert_eq!(login("wrong password").await.unwrap().status(), 401);
+
+    let response = login("correct horse").await.unwrap();
+    assert_eq!(response.status(), 200);
+    let cookie = response.headers()["set-cookie"]
+        .to_str()
+        .unwrap()
+        .split(';')
+        .next()
+        .unwrap()
+        .to_string();
+    assert!(cookie.starts_with("sgw_session="));
+
+    let me: Value = client
+        .get(format!("{}/me", base))
+        .header("Cookie", &cookie)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(me["name"], "intern");
+    assert_eq!(me["role"], "read_only");
+
+    let status = client
+        .post(format!("{}/sms", base))
+        .header("Cookie", &cookie)
+        .json(&json!({ "sim_id": "sim-a", "contact": { "id": "", "name": "+10000000000" }, "message": "hi", "new": true }))
+        .send()
+        .await
+        .unwrap()
+        .status();
+    assert_eq!(status, 403);
+    let status = client
+        .get(format!("{}/sims/sim-b/storage", base))
+        .header("Cookie", &cookie)
+        .send()
+        .await
+        .unwrap()
+        .status();
+    assert_eq!(status, 403);
+
+    client
+        .post(format!("{}/logout", base))
+        .header("Cookie", &cookie)
+        .send()
+        .await
+        .unwrap();
+    let status = client
+        .get(format!("{}/me", base))
+        .header("Cookie", &cookie)
+        .send()
+        .await
+        .unwrap()
+        .status();
+    assert_eq!(status, 401);
+
+    // A user admin limited to one SIM cannot create or promote users beyond its own access
+    let created: Value = client
+        .post(format!("{}/keys", base))
+        .basic_auth("admin", Some("secret"))
+        .json(&json!({ "name": "user-admin", "scopes": ["users:admin", "sms:read", "sms:send"], "sim_ids": ["sim-a"] }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let token = created["token"].as_str().unwrap().to_string();
+    let create = |body: Value| client.post(format!("{}/users", base)).bearer_auth(&token).json(&body).send();
+    let status = create(json!({ "username": "boss", "password": "long enough", "role": "admin", "sim_ids": ["sim-a"] }))
+        .await
+        .unwrap()
+        .status();
+    assert_eq!(status, 403);
+    let status = create(json!({ "username": "roamer", "password": "long enough", "role": "operator" }))
+        .await
+        .unwrap()
+        .status();
+    assert_eq!(status, 403);
+    let user: Value = create(json!({ "username": "clerk", "password": "long enough", "role": "operator", "sim_ids": ["sim-a"] }))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let status = client
+        .put(format!("{}/users/{}", base, user["id"]))
+        .bearer_auth(&token)
+        .json(&json!({ "username": "clerk", "role": "admin", "sim_ids": ["sim-a"] }))
+        .send()
+        .await
+        .unwrap()
+        .status();
+    assert_eq!(status, 403);
+}
+
+#[tokio::test]
+async fn test_audit_log() {
+    let base = start_api().await;
+    let client = reqwest::Client::new();
+
+    let user: Value = client
+        .post(format!("{}/users", base))
+        .basic_auth("admin", Some("secret"))
+        .json(&json!({ "username": "auditor-target", "password": "long enough", "role": "operator" }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let id = user["id"].as_i64().unwrap();
+    let status = client
+        .put(format!("{}/users/{}", base, id))
+        .basic_auth("admin", Some("secret"))
+        .json(&json!({ "username": "auditor-target", "role": "read_only" }))
+        .send()
+        .await
+        .unwrap()
+        .status();
+    assert_eq!(status, 200);
+
+    let audit: Value = client
+        .get(format!("{}/audit?page=1&per_page=10&action=user.&target={}", base, id))
+        .basic_auth("admin", Some("secret"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(audit["total"], 2);
+    let latest = &audit["data"][0];
+    assert_eq!(latest["action"], "user.update");
+    assert_eq!(latest["actor"], "admin");
+    assert_eq!(latest["source_ip"], "127.0.0.1");
+    assert_eq!(latest["before"]["role"], "operator");
+    assert_eq!(latest["after"]["role"], "read_only");
+    assert!(latest["after"].get("password_hash").is_none());
+    assert_eq!(audit["data"][1]["action"], "user.create");
+
+    // Entries cannot be edited
+    let pool = db::get_pool().unwrap();
+    let result = sqlx::query("UPDATE audit_log SET actor = 'someone else'").execute(pool).await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn test_tls_client_certificates_and_redirect() {
+    use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa, KeyPair};
+
+    start_api().await;
+    let ca_key = KeyPair::generate().unwrap();
+    let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    ca_params.distinguished_name.push(DnType::CommonName, "Test CA");
+    let ca = ca_params.self_signed(&ca_key).unwrap();
+
+    let server_key = KeyPair::generate().unwrap();
+    let server = CertificateParams::new(vec!["localhost".to_string()])
+        .unwrap()
+        .signed_by(&server_key, &ca, &ca_key)
+        .unwrap();
+    let client_key = KeyPair::generate().unwrap();
+    let mut client_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+    client_params.distinguished_name.push(DnType::CommonName, "billing-server");
+    let client = client_params.signed_by(&client_key, &ca, &ca_key).unwrap();
+
+    let dir = std::env::temp_dir().join(format!("sms-gateway-tls-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let write = |name: &str, pem: String| {
+        let path = dir.join(name);
+        std::fs::write(&path, pem).unwrap();
+        path
+    };
+    let tls: crate::config::TlsConfig = serde_json::from_value(json!({
+        "cert_file": write("server.pem", server.pem()),
+        "key_file": write("server.key", server_key.serialize_pem()),
+        "client_ca_file": write("ca.pem", ca.pem()),
+        "client_scopes": ["sms:read"],
+    }))
+    .unwrap();
+
+    let app = api::router(
+        Arc::new(ModemManager::empty()),
+        Some(("admin".to_string(), "secret".to_string())),
+        &SecurityConfig::default(),
+        Arc::new(api::SseManager::new()),
+        start_webhook_worker_with_concurrency(vec![], 1),
+    );
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(api::serve_tls(listener, app, tls));
+    let redirect = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let redirect_addr = redirect.local_addr().unwrap();
+    tokio::spawn(api::serve_redirect(redirect, port));
+
+    let ca_cert = reqwest::Certificate::from_pem(ca.pem().as_bytes()).unwrap();
+    let base = format!("https://localhost:{}/api", port);
+
+    let anonymous = reqwest::Client::builder()
+        .add_root_certificate(ca_cert.clone())
+        .build()
+        .unwrap();
+    let status = anonymous.get(format!("{}/me", base)).send().await.unwrap().status();
+    assert_eq!(status, 401);
+    let status = anonymous
+        .get(format!("{}/me", base))
+        .basic_auth("admin", Some("secret"))
+        .send()
+        .await
+        .unwrap()
+        .status();
+    assert_eq!(status, 200);
+
+    let identity = reqwest::Identity::from_pkcs8_pem(
+        client.pem().as_bytes(),
+        client_key.serialize_pem().as_bytes(),
+    )
+    .unwrap();
+    let machine = reqwest::Client::builder()
+        .add_root_certificate(ca_cert)
+        .identity(identity)
+        .build()
+        .unwrap();
+    let me: Value = machine.get(format!("{}/me", base)).send().await.unwrap().json().await.unwrap();
+    assert_eq!(me["name"], "cert:billing-server");
+    assert_eq!(me["scopes"], json!(["sms:read"]));
+    let status = machine.get(format!("{}/webhooks", base)).send().await.unwrap().status();
+    assert_eq!(status, 403);
+
+    let response = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+        .get(format!("http://localhost:{}/api/check?x=1", redirect_addr.port()))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 308);
+    assert_eq!(
+        response.headers()["location"],
+        format!("https://localhost:{}/api/check?x=1", port).as_str()
+    );
+}
+
+#[tokio::test]
+async fn test_failed_login_lockout() {
+    let base = start_api_with(SecurityConfig {
+        max_failed_logins: 3,
+        ..SecurityConfig::default()
+    })
+    .await;
+    let client = reqwest::Client::new();
+    let check = |password: &'static str| {
+        client
+            .get(format!("{}/check", base))
+            .basic_auth("admin", Some(password))
+            .send()
+    };
+
+    for _ in 0..3 {
+        assert_eq!(check("guess").await.unwrap().status(), 401);
+    }
+    let response = check("secret").await.unwrap();
+    assert_eq!(response.status(), 429);
+    assert!(response.headers().contains_key("retry-after"));
+
+    let status = client
+        .post(format!("{}/login", base))
+        .json(&json!({ "username": "someone", "password": "whatever" }))
+        .send()
+        .await
+        .unwrap()
+        .status();
+    assert_eq!(status, 429, "the client IP is locked out as well");
+}
+
+#[tokio::test]
+async fn test_ip_allow_and_deny_lists() {
+    let parse = |value: Value| serde_json::from_value::<SecurityConfig>(value).unwrap();
+
+    let base = start_api_with(parse(json!({ "deny": ["127.0.0.0/8"] }))).await;
+    let client = reqwest::Client::new();
+    let status = client
+        .get(format!("{}/check", base))
+        .basic_auth("admin", Some("secret"))
+        .send()
+        .await
+        .unwrap()
+        .status();
+    assert_eq!(status, 403);
+    let ui = base.trim_end_matches("/api");
+    assert_eq!(client.get(ui).send().await.unwrap().status(), 403);
+
+    let base = start_api_with(parse(json!({ "allow": ["10.0.0.0/8", "127.0.0.1"] }))).await;
+    let status = client
+        .get(format!("{}/check", base))
+        .basic_auth("admin", Some("secret"))
+        .send()
+        .await
+        .unwrap()
+        .status();
+    assert_eq!(status, 204);
+
+    let base = start_api_with(parse(json!({ "allow": ["192.168.1.0/24"] }))).await;
+    let status = client.get(format!("{}/check", base)).send().await.unwrap().status();
+    assert_eq!(status, 403);
+
+    assert!(serde_json::from_value::<SecurityConfig>(json!({ "deny": ["not-an-ip"] })).is_err());
+}
+
+#[tokio::test]
+async fn test_sms_search() {
+    let base = start_api().await;
+    let client = reqwest::Client::new();
+
+    let received = |contact: &str, message: &str, time: &str| db::ModemSMS {
+        contact: contact.to_string(),
+        timestamp: chrono::NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M:%S").unwrap(),
+        utc_offset: chrono::FixedOffset::east_opt(0),
+        message: message.to_string(),
+        sim_id: "search-sim".to_string(),
+        ..Default::default()
+    };
+    for sms in [
+        received("Bank", "Your verification code is 481516 <do not share>", "2026-10-13 09:00:00"),
+        received("Shop", "Order shipped, no code needed", "2026-10-14 09:00:00"),
+        received("+8613800000000", "您的验证码是 2342，五分钟内有效", "2026-10-15 09:00:00"),
+    ] {
+        sms.insert().await.unwrap();
+    }
+
+    let search = |query: &str| {
+        client
+            .get(format!("{}/sms/search?page=1&per_page=10&sim_id=search-sim&{}", base, query))
+            .basic_auth("admin", Some("secret"))
+            .send()
+    };
+
+    let result: Value = search("q=VERIFICATION%20code").await.unwrap().json().await.unwrap();
+    assert_eq!(result["total"], 1);
+    let hit = &result["data"][0];
+    assert_eq!(hit["contact_name"], "Bank");
+    assert_eq!(
+        hit["snippet"],
+        "Your <mark>verification</mark> <mark>code</mark> is 481516 &lt;do not share&gt;"
+    );
+
+    let result: Value = search("q=验证码").await.unwrap().json().await.unwrap();
+    assert_eq!(result["total"], 1);
+    assert_eq!(result["data"][0]["contact_name"], "+8613800000000");
+
+    // Contact names are searchable and the date range narrows the results
+    let result: Value = search("q=shop").await.unwrap().json().await.unwrap();
+    assert_eq!(result["total"], 1);
+    let result: Value = search("q=code&order=newest").await.unwrap().json().await.unwrap();
+    assert_eq!(result["total"], 2);
+    assert_eq!(result["data"][0]["contact_name"], "Shop");
+    let result: Value = search("q=code&from=2026-10-14T00:00:00Z").await.unwrap().json().await.unwrap();
+    assert_eq!(result["total"], 1);
+
+    // Display names are searchable too, including ones set after the messages arrived
+    sqlx::query("UPDATE contacts SET display_name = 'Zhang Wei' WHERE name = '+8613800000000'")
+        .execute(db::get_pool().unwrap())
+        .await
+        .unwrap();
+    let result: Value = search("q=zhang").await.unwrap().json().await.unwrap();
+    assert_eq!(result["total"], 1);
+    let result: Value = search("q=8613800").await.unwrap().json().await.unwrap();
+    assert_eq!(result["total"], 1);
+
+    assert_eq!(search("q=is").await.unwrap().status(), 400);
+    assert_eq!(search("q=\"code").await.unwrap().status(), 200);
+}
+
+#[tokio::test]
+async fn test_sms_filters_and_cursor_pagination() {
+    let base = start_api().await;
+    let client = reqwest::Client::new();
+
+    let sms = |message: &str, send: bool, time: &str| db::ModemSMS {
+        contact: "+15550001111".to_string(),
+        timestamp: chrono::NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M:%S").unwrap(),
+        utc_offset: chrono::FixedOffset::east_opt(0),
+        message: message.to_string(),
+        send,
+        sim_id: "cursor-sim".to_string(),
+        ..Default::default()
+    };
+    for (message, send, time) in [
+        ("one", false, "2026-10-01 08:00:00"),
+        ("two", true, "2026-10-02 08:00:00"),
+        ("three", false, "2026-10-03 08:00:00"),
+        ("four", false, "2026-10-03 08:00:00"), // Same timestamp, ordered by id
+        ("five", true, "2026-10-05 08:00:00"),
+    ] {
+        sms(message, send, time).insert().await.unwrap();
+    }
+
+    let get = |query: String| {
+        client
+            .get(format!("{}/sms?sim_id=cursor-sim&{}", base, query))
+            .basic_auth("admin", Some("secret"))
+            .send()
+    };
+    let messages = |page: &Value| -> Vec<String> {
+        page["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|sms| sms["message"].as_str().unwrap().to_string())
+            .collect()
+    };
+
+    let first: Value = get("per_page=2".to_string()).await.unwrap().json().await.unwrap();
+    assert_eq!(messages(&first), ["five", "four"]);
+    let cursor = first["next_cursor"].as_str().unwrap().to_string();
+
+    // A newer message arriving in between does not shift the following pages
+    sms("six", false, "2026-10-06 08:00:00").insert().await.unwrap();
+    let second: Value = get(format!("per_page=2&cursor={}", cursor)).await.unwrap().json().await.unwrap();
+    assert_eq!(messages(&second), ["three", "two"]);
+    let cursor = second["next_cursor"].as_str().unwrap().to_string();
+    let last: Value = get(format!("per_page=2&cursor={}", cursor)).await.unwrap().json().await.unwrap();
+    assert_eq!(messages(&last), ["one"]);
+    assert!(last["next_cursor"].is_null());
+
+    let ascending: Value = get("per_page=3&order=asc&send=false".to_string()).await.unwrap().json().await.unwrap();
+    assert_eq!(messages(&ascending), ["one", "three", "four"]);
+
+    let range: Value = get("page=1&per_page=10&from=2026-10-02T00:00:00Z&to=2026-10-05T00:00:00Z&status=0".to_string())
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(messages(&range), ["four", "three"]);
+    assert_eq!(range["total"], 2);
+
+    assert_eq!(get("per_page=2&cursor=garbage".to_string()).await.unwrap().status(), 400);
+}
+
+#[tokio::test]
+async fn test_sms_deletion_restore_and_retention() {
+    let base = start_api().await;
+    let client = reqwest::Client::new();
+
+    let sms = |contact: &str, message: &str, time: &str| db::ModemSMS {
+        contact: contact.to_string(),
+        timestamp: chrono::NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M:%S").unwrap(),
+        utc_offset: chrono::FixedOffset::east_opt(0),
+        message: message.to_string(),
+        sim_id: "delete-sim".to_string(),
+        ..Default::default()
+    };
+    let first = sms("+15550002222", "first", "2026-10-01 08:00:00").insert().await.unwrap();
+    sms("+15550002222", "second", "2026-10-02 08:00:00").insert().await.unwrap();
+    sms("+15550003333", "ancient", "2020-01-01 08:00:00").insert().await.unwrap();
+
+    let list = || async {
+        let page: Value = client
+            .get(format!("{}/sms?page=1&per_page=10&sim_id=delete-sim&order=asc", base))
+            .basic_auth("admin", Some("secret"))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        page["data"].as_array().unwrap().clone()
+    };
+    let messages = |data: &[Value]| -> Vec<String> {
+        data.iter().map(|sms| sms["message"].as_str().unwrap().to_string()).collect()
+    };
+
+    let deleted: Value = client
+        .delete(format!("{}/sms/{}", base, first))
+        .basic_auth("admin", Some("secret"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(deleted["deleted"], 1);
+    assert_eq!(messages(&list().await), ["ancient", "second"]);
+
+    let restore = |deletion_id: String| {
+        client
+            .post(format!("{}/deletions/{}/restore", base, deletion_id))
+            .basic_auth("admin", Some("secret"))
+            .send()
+    };
+    let restored: Value = restore(deleted["deletion_id"].as_str().unwrap().to_string())
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(restored["restored"], 1);
+    assert_eq!(messages(&list().await), ["ancient", "first", "second"]);
+
+    // Deleting the conversation keeps the contact while the messages can still be restored
+    let data = list().await;
+    let contact_id = data[1]["contact_id"].as_str().unwrap().to_string();
+    let deleted: Value = client
+        .delete(format!("{}/conversations/{}", base, contact_id))
+        .basic_auth("admin", Some("secret"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(deleted["deleted"], 2);
+    assert_eq!(messages(&list().await), ["ancient"]);
+    db::Contact::delete_contacts_without_messages().await.unwrap();
+    let restored: Value = restore(deleted["deletion_id"].as_str().unwrap().to_string())
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(restored["restored"], 2);
+    assert_eq!(messages(&list().await), ["ancient", "first", "second"]);
+
+    let deleted: Value = client
+        .delete(format!("{}/conversations/{}", base, contact_id))
+        .basic_auth("admin", Some("secret"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let deletion_id = deleted["deletion_id"].as_str().unwrap().to_string();
+
+    let archive_dir = std::env::temp_dir().join(format!("sms-gateway-archive-{}", std::process::id()));
+    let mut config = crate::config::RetentionConfig {
+        undo_hours: 0,
+        archive_dir: Some(archive_dir.clone()),
+        rules: vec![crate::config::RetentionRule {
+            max_age_days: 365,
+            sim_id: Some("delete-sim".to_string()),
+            contact: Some("001 555 000 3333".to_string()),
+        }],
+        ..Default::default()
+    };
+    // As when the config is loaded, the contact matches however it is written
+    config.normalize_contacts(None);
+    assert_eq!(config.rules[0].contact.as_deref(), Some("+15550003333"));
+    assert_eq!(crate::retention::apply(&config).await.unwrap(), 1);
+    assert!(list().await.is_empty());
+    // Past the undo window the deletion is gone for good
+    assert_eq!(restore(deletion_id).await.unwrap().status(), 404);
+
+    let mut archived = String::new();
+    for entry in std::fs::read_dir(&archive_dir).unwrap() {
+        let file = std::fs::File::open(entry.unwrap().path()).unwrap();
+        std::io::Read::read_to_string(&mut flate2::read::MultiGzDecoder::new(file), &mut archived).unwrap();
+    }
+    let line: Value = serde_json::from_str(archived.lines().next().unwrap()).unwrap();
+    assert_eq!(line["message"], "ancient");
+    assert_eq!(line["contact_name"], "+15550003333");
+    std::fs::remove_dir_all(&archive_dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_contact_deletion_is_limited_to_own_sims() {
+    let base = start_api().await;
+    let client = reqwest::Client::new();
+
+    let sms = |sim_id: &str, message: &str| db::ModemSMS {
+        contact: "+15550004444".to_string(),
+        timestamp: chrono::NaiveDateTime::parse_from_str("2026-10-03 08:00:00", "%Y-%m-%d %H:%M:%S").unwrap(),
+        utc_offset: chrono::FixedOffset::east_opt(0),
+        message: message.to_string(),
+        sim_id: sim_id.to_string(),
+        ..Default::default()
+    };
+    sms("contact-sim-a", "on a").insert().await.unwrap();
+    sms("contact-sim-b", "on b").insert().await.unwrap();
+    let contact_id = db::Contact::find_by_number("+15550004444").await.unwrap().unwrap().id;
+
+    let created: Value = client
+        .post(format!("{}/keys", base))
+        .basic_auth("admin", Some("secret"))
+        .json(&json!({ "name": "sender", "scopes": ["sms:send"], "sim_ids": ["contact-sim-a"] }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let status = client
+        .delete(format!("{}/contacts/{}", base, contact_id))
+        .bearer_auth(created["token"].as_str().unwrap())
+        .send()
+        .await
+        .unwrap()
+        .status();
+    assert_eq!(status, 403);
+
+    let created: Value = client
+        .post(format!("{}/keys", base))
+        .basic_auth("admin", Some("secret"))
+        .json(&json!({ "name": "deleter", "scopes": ["sms:delete"], "sim_ids": ["contact-sim-a"] }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let token = created["token"].as_str().unwrap().to_string();
+
+    // A key for one SIM only removes the messages on that SIM
+    let deleted: Value = client
+        .delete(format!("{}/contacts/{}", base, contact_id))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(deleted["deleted"], 1);
+    assert_eq!(deleted["contact_deleted"], false);
+    let status = client
+        .delete(format!("{}/contacts/{}", base, contact_id))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .unwrap()
+        .status();
+    assert_eq!(status, 404);
+
+    // The deletion can be undone
+    let restored: Value = client
+        .post(format!("{}/deletions/{}/restore", base, deleted["deletion_id"].as_str().unwrap()))
+        .basic_auth("admin", Some("secret"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(restored["restored"], 1);
+
+    // The contact stays while its deleted messages can still be restored
+    let deleted: Value = client
+        .delete(format!("{}/contacts/{}", base, contact_id))
+        .basic_auth("admin", Some("secret"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(deleted["deleted"], 2);
+    assert_eq!(deleted["contact_deleted"], false);
+    assert!(db::Contact::find_by_number("+15550004444").await.unwrap().is_some());
+
+    let audit: Value = client
+        .get(format!("{}/audit?page=1&per_page=10&action=contact.delete&target={}", base, contact_id))
+        .basic_auth("admin", Some("secret"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(audit["total"], 2);
+    assert_eq!(audit["data"][1]["actor"], "key:deleter");
+}
+
+#[tokio::test]
+async fn test_contacts_are_limited_to_own_sims() {
+    let base = start_api().await;
+    let client = reqwest::Client::new();
+
+    for (number, sim_id) in [("+15550005551", "visible-sim-a"), ("+15550005552", "visible-sim-b")] {
+        let response = client
+            .post(format!("{}/contacts", base))
+            .basic_auth("admin", Some("secret"))
+            .json(&json!({ "name": number, "tags": ["visible-test"] }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        db::ModemSMS {
+            contact: number.to_string(),
+            message: "hello".to_string(),
+            sim_id: sim_id.to_string(),
+            ..Default::default()
+        }
+        .insert()
+        .await
+        .unwrap();
+    }
+    let hidden = db::Contact::find_by_number("+15550005552").await.unwrap().unwrap().id;
+
+    let created: Value = client
+        .post(format!("{}/keys", base))
+        .basic_auth("admin", Some("secret"))
+        .json(&json!({ "name": "contact-reader", "scopes": ["sms:read", "sms:send"], "sim_ids": ["visible-sim-a"] }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let token = created["token"].as_str().unwrap().to_string();
+    let get = |path: String| client.get(format!("{}/{}", base, path)).bearer_auth(&token).send();
+
+    let contacts: Value = get("contacts?tag=visible-test".to_string()).await.unwrap().json().await.unwrap();
+    assert_eq!(contacts.as_array().unwrap().len(), 1);
+    assert_eq!(contacts[0]["name"], "+15550005551");
+    let tags: Value = get("contact-tags".to_string()).await.unwrap().json().await.unwrap();
+    let tag = tags.as_array().unwrap().iter().find(|tag| tag["tag"] == "visible-test").unwrap();
+    assert_eq!(tag["contacts"], 1);
+    let export = get("contacts/export?format=csv&tag=visible-test".to_string()).await.unwrap().text().await.unwrap();
+    assert!(export.contains("+15550005551") && !export.contains("+15550005552"));
+
+    // Contacts on other SIMs are not found, so they cannot be changed either
+    assert_eq!(get(format!("contacts/{}", hidden)).await.unwrap().status(), 404);
+    let status = client
+        .put(format!("{}/contacts/{}", base, hidden))
+        .bearer_auth(&token)
+        .json(&json!({ "display_name": "Mallory" }))
+        .send()
+        .await
+        .unwrap()
+        .status();
+    assert_eq!(status, 404);
+
+    let contacts: Value = client
+        .get(format!("{}/contacts?tag=visible-test", base))
+        .basic_auth("admin", Some("secret"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(contacts.as_array().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn test_sms_export_formats() {
+    let base = start_api().await;
+    let client = reqwest::Client::new();
+
+    db::SimCard {
+        id: "export-sim".to_string(),
+        alias: Some("Office".to_string()),
+        ..Default::default()
+    }
+    .insert()
+    .await
+    .unwrap();
+    for (message, send, time) in [
+        ("plain", false, "2026-10-01 08:00:00"),
+        ("comma, \"quote\" & <tag>\nline", true, "2026-10-02 08:00:00"),
+    ] {
+        db::ModemSMS {
+            contact: "+15550004444".to_string(),
+            timestamp: chrono::NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M:%S").unwrap(),
+            utc_offset: chrono::FixedOffset::east_opt(3600),
+            message: message.to_string(),
+            send,
+            sim_id: "export-sim".to_string(),
+            ..Default::default()
+        }
+        .insert()
+        .await
+        .unwrap();
+    }
+
+    let export = |format: &str| {
+        let url = format!("{}/export?sim_id=export-sim&order=asc&format={}", base, format);
+        let client = client.clone();
+        async move {
+            let response = client.get(url).basic_auth("admin", Some("secret")).send().await.unwrap();
+            assert_eq!(response.status(), 200);
+            let disposition = response.headers()["content-disposition"].to_str().unwrap().to_string();
+            assert!(disposition.starts_with("attachment; filename=\"sms-export-"));
+            response.text().await.unwrap()
+        }
+    };
+
+    let csv = export("csv").await;
+    let mut lines = csv.lines();
+    assert_eq!(
+        lines.next().unwrap(),
+        "id,timestamp,received_at,direction,contact,sim_id,sim_alias,status,message"
+    );
+    assert!(lines
+        .next()
+        .unwrap()
+        .ends_with(",2026-10-01T08:00:00+01:00,,incoming,+15550004444,export-sim,Office,unread,plain"));
+    assert!(csv.contains(",outgoing,+15550004444,export-sim,Office,read,\"comma, \"\"quote\"\" & <tag>\nline\"\n"));
+
+    let ndjson = export("ndjson").await;
+    let rows: Vec<Value> = ndjson.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[0]["contact_name"], "+15550004444");
+    assert_eq!(rows[0]["sim_alias"], "Office");
+    assert_eq!(rows[1]["message"], "comma, \"quote\" & <tag>\nline");
+
+    let xml = export("xml").await;
+    assert!(xml.starts_with("<?xml version='1.0' encoding='UTF-8' standalone='yes' ?>\n<smses count=\"2\">\n"));
+    assert!(xml.contains("address=\"+15550004444\" date=\"1790838000000\" type=\"1\""));
+    assert!(xml.contains("type=\"2\" subject=\"null\" body=\"comma, &quot;quote&quot; &amp; &lt;tag&gt;&#10;line\""));
+    assert!(xml.ends_with("</smses>\n"));
+
+    let response = client
+        .get(format!("{}/export?format=pdf", base))
+        .basic_auth("admin", Some("secret"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+}
+
+#[tokio::test]
+async fn test_sms_import_and_dedup() {
+    let base = start_api().await;
+    let client = reqwest::Client::new();
+    let import = |query: &str, body: String| {
+        client
+            .post(format!("{}/import?{}", base, query))
+            .basic_auth("admin", Some("secret"))
+            .body(body)
+            .send()
+    };
+
+    let backup = r#"<?xml version='1.0' encoding='UTF-8' standalone='yes' ?>
+<smses count="6">
+  <sms protocol="0" address="+15550005555" date="1790838000000" type="1" body="Hi &amp; welcome &#55357;&#56832;" read="1" status="-1" />
+  <sms protocol="0" address="+15550005555" date="1790841600000" type="2" body="Thanks&#10;bye" read="1" status="-1" />
+  <sms protocol="0" address="+15550005555" date="1790841600000" type="2" body="Thanks&#10;bye" read="1" status="-1" />
+  <sms protocol="0" address="+15550005555" date="1790845200000" type="3" body="draft" read="1" />
+  <mms date="1790845200000" msg_box="1"><parts><part seq="0" ct="text/plain" text="mms" /></parts></mms>
+  <sms protocol="0" date="1790848800000" type="1" body="no address" read="0" />
+</smses>"#;
+
+    let report: Value = import("format=xml&sim_id=import-sim", backup.to_string())
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(report["inserted"], 2);
+    assert_eq!(report["skipped"], 3); // Repeated message, draft and MMS
+    assert_eq!(report["invalid"], 1);
+    assert_eq!(report["errors"][0], "record 6: Missing address");
+
+    // Importing the same backup again only finds duplicates
+    let report: Value = import("format=xml&sim_id=import-sim", backup.to_string())
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(report["inserted"], 0);
+    assert_eq!(report["skipped"], 5);
+
+    let page: Value = client
+        .get(format!("{}/sms?page=1&per_page=10&sim_id=import-sim&order=asc", base))
+        .basic_auth("admin", Some("secret"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(page["data"][0]["message"], "Hi & welcome \u{1F600}");
+    assert_eq!(page["data"][0]["status"], 1);
+    assert_eq!(page["data"][1]["message"], "Thanks\nbye");
+    assert_eq!(page["data"][1]["send"], true);
+
+    // Exports import back unchanged, onto another SIM here
+    for format in ["csv", "ndjson"] {
+        let exported = client
+            .get(format!("{}/export?sim_id=import-sim&format={}", base, format))
+            .basic_auth("admin", Some("secret"))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        let sim = format!("import-sim-{}", format);
+        let report: Value = import(&format!("format={}&sim_id={}", format, sim), exported)
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(report["inserted"], 2, "{}: {}", format, report);
+
+        let copy: Value = client
+            .get(format!("{}/sms?page=1&per_page=10&sim_id={}&order=asc", base, sim))
+            .basic_auth("admin", Some("secret"))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        for field in ["message", "timestamp", "send", "status"] {
+            assert_eq!(copy["data"][0][field], page["data"][0][field], "{} {}", format, field);
+            assert_eq!(copy["data"][1][field], page["data"][1][field], "{} {}", format, field);
+        }
+    }
+
+    let response = import("format=xml", backup.to_string()).await.unwrap();
+    let report: Value = response.json().await.unwrap();
+    assert_eq!(report["inserted"], 0);
+    assert_eq!(report["errors"][0], "record 1: No SIM, pass sim_id for files without one");
+}
+
+#[tokio::test]
+async fn test_repeated_reads_are_deduplicated() {
+    let base = start_api().await;
+    let read = || {
+        let sms = |message: &str, concat_ref: Option<u8>| db::ModemSMS {
+            contact: "+15550006666".to_string(),
+            timestamp: chrono::NaiveDateTime::parse_from_str("2026-10-01 08:00:00", "%Y-%m-%d %H:%M:%S").unwrap(),
+            utc_offset: chrono::FixedOffset::east_opt(7200),
+            message: message.to_string(),
+            sim_id: "dedup-sim".to_string(),
+            concat_ref,
+            ..Default::default()
+        };
+        // The same long text sent twice in one second differs only in the concatenation reference
+        vec![sms("short", None), sms("long text", Some(7)), sms("long text", Some(8))]
+    };
+
+    let mut first = read();
+    let contacts = db::ModemSMS::bulk_insert(&mut first).await.unwrap();
+    assert_eq!(contacts.len(), 1);
+    assert!(first.iter().all(|sms| sms.id.is_some()));
+
+    // Reading the modem again, e.g. after a crash or a full refresh, stores nothing new
+    let mut again = read();
+    assert!(db::ModemSMS::bulk_insert(&mut again).await.unwrap().is_empty());
+    assert!(again.iter().all(|sms| sms.id.is_none()));
+    assert_eq!(read()[0].insert().await.unwrap(), first[0].id.unwrap());
+
+    // The sender in another notation is the same number
+    let mut other_notation = read();
+    other_notation[0].contact = "0015550006666".to_string();
+    assert_eq!(other_notation[0].insert().await.unwrap(), first[0].id.unwrap());
+
+    // Messages without a fingerprint get it back from their contact's number
+    sqlx::query("UPDATE sms SET fingerprint = NULL WHERE sim_id = 'dedup-sim'")
+        .execute(db::get_pool().unwrap())
+        .await
+        .unwrap();
+    db::backfill_fingerprints().await.unwrap();
+    assert_eq!(other_notation[0].insert().await.unwrap(), first[0].id.unwrap());
+
+    let page: Value = reqwest::Client::new()
+        .get(format!("{}/sms?page=1&per_page=10&sim_id=dedup-sim", base))
+        .basic_auth("admin", Some("secret"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(page["total"], 3);
+
+    // Outgoing messages sent twice in the same second are both kept
+    let contact_id = page["data"][0]["contact_id"].as_str().unwrap().to_string();
+    let sent = db::Sms {
+        contact_id,
+        timestamp: db::SmsTimestamp::now(),
+        message: "on my way".to_string(),
+        sim_id: "dedup-sim".to_string(),
+        send: true,
+        ..Default::default()
+    };
+    let first_send = sent.insert().await.unwrap();
+    assert_ne!(sent.insert().await.unwrap(), first_send);
+}
+
+#[tokio::test]
+async fn test_contacts_numbers_tags_and_blocking() {
+    let base = start_api().await;
+    let client = reqwest::Client::new();
+
+    let response = client
+        .post(format!("{}/contacts", base))
+        .basic_auth("admin", Some("secret"))
+        .json(&json!({
+            "display_name": "Alice",
+            "numbers": [
+                { "number": "+1 (555) 000-7777", "label": "mobile" },
+                { "number": "0044 20 7946 0777", "label": "work" },
+            ],
+            "tags": [" friends ", "family", "friends"],
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let alice: Value = response.json().await.unwrap();
+    let id = alice["id"].as_str().unwrap().to_string();
+    assert_eq!(alice["name"], "+15550007777");
+    assert_eq!(alice["numbers"][1], json!({ "number": "+442079460777", "label": "work" }));
+    assert_eq!(alice["tags"], json!(["family", "friends"]));
+
+    // A number belongs to one contact only
+    let response = client
+        .post(format!("{}/contacts", base))
+        .basic_auth("admin", Some("secret"))
+        .json(&json!({ "name": "+15550007777" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 409);
+
+    let found: Value = client
+        .get(format!("{}/contacts?q=alic&tag=family", base))
+        .basic_auth("admin", Some("secret"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert!(found.as_array().unwrap().iter().any(|c| c["id"] == id.as_str()));
+
+    // Incoming messages from any of the numbers, in any notation, go to the contact
+    let sms = |contact: &str, message: &str| db::ModemSMS {
+        contact: contact.to_string(),
+        timestamp: chrono::Local::now().naive_local(),
+        message: message.to_string(),
+        sim_id: "contacts-sim".to_string(),
+        ..Default::default()
+    };
+    let mut incoming = vec![sms("+442079460777", "from work"), sms("0015550007777", "from mobile")];
+    let contacts = db::ModemSMS::bulk_insert(&mut incoming).await.unwrap();
+    assert_eq!(contacts, vec![id.clone()]);
+
+    let response = client
+        .put(format!("{}/contacts/{}", base, id))
+        .basic_auth("admin", Some("secret"))
+        .json(&json!({ "blocked": true, "notes": "asked not to be texted" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let updated: Value = response.json().await.unwrap();
+    assert_eq!(updated["blocked"], true);
+    assert_eq!(updated["display_name"], "Alice");
+    assert_eq!(updated["numbers"].as_array().unwrap().len(), 2);
+
+    // Messages from blocked contacts are kept, but as read
+    sms("+15550007777", "still blocked").insert().await.unwrap();
+    let page: Value = client
+        .get(format!("{}/sms?page=1&per_page=10&contact_id={}&status=0", base, id))
+        .basic_auth("admin", Some("secret"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(page["total"], 2);
+    assert!(db::Contact::blocked_numbers().await.unwrap().contains("+442079460777"));
+
+    let tags: Value = client
+        .get(format!("{}/contact-tags", base))
+        .basic_auth("admin", Some("secret"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert!(tags.as_array().unwrap().iter().any(|t| t["tag"] == "family"));
+}
+
+#[tokio::test]
+async fn test_sim_country_merges_duplicate_contacts() {
+    let base = start_api().await;
+    let client = reqwest::Client::new();
+    db::SimCard {
+        id: "e164-sim".to_string(),
+        ..Default::default()
+    }
+    .insert()
+    .await
+    .unwrap();
+
+    // Without a country the national and the international form are different contacts
+    let sms = |contact: &str, message: &str| db::ModemSMS {
+        contact: contact.to_string(),
+        timestamp: chrono::Local::now().naive_local(),
+        message: message.to_string(),
+        sim_id: "e164-sim".to_string(),
+        ..Default::default()
+    };
+    let national = db::ModemSMS::bulk_insert(&mut [sms("13900139000", "national")]).await.unwrap();
+    let international =
+        db::ModemSMS::bulk_insert(&mut [sms("+8613900139000", "international")]).await.unwrap();
+    assert_ne!(national, international);
+
+    let response = client
+        .put(format!("{}/sim-cards/e164-sim/country", base))
+        .basic_auth("admin", Some("secret"))
+        .json(&json!({ "country": "CN" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let sim: Value = response.json().await.unwrap();
+    assert_eq!(sim["default_country"], "CN");
+
+    // The older contact keeps both conversations under the E.164 number
+    let contacts: Value = client
+        .get(format!("{}/contacts?q=13900139000", base))
+        .basic_auth("admin", Some("secret"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(contacts.as_array().unwrap().len(), 1);
+    assert_eq!(contacts[0]["id"], national[0].as_str());
+    assert_eq!(contacts[0]["name"], "+8613900139000");
+
+    // New messages in any notation join it
+    let later = db::ModemSMS::bulk_insert(&mut [sms("013900139000", "trunk prefix")]).await.unwrap();
+    assert_eq!(later, national);
+    let page: Value = client
+        .get(format!("{}/sms?page=1&per_page=10&contact_id={}", base, national[0]))
+        .basic_auth("admin", Some("secret"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(page["total"], 3);
+
+    let response = client
+        .put(format!("{}/sim-cards/e164-sim/country", base))
+        .basic_auth("admin", Some("secret"))
+        .json(&json!({ "country": "Narnia" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+}
+
+#[tokio::test]
+async fn test_contacts_vcard_and_csv_import_export() {
+    let base = start_api().await;
+    let client = reqwest::Client::new();
+
+    let response = client
+        .post(format!("{}/contacts", base))
+        .basic_auth("admin", Some("secret"))
+        .json(&json!({ "display_name": "Bob", "numbers": [{ "number": "+15550008801" }] }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let bob: Value = response.json().await.unwrap();
+
+    let vcards = "BEGIN:VCARD\r\nVERSION:3.0\r\nFN:Robert\r\nTEL;TYPE=CELL:+1 555 000 8801\r\n\
+        TEL;TYPE=work,voice:+1 555 000 8802\r\nCATEGORIES:clients,vip\r\nEND:VCARD\r\n\
+        BEGIN:VCARD\r\nVERSION:4.0\r\nN:Smith;Carol;;;\r\nTEL;VALUE=uri;TYPE=cell:tel:+1-555-000-8803\r\n\
+        NOTE:met at the\r\n  fair\\, twice\r\nEND:VCARD\r\n\
+        BEGIN:VCARD\r\nVERSION:3.0\r\nFN:Nobody\r\nEND:VCARD\r\n";
+    let import = |format: &'static str, body: &'static str| {
+        client
+            .post(format!("{}/contacts/import?format={}", base, format))
+            .basic_auth("admin", Some("secret"))
+            .body(body)
+            .send()
+    };
+    let report: Value = import("vcard", vcards).await.unwrap().json().await.unwrap();
+    assert_eq!(report["created"], 1);
+    assert_eq!(report["updated"], 1);
+    assert_eq!(report["invalid"], 1);
+    // The stored display name wins and the difference is reported
+    assert_eq!(
+        report["conflicts"],
+        json!([{
+            "record": 1,
+            "contact_id": bob["id"],
+            "field": "display_name",
+            "existing": "Bob",
+            "imported": "Robert",
+        }])
+    );
+
+    let merged: Value = client
+        .get(format!("{}/contacts/{}", base, bob["id"].as_str().unwrap()))
+        .basic_auth("admin", Some("secret"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(merged["display_name"], "Bob");
+    assert_eq!(
+        merged["numbers"],
+        json!([
+            { "number": "+15550008801", "label": "cell" },
+            { "number": "+15550008802", "label": "work" },
+        ])
+    );
+    assert_eq!(merged["tags"], json!(["clients", "vip"]));
+
+    // Importing the same file again changes nothing
+    let report: Value = import("vcard", vcards).await.unwrap().json().await.unwrap();
+    assert_eq!((report["created"].clone(), report["unchanged"].clone()), (json!(0), json!(2)));
+
+    let export = |query: &'static str| {
+        client
+            .get(format!("{}/contacts/export?{}", base, query))
+            .basic_auth("admin", Some("secret"))
+            .send()
+    };
+    let response = export("format=vcard4&q=Carol").await.unwrap();
+    assert_eq!(response.headers()["content-type"], "text/vcard; charset=utf-8");
+    let vcard = response.text().await.unwrap();
+    assert!(vcard.contains("FN:Carol Smith\r\n"));
+    assert!(vcard.contains("TEL;VALUE=uri;PREF=1;TYPE=cell:tel:+15550008803\r\n"));
+    assert!(vcard.contains("NOTE:met at the fair\\, twice\r\n"));
+
+    let csv = export("format=csv&tag=vip").await.unwrap().text().await.unwrap();
+    assert_eq!(
+        csv,
+        format!(
+            "id,display_name,numbers,labels,tags,notes,blocked\n\
+             {},Bob,+15550008801;+15550008802,cell;work,clients;vip,,false\n",
+            bob["id"].as_str().unwrap()
+        )
+    );
+
+    let report: Value = import("csv", "name,phone,tags,blocked\nDave,+1 555 000 8804,vip;clients,yes\n")
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(report["created"], 1);
+    let blocked: Value = client
+        .get(format!("{}/contacts?q=Dave&blocked=true", base))
+        .basic_auth("admin", Some("secret"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(blocked[0]["tags"], json!(["clients", "vip"]));
+}
+
+#[tokio::test]
+async fn test_campaign_lifecycle() {
+    let base = start_api().await;
+    let client = reqwest::Client::new();
+
+    for (number, blocked) in [("+15550009903", true), ("+15550009904", false)] {
+        let response = client
+            .post(format!("{}/contacts", base))
+            .basic_auth("admin", Some("secret"))
+            .json(&json!({ "name": number, "tags": ["campaign-vip"], "blocked": blocked }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+    }
+
+    // Tagged contacts have no @code@
+    let response = client
+        .post(format!("{}/campaigns", base))
+        .basic_auth("admin", Some("secret"))
+        .json(&json!({
+            "name": "codes",
+            "template": "Your code is @code@",
+            "recipients": [{ "number": "+15550009901", "variables": { "code": "1" } }],
+            "tag": "campaign-vip",
+            "sim_ids": ["campaign-sim"],
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+
+    let response = client
+        .post(format!("{}/campaigns", base))
+        .basic_auth("admin", Some("secret"))
+        .json(&json!({
+            "name": "opening",
+            "template": "Hi @name@, we open at 9. Questions: shop@example.com",
+            "recipients": [
+                { "number": "+1 555 000 9901", "variables": { "name": "Ann" } },
+                { "number": "+15550009901" },
+            ],
+            "tag": "campaign-vip",
+            "sim_ids": ["campaign-sim"],
+            "per_minute": 600,
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let created: Value = response.json().await.unwrap();
+    let id = created["id"].as_i64().unwrap();
+    assert_eq!(created["progress"]["pending"], 2);
+    assert_eq!(created["progress"]["skipped"], 1);
+
+    // No modem holds the SIM, so the campaign pauses itself
+    let get = |path: String| {
+        client
+            .get(format!("{}/campaigns/{}", base, path))
+            .basic_auth("admin", Some("secret"))
+            .send()
+    };
+    let mut campaign = Value::Null;
+    for _ in 0..50 {
+        campaign = get(id.to_string()).await.unwrap().json().await.unwrap();
+        if campaign["status"] == "paused" {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    assert_eq!(campaign["status"], "paused");
+    assert_eq!(campaign["last_error"], "None of the campaign's SIMs is connected");
+
+    let post = |action: &str| {
+        client
+            .post(format!("{}/campaigns/{}/{}", base, id, action))
+            .basic_auth("admin", Some("secret"))
+            .send()
+    };
+    assert_eq!(post("pause").await.unwrap().status(), 409);
+    assert_eq!(post("resume").await.unwrap().status(), 200);
+    let cancelled: Value = post("cancel").await.unwrap().json().await.unwrap();
+    assert_eq!(cancelled["status"], "cancelled");
+    assert_eq!(cancelled["progress"]["cancelled"], 2);
+    assert_eq!(post("resume").await.unwrap().status(), 409);
+
+    let report: Value = get(format!("{}/report", id)).await.unwrap().json().await.unwrap();
+    assert!(report["duration_secs"].is_i64());
+    assert_eq!(
+        report["issues"],
+        json!([
+            { "status": "cancelled", "error": null, "recipients": 2 },
+            { "status": "skipped", "error": "Contact is blocked", "recipients": 1 },
+        ])
+    );
+    let skipped: Value =
+        get(format!("{}/recipients?status=skipped", id)).await.unwrap().json().await.unwrap();
+    assert_eq!(skipped["total"], 1);
+    assert_eq!(skipped["data"][0]["number"], "+15550009903");
+
+    // Campaign bodies use the webhook template syntax
+    let template = "Hi @name|upper@@if:order@, order @order@ is ready@endif@. @sign|default:Shop@ @sim_id@";
+    let segments = crate::campaign::parse_template(template).unwrap();
+    assert!(crate::campaign::required_variables(&segments).is_empty());
+    let variables = [("name".to_string(), "Ann".to_string())].into();
+    assert_eq!(
+        crate::campaign::render(&segments, "campaign-sim", "+15550009901", variables).await,
+        "Hi ANN. Shop campaign-sim"
+    );
+    let variables = [("order".to_string(), "42".to_string())].into();
+    assert_eq!(
+        crate::campaign::render(&segments, "campaign-sim", "+15550009901", variables).await,
+        "Hi , order 42 is ready. Shop campaign-sim"
+    );
+    let segments = crate::campaign::parse_template("Code @code@ for @number@").unwrap();
+    assert_eq!(crate::campaign::required_variables(&segments), ["code"]);
+    assert!(crate::campaign::parse_template("Mail shop@example.com about @order@").is_err());
+}
+
+/// Records what auto-reply rules send instead of using a modem
+#[derive(Default)]
+struct RecordingSender(std::sync::Mutex<Vec<(String, String)>>);
+
+impl crate::autoreply::AutoSender for RecordingSender {
+    async fn send(&self, contact: &db::Contact, message: &str) -> anyhow::Result<i64> {
+        let mut sent = self.0.lock().unwrap();
+        sent.push((contact.name.clone(), message.to_string()));
+        Ok(sent.len() as i64)
+    }
+
+    async fn confirm(&self, number: &str, message: &str) -> anyhow::Result<()> {
+        self.0.lock().unwrap().push((number.to_string(), message.to_string()));
+        Ok(())
+    }
+}
+
+impl RecordingSender {
+    fn sent_to(&self, number: &str) -> Vec<String> {
+        let sent = self.0.lock().unwrap();
+        sent.iter().filter(|(to, _)| to == number).map(|(_, text)| text.clone()).collect()
+    }
+}
+
+#[tokio::test]
+async fn test_auto_reply_rules() {
+    let base = start_api().await;
+    let client = reqwest::Client::new();
+    db::SimCard {
+        id: "bot-sim".to_string(),
+        phone_number: Some("+15550006999".to_string()),
+        ..Default::default()
+    }
+    .insert()
+    .await
+    .unwrap();
+
+    let create = |rule: Value| {
+        client
+            .post(format!("{}/auto-replies", base))
+            .basic_auth("admin", Some("secret"))
+            .json(&rule)
+            .send()
+    };
+    let response = create(json!({
+        "config": { "actions": [{ "type": "forward", "to": "the office" }] }
+    }))
+    .await
+    .unwrap();
+    assert_eq!(response.status(), 400);
+
+    let response = create(json!({
+        "name": "status",
+        "priority": 1,
+        "config": {
+            "keywords": ["status"],
+            "filter": { "sim": { "iccid": ["bot-sim"] } },
+            "cooldown_secs": 3600,
+            "actions": [
+                { "type": "reply", "template": "@contact@: all systems go" },
+                { "type": "tag", "tag": "bot-users" }
+            ]
+        }
+    }))
+    .await
+    .unwrap();
+    assert_eq!(response.status(), 201);
+    let response = create(json!({
+        "name": "orders",
+        "priority": 2,
+        "config": {
+            "keywords": ["order"],
+            "filter": { "sim": { "iccid": ["bot-sim"] } },
+            "cooldown_secs": 0,
+            "actions": [
+                { "type": "reply", "template": "Order @message::(\\d+)::1@ received" },
+                { "type": "forward", "to": "+15550007000" }
+            ]
+        }
+    }))
+    .await
+    .unwrap();
+    assert_eq!(response.status(), 201);
+
+    let sms = |contact: &str, message: &str| db::ModemSMS {
+        contact: contact.to_string(),
+        message: message.to_string(),
+        sim_id: "bot-sim".to_string(),
+        ..Default::default()
+    };
+    let sender = RecordingSender::default();
+    crate::autoreply::handle(&sender, &[sms("+15550006001", "Status?")], None).await;
+    assert_eq!(sender.sent_to("+15550006001"), ["+15550006001: all systems go"]);
+    let tagged: Value = client
+        .get(format!("{}/contacts?tag=bot-users", base))
+        .basic_auth("admin", Some("secret"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(tagged.as_array().unwrap().len(), 1);
+
+    // Cooling down, the gateway's own number and sender ids get no reply
+    crate::autoreply::handle(
+        &sender,
+        &[
+            sms("+15550006001", "STATUS"),
+            sms("+15550006999", "status"),
+            sms("ACME", "status"),
+        ],
+        None,
+    )
+    .await;
+    assert_eq!(sender.sent_to("+15550006001").len(), 1);
+    assert!(sender.sent_to("+15550006999").is_empty());
+    assert!(sender.sent_to("ACME").is_empty());
+
+    let matches: Value = client
+        .post(format!("{}/auto-replies/test", base))
+        .basic_auth("admin", Some("secret"))
+        .json(&json!({ "message": { "contact": "+15550006002", "message": "order 42", "sim_id": "bot-sim" } }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(matches.as_array().unwrap().len(), 1);
+    assert_eq!(matches[0]["name"], "orders");
+    assert_eq!(matches[0]["actions"][0]["text"], "Order 42 received");
+    assert_eq!(matches[0]["actions"][1]["to"], "+15550007000");
+    assert_eq!(matches[0]["actions"][1]["text"], "+15550006002: order 42");
+    assert!(sender.sent_to("+15550006002").is_empty());
+
+    // No number gets more than the hourly cap of automatic messages
+    for order in 1..=crate::autoreply::MAX_MESSAGES_PER_HOUR + 2 {
+        let message = format!("Order {}", order);
+        crate::autoreply::handle(&sender, &[sms("+15550006003", &message)], None).await;
+    }
+    let replies = sender.sent_to("+15550006003");
+    assert_eq!(replies.len() as i64, crate::autoreply::MAX_MESSAGES_PER_HOUR);
+    assert_eq!(replies[0], "Order 1 received");
+}
+
+#[tokio::test]
+async fn test_opt_out_suppression_list() {
+    let base = start_api().await;
+    let client = reqwest::Client::new();
+    let add = |number: &str| {
+        client
+            .post(format!("{}/suppressions", base))
+            .basic_auth("admin", Some("secret"))
+            .json(&json!({ "number": number, "reason": "Asked by phone" }))
+            .send()
+    };
+    assert_eq!(add("+1 555 000 8001").await.unwrap().status(), 201);
+    assert_eq!(add("+15550008001").await.unwrap().status(), 200);
+    assert_eq!(add("ACME").await.unwrap().status(), 400);
+    assert_eq!(add("555 000 8001").await.unwrap().status(), 400);
+
+    // Sends to a national number are checked in E.164 with the SIM's country
+    let us = crate::phone::Country::from_code("US").ok();
+    let refused = crate::optout::ensure_allowed("(555) 000-8001", us).await.unwrap_err();
+    assert!(refused.is::<crate::optout::OptedOut>());
+    assert!(crate::optout::ensure_allowed("(555) 000-8099", us).await.is_ok());
+
+    let sms = |contact: &str, message: &str| db::ModemSMS {
+        contact: contact.to_string(),
+        message: message.to_string(),
+        sim_id: "optout-sim".to_string(),
+        ..Default::default()
+    };
+    let confirmations = crate::optout::handle(&[
+        sms("+15550008002", "Stop"),
+        sms("+15550008003", "Please don't stop"),
+    ])
+    .await;
+    assert_eq!(confirmations.len(), 1);
+    assert_eq!(confirmations[0].number, "+15550008002");
+    assert!(crate::optout::handle(&[sms("+15550008002", "STOP!")]).await.is_empty());
+
+    let list: Value = client
+        .get(format!("{}/suppressions", base))
+        .basic_auth("admin", Some("secret"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let entry = |number: &str| list.as_array().unwrap().iter().find(|s| s["number"] == number).cloned();
+    assert_eq!(entry("+15550008001").unwrap()["source"], "admin");
+    assert_eq!(entry("+15550008002").unwrap()["source"], "keyword");
+    assert!(entry("+15550008003").is_none());
+
+    // Campaigns and auto-replies leave opted-out numbers alone
+    let response = client
+        .post(format!("{}/auto-replies", base))
+        .basic_auth("admin", Some("secret"))
+        .json(&json!({
+            "config": {
+                "keywords": ["hours"],
+                "filter": { "sim": { "iccid": ["optout-sim"] } },
+                "actions": [{ "type": "reply", "template": "We open at 9" }]
+            }
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 201);
+    let sender = RecordingSender::default();
+    crate::autoreply::handle(
+        &sender,
+        &[sms("+15550008002", "hours"), sms("+15550008004", "hours")],
+        None,
+    )
+    .await;
+    assert!(sender.sent_to("+15550008002").is_empty());
+    assert_eq!(sender.sent_to("+15550008004"), ["We open at 9"]);
+
+    let created: Value = client
+        .post(format!("{}/campaigns", base))
+        .basic_auth("admin", Some("secret"))
+        .json(&json!({
+            "name": "opt-out",
+            "template": "News",
+            "recipients": [{ "number": "+15550008001" }, { "number": "+15550008005" }],
+            "sim_ids": ["optout-sim"],
+        }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(created["progress"]["pending"], 1);
+    assert_eq!(created["progress"]["skipped"], 1);
+
+    let confirmations = crate::optout::handle(&[sms("+15550008002", "start")]).await;
+    assert_eq!(confirmations.len(), 1);
+    assert!(!crate::db::Suppression::contains("+15550008002").await.unwrap());
+
+    // Blocked contacts can still opt out, but get no auto-replies
+    let mut first = vec![sms("+15550008006", "hello")];
+    let contact_ids = db::ModemSMS::bulk_insert(&mut first).await.unwrap();
+    let status = client
+        .put(format!("{}/contacts/{}", base, contact_ids[0]))
+        .basic_auth("admin", Some("secret"))
+        .json(&json!({ "blocked": true }))
+        .send()
+        .await
+        .unwrap()
+        .status();
+    assert_eq!(status, 200);
+    let mut incoming = vec![sms("+15550008006", "STOP"), sms("+15550008006", "hours")];
+    db::ModemSMS::bulk_insert(&mut incoming).await.unwrap();
+    let sender = RecordingSender::default();
+    crate::modem::core::handle_new_messages(&sender, incoming, true, None).await;
+    assert!(crate::db::Suppression::contains("+15550008006").await.unwrap());
+    assert_eq!(sender.sent_to("+15550008006"), [crate::config::OptOutConfig::default().opt_out_reply]);
+
+    let export = client
+        .get(format!("{}/suppressions/export", base))
+        .basic_auth("admin", Some("secret"))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(export.starts_with("number,source,sim_id,reason,created_at\n"));
+    assert!(export.contains("+15550008001,admin,,Asked by phone,"));
+
+    let remove = || {
+        client
+            .delete(format!("{}/suppressions/+15550008001", base))
+            .basic_auth("admin", Some("secret"))
+            .send()
+    };
+    assert_eq!(remove().await.unwrap().status(), 204);
+    assert_eq!(remove().await.unwrap().status(), 404);
+}
@@ -0,0 +1,77 @@
+use crate::db::{ModemSMS, SmsTimestamp};
+
+use chrono::{FixedOffset, Local, NaiveDateTime, TimeZone};
+
+fn parse(s: &str) -> NaiveDateTime {
+    NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").unwrap()
+}
+
+#[test]
+fn test_sms_timestamp_keeps_offset() {
+    let sms = ModemSMS {
+        timestamp: parse("2025-03-30 03:30:00"),
+        utc_offset: FixedOffset::east_opt(2 * 3600),
+        ..Default::default()
+    };
+
+    let stored = sms.stored_timestamp();
+    assert_eq!(stored.timestamp, parse("2025-03-30 01:30:00"));
+    assert_eq!(stored.utc_offset, Some(7200));
+    assert_eq!(
+        serde_json::to_value(stored).unwrap(),
+        "2025-03-30T03:30:00+02:00"
+    );
+
+    // Without an offset the SMSC time is taken as the gateway's local time
+    let local = ModemSMS {
+        timestamp: parse("2025-01-15 12:00:00"),
+        ..Default::default()
+    }
+    .stored_timestamp();
+    let expected = Local
+        .from_local_datetime(&parse("2025-01-15 12:00:00"))
+        .unwrap();
+    assert_eq!(local.timestamp, expected.naive_utc());
+    assert_eq!(local.utc_offset, Some(expected.offset().local_minus_utc()));
+    assert_eq!(serde_json::to_value(local).unwrap(), expected.to_rfc3339());
+
+    let parsed: SmsTimestamp = serde_json::from_value("2025-03-30T03:30:00+02:00".into()).unwrap();
+    assert_eq!(parsed, stored);
+    let parsed: SmsTimestamp = serde_json::from_value("2025-01-15T12:00:00".into()).unwrap();
+    assert_eq!(parsed, local);
+}
+
+#[test]
+fn test_numbers_are_normalised_to_e164() {
+    use crate::decode::parse_pdu_sms;
+    use crate::modem::pdu::build_pdu;
+    use crate::phone::{to_e164, Country};
+
+    let cn = Country::from_code("cn").ok();
+    assert_eq!(to_e164("138 0013 8000", cn), "+8613800138000");
+    assert_eq!(to_e164("013800138000", cn), "+8613800138000");
+    assert_eq!(to_e164("8613800138000", cn), "+8613800138000");
+    assert_eq!(to_e164("0044 7911 123456", cn), "+447911123456");
+    assert_eq!(to_e164("10086", cn), "10086"); // Short codes stay as they are
+    assert_eq!(to_e164("Google", cn), "Google");
+    assert_eq!(to_e164("13800138000", None), "13800138000");
+    assert_eq!(to_e164("07911 123456", Country::from_code("GB").ok()), "+447911123456");
+    assert_eq!(to_e164("(555) 123-4567", Country::from_code("US").ok()), "+15551234567");
+    assert!(Country::from_code("XX").is_err());
+
+    // National numbers are sent as international ones, short codes with an unknown type
+    let (pdu, _) = build_pdu("13800138000", "hi", cn).unwrap();
+    assert!(pdu.starts_with("0011000D91683108108300F0"), "{}", pdu);
+    let (pdu, _) = build_pdu("10086", "hi", cn).unwrap();
+    assert!(pdu.starts_with("00110005810180F6"), "{}", pdu);
+    assert!(build_pdu("Google", "hi", cn).is_err());
+
+    // A sender with a national type of number
+    let pdu = "0004 0BA1 310810830 0F0 0000 62017121000023 02 E834".replace(' ', "");
+    let listing = format!("+CMGL: 1,0,,20\r\n{}\r\n", pdu);
+    let messages = parse_pdu_sms(&listing, "sim", cn);
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].contact, "+8613800138000");
+    assert_eq!(messages[0].message, "hi");
+    assert_eq!(parse_pdu_sms(&listing, "sim", None)[0].contact, "13800138000");
+}
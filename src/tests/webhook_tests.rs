@@ -16,6 +16,7 @@ fn create_test_sms() -> ModemSMS {
             .unwrap(),
         send: false,
         sim_id: "test_sim_id".to_string(),
+        ..Default::default()
     }
 }
 
@@ -172,6 +173,7 @@ async fn test_contact_filter() {
             .unwrap(),
         send: false,
         sim_id: "test_sim_id".to_string(),
+        ..Default::default()
     };
     let non_matching_sms = ModemSMS {
         contact: "13700137000".to_string(),
@@ -180,6 +182,7 @@ async fn test_contact_filter() {
             .unwrap(),
         send: false,
         sim_id: "test_sim_id".to_string(),
+        ..Default::default()
     };
 
     Mock::given(method("POST"))
@@ -212,6 +215,7 @@ async fn test_device_filter() {
             .unwrap(),
         send: false,
         sim_id: "test_sim_id".to_string(),
+        ..Default::default()
     };
 
     let non_matching_sms = ModemSMS {
@@ -221,6 +225,7 @@ async fn test_device_filter() {
             .unwrap(),
         send: false,
         sim_id: "different_device".to_string(),
+        ..Default::default()
     };
 
     Mock::given(method("POST"))
@@ -252,6 +257,7 @@ async fn test_message_filter() {
             .unwrap(),
         send: false,
         sim_id: "test_sim_id".to_string(),
+        ..Default::default()
     };
 
     let contains_ignore_sms = ModemSMS {
@@ -261,6 +267,7 @@ async fn test_message_filter() {
             .unwrap(),
         send: false,
         sim_id: "test_sim_id".to_string(),
+        ..Default::default()
     };
 
     let regex_mismatch_sms = ModemSMS {
@@ -270,6 +277,7 @@ async fn test_message_filter() {
             .unwrap(),
         send: false,
         sim_id: "test_sim_id".to_string(),
+        ..Default::default()
     };
 
     Mock::given(method("POST"))
@@ -302,6 +310,7 @@ async fn test_time_filter() {
             .unwrap(), 
         send: false,
         sim_id: "test_sim_id".to_string(),
+        ..Default::default()
     };
 
     let off_hours_sms = ModemSMS {
@@ -311,6 +320,7 @@ async fn test_time_filter() {
             .unwrap(),
         send: false,
         sim_id: "test_sim_id".to_string(),
+        ..Default::default()
     };
 
     let weekend_sms = ModemSMS {
@@ -320,6 +330,7 @@ async fn test_time_filter() {
             .unwrap(), 
         send: false,
         sim_id: "test_sim_id".to_string(),
+        ..Default::default()
     };
 
     Mock::given(method("POST"))
@@ -370,6 +381,7 @@ Content-Type = "application/json"
             .unwrap(),
         send: true,
         sim_id: "test_sim_id".to_string(),
+        ..Default::default()
     };
 
     let received_sms = ModemSMS {
@@ -379,6 +391,7 @@ Content-Type = "application/json"
             .unwrap(),
         send: false,
         sim_id: "test_sim_id".to_string(),
+        ..Default::default()
     };
 
     Mock::given(method("POST"))
@@ -428,6 +441,7 @@ Content-Type = "application/json"
             .unwrap(),
         send: true,
         sim_id: "test_sim_id".to_string(),
+        ..Default::default()
     };
 
     let received_sms = ModemSMS {
@@ -437,6 +451,7 @@ Content-Type = "application/json"
             .unwrap(),
         send: false,
         sim_id: "test_sim_id".to_string(),
+        ..Default::default()
     };
 
     Mock::given(method("POST"))
@@ -491,6 +506,7 @@ Content-Type = "application/json"
             .unwrap(),
         send: true,
         sim_id: "test_sim_id".to_string(),
+        ..Default::default()
     };
 
     let received_sms = ModemSMS {
@@ -500,6 +516,7 @@ Content-Type = "application/json"
             .unwrap(),
         send: false,
         sim_id: "test_sim_id".to_string(),
+        ..Default::default()
     };
 
     Mock::given(method("POST"))
@@ -535,6 +552,7 @@ async fn test_url_encoding_fix() {
             .unwrap(),
         send: false,
         sim_id: "test_sim_id".to_string(),
+        ..Default::default()
     };
     let expected_body = json!({
         "contact": "13800138000",
@@ -582,3 +600,573 @@ message = "@message@"
     mock_server.verify().await;
 }
 
+
+#[tokio::test]
+async fn test_template_filters_and_conditionals() {
+    let mock_server = MockServer::start().await;
+
+    let mut test_sms = create_test_sms();
+    test_sms.message = "He said \"hi\" & <left>".to_string();
+    test_sms.segments = 2;
+
+    let expected_body = json!({
+        "text": "He said \"hi\" & <left>",
+        "short": "HE SAID",
+        "xml": "He said &quot;hi&quot; &amp; &lt;left&gt;",
+        "b64": "MTM4MDAxMzgwMDA=",
+        "date": "2025/05/23",
+        "alias": "none",
+        "direction": "in",
+        "parts": "2"
+    });
+
+    Mock::given(method("POST"))
+        .and(path("/webhook"))
+        .and(body_json(&expected_body))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let toml = format!(
+        r#"
+url = "{url}/webhook"
+method = "POST"
+timeout = 5
+body = '''{{"text":"@message|json@","short":"@message|truncate:7|upper@","xml":"@message|xml|json@","b64":"@contact|base64@","date":"@timestamp|format:%Y/%m/%d@","alias":"@sim_alias|default:none@","direction":"@if:send@out@else@in@endif@","parts":"@segments@"}}'''
+"#,
+        url = mock_server.uri()
+    );
+    let config: WebhookConfig = toml::from_str(&toml).expect("Failed to parse WebhookConfig");
+    let webhook_manager = start_webhook_worker_with_concurrency(vec![config], 5);
+
+    webhook_manager.send(test_sms).unwrap();
+
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    mock_server.verify().await;
+}
+
+#[test]
+fn test_invalid_template_rejected() {
+    let invalid = [
+        r#"body = "@message|shout@""#,
+        r#"body = "@message|format:%Y@""#,
+        r#"body = "@if:send@yes""#,
+        r#"body = "@else@""#,
+    ];
+
+    for body in invalid {
+        let toml = format!("url = \"http://localhost/webhook\"\nmethod = \"POST\"\n{}\n", body);
+        assert!(
+            toml::from_str::<WebhookConfig>(&toml).is_err(),
+            "template should be rejected: {}",
+            body
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_webhook_script_transform() {
+    let mock_server = MockServer::start().await;
+
+    let mut test_sms = create_test_sms();
+    test_sms.message = "Your code is 4711".to_string();
+    let mut skipped_sms = create_test_sms();
+    skipped_sms.message = "ignore me".to_string();
+
+    Mock::given(method("PUT"))
+        .and(path("/scripted"))
+        .and(header("X-Source", "SIM-m_id"))
+        .and(body_json(json!({
+            "blocks": [{"type": "code", "text": "4711"}],
+            "length": 17
+        })))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let toml = format!(
+        r#"
+url = "{url}/webhook"
+method = "POST"
+timeout = 5
+script = '''
+if msg.message.contains("ignore") {{ return (); }}
+let code = msg.message.sub_string(13);
+request.method = "PUT";
+request.url.replace("/webhook", "/scripted");
+request.headers = #{{ "X-Source": msg.sim }};
+request.body = #{{ blocks: [#{{ type: "code", text: code }}], length: msg.message.len() }};
+request
+'''
+"#,
+        url = mock_server.uri()
+    );
+    let config: WebhookConfig = toml::from_str(&toml).expect("Failed to parse WebhookConfig");
+    let webhook_manager = start_webhook_worker_with_concurrency(vec![config], 5);
+
+    webhook_manager.send(test_sms).unwrap();
+    webhook_manager.send(skipped_sms).unwrap();
+
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    mock_server.verify().await;
+}
+
+#[test]
+fn test_invalid_webhook_script_rejected() {
+    let toml = r#"
+url = "http://localhost/webhook"
+method = "POST"
+script = "let x = ;"
+"#;
+    assert!(toml::from_str::<WebhookConfig>(toml).is_err());
+}
+
+#[tokio::test]
+async fn test_builtin_channels() {
+    use crate::config::ChannelConfig;
+
+    let mock_server = MockServer::start().await;
+    let uri = mock_server.uri();
+    let text = "SMS from 13800138000 to SIM-m_id at 2025-05-23 15:00:00\n\nTest message content";
+
+    Mock::given(method("POST"))
+        .and(path("/bot123:abc/sendMessage"))
+        .and(body_json(json!({"chat_id": "-10042", "text": text, "disable_web_page_preview": true})))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/slack"))
+        .and(body_json(json!({"text": "13800138000: Test message content"})))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/discord"))
+        .and(body_json(json!({"content": text, "username": "sms-gateway"})))
+        .respond_with(ResponseTemplate::new(204))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    Mock::given(method("PUT"))
+        .and(wiremock::matchers::path_regex(r"^/_matrix/client/v3/rooms/%21room%3Aexample.org/send/m.room.message/sms-.+$"))
+        .and(header("Authorization", "Bearer matrix-token"))
+        .and(body_json(json!({"msgtype": "m.text", "body": text})))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/ntfy"))
+        .and(body_json(json!({"topic": "sms", "title": "SMS from 13800138000", "message": text, "priority": 4})))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/gotify/message"))
+        .and(header("X-Gotify-Key", "gotify-token"))
+        .and(body_json(json!({"title": "SMS from 13800138000", "message": text, "priority": 5})))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let channels = [
+        format!("type = \"telegram\"\nbot_token = \"123:abc\"\nchat_id = -10042\napi_url = \"{uri}\""),
+        format!("type = \"slack\"\nwebhook_url = \"{uri}/slack\"\ntemplate = \"@contact@: @message@\""),
+        format!("type = \"discord\"\nwebhook_url = \"{uri}/discord\"\nusername = \"sms-gateway\""),
+        format!("type = \"matrix\"\nhomeserver = \"{uri}\"\naccess_token = \"matrix-token\"\nroom_id = \"!room:example.org\""),
+        format!("type = \"ntfy\"\nserver = \"{uri}/ntfy\"\ntopic = \"sms\"\npriority = 4"),
+        format!("type = \"gotify\"\nserver = \"{uri}/gotify\"\ntoken = \"gotify-token\"\ncontact_filter = [\"13800138000\"]"),
+    ];
+    let configs: Vec<WebhookConfig> = channels
+        .iter()
+        .map(|toml| {
+            let channel: ChannelConfig = toml::from_str(toml).expect("Failed to parse ChannelConfig");
+            WebhookConfig::try_from(&channel).expect("Invalid channel")
+        })
+        .collect();
+
+    let webhook_manager = start_webhook_worker_with_concurrency(configs, 5);
+
+    webhook_manager.send(create_test_sms()).unwrap();
+    let mut self_sent = create_test_sms();
+    self_sent.send = true;
+    webhook_manager.send(self_sent).unwrap();
+
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    mock_server.verify().await;
+}
+
+#[test]
+fn test_invalid_channel_rejected() {
+    use crate::config::ChannelConfig;
+
+    let invalid = [
+        "type = \"slack\"\nwebhook_url = \"ftp://example.org\"",
+        "type = \"telegram\"\nbot_token = \"\"\nchat_id = \"1\"",
+        "type = \"email\"\nsmtp_host = \"localhost\"\nfrom = \"not an address\"\nto = [\"ops@example.org\"]",
+        "type = \"email\"\nsmtp_host = \"localhost\"\nfrom = \"gateway@example.org\"\nto = []",
+        "type = \"ntfy\"\ntopic = \"sms\"\ntemplate = \"@nope@\"",
+    ];
+
+    for toml in invalid {
+        let channel: ChannelConfig = toml::from_str(toml).expect("Failed to parse ChannelConfig");
+        assert!(WebhookConfig::try_from(&channel).is_err(), "channel should be rejected: {}", toml);
+    }
+}
+
+#[tokio::test]
+async fn test_slow_endpoint_does_not_block_others() {
+    let mock_server = MockServer::start().await;
+
+    Mock::given(method("POST"))
+        .and(path("/slow"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(2)))
+        .mount(&mock_server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/fast"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(3)
+        .mount(&mock_server)
+        .await;
+
+    let slow: WebhookConfig = toml::from_str(&format!(
+        "url = \"{}/slow\"\nmethod = \"POST\"\ntimeout = 5\nordered = true\n",
+        mock_server.uri()
+    ))
+    .expect("Failed to parse WebhookConfig");
+    let fast = create_simple_webhook_config(&format!("{}/fast", mock_server.uri()));
+    let webhook_manager = start_webhook_worker_with_concurrency(vec![slow, fast], 1);
+
+    for _ in 0..3 {
+        webhook_manager.send(create_test_sms()).unwrap();
+    }
+
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    mock_server.verify().await;
+
+    let statuses = webhook_manager.endpoint_statuses();
+    assert_eq!(statuses[0].in_flight, 1);
+    assert_eq!(statuses[0].queued, 2);
+    assert_eq!(statuses[1].delivered, 3);
+}
+
+#[tokio::test]
+async fn test_circuit_breaker_pauses_and_recovers() {
+    use crate::webhook::delivery::BreakerState;
+
+    let mock_server = MockServer::start().await;
+
+    Mock::given(method("POST"))
+        .and(path("/flaky"))
+        .respond_with(ResponseTemplate::new(503))
+        .mount(&mock_server)
+        .await;
+
+    let toml = format!(
+        r#"
+url = "{url}/flaky"
+method = "POST"
+timeout = 5
+
+[circuit_breaker]
+failure_threshold = 2
+open_seconds = 1
+"#,
+        url = mock_server.uri()
+    );
+    let config: WebhookConfig = toml::from_str(&toml).expect("Failed to parse WebhookConfig");
+    let webhook_manager = start_webhook_worker_with_concurrency(vec![config], 1);
+
+    for _ in 0..4 {
+        webhook_manager.send(create_test_sms()).unwrap();
+    }
+
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let status = &webhook_manager.endpoint_statuses()[0];
+    assert_eq!(status.state, BreakerState::Open);
+    assert_eq!(status.failed, 2);
+    assert_eq!(status.queued, 2);
+
+    mock_server.reset().await;
+    Mock::given(method("POST"))
+        .and(path("/flaky"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&mock_server)
+        .await;
+
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    let status = &webhook_manager.endpoint_statuses()[0];
+    assert_eq!(status.state, BreakerState::Closed);
+    assert_eq!(status.delivered, 2);
+    assert_eq!(status.queued, 0);
+    mock_server.verify().await;
+}
+
+#[test]
+fn test_invalid_delivery_options_rejected() {
+    let invalid = [
+        "ordered = true\nmax_concurrent = 2",
+        "max_concurrent = 0",
+        "rate_limit = 0",
+        "[circuit_breaker]\nfailure_threshold = 0",
+    ];
+
+    for options in invalid {
+        let toml = format!("url = \"http://localhost/webhook\"\nmethod = \"POST\"\n{}\n", options);
+        assert!(
+            toml::from_str::<WebhookConfig>(&toml).is_err(),
+            "delivery options should be rejected: {}",
+            options
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_stored_webhooks_applied_live() {
+    let mock_server = MockServer::start().await;
+
+    Mock::given(method("POST"))
+        .and(path("/first"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/second"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let webhook_manager = start_webhook_worker_with_concurrency(vec![], 5);
+    let config = |p: &str| {
+        WebhookConfig::from_json(&json!({
+            "url": format!("{}/{}", mock_server.uri(), p),
+            "method": "POST",
+            "body": "@message@"
+        }))
+        .expect("Failed to parse WebhookConfig")
+    };
+
+    webhook_manager.upsert_stored(7, config("first"));
+    webhook_manager.send(create_test_sms()).unwrap();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    webhook_manager.upsert_stored(7, config("second"));
+    assert_eq!(webhook_manager.config_count(), 1);
+    webhook_manager.send(create_test_sms()).unwrap();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    webhook_manager.remove_stored(7);
+    assert_eq!(webhook_manager.config_count(), 0);
+    webhook_manager.send(create_test_sms()).unwrap();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    mock_server.verify().await;
+}
+
+#[tokio::test]
+async fn test_webhook_test_with_sample_message() {
+    let mock_server = MockServer::start().await;
+
+    Mock::given(method("POST"))
+        .and(path("/preview"))
+        .and(body_json(json!({"from": "13800138000"})))
+        .respond_with(ResponseTemplate::new(202).set_body_string("queued"))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let config = WebhookConfig::from_json(&json!({
+        "url": format!("{}/preview", mock_server.uri()),
+        "method": "POST",
+        "body": "{\"from\":\"@contact@\"}",
+        "contact_filter": ["+100"]
+    }))
+    .expect("Failed to parse WebhookConfig");
+    let webhook_manager = start_webhook_worker_with_concurrency(vec![], 5);
+
+    let preview = webhook_manager.test_webhook(&config, create_test_sms(), false).await;
+    assert!(preview.filtered);
+    assert_eq!(preview.request.unwrap().body.as_deref(), Some("{\"from\":\"13800138000\"}"));
+    assert_eq!(preview.status, None);
+
+    let sent = webhook_manager.test_webhook(&config, create_test_sms(), true).await;
+    assert_eq!(sent.status, Some(202));
+    assert_eq!(sent.response.as_deref(), Some("queued"));
+
+    assert!(WebhookConfig::from_json(&json!({"url": "ftp://example.org", "method": "POST"})).is_err());
+    mock_server.verify().await;
+}
+
+#[tokio::test]
+async fn test_filter_expressions() {
+    let toml = r#"
+url = "http://localhost/webhook"
+method = "POST"
+include_self_sent = true
+
+[filter]
+all = [
+    { any = [{ contact = { prefix = ["+44", "1380"] } }, { sim = { iccid = ["other_sim"] } }] },
+    { contact = { exclude = ["13800138999"] } },
+    { not = { message = { regex = "(?i)unsubscribe" } } },
+    { length = { min = 5, max = 40 } },
+    { direction = "incoming" },
+]
+"#;
+    let config: WebhookConfig = toml::from_str(toml).expect("Failed to parse WebhookConfig");
+    let webhook_manager = start_webhook_worker_with_concurrency(vec![config], 1);
+
+    assert!(webhook_manager.test_passes_filters(&create_test_sms()).await);
+
+    let mut excluded = create_test_sms();
+    excluded.contact = "13800138999".to_string();
+    let mut other_sender = create_test_sms();
+    other_sender.contact = "+15550100".to_string();
+    let mut other_sender_on_sim = other_sender.clone();
+    other_sender_on_sim.sim_id = "other_sim".to_string();
+    let mut unsubscribe = create_test_sms();
+    unsubscribe.message = "UNSUBSCRIBE please".to_string();
+    let mut too_long = create_test_sms();
+    too_long.message = "x".repeat(41);
+    let mut outgoing = create_test_sms();
+    outgoing.send = true;
+
+    assert!(!webhook_manager.test_passes_filters(&excluded).await);
+    assert!(!webhook_manager.test_passes_filters(&other_sender).await);
+    assert!(webhook_manager.test_passes_filters(&other_sender_on_sim).await);
+    assert!(!webhook_manager.test_passes_filters(&unsubscribe).await);
+    assert!(!webhook_manager.test_passes_filters(&too_long).await);
+    assert!(!webhook_manager.test_passes_filters(&outgoing).await);
+}
+
+#[test]
+fn test_invalid_filter_expressions_rejected() {
+    let invalid = [
+        "filter = { all = [] }",
+        "filter = { both = [] }",
+        "filter = { contact = {} }",
+        "filter = { contact = { regex = \"(\" } }",
+        "filter = { sim = { number = [\"1\"] } }",
+        "filter = { length = { min = 10, max = 2 } }",
+        "filter = { direction = \"sideways\" }",
+        "filter = { not = { any = [{ length = {} }] } }",
+    ];
+
+    for filter in invalid {
+        let toml = format!("url = \"http://localhost/webhook\"\nmethod = \"POST\"\n{}\n", filter);
+        assert!(
+            toml::from_str::<WebhookConfig>(&toml).is_err(),
+            "filter should be rejected: {}",
+            filter
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_time_windows() {
+    let sms_at = |timestamp: &str| ModemSMS {
+        timestamp: NaiveDateTime::parse_from_str(timestamp, "%Y-%m-%d %H:%M:%S").unwrap(),
+        ..create_test_sms()
+    };
+
+    // Friday and Saturday night on-call, not on Christmas or New Year's Eve 2025
+    let toml = r#"
+url = "http://localhost/webhook"
+method = "POST"
+
+[time_filter]
+exclude_dates = ["12-25", "2025-12-31"]
+windows = [
+    { start = "22:00", end = "06:00", days = ["fri", "sat"] },
+    { start = "12:00", end = "13:00" },
+]
+"#;
+    let config: WebhookConfig = toml::from_str(toml).expect("Failed to parse WebhookConfig");
+    let webhook_manager = start_webhook_worker_with_concurrency(vec![config], 1);
+
+    assert!(webhook_manager.test_passes_filters(&sms_at("2025-05-23 23:30:00")).await); // Fri night
+    assert!(webhook_manager.test_passes_filters(&sms_at("2025-05-24 05:59:00")).await); // Sat morning
+    assert!(webhook_manager.test_passes_filters(&sms_at("2025-05-25 02:00:00")).await); // Sun morning
+    assert!(!webhook_manager.test_passes_filters(&sms_at("2025-05-26 02:00:00")).await); // Mon morning
+    assert!(!webhook_manager.test_passes_filters(&sms_at("2025-05-23 21:00:00")).await);
+    assert!(webhook_manager.test_passes_filters(&sms_at("2025-05-21 12:30:00")).await); // Wed lunch
+    assert!(!webhook_manager.test_passes_filters(&sms_at("2025-12-25 12:30:00")).await);
+    assert!(!webhook_manager.test_passes_filters(&sms_at("2025-12-31 12:30:00")).await);
+    assert!(webhook_manager.test_passes_filters(&sms_at("2026-12-31 12:30:00")).await);
+
+    // 09:00-17:00 in New York, SMSC timestamps in UTC+8
+    let toml = r#"
+url = "http://localhost/webhook"
+method = "POST"
+
+[time_filter]
+timezone = "America/New_York"
+start_time = "09:00"
+end_time = "17:00"
+"#;
+    let config: WebhookConfig = toml::from_str(toml).expect("Failed to parse WebhookConfig");
+    let webhook_manager = start_webhook_worker_with_concurrency(vec![config], 1);
+
+    let mut in_hours = sms_at("2025-05-23 22:00:00"); // 10:00 EDT
+    in_hours.utc_offset = chrono::FixedOffset::east_opt(8 * 3600);
+    let mut off_hours = sms_at("2025-05-23 15:00:00"); // 03:00 EDT
+    off_hours.utc_offset = chrono::FixedOffset::east_opt(8 * 3600);
+    assert!(webhook_manager.test_passes_filters(&in_hours).await);
+    assert!(!webhook_manager.test_passes_filters(&off_hours).await);
+
+    // Received time is used instead of the PDU timestamp
+    let toml = r#"
+url = "http://localhost/webhook"
+method = "POST"
+
+[time_filter]
+timezone = "UTC"
+basis = "received"
+windows = [{ start = "09:00", end = "17:00" }]
+"#;
+    let config: WebhookConfig = toml::from_str(toml).expect("Failed to parse WebhookConfig");
+    let webhook_manager = start_webhook_worker_with_concurrency(vec![config], 1);
+
+    let mut received = sms_at("2025-05-23 03:00:00");
+    received.received_at = Some(
+        chrono::DateTime::parse_from_rfc3339("2025-05-23T10:00:00Z").unwrap().to_utc(),
+    );
+    assert!(webhook_manager.test_passes_filters(&received).await);
+}
+
+#[test]
+fn test_invalid_time_filter_rejected() {
+    let invalid = [
+        "timezone = \"Mars/Olympus\"",
+        "exclude_dates = [\"2025-13-01\"]",
+        "windows = [{ start = \"22:00\" }]",
+        "windows = [{ start = \"22:00\", end = \"06:00\" }]\nstart_time = \"09:00\"",
+        "basis = \"sent\"",
+    ];
+
+    for time_filter in invalid {
+        let toml = format!(
+            "url = \"http://localhost/webhook\"\nmethod = \"POST\"\n[time_filter]\n{}\n",
+            time_filter
+        );
+        assert!(
+            toml::from_str::<WebhookConfig>(&toml).is_err(),
+            "time filter should be rejected: {}",
+            time_filter
+        );
+    }
+
+    let overnight = "url = \"http://localhost/webhook\"\nmethod = \"POST\"\n[time_filter]\nstart_time = \"22:00\"\nend_time = \"06:00\"\n";
+    assert!(toml::from_str::<WebhookConfig>(overnight).is_ok());
+}
@@ -0,0 +1,317 @@
+use std::{collections::HashMap, fmt, sync::Arc, time::Duration};
+
+use lettre::{
+    message::Mailbox, transport::smtp::authentication::Credentials, AsyncSmtpTransport,
+    AsyncTransport, Message, Tokio1Executor,
+};
+use serde_json::json;
+use urlencoding::encode;
+
+use super::{apply_template_segments, RenderedRequest, TemplateContext};
+use crate::config::{
+    parse_template_segments, validate_url, ChannelConfig, ChannelKind, EmailChannelConfig, Method,
+    SmtpTls, TemplateSegment, WebhookConfig,
+};
+
+const DEFAULT_TEMPLATE: &str = "SMS from @contact@ to @sim@ at @timestamp@\n\n@message@";
+const DEFAULT_TITLE: &str = "SMS from @contact@";
+const DEFAULT_TELEGRAM_API: &str = "https://api.telegram.org";
+const DEFAULT_NTFY_SERVER: &str = "https://ntfy.sh";
+
+/// A validated notification channel with its templates parsed
+pub struct Channel {
+    kind: ChannelKind,
+    text: Vec<TemplateSegment>,
+    title: Vec<TemplateSegment>,
+    mailer: Option<Mailer>,
+}
+
+struct Mailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    to: Vec<Mailbox>,
+}
+
+impl fmt::Debug for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Channel")
+            .field("kind", &self.kind_name())
+            .finish_non_exhaustive()
+    }
+}
+
+impl TryFrom<&ChannelConfig> for WebhookConfig {
+    type Error = String;
+
+    fn try_from(cfg: &ChannelConfig) -> Result<Self, Self::Error> {
+        if cfg.timeout == Some(0) {
+            return Err("Channel timeout cannot be zero".to_string());
+        }
+        cfg.delivery.validate()?;
+
+        let channel = Channel::new(cfg)?;
+
+        Ok(WebhookConfig {
+            url: vec![TemplateSegment::Fixed(channel.endpoint())],
+            method: Method::Post,
+            headers: None,
+            body: None,
+            url_params: None,
+            timeout: cfg.timeout,
+            script: None,
+            channel: Some(Arc::new(channel)),
+            filters: cfg.filters.clone(),
+            delivery: cfg.delivery.clone(),
+        })
+    }
+}
+
+impl Channel {
+    pub fn new(cfg: &ChannelConfig) -> Result<Self, String> {
+        let text = parse_template_segments(cfg.template.as_deref().unwrap_or(DEFAULT_TEMPLATE))
+            .map_err(|e| format!("Invalid channel template: {}", e))?;
+        let title = parse_template_segments(cfg.title.as_deref().unwrap_or(DEFAULT_TITLE))
+            .map_err(|e| format!("Invalid channel title: {}", e))?;
+
+        let mut mailer = None;
+        match &cfg.kind {
+            ChannelKind::Telegram { bot_token, chat_id, api_url } => {
+                if bot_token.trim().is_empty() || chat_id.trim().is_empty() {
+                    return Err("Telegram channel needs bot_token and chat_id".to_string());
+                }
+                if let Some(api_url) = api_url {
+                    validate_url(api_url)?;
+                }
+            }
+            ChannelKind::Slack { webhook_url } | ChannelKind::Discord { webhook_url, .. } => {
+                validate_url(webhook_url)?;
+            }
+            ChannelKind::Matrix { homeserver, access_token, room_id } => {
+                validate_url(homeserver)?;
+                if access_token.trim().is_empty() || room_id.trim().is_empty() {
+                    return Err("Matrix channel needs access_token and room_id".to_string());
+                }
+            }
+            ChannelKind::Ntfy { server, topic, .. } => {
+                if let Some(server) = server {
+                    validate_url(server)?;
+                }
+                if topic.trim().is_empty() {
+                    return Err("ntfy channel needs a topic".to_string());
+                }
+            }
+            ChannelKind::Gotify { server, token, .. } => {
+                validate_url(server)?;
+                if token.trim().is_empty() {
+                    return Err("Gotify channel needs an application token".to_string());
+                }
+            }
+            ChannelKind::Email(email) => {
+                mailer = Some(Mailer::new(email, cfg.timeout)?);
+            }
+        }
+
+        Ok(Self {
+            kind: cfg.kind.clone(),
+            text,
+            title,
+            mailer,
+        })
+    }
+
+    fn kind_name(&self) -> &'static str {
+        match self.kind {
+            ChannelKind::Telegram { .. } => "telegram",
+            ChannelKind::Slack { .. } => "slack",
+            ChannelKind::Discord { .. } => "discord",
+            ChannelKind::Matrix { .. } => "matrix",
+            ChannelKind::Ntfy { .. } => "ntfy",
+            ChannelKind::Gotify { .. } => "gotify",
+            ChannelKind::Email(_) => "email",
+        }
+    }
+
+    /// Where the channel delivers to, without credentials; used for logging
+    pub fn endpoint(&self) -> String {
+        match &self.kind {
+            ChannelKind::Telegram { api_url, chat_id, .. } => format!(
+                "{}/bot***/sendMessage?chat_id={}",
+                api_url.as_deref().unwrap_or(DEFAULT_TELEGRAM_API),
+                chat_id
+            ),
+            ChannelKind::Slack { .. } => "slack incoming webhook".to_string(),
+            ChannelKind::Discord { .. } => "discord webhook".to_string(),
+            ChannelKind::Matrix { homeserver, room_id, .. } => format!("{}#{}", homeserver, room_id),
+            ChannelKind::Ntfy { server, topic, .. } => format!(
+                "{}/{}",
+                server.as_deref().unwrap_or(DEFAULT_NTFY_SERVER),
+                topic
+            ),
+            ChannelKind::Gotify { server, .. } => format!("{}/message", server.trim_end_matches('/')),
+            ChannelKind::Email(email) => format!("smtp://{} -> {}", email.smtp_host, email.to.join(",")),
+        }
+    }
+
+    pub fn is_email(&self) -> bool {
+        self.mailer.is_some()
+    }
+
+    /// Builds the HTTP request for every channel except email
+    pub async fn render(&self, ctx: &TemplateContext) -> RenderedRequest {
+        let text = apply_template_segments(&self.text, ctx).await;
+        let title = apply_template_segments(&self.title, ctx).await;
+
+        let mut headers = HashMap::new();
+        let (method, url, body) = match &self.kind {
+            ChannelKind::Telegram { bot_token, chat_id, api_url } => (
+                reqwest::Method::POST,
+                format!(
+                    "{}/bot{}/sendMessage",
+                    api_url.as_deref().unwrap_or(DEFAULT_TELEGRAM_API).trim_end_matches('/'),
+                    bot_token
+                ),
+                json!({ "chat_id": chat_id, "text": text, "disable_web_page_preview": true }),
+            ),
+            ChannelKind::Slack { webhook_url } => (
+                reqwest::Method::POST,
+                webhook_url.clone(),
+                json!({ "text": text }),
+            ),
+            ChannelKind::Discord { webhook_url, username } => {
+                let mut body = json!({ "content": text });
+                if let Some(username) = username {
+                    body["username"] = json!(username);
+                }
+                (reqwest::Method::POST, webhook_url.clone(), body)
+            }
+            ChannelKind::Matrix { homeserver, access_token, room_id } => {
+                let msg = ctx.msg();
+                let txn_id = format!(
+                    "sms-{}-{}-{}",
+                    msg.sim_id,
+                    msg.id.unwrap_or_default(),
+                    msg.timestamp.and_utc().timestamp()
+                );
+                headers.insert("Authorization".to_string(), format!("Bearer {}", access_token));
+                (
+                    reqwest::Method::PUT,
+                    format!(
+                        "{}/_matrix/client/v3/rooms/{}/send/m.room.message/{}",
+                        homeserver.trim_end_matches('/'),
+                        encode(room_id),
+                        encode(&txn_id)
+                    ),
+                    json!({ "msgtype": "m.text", "body": text }),
+                )
+            }
+            ChannelKind::Ntfy { server, topic, token, priority } => {
+                if let Some(token) = token {
+                    headers.insert("Authorization".to_string(), format!("Bearer {}", token));
+                }
+                let mut body = json!({ "topic": topic, "title": title, "message": text });
+                if let Some(priority) = priority {
+                    body["priority"] = json!(priority);
+                }
+                (
+                    reqwest::Method::POST,
+                    server
+                        .as_deref()
+                        .unwrap_or(DEFAULT_NTFY_SERVER)
+                        .trim_end_matches('/')
+                        .to_string(),
+                    body,
+                )
+            }
+            ChannelKind::Gotify { server, token, priority } => {
+                headers.insert("X-Gotify-Key".to_string(), token.clone());
+                (
+                    reqwest::Method::POST,
+                    format!("{}/message", server.trim_end_matches('/')),
+                    json!({ "title": title, "message": text, "priority": priority.unwrap_or(5) }),
+                )
+            }
+            ChannelKind::Email(_) => (reqwest::Method::POST, self.endpoint(), json!({})),
+        };
+
+        RenderedRequest {
+            method,
+            url,
+            headers,
+            url_params: HashMap::new(),
+            body: Some(body.to_string()),
+        }
+    }
+
+    pub async fn send_email(&self, ctx: &TemplateContext) -> Result<(), String> {
+        let mailer = self
+            .mailer
+            .as_ref()
+            .ok_or_else(|| format!("{} channel does not send email", self.kind_name()))?;
+
+        let text = apply_template_segments(&self.text, ctx).await;
+        let title = apply_template_segments(&self.title, ctx).await;
+
+        let mut builder = Message::builder().from(mailer.from.clone()).subject(title);
+        for to in &mailer.to {
+            builder = builder.to(to.clone());
+        }
+        let message = builder
+            .body(text)
+            .map_err(|e| format!("Failed to build email: {}", e))?;
+
+        mailer
+            .transport
+            .send(message)
+            .await
+            .map(|_| ())
+            .map_err(|e| format!("Failed to send email via {}: {}", self.endpoint(), e))
+    }
+}
+
+impl Mailer {
+    fn new(cfg: &EmailChannelConfig, timeout: Option<u64>) -> Result<Self, String> {
+        let from = cfg
+            .from
+            .parse::<Mailbox>()
+            .map_err(|e| format!("Invalid email sender '{}': {}", cfg.from, e))?;
+        if cfg.to.is_empty() {
+            return Err("Email channel needs at least one recipient".to_string());
+        }
+        let to = cfg
+            .to
+            .iter()
+            .map(|addr| {
+                addr.parse::<Mailbox>()
+                    .map_err(|e| format!("Invalid email recipient '{}': {}", addr, e))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut builder = match cfg.tls.unwrap_or(SmtpTls::Starttls) {
+            SmtpTls::None => {
+                AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&cfg.smtp_host)
+            }
+            SmtpTls::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&cfg.smtp_host)
+                .map_err(|e| format!("Invalid SMTP host '{}': {}", cfg.smtp_host, e))?,
+            SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&cfg.smtp_host)
+                .map_err(|e| format!("Invalid SMTP host '{}': {}", cfg.smtp_host, e))?,
+        };
+
+        if let Some(port) = cfg.smtp_port {
+            builder = builder.port(port);
+        }
+        match (&cfg.username, &cfg.password) {
+            (Some(username), Some(password)) => {
+                builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+            }
+            (None, None) => {}
+            _ => return Err("Email channel needs both username and password, or neither".to_string()),
+        }
+        builder = builder.timeout(Some(Duration::from_secs(timeout.unwrap_or(10))));
+
+        Ok(Self {
+            transport: builder.build(),
+            from,
+            to,
+        })
+    }
+}
@@ -0,0 +1,172 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use serde::Serialize;
+use tokio::time::Instant;
+
+use crate::config::CircuitBreakerConfig;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryOutcome {
+    Delivered,
+    Skipped,
+    Failed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakerState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+/// Tracks consecutive failures of one endpoint. Without a config it never opens.
+pub struct CircuitBreaker {
+    config: Option<CircuitBreakerConfig>,
+    inner: Mutex<BreakerInner>,
+}
+
+struct BreakerInner {
+    state: BreakerState,
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: Option<CircuitBreakerConfig>) -> Self {
+        Self {
+            config,
+            inner: Mutex::new(BreakerInner {
+                state: BreakerState::Closed,
+                consecutive_failures: 0,
+                open_until: None,
+            }),
+        }
+    }
+
+    /// Waits while the breaker is open. Returns true when the next delivery is the trial request.
+    pub async fn ready(&self) -> bool {
+        loop {
+            let until = {
+                let mut inner = self.inner.lock().unwrap();
+                match (inner.state, inner.open_until) {
+                    (BreakerState::Closed, _) => return false,
+                    (BreakerState::HalfOpen, _) => return true,
+                    (BreakerState::Open, Some(until)) if until > Instant::now() => until,
+                    (BreakerState::Open, _) => {
+                        inner.state = BreakerState::HalfOpen;
+                        return true;
+                    }
+                }
+            };
+            tokio::time::sleep_until(until).await;
+        }
+    }
+
+    /// Returns the new state when the outcome changed it
+    pub fn record(&self, outcome: DeliveryOutcome) -> Option<BreakerState> {
+        let mut inner = self.inner.lock().unwrap();
+        let previous = inner.state;
+
+        match outcome {
+            DeliveryOutcome::Skipped => {}
+            DeliveryOutcome::Delivered => {
+                inner.consecutive_failures = 0;
+                inner.state = BreakerState::Closed;
+                inner.open_until = None;
+            }
+            DeliveryOutcome::Failed => {
+                inner.consecutive_failures = inner.consecutive_failures.saturating_add(1);
+                if let Some(config) = &self.config {
+                    if inner.state == BreakerState::HalfOpen
+                        || inner.consecutive_failures >= config.failure_threshold
+                    {
+                        inner.state = BreakerState::Open;
+                        inner.open_until =
+                            Some(Instant::now() + Duration::from_secs(config.open_seconds));
+                    }
+                }
+            }
+        }
+
+        (inner.state != previous).then_some(inner.state)
+    }
+
+    fn snapshot(&self) -> (BreakerState, u32, Option<u64>) {
+        let inner = self.inner.lock().unwrap();
+        let reopens_in = inner
+            .open_until
+            .filter(|_| inner.state == BreakerState::Open)
+            .map(|until| until.saturating_duration_since(Instant::now()).as_secs());
+        (inner.state, inner.consecutive_failures, reopens_in)
+    }
+}
+
+#[derive(Default)]
+pub struct EndpointStats {
+    pub queued: AtomicUsize,
+    pub in_flight: AtomicUsize,
+    pub delivered: AtomicU64,
+    pub failed: AtomicU64,
+}
+
+impl EndpointStats {
+    pub fn record(&self, outcome: DeliveryOutcome) {
+        match outcome {
+            DeliveryOutcome::Delivered => self.delivered.fetch_add(1, Ordering::Relaxed),
+            DeliveryOutcome::Failed => self.failed.fetch_add(1, Ordering::Relaxed),
+            DeliveryOutcome::Skipped => 0,
+        };
+    }
+}
+
+/// Where an endpoint was defined: its position in config.toml or its database id
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "source", content = "id", rename_all = "lowercase")]
+pub enum WebhookSource {
+    Config(usize),
+    Database(i64),
+}
+
+/// Delivery state of one webhook or channel, as reported by the API
+#[derive(Debug, Clone, Serialize)]
+pub struct EndpointStatus {
+    #[serde(flatten)]
+    pub source: WebhookSource,
+    pub target: String,
+    pub state: BreakerState,
+    pub consecutive_failures: u32,
+    pub retry_in_seconds: Option<u64>,
+    pub queued: usize,
+    pub in_flight: usize,
+    pub delivered: u64,
+    pub failed: u64,
+}
+
+impl EndpointStatus {
+    pub fn new(
+        source: WebhookSource,
+        target: String,
+        breaker: &CircuitBreaker,
+        stats: &EndpointStats,
+    ) -> Self {
+        let (state, consecutive_failures, retry_in_seconds) = breaker.snapshot();
+        Self {
+            source,
+            target,
+            state,
+            consecutive_failures,
+            retry_in_seconds,
+            queued: stats.queued.load(Ordering::Relaxed),
+            in_flight: stats.in_flight.load(Ordering::Relaxed),
+            delivered: stats.delivered.load(Ordering::Relaxed),
+            failed: stats.failed.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -0,0 +1,210 @@
+use std::{future::Future, pin::Pin};
+
+use chrono::{Datelike, Local, NaiveDateTime, TimeZone, Utc, Weekday};
+
+use super::TemplateContext;
+use crate::config::{
+    ContactMatch, DateExclusion, Direction, FilterExpr, FilterSet, LengthRange, MessageFilter,
+    SegmentName, SimMatch, TimeBasis, TimeFilter, TimeWindow,
+};
+use crate::db::ModemSMS;
+
+/// True when the message passes every configured filter of a webhook
+pub async fn passes(filters: &FilterSet, ctx: &TemplateContext) -> bool {
+    let msg = ctx.msg();
+
+    if let Some(contacts) = &filters.contact_filter {
+        if !contacts.is_empty() && !contacts.contains(&msg.contact) {
+            return false;
+        }
+    }
+
+    if let Some(sims) = &filters.sim_filter {
+        if !sims.is_empty() {
+            let alias = ctx.value(&SegmentName::Sim, None).await;
+            if !sims.contains(&alias) {
+                return false;
+            }
+        }
+    }
+
+    if let Some(time_filter) = &filters.time_filter {
+        if !time_matches(time_filter, msg) {
+            return false;
+        }
+    }
+
+    if let Some(message_filter) = &filters.message_filter {
+        if !message_matches(message_filter, &msg.message) {
+            return false;
+        }
+    }
+
+    let include_self_sent = filters.include_self_sent.unwrap_or(false);
+    if msg.send && !include_self_sent {
+        return false;
+    }
+
+    match &filters.filter {
+        Some(expr) => matches(expr, ctx).await,
+        None => true,
+    }
+}
+
+fn matches<'a>(
+    expr: &'a FilterExpr,
+    ctx: &'a TemplateContext,
+) -> Pin<Box<dyn Future<Output = bool> + Send + 'a>> {
+    Box::pin(async move {
+        let msg = ctx.msg();
+        match expr {
+            FilterExpr::All(exprs) => {
+                for expr in exprs {
+                    if !matches(expr, ctx).await {
+                        return false;
+                    }
+                }
+                true
+            }
+            FilterExpr::Any(exprs) => {
+                for expr in exprs {
+                    if matches(expr, ctx).await {
+                        return true;
+                    }
+                }
+                false
+            }
+            FilterExpr::Not(expr) => !matches(expr, ctx).await,
+            FilterExpr::Contact(contact) => contact_matches(contact, &msg.contact),
+            FilterExpr::Sim(sim) => sim_matches(sim, ctx).await,
+            FilterExpr::Message(message_filter) => message_matches(message_filter, &msg.message),
+            FilterExpr::Length(range) => length_matches(range, &msg.message),
+            FilterExpr::Direction(direction) => match direction {
+                Direction::Incoming => !msg.send,
+                Direction::Outgoing => msg.send,
+            },
+            FilterExpr::Time(time_filter) => time_matches(time_filter, msg),
+        }
+    })
+}
+
+fn contact_matches(filter: &ContactMatch, contact: &str) -> bool {
+    if filter.exclude.iter().any(|c| c == contact) {
+        return false;
+    }
+
+    let has_includes =
+        !filter.equals.is_empty() || !filter.prefix.is_empty() || filter.regex.is_some();
+    !has_includes
+        || filter.equals.iter().any(|c| c == contact)
+        || filter.prefix.iter().any(|p| contact.starts_with(p.as_str()))
+        || filter
+            .regex
+            .as_ref()
+            .is_some_and(|re| re.is_match(contact).unwrap_or(false))
+}
+
+async fn sim_matches(filter: &SimMatch, ctx: &TemplateContext) -> bool {
+    let iccid = ctx.msg().sim_id.as_str();
+    let imsi = ctx.sim().await.and_then(|sim| sim.imsi.clone());
+    let alias = ctx.value(&SegmentName::Sim, None).await;
+
+    let is = |value: &String| {
+        value == iccid || Some(value) == imsi.as_ref() || *value == alias
+    };
+    if filter.exclude.iter().any(is) {
+        return false;
+    }
+
+    let has_includes =
+        !filter.iccid.is_empty() || !filter.imsi.is_empty() || !filter.alias.is_empty();
+    !has_includes
+        || filter.iccid.iter().any(|v| v == iccid)
+        || filter.imsi.iter().any(|v| Some(v) == imsi.as_ref())
+        || filter.alias.contains(&alias)
+}
+
+fn length_matches(range: &LengthRange, message: &str) -> bool {
+    let len = message.chars().count();
+    range.min.is_none_or(|min| len >= min) && range.max.is_none_or(|max| len <= max)
+}
+
+fn time_matches(time_filter: &TimeFilter, msg: &ModemSMS) -> bool {
+    let local = local_time(time_filter, msg);
+
+    let date = local.date();
+    let excluded = time_filter.exclude_dates.iter().any(|exclusion| match exclusion {
+        DateExclusion::Date(d) => *d == date,
+        DateExclusion::Annual(month, day) => date.month() == *month && date.day() == *day,
+    });
+    if excluded {
+        return false;
+    }
+
+    time_filter.windows.is_empty()
+        || time_filter
+            .windows
+            .iter()
+            .any(|window| window_matches(window, &local))
+}
+
+/// Wall-clock time of the message in the filter's timezone
+fn local_time(time_filter: &TimeFilter, msg: &ModemSMS) -> NaiveDateTime {
+    let instant = match time_filter.basis {
+        TimeBasis::Received => msg.received_at.unwrap_or_else(Utc::now),
+        // Without a timezone, or without an offset in the PDU, the SMSC's wall-clock time is used
+        TimeBasis::Timestamp => match (time_filter.timezone, msg.utc_offset) {
+            (Some(_), Some(offset)) => match offset.from_local_datetime(&msg.timestamp).single() {
+                Some(dt) => dt.with_timezone(&Utc),
+                None => return msg.timestamp,
+            },
+            _ => return msg.timestamp,
+        },
+    };
+
+    match time_filter.timezone {
+        Some(tz) => instant.with_timezone(&tz).naive_local(),
+        None => instant.with_timezone(&Local).naive_local(),
+    }
+}
+
+fn window_matches(window: &TimeWindow, local: &NaiveDateTime) -> bool {
+    let on = |day: Weekday| {
+        window
+            .days
+            .as_ref()
+            .is_none_or(|days| days.is_empty() || days.contains(&day))
+    };
+    let day = local.weekday();
+    let time = local.time();
+
+    match window.range {
+        None => on(day),
+        Some((start, end)) if start <= end => on(day) && time >= start && time <= end,
+        // Overnight: the early-morning part belongs to the previous day's window
+        Some((start, end)) => (time >= start && on(day)) || (time <= end && on(day.pred())),
+    }
+}
+
+fn message_matches(message_filter: &MessageFilter, message: &str) -> bool {
+    if let Some(contains_list) = &message_filter.contains {
+        if !contains_list.is_empty() && !contains_list.iter().any(|s| message.contains(s)) {
+            return false;
+        }
+    }
+
+    if let Some(not_contains_list) = &message_filter.not_contains {
+        if !not_contains_list.is_empty() && not_contains_list.iter().any(|s| message.contains(s)) {
+            return false;
+        }
+    }
+
+    if let Some(re) = &message_filter.regex {
+        match re.is_match(message) {
+            Ok(true) => {}
+            _ => return false,
+        }
+    }
+
+    true
+}
@@ -0,0 +1,850 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    future::Future,
+    pin::Pin,
+    sync::{atomic::Ordering, Arc, OnceLock, RwLock},
+    time::Duration,
+};
+use urlencoding::encode;
+
+use crate::config::{
+    Condition, Conditional, Placeholder, SegmentName, TemplateFilter, TemplateSegment,
+    WebhookConfig,
+};
+use crate::db::{Contact, ModemSMS, SimCard, StoredWebhook};
+use crate::ModemManagerRef;
+use base64::prelude::*;
+use log::{debug, error, info, warn};
+use reqwest::Client;
+use serde::Serialize;
+use tokio::{
+    sync::{mpsc, OnceCell, Semaphore},
+    time::Instant,
+};
+
+use delivery::{
+    BreakerState, CircuitBreaker, DeliveryOutcome, EndpointStats, EndpointStatus, WebhookSource,
+};
+
+pub mod channels;
+pub mod delivery;
+pub mod filter;
+pub mod script;
+
+/// Per-message values available to templates. Lookups that need the database or
+/// the modem are resolved lazily, once per message.
+pub struct TemplateContext {
+    msg: ModemSMS,
+    modem_manager: Option<ModemManagerRef>,
+    sim: OnceCell<Option<SimCard>>,
+    contact_name: OnceCell<Option<String>>,
+    operator: OnceCell<Option<String>>,
+    variables: BTreeMap<String, String>,
+}
+
+impl TemplateContext {
+    pub fn new(msg: ModemSMS, modem_manager: Option<ModemManagerRef>) -> Self {
+        Self {
+            msg,
+            modem_manager,
+            sim: OnceCell::new(),
+            contact_name: OnceCell::new(),
+            operator: OnceCell::new(),
+            variables: BTreeMap::new(),
+        }
+    }
+
+    /// Values for the [`SegmentName::Variable`]s of the template; missing ones render empty
+    pub fn with_variables(mut self, variables: BTreeMap<String, String>) -> Self {
+        self.variables = variables;
+        self
+    }
+
+    pub fn msg(&self) -> &ModemSMS {
+        &self.msg
+    }
+
+    async fn sim(&self) -> Option<&SimCard> {
+        self.sim
+            .get_or_init(|| async {
+                SimCard::find_by_conditions(Some(&self.msg.sim_id), None, None, None)
+                    .await
+                    .ok()
+                    .and_then(|cards| cards.into_iter().next())
+            })
+            .await
+            .as_ref()
+    }
+
+    async fn contact_name(&self) -> Option<&String> {
+        self.contact_name
+            .get_or_init(|| async {
+                Contact::find_by_number(&self.msg.contact)
+                    .await
+                    .ok()
+                    .flatten()
+                    .and_then(|c| c.display_name)
+            })
+            .await
+            .as_ref()
+    }
+
+    async fn operator(&self) -> Option<&String> {
+        self.operator
+            .get_or_init(|| async {
+                let modem_manager = self.modem_manager.as_ref()?;
+                let info = tokio::time::timeout(
+                    std::time::Duration::from_secs(5),
+                    modem_manager.check_operator(&self.msg.sim_id),
+                )
+                .await
+                .ok()?
+                .ok()??;
+                Some(info.operator_name().to_string())
+            })
+            .await
+            .as_ref()
+    }
+
+    async fn modem_name(&self) -> Option<String> {
+        let modem_manager = self.modem_manager.as_ref()?;
+        modem_manager
+            .get_modem(&self.msg.sim_id)
+            .await
+            .map(|modem| modem.name.clone())
+    }
+
+    /// Raw value of a placeholder, `format` is the optional timestamp format filter
+    pub async fn value(&self, name: &SegmentName, format: Option<&str>) -> String {
+        let msg = &self.msg;
+        match name {
+            SegmentName::Contact => msg.contact.clone(),
+            SegmentName::ContactName => self.contact_name().await.cloned().unwrap_or_default(),
+            SegmentName::Message => msg.message.clone(),
+            SegmentName::MessageId => msg.id.map(|id| id.to_string()).unwrap_or_default(),
+            SegmentName::Segments => msg.segments.max(1).to_string(),
+            SegmentName::Sim => match self.sim().await {
+                Some(sim) => sim.get_effective_alias(),
+                None => format!("SIM-{}", &msg.sim_id[msg.sim_id.len().saturating_sub(4)..]),
+            },
+            SegmentName::SimId => msg.sim_id.clone(),
+            SegmentName::SimAlias => self
+                .sim()
+                .await
+                .and_then(|sim| sim.alias.clone())
+                .unwrap_or_default(),
+            SegmentName::PhoneNumber => self
+                .sim()
+                .await
+                .and_then(|sim| sim.phone_number.clone())
+                .unwrap_or_default(),
+            SegmentName::Operator => self.operator().await.cloned().unwrap_or_default(),
+            SegmentName::Modem => self.modem_name().await.unwrap_or_default(),
+            SegmentName::Timestamp => match format {
+                Some(fmt) => msg.timestamp.format(fmt).to_string(),
+                None => msg.timestamp.to_string(),
+            },
+            SegmentName::Send => msg.send.to_string(),
+            SegmentName::Variable(name) => self.variables.get(name).cloned().unwrap_or_default(),
+        }
+    }
+}
+
+fn apply_filter(filter: &TemplateFilter, value: String) -> String {
+    match filter {
+        TemplateFilter::Json => {
+            let quoted = serde_json::to_string(&value).unwrap_or_default();
+            quoted[1..quoted.len() - 1].to_string()
+        }
+        TemplateFilter::Url => encode(&value).into_owned(),
+        TemplateFilter::Xml => value
+            .replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;")
+            .replace('\'', "&apos;"),
+        TemplateFilter::Base64 => BASE64_STANDARD.encode(value.as_bytes()),
+        TemplateFilter::Upper => value.to_uppercase(),
+        TemplateFilter::Lower => value.to_lowercase(),
+        TemplateFilter::Truncate(n) => value.chars().take(*n).collect(),
+        TemplateFilter::Format(_) => value,
+        TemplateFilter::Default(default) => {
+            if value.is_empty() {
+                default.clone()
+            } else {
+                value
+            }
+        }
+    }
+}
+
+async fn render_placeholder(placeholder: &Placeholder, ctx: &TemplateContext) -> String {
+    let format = placeholder.filters.iter().find_map(|f| match f {
+        TemplateFilter::Format(fmt) => Some(fmt.as_str()),
+        _ => None,
+    });
+    let value = ctx.value(&placeholder.name, format).await;
+
+    let value = if let Some(regex) = &placeholder.regex {
+        match regex.captures(&value) {
+            Ok(Some(caps)) => {
+                let extracted = if let Some(name) = &placeholder.regex_name {
+                    caps.name(name).map(|m| m.as_str().to_string())
+                } else if let Some(index) = placeholder.regex_index {
+                    caps.get(index).map(|m| m.as_str().to_string())
+                } else {
+                    caps.get(1).map(|m| m.as_str().to_string())
+                };
+                extracted.unwrap_or_default()
+            }
+            _ => String::new(),
+        }
+    } else {
+        value
+    };
+
+    placeholder
+        .filters
+        .iter()
+        .fold(value, |value, filter| apply_filter(filter, value))
+}
+
+async fn condition_holds(conditional: &Conditional, ctx: &TemplateContext) -> bool {
+    let value = ctx.value(&conditional.name, None).await;
+    match &conditional.condition {
+        Condition::Present => !value.is_empty() && value != "false",
+        Condition::Absent => value.is_empty() || value == "false",
+        Condition::Equals(expected) => &value == expected,
+        Condition::NotEquals(expected) => &value != expected,
+    }
+}
+
+/// Renders segments; `encode_fixed`/`encode_values` URL-encode literal text and
+/// placeholder values respectively.
+fn render_segments<'a>(
+    segments: &'a [TemplateSegment],
+    ctx: &'a TemplateContext,
+    encode_fixed: bool,
+    encode_values: bool,
+) -> Pin<Box<dyn Future<Output = String> + Send + 'a>> {
+    Box::pin(async move {
+        let mut result = String::new();
+
+        for segment in segments {
+            match segment {
+                TemplateSegment::Fixed(text) => {
+                    if encode_fixed {
+                        result.push_str(&encode(text));
+                    } else {
+                        result.push_str(text);
+                    }
+                }
+                TemplateSegment::Placeholder(placeholder) => {
+                    let value = render_placeholder(placeholder, ctx).await;
+                    if encode_values {
+                        result.push_str(&encode(&value));
+                    } else {
+                        result.push_str(&value);
+                    }
+                }
+                TemplateSegment::Conditional(conditional) => {
+                    let branch = if condition_holds(conditional, ctx).await {
+                        &conditional.then
+                    } else {
+                        &conditional.otherwise
+                    };
+                    result.push_str(&render_segments(branch, ctx, encode_fixed, encode_values).await);
+                }
+            }
+        }
+
+        result
+    })
+}
+
+pub async fn apply_template_segments(segments: &[TemplateSegment], ctx: &TemplateContext) -> String {
+    render_segments(segments, ctx, false, false).await
+}
+
+pub async fn apply_template_segments_url(segments: &[TemplateSegment], ctx: &TemplateContext) -> String {
+    render_segments(segments, ctx, false, true).await
+}
+
+pub async fn apply_template_segments_url_params(segments: &[TemplateSegment], ctx: &TemplateContext) -> String {
+    render_segments(segments, ctx, true, true).await
+}
+
+/// A webhook request after template rendering, before it is handed to reqwest
+#[derive(Debug, Clone, Serialize)]
+pub struct RenderedRequest {
+    #[serde(serialize_with = "serialize_method")]
+    pub method: reqwest::Method,
+    pub url: String,
+    pub headers: HashMap<String, String>,
+    pub url_params: HashMap<String, String>,
+    pub body: Option<String>,
+}
+
+fn serialize_method<S: serde::Serializer>(method: &reqwest::Method, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(method.as_str())
+}
+
+/// Outcome of rendering, and optionally sending, a webhook for a sample message
+#[derive(Debug, Default, Serialize)]
+pub struct WebhookTestResult {
+    pub filtered: bool, // The webhook's filters would have dropped this message
+    pub skipped: bool,  // The script returned ()
+    pub request: Option<RenderedRequest>,
+    pub status: Option<u16>,
+    pub response: Option<String>,
+    pub error: Option<String>,
+}
+
+pub async fn render_request(cfg: &WebhookConfig, ctx: &TemplateContext) -> RenderedRequest {
+    if let Some(channel) = &cfg.channel {
+        return channel.render(ctx).await;
+    }
+
+    let url = apply_template_segments_url(&cfg.url, ctx).await;
+
+    let mut headers = HashMap::new();
+    if let Some(h) = &cfg.headers {
+        for (key, segments) in h {
+            headers.insert(key.clone(), apply_template_segments(segments, ctx).await);
+        }
+    }
+
+    let body = if let Some(body) = &cfg.body {
+        Some(apply_template_segments(body, ctx).await)
+    } else {
+        None
+    };
+
+    let mut url_params = HashMap::new();
+    if let Some(params) = &cfg.url_params {
+        for (key, segments) in params {
+            // 对参数名和参数值都进行URL编码
+            let encoded_key = encode(key);
+            let encoded_value = apply_template_segments_url_params(segments, ctx).await;
+            url_params.insert(encoded_key.into_owned(), encoded_value);
+        }
+    }
+
+    RenderedRequest {
+        method: cfg.method.clone().into(),
+        url,
+        headers,
+        url_params,
+        body,
+    }
+}
+
+/// Renders the templates and runs the script; `None` means the script skipped the message
+pub async fn prepare_request(
+    cfg: &WebhookConfig,
+    ctx: &TemplateContext,
+) -> Result<Option<RenderedRequest>, String> {
+    let request = render_request(cfg, ctx).await;
+    match &cfg.script {
+        Some(script) => script.transform(script_message(ctx).await, request),
+        None => Ok(Some(request)),
+    }
+}
+
+/// The message as seen by webhook scripts
+async fn script_message(ctx: &TemplateContext) -> rhai::Map {
+    let mut map = rhai::Map::new();
+    let fields = [
+        ("contact", SegmentName::Contact),
+        ("contact_name", SegmentName::ContactName),
+        ("message", SegmentName::Message),
+        ("message_id", SegmentName::MessageId),
+        ("timestamp", SegmentName::Timestamp),
+        ("sim", SegmentName::Sim),
+        ("sim_id", SegmentName::SimId),
+        ("sim_alias", SegmentName::SimAlias),
+        ("phone_number", SegmentName::PhoneNumber),
+        ("operator", SegmentName::Operator),
+        ("modem", SegmentName::Modem),
+    ];
+    for (key, name) in fields {
+        map.insert(key.into(), ctx.value(&name, None).await.into());
+    }
+    map.insert("segments".into(), (ctx.msg().segments.max(1) as i64).into());
+    map.insert("send".into(), ctx.msg().send.into());
+    map
+}
+
+struct Endpoint {
+    config: WebhookConfig,
+    semaphore: Arc<Semaphore>,
+    max_concurrent: usize,
+    breaker: CircuitBreaker,
+    stats: EndpointStats,
+}
+
+/// A running endpoint. Dropping it closes the queue; its worker drains what is left and exits.
+struct Target {
+    source: WebhookSource,
+    endpoint: Arc<Endpoint>,
+    queue: mpsc::UnboundedSender<Arc<TemplateContext>>,
+}
+
+/// Webhook URL or channel endpoint with placeholders elided; used in logs and status reports
+fn target_name(cfg: &WebhookConfig) -> String {
+    if let Some(channel) = &cfg.channel {
+        return channel.endpoint();
+    }
+    cfg.url
+        .iter()
+        .map(|segment| match segment {
+            TemplateSegment::Fixed(s) => s.as_str(),
+            _ => "@…@",
+        })
+        .collect()
+}
+
+#[derive(Clone)]
+pub struct WebhookManager {
+    client: Client,
+    targets: Arc<RwLock<Vec<Target>>>,
+    sender: mpsc::UnboundedSender<ModemSMS>,
+    max_concurrent_requests: usize,
+    modem_manager: Arc<OnceLock<ModemManagerRef>>,
+}
+
+impl WebhookManager {
+    pub fn new(configs: Vec<WebhookConfig>) -> Self {
+        Self::new_with_concurrency(configs, 10)
+    }
+
+    /// `max_concurrent` is the per-endpoint limit for webhooks that don't set their own
+    pub fn new_with_concurrency(configs: Vec<WebhookConfig>, max_concurrent: usize) -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        let manager = WebhookManager {
+            client: Client::new(),
+            targets: Arc::new(RwLock::new(Vec::new())),
+            sender,
+            max_concurrent_requests: max_concurrent,
+            modem_manager: Arc::new(OnceLock::new()),
+        };
+
+        let targets = configs
+            .into_iter()
+            .enumerate()
+            .map(|(index, config)| manager.spawn_target(WebhookSource::Config(index), config))
+            .collect();
+        *manager.targets.write().unwrap() = targets;
+
+        let manager_clone = manager.clone();
+        tokio::spawn(async move {
+            manager_clone.receiver_loop(receiver).await;
+        });
+
+        manager
+    }
+
+    fn spawn_target(&self, source: WebhookSource, config: WebhookConfig) -> Target {
+        let (queue, queue_receiver) = mpsc::unbounded_channel();
+        let limit = if config.delivery.ordered.unwrap_or(false) {
+            1
+        } else {
+            config
+                .delivery
+                .max_concurrent
+                .unwrap_or(self.max_concurrent_requests)
+                .max(1)
+        };
+        let endpoint = Arc::new(Endpoint {
+            breaker: CircuitBreaker::new(config.delivery.circuit_breaker.clone()),
+            config,
+            semaphore: Arc::new(Semaphore::new(limit)),
+            max_concurrent: limit,
+            stats: EndpointStats::default(),
+        });
+
+        let manager_clone = self.clone();
+        let endpoint_clone = endpoint.clone();
+        tokio::spawn(async move {
+            manager_clone.endpoint_loop(endpoint_clone, queue_receiver).await;
+        });
+
+        Target {
+            source,
+            endpoint,
+            queue,
+        }
+    }
+
+    /// Adds or replaces a webhook stored in the database. Deliveries already queued
+    /// for the old definition are still sent with it.
+    pub fn upsert_stored(&self, id: i64, config: WebhookConfig) {
+        let target = self.spawn_target(WebhookSource::Database(id), config);
+        let mut targets = self.targets.write().unwrap();
+        match targets.iter_mut().find(|t| t.source == WebhookSource::Database(id)) {
+            Some(existing) => *existing = target,
+            None => targets.push(target),
+        }
+        info!("Webhook {} applied", id);
+    }
+
+    pub fn remove_stored(&self, id: i64) {
+        self.targets
+            .write()
+            .unwrap()
+            .retain(|t| t.source != WebhookSource::Database(id));
+        info!("Webhook {} removed", id);
+    }
+
+    fn endpoints(&self) -> Vec<(Arc<Endpoint>, mpsc::UnboundedSender<Arc<TemplateContext>>)> {
+        self.targets
+            .read()
+            .unwrap()
+            .iter()
+            .map(|t| (t.endpoint.clone(), t.queue.clone()))
+            .collect()
+    }
+
+    /// Gives templates access to the modems for the `operator` and `modem` placeholders
+    pub fn attach_modem_manager(&self, modem_manager: ModemManagerRef) {
+        let _ = self.modem_manager.set(modem_manager);
+    }
+
+    pub fn send(&self, msg: ModemSMS) -> Result<(), Box<mpsc::error::SendError<ModemSMS>>> {
+        self.sender.send(msg).map_err(Box::new)
+    }
+
+    /// Fans each message out to the queues of the endpoints whose filters it passes
+    async fn receiver_loop(&self, mut receiver: mpsc::UnboundedReceiver<ModemSMS>) {
+        while let Some(msg) = receiver.recv().await {
+            debug!("Webhook worker received message: {:?}", msg);
+
+            let ctx = Arc::new(TemplateContext::new(msg, self.modem_manager.get().cloned()));
+
+            for (endpoint, queue) in self.endpoints() {
+                if !self.passes_filters(&endpoint.config, &ctx).await {
+                    debug!(
+                        "Message from {} filtered out by webhook configuration",
+                        ctx.msg().contact
+                    );
+                    continue;
+                }
+
+                endpoint.stats.queued.fetch_add(1, Ordering::Relaxed);
+                if queue.send(ctx.clone()).is_err() {
+                    endpoint.stats.queued.fetch_sub(1, Ordering::Relaxed);
+                    error!("Webhook queue for {} is closed", target_name(&endpoint.config));
+                }
+            }
+        }
+    }
+
+    /// Delivers one endpoint's queue, honouring its concurrency, ordering, rate limit and breaker
+    async fn endpoint_loop(
+        &self,
+        endpoint: Arc<Endpoint>,
+        mut queue: mpsc::UnboundedReceiver<Arc<TemplateContext>>,
+    ) {
+        let ordered = endpoint.config.delivery.ordered.unwrap_or(false);
+        let interval = endpoint
+            .config
+            .delivery
+            .rate_limit
+            .map(|per_minute| Duration::from_secs(60) / per_minute);
+        let mut next_slot = Instant::now();
+
+        while let Some(ctx) = queue.recv().await {
+            let permit = match endpoint.semaphore.clone().acquire_owned().await {
+                Ok(permit) => permit,
+                Err(_) => {
+                    error!("Failed to acquire semaphore permit for webhook");
+                    return;
+                }
+            };
+
+            // Checked after the permit so deliveries still in flight can open the breaker first
+            let trial = endpoint.breaker.ready().await;
+
+            if let Some(interval) = interval {
+                tokio::time::sleep_until(next_slot).await;
+                next_slot = Instant::now() + interval;
+            }
+
+            endpoint.stats.queued.fetch_sub(1, Ordering::Relaxed);
+            endpoint.stats.in_flight.fetch_add(1, Ordering::Relaxed);
+
+            // The trial request after a pause is awaited so its result decides what happens next
+            if ordered || trial {
+                self.deliver(&endpoint, &ctx).await;
+                drop(permit);
+            } else {
+                let self_clone = self.clone();
+                let endpoint = endpoint.clone();
+                tokio::spawn(async move {
+                    self_clone.deliver(&endpoint, &ctx).await;
+                    drop(permit);
+                });
+            }
+        }
+    }
+
+    async fn deliver(&self, endpoint: &Endpoint, ctx: &TemplateContext) {
+        let outcome = self.process_webhook(&endpoint.config, ctx).await;
+
+        endpoint.stats.in_flight.fetch_sub(1, Ordering::Relaxed);
+        endpoint.stats.record(outcome);
+
+        match endpoint.breaker.record(outcome) {
+            Some(BreakerState::Open) => warn!(
+                "Circuit breaker opened for {}, queueing deliveries",
+                target_name(&endpoint.config)
+            ),
+            Some(BreakerState::Closed) => info!(
+                "Circuit breaker closed for {}, resuming deliveries",
+                target_name(&endpoint.config)
+            ),
+            _ => {}
+        }
+    }
+
+    /// Current breaker state and queue counters of every webhook and channel
+    pub fn endpoint_statuses(&self) -> Vec<EndpointStatus> {
+        self.targets
+            .read()
+            .unwrap()
+            .iter()
+            .map(|t| {
+                EndpointStatus::new(
+                    t.source,
+                    target_name(&t.endpoint.config),
+                    &t.endpoint.breaker,
+                    &t.endpoint.stats,
+                )
+            })
+            .collect()
+    }
+
+    pub(crate) async fn passes_filters(&self, config: &WebhookConfig, ctx: &TemplateContext) -> bool {
+        filter::passes(&config.filters, ctx).await
+    }
+
+    /// Connection errors, timeouts, 5xx and 429 responses count as failures for the circuit breaker
+    async fn process_webhook(&self, cfg: &WebhookConfig, ctx: &TemplateContext) -> DeliveryOutcome {
+        let msg = ctx.msg();
+        let start_time = std::time::Instant::now();
+
+        if let Some(channel) = cfg.channel.as_ref().filter(|c| c.is_email()) {
+            return match channel.send_email(ctx).await {
+                Ok(()) => {
+                    info!("Email notification sent in {:?}", start_time.elapsed());
+                    DeliveryOutcome::Delivered
+                }
+                Err(e) => {
+                    error!("{}", e);
+                    DeliveryOutcome::Failed
+                }
+            };
+        }
+
+        let request = match prepare_request(cfg, ctx).await {
+            Ok(Some(request)) => request,
+            Ok(None) => {
+                debug!("Message from {} skipped by webhook script", msg.contact);
+                return DeliveryOutcome::Skipped;
+            }
+            Err(e) => {
+                error!("{}", e);
+                return DeliveryOutcome::Skipped;
+            }
+        };
+        let url = request.url.clone();
+
+        match self.send_request(cfg, request).await {
+            Ok(response) => {
+                let elapsed = start_time.elapsed();
+                let status = response.status();
+                info!(
+                    "Webhook to {} responded with status: {} in {:?}",
+                    url,
+                    status,
+                    elapsed
+                );
+
+                if log::log_enabled!(log::Level::Debug) {
+                    if let Ok(text) = response.text().await {
+                        debug!("Webhook response body: {}", text);
+                    }
+                }
+
+                if status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+                    DeliveryOutcome::Failed
+                } else {
+                    DeliveryOutcome::Delivered
+                }
+            }
+            Err(e) => {
+                let elapsed = start_time.elapsed();
+                error!(
+                    "Failed to send webhook to {} after {:?}: {}",
+                    url, elapsed, e
+                );
+                DeliveryOutcome::Failed
+            }
+        }
+    }
+
+    async fn send_request(
+        &self,
+        cfg: &WebhookConfig,
+        request: RenderedRequest,
+    ) -> Result<reqwest::Response, reqwest::Error> {
+        let RenderedRequest {
+            method,
+            url,
+            headers: raw_headers,
+            url_params,
+            body: body_str,
+        } = request;
+
+        let mut headers = reqwest::header::HeaderMap::new();
+        for (key, value) in raw_headers {
+            match reqwest::header::HeaderName::from_bytes(key.as_bytes()) { Ok(header_name) => {
+                match reqwest::header::HeaderValue::from_str(&value) { Ok(header_value) => {
+                    headers.insert(header_name, header_value);
+                } _ => {
+                    error!("Invalid header value for key {}: {}", key, value);
+                }}
+            } _ => {
+                error!("Invalid header name: {}", key);
+            }}
+        }
+
+        let timeout_duration = std::time::Duration::from_secs(cfg.timeout.unwrap_or(10)); 
+
+        let mut request_builder = self
+            .client
+            .request(method.clone(), &url)
+            .headers(headers)
+            .timeout(timeout_duration);
+
+        if !url_params.is_empty() {
+            request_builder = request_builder.query(&url_params);
+        }
+
+        if let Some(body_content) = body_str {
+            if let Ok(json_body) = serde_json::from_str::<serde_json::Value>(&body_content) {
+                request_builder = request_builder.json(&json_body);
+            } else {
+                request_builder = request_builder.body(body_content);
+            }
+        }
+
+        info!("Sending webhook to URL: {}, Method: {}", url, method);
+
+        request_builder.send().await
+    }
+
+    /// Starts the enabled webhooks stored in the database
+    pub async fn load_stored(&self) -> anyhow::Result<()> {
+        for webhook in StoredWebhook::query_all().await? {
+            if !webhook.enabled {
+                continue;
+            }
+            match WebhookConfig::from_json(&webhook.config) {
+                Ok(config) => self.upsert_stored(webhook.id, config),
+                Err(e) => error!("Stored webhook {} is invalid and was not started: {}", webhook.id, e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Delivers `msg` once outside the queue, without checking the webhook's filters
+    pub async fn deliver_once(&self, cfg: &WebhookConfig, msg: ModemSMS) -> DeliveryOutcome {
+        let ctx = TemplateContext::new(msg, self.modem_manager.get().cloned());
+        self.process_webhook(cfg, &ctx).await
+    }
+
+    /// Renders the webhook for `msg` and, when `send` is set, delivers it once outside the queue
+    pub async fn test_webhook(&self, cfg: &WebhookConfig, msg: ModemSMS, send: bool) -> WebhookTestResult {
+        let ctx = TemplateContext::new(msg, self.modem_manager.get().cloned());
+        let mut result = WebhookTestResult {
+            filtered: !self.passes_filters(cfg, &ctx).await,
+            ..Default::default()
+        };
+
+        let request = match prepare_request(cfg, &ctx).await {
+            Ok(Some(request)) => request,
+            Ok(None) => {
+                result.skipped = true;
+                return result;
+            }
+            Err(e) => {
+                result.error = Some(e);
+                return result;
+            }
+        };
+        result.request = Some(request.clone());
+
+        if send {
+            match self.send_request(cfg, request).await {
+                Ok(response) => {
+                    result.status = Some(response.status().as_u16());
+                    result.response = response.text().await.ok();
+                }
+                Err(e) => result.error = Some(e.to_string()),
+            }
+        }
+
+        result
+    }
+
+    pub fn config_count(&self) -> usize {
+        self.targets.read().unwrap().len()
+    }
+
+    pub fn max_concurrent_requests(&self) -> usize {
+        self.max_concurrent_requests
+    }
+
+    pub fn available_permits(&self) -> usize {
+        self.endpoints()
+            .iter()
+            .map(|(endpoint, _)| endpoint.semaphore.available_permits())
+            .sum()
+    }
+
+    #[cfg(test)]
+    pub async fn test_passes_filters(&self, msg: &ModemSMS) -> bool {
+        if let Some((endpoint, _)) = self.endpoints().first() {
+            let ctx = TemplateContext::new(msg.clone(), None);
+            self.passes_filters(&endpoint.config, &ctx).await
+        } else {
+            false
+        }
+    }
+
+    pub async fn shutdown(&self) {
+        info!("Shutting down webhook manager...");
+
+        for (endpoint, _) in self.endpoints() {
+            let _permits = endpoint
+                .semaphore
+                .acquire_many(endpoint.max_concurrent as u32)
+                .await;
+        }
+
+        info!("Webhook manager shutdown complete");
+    }
+}
+
+pub fn _start_webhook_worker(configs: Vec<WebhookConfig>) -> WebhookManager {
+    WebhookManager::new(configs.to_vec())
+}
+
+pub fn start_webhook_worker_with_concurrency(
+    configs: Vec<WebhookConfig>,
+    max_concurrent: usize,
+) -> WebhookManager {
+    WebhookManager::new_with_concurrency(configs, max_concurrent)
+}
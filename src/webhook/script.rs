@@ -0,0 +1,128 @@
+use std::{collections::HashMap, fmt};
+
+use rhai::{Dynamic, Engine, Map, Scope, AST};
+
+use super::RenderedRequest;
+
+/// Upper bound on script operations so a runaway loop can't stall the webhook worker
+const MAX_OPERATIONS: u64 = 100_000;
+
+/// A compiled Rhai transformer attached to a webhook.
+///
+/// The script sees two variables: `msg`, the message and its SIM as a map, and
+/// `request`, the request rendered from the templates (`method`, `url`, `headers`,
+/// `params`, `body`). It returns the request to send, or `()` to skip delivery.
+pub struct WebhookScript {
+    engine: Engine,
+    ast: AST,
+}
+
+impl fmt::Debug for WebhookScript {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WebhookScript").finish_non_exhaustive()
+    }
+}
+
+impl WebhookScript {
+    pub fn compile(source: &str) -> Result<Self, String> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+
+        let ast = engine
+            .compile(source)
+            .map_err(|e| format!("Invalid webhook script: {}", e))?;
+
+        Ok(Self { engine, ast })
+    }
+
+    pub fn transform(
+        &self,
+        msg: Map,
+        request: RenderedRequest,
+    ) -> Result<Option<RenderedRequest>, String> {
+        let mut scope = Scope::new();
+        scope.push("msg", msg);
+        scope.push("request", request_to_map(&request));
+
+        let result: Dynamic = self
+            .engine
+            .eval_ast_with_scope(&mut scope, &self.ast)
+            .map_err(|e| format!("Webhook script failed: {}", e))?;
+
+        if result.is_unit() {
+            return Ok(None);
+        }
+        if let Some(false) = result.clone().try_cast::<bool>() {
+            return Ok(None);
+        }
+
+        let map = result
+            .try_cast::<Map>()
+            .ok_or_else(|| "Webhook script must return a request map or ()".to_string())?;
+
+        map_to_request(map, request).map(Some)
+    }
+}
+
+fn request_to_map(request: &RenderedRequest) -> Map {
+    let to_map = |values: &HashMap<String, String>| -> Map {
+        values
+            .iter()
+            .map(|(k, v)| (k.as_str().into(), v.clone().into()))
+            .collect()
+    };
+
+    let mut map = Map::new();
+    map.insert("method".into(), request.method.to_string().into());
+    map.insert("url".into(), request.url.clone().into());
+    map.insert("headers".into(), to_map(&request.headers).into());
+    map.insert("params".into(), to_map(&request.url_params).into());
+    map.insert(
+        "body".into(),
+        request.body.clone().map(Dynamic::from).unwrap_or(Dynamic::UNIT),
+    );
+    map
+}
+
+/// Fields missing from the returned map keep their templated values
+fn map_to_request(mut map: Map, mut request: RenderedRequest) -> Result<RenderedRequest, String> {
+    let string_map = |value: Dynamic, field: &str| -> Result<HashMap<String, String>, String> {
+        value
+            .try_cast::<Map>()
+            .ok_or_else(|| format!("Script field '{}' must be a map", field))
+            .map(|m| m.into_iter().map(|(k, v)| (k.to_string(), v.to_string())).collect())
+    };
+
+    if let Some(method) = map.remove("method") {
+        request.method = method
+            .to_string()
+            .parse::<reqwest::Method>()
+            .map_err(|_| format!("Invalid HTTP method returned by script: {}", method))?;
+    }
+    if let Some(url) = map.remove("url") {
+        let url = url.to_string();
+        if !(url.starts_with("http://") || url.starts_with("https://")) {
+            return Err(format!("URL returned by script must start with http:// or https:// (got: {})", url));
+        }
+        request.url = url;
+    }
+    if let Some(headers) = map.remove("headers") {
+        request.headers = string_map(headers, "headers")?;
+    }
+    if let Some(params) = map.remove("params") {
+        request.url_params = string_map(params, "params")?;
+    }
+    if let Some(body) = map.remove("body") {
+        request.body = if body.is_unit() {
+            None
+        } else if body.is_string() {
+            Some(body.to_string())
+        } else {
+            let json: serde_json::Value = rhai::serde::from_dynamic(&body)
+                .map_err(|e| format!("Script body is not serializable: {}", e))?;
+            Some(json.to_string())
+        };
+    }
+
+    Ok(request)
+}